@@ -184,6 +184,8 @@ pub async fn db_auth_middleware(
     req.extensions_mut().insert(AuthContext {
         principal: username,
         role,
+        tenant: None,
+        api_key: None,
     });
 
     Ok(next.run(req).await)
@@ -6,6 +6,7 @@ use uuid::Uuid;
 
 use crate::auth::{new_session_token, require_role, role_from_str, role_to_str, session_expiry, verify_password, AuthContext, Role};
 use crate::state::AppState;
+use nebula_common::{ApiError, ErrorCode};
 
 #[derive(Deserialize)]
 pub struct LoginRequest {
@@ -75,19 +76,14 @@ pub struct UpdateUserRequest {
     pub password: Option<String>,
 }
 
-#[derive(Serialize)]
-struct ErrorBody {
-    error: String,
-}
-
-fn err(status: StatusCode, msg: &str) -> Response {
-    (status, Json(ErrorBody { error: msg.to_string() })).into_response()
+fn err(code: ErrorCode, msg: &str) -> Response {
+    ApiError::new(code, msg).into_response()
 }
 
 pub async fn login(State(st): State<AppState>, Json(req): Json<LoginRequest>) -> Response {
     let username = req.username.trim();
     if username.is_empty() || req.password.is_empty() {
-        return err(StatusCode::BAD_REQUEST, "username and password are required");
+        return err(ErrorCode::InvalidRequest, "username and password are required");
     }
 
     let row = match sqlx::query(
@@ -105,22 +101,22 @@ pub async fn login(State(st): State<AppState>, Json(req): Json<LoginRequest>) ->
         Ok(v) => v,
         Err(e) => {
             tracing::error!(error=%e, "login query failed");
-            return err(StatusCode::INTERNAL_SERVER_ERROR, "database error");
+            return err(ErrorCode::InternalError, "database error");
         }
     };
 
     let Some(row) = row else {
-        return err(StatusCode::UNAUTHORIZED, "invalid credentials");
+        return err(ErrorCode::InvalidCredentials, "invalid credentials");
     };
 
     let is_active: bool = row.get("is_active");
     if !is_active {
-        return err(StatusCode::FORBIDDEN, "user disabled");
+        return err(ErrorCode::PermissionDenied, "user disabled");
     }
 
     let password_hash: String = row.get("password_hash");
     if !verify_password(&password_hash, &req.password) {
-        return err(StatusCode::UNAUTHORIZED, "invalid credentials");
+        return err(ErrorCode::InvalidCredentials, "invalid credentials");
     }
 
     let user_id: Uuid = row.get("id");
@@ -135,7 +131,7 @@ pub async fn login(State(st): State<AppState>, Json(req): Json<LoginRequest>) ->
         .await
     {
         tracing::error!(error=%e, "insert session failed");
-        return err(StatusCode::INTERNAL_SERVER_ERROR, "database error");
+        return err(ErrorCode::InternalError, "database error");
     }
 
     let role_raw: String = row.get("role");
@@ -167,7 +163,7 @@ pub async fn logout(
         .and_then(|v| v.strip_prefix("Bearer "))
         .map(|s| s.to_string())
     else {
-        return err(StatusCode::BAD_REQUEST, "missing token");
+        return err(ErrorCode::InvalidRequest, "missing token");
     };
 
     let _ = sqlx::query("DELETE FROM bff_sessions WHERE token = $1")
@@ -195,12 +191,12 @@ pub async fn me(State(st): State<AppState>, Extension(ctx): Extension<AuthContex
         Ok(v) => v,
         Err(e) => {
             tracing::error!(error=%e, "me query failed");
-            return err(StatusCode::INTERNAL_SERVER_ERROR, "database error").into_response();
+            return err(ErrorCode::InternalError, "database error").into_response();
         }
     };
 
     let Some(row) = row else {
-        return err(StatusCode::NOT_FOUND, "user not found").into_response();
+        return err(ErrorCode::NotFound, "user not found").into_response();
     };
 
     let role_raw: String = row.get("role");
@@ -241,7 +237,7 @@ pub async fn update_profile(
     .await
     {
         tracing::error!(error=%e, "update profile failed");
-        return err(StatusCode::INTERNAL_SERVER_ERROR, "database error");
+        return err(ErrorCode::InternalError, "database error");
     }
 
     (StatusCode::OK, Json(serde_json::json!({"ok": true}))).into_response()
@@ -256,12 +252,12 @@ pub async fn get_settings(State(st): State<AppState>, Extension(ctx): Extension<
         Ok(v) => v,
         Err(e) => {
             tracing::error!(error=%e, "get settings user query failed");
-            return err(StatusCode::INTERNAL_SERVER_ERROR, "database error").into_response();
+            return err(ErrorCode::InternalError, "database error").into_response();
         }
     };
 
     let Some(user_row) = user_row else {
-        return err(StatusCode::NOT_FOUND, "user not found").into_response();
+        return err(ErrorCode::NotFound, "user not found").into_response();
     };
     let user_id: Uuid = user_row.get("id");
 
@@ -278,7 +274,7 @@ pub async fn get_settings(State(st): State<AppState>, Extension(ctx): Extension<
         Ok(v) => v,
         Err(e) => {
             tracing::error!(error=%e, "get settings query failed");
-            return err(StatusCode::INTERNAL_SERVER_ERROR, "database error").into_response();
+            return err(ErrorCode::InternalError, "database error").into_response();
         }
     };
 
@@ -303,12 +299,12 @@ pub async fn update_settings(
         Ok(v) => v,
         Err(e) => {
             tracing::error!(error=%e, "update settings user query failed");
-            return err(StatusCode::INTERNAL_SERVER_ERROR, "database error");
+            return err(ErrorCode::InternalError, "database error");
         }
     };
 
     let Some(user_row) = user_row else {
-        return err(StatusCode::NOT_FOUND, "user not found");
+        return err(ErrorCode::NotFound, "user not found");
     };
     let user_id: Uuid = user_row.get("id");
 
@@ -325,7 +321,7 @@ pub async fn update_settings(
         Ok(v) => v,
         Err(e) => {
             tracing::error!(error=%e, "load current settings failed");
-            return err(StatusCode::INTERNAL_SERVER_ERROR, "database error");
+            return err(ErrorCode::InternalError, "database error");
         }
     };
 
@@ -342,7 +338,7 @@ pub async fn update_settings(
     .await
     {
         tracing::error!(error=%e, "update settings failed");
-        return err(StatusCode::INTERNAL_SERVER_ERROR, "database error");
+        return err(ErrorCode::InternalError, "database error");
     }
 
     (StatusCode::OK, Json(serde_json::json!({"ok": true}))).into_response()
@@ -362,7 +358,7 @@ pub async fn list_users(State(st): State<AppState>, Extension(ctx): Extension<Au
         Ok(v) => v,
         Err(e) => {
             tracing::error!(error=%e, "list users failed");
-            return err(StatusCode::INTERNAL_SERVER_ERROR, "database error").into_response();
+            return err(ErrorCode::InternalError, "database error").into_response();
         }
     };
 
@@ -396,17 +392,17 @@ pub async fn create_user(
 
     let username = req.username.trim();
     if username.is_empty() || req.password.trim().is_empty() {
-        return err(StatusCode::BAD_REQUEST, "username/password required").into_response();
+        return err(ErrorCode::InvalidRequest, "username/password required").into_response();
     }
 
     let Some(role) = role_from_str(&req.role) else {
-        return err(StatusCode::BAD_REQUEST, "invalid role").into_response();
+        return err(ErrorCode::InvalidRequest, "invalid role").into_response();
     };
 
     let user_id = Uuid::new_v4();
     let password_hash = match crate::auth::hash_password(req.password.trim()) {
         Ok(v) => v,
-        Err(_) => return err(StatusCode::INTERNAL_SERVER_ERROR, "failed to hash password").into_response(),
+        Err(_) => return err(ErrorCode::InternalError, "failed to hash password").into_response(),
     };
 
     let insert = sqlx::query(
@@ -426,7 +422,7 @@ pub async fn create_user(
 
     if let Err(e) = insert {
         tracing::warn!(error=%e, "create user failed");
-        return err(StatusCode::CONFLICT, "user already exists").into_response();
+        return err(ErrorCode::UserExists, "user already exists").into_response();
     }
 
     let _ = sqlx::query("INSERT INTO bff_user_settings (user_id) VALUES ($1) ON CONFLICT (user_id) DO NOTHING")
@@ -448,7 +444,7 @@ pub async fn update_user(
     }
 
     let Ok(user_uuid) = Uuid::parse_str(&user_id) else {
-        return err(StatusCode::BAD_REQUEST, "invalid user id").into_response();
+        return err(ErrorCode::InvalidRequest, "invalid user id").into_response();
     };
 
     let existing = match sqlx::query("SELECT role, display_name, email, is_active FROM bff_users WHERE id = $1")
@@ -457,17 +453,17 @@ pub async fn update_user(
         .await
     {
         Ok(v) => v,
-        Err(_) => return err(StatusCode::INTERNAL_SERVER_ERROR, "database error").into_response(),
+        Err(_) => return err(ErrorCode::InternalError, "database error").into_response(),
     };
 
     let Some(existing) = existing else {
-        return err(StatusCode::NOT_FOUND, "user not found").into_response();
+        return err(ErrorCode::NotFound, "user not found").into_response();
     };
 
     let role = match req.role.as_deref() {
         Some(raw) => match role_from_str(raw) {
             Some(v) => role_to_str(v).to_string(),
-            None => return err(StatusCode::BAD_REQUEST, "invalid role").into_response(),
+            None => return err(ErrorCode::InvalidRequest, "invalid role").into_response(),
         },
         None => existing.get::<String, _>("role"),
     };
@@ -497,13 +493,13 @@ pub async fn update_user(
     .execute(&st.db)
     .await
     {
-        return err(StatusCode::INTERNAL_SERVER_ERROR, "database error").into_response();
+        return err(ErrorCode::InternalError, "database error").into_response();
     }
 
     if let Some(password) = req.password.as_deref().map(str::trim).filter(|p| !p.is_empty()) {
         let hash = match crate::auth::hash_password(password) {
             Ok(v) => v,
-            Err(_) => return err(StatusCode::INTERNAL_SERVER_ERROR, "failed to hash password").into_response(),
+            Err(_) => return err(ErrorCode::InternalError, "failed to hash password").into_response(),
         };
         let _ = sqlx::query("UPDATE bff_users SET password_hash = $1, updated_at = NOW() WHERE id = $2")
             .bind(hash)
@@ -525,7 +521,7 @@ pub async fn delete_user(
     }
 
     let Ok(user_uuid) = Uuid::parse_str(&user_id) else {
-        return err(StatusCode::BAD_REQUEST, "invalid user id").into_response();
+        return err(ErrorCode::InvalidRequest, "invalid user id").into_response();
     };
 
     let row = match sqlx::query("SELECT username FROM bff_users WHERE id = $1")
@@ -534,15 +530,15 @@ pub async fn delete_user(
         .await
     {
         Ok(v) => v,
-        Err(_) => return err(StatusCode::INTERNAL_SERVER_ERROR, "database error").into_response(),
+        Err(_) => return err(ErrorCode::InternalError, "database error").into_response(),
     };
 
     let Some(row) = row else {
-        return err(StatusCode::NOT_FOUND, "user not found").into_response();
+        return err(ErrorCode::NotFound, "user not found").into_response();
     };
     let username: String = row.get("username");
     if username == "admin" {
-        return err(StatusCode::BAD_REQUEST, "default admin cannot be deleted").into_response();
+        return err(ErrorCode::InvalidRequest, "default admin cannot be deleted").into_response();
     }
 
     if let Err(_) = sqlx::query("DELETE FROM bff_users WHERE id = $1")
@@ -550,7 +546,7 @@ pub async fn delete_user(
         .execute(&st.db)
         .await
     {
-        return err(StatusCode::INTERNAL_SERVER_ERROR, "database error").into_response();
+        return err(ErrorCode::InternalError, "database error").into_response();
     }
 
     (StatusCode::OK, Json(serde_json::json!({"ok": true}))).into_response()
@@ -14,35 +14,15 @@ use crate::auth::{require_role, AuthContext, Role};
 use crate::args::XtraceAuthMode;
 use crate::state::AppState;
 use nebula_common::{
-    ClusterStatus, EndpointInfo, EndpointStats, ModelLoadRequest, ModelRequest, ModelRequestStatus,
-    NodeStatus, PlacementPlan,
+    ApiError, ClusterStatus, EndpointInfo, EndpointStats, ErrorCode, ModelLoadRequest, ModelRequest,
+    ModelRequestStatus, NodeStatus, PlacementPlan,
 };
 use nebula_meta::MetaStore;
 
-#[derive(Serialize)]
-struct ErrorDetail {
-    code: String,
-    message: String,
-    request_id: String,
-    #[serde(skip_serializing_if = "Option::is_none")]
-    details: Option<serde_json::Value>,
-}
-
-#[derive(Serialize)]
-struct ErrorResponse {
-    error: ErrorDetail,
-}
-
-fn error_response(status: StatusCode, code: &str, message: &str) -> Response {
-    let body = ErrorResponse {
-        error: ErrorDetail {
-            code: code.to_string(),
-            message: message.to_string(),
-            request_id: format!("req_{}", Uuid::new_v4()),
-            details: None,
-        },
-    };
-    (status, Json(body)).into_response()
+fn error_response(code: ErrorCode, message: &str) -> Response {
+    ApiError::new(code, message)
+        .with_request_id(format!("req_{}", Uuid::new_v4()))
+        .into_response()
 }
 
 
@@ -76,8 +56,7 @@ pub async fn overview(
         Ok(n) => n,
         Err(e) => {
             return error_response(
-                StatusCode::INTERNAL_SERVER_ERROR,
-                "etcd_error",
+                ErrorCode::MetaStoreError,
                 &format!("etcd error: {}", e),
             );
         }
@@ -93,8 +72,7 @@ pub async fn overview(
         Ok(e) => e,
         Err(e) => {
             return error_response(
-                StatusCode::INTERNAL_SERVER_ERROR,
-                "etcd_error",
+                ErrorCode::MetaStoreError,
                 &format!("etcd error: {}", e),
             );
         }
@@ -110,8 +88,7 @@ pub async fn overview(
         Ok(p) => p,
         Err(e) => {
             return error_response(
-                StatusCode::INTERNAL_SERVER_ERROR,
-                "etcd_error",
+                ErrorCode::MetaStoreError,
                 &format!("etcd error: {}", e),
             );
         }
@@ -127,8 +104,7 @@ pub async fn overview(
         Ok(r) => r,
         Err(e) => {
             return error_response(
-                StatusCode::INTERNAL_SERVER_ERROR,
-                "etcd_error",
+                ErrorCode::MetaStoreError,
                 &format!("etcd error: {}", e),
             );
         }
@@ -161,8 +137,7 @@ pub async fn list_requests(
         Ok(r) => r,
         Err(e) => {
             return error_response(
-                StatusCode::INTERNAL_SERVER_ERROR,
-                "etcd_error",
+                ErrorCode::MetaStoreError,
                 &format!("etcd error: {}", e),
             )
         }
@@ -212,8 +187,7 @@ pub async fn metrics(
         Ok(resp) => resp,
         Err(e) => {
             return error_response(
-                StatusCode::BAD_GATEWAY,
-                "upstream_error",
+                ErrorCode::UpstreamError,
                 &format!("router request failed: {}", e),
             )
         }
@@ -224,8 +198,7 @@ pub async fn metrics(
         Ok(text) => text,
         Err(e) => {
             return error_response(
-                StatusCode::BAD_GATEWAY,
-                "upstream_error",
+                ErrorCode::UpstreamError,
                 &format!("failed to read router response: {}", e),
             )
         }
@@ -235,7 +208,7 @@ pub async fn metrics(
 }
 
 pub async fn logs(State(_st): State<AppState>) -> impl IntoResponse {
-    error_response(StatusCode::NOT_IMPLEMENTED, "not_implemented", "logs not implemented")
+    error_response(ErrorCode::NotImplemented, "logs not implemented")
 }
 
 pub async fn engine_stats(
@@ -250,8 +223,7 @@ pub async fn engine_stats(
         Ok(c) => c,
         Err(e) => {
             return error_response(
-                StatusCode::INTERNAL_SERVER_ERROR,
-                "xtrace_error",
+                ErrorCode::InternalError,
                 &format!("failed to create xtrace client: {}", e),
             )
         }
@@ -385,8 +357,7 @@ async fn search_huggingface(http: &reqwest::Client, query: &str, limit: usize) -
         Ok(r) => r,
         Err(e) => {
             return error_response(
-                StatusCode::BAD_GATEWAY,
-                "upstream_error",
+                ErrorCode::UpstreamError,
                 &format!("HuggingFace API error: {}", e),
             )
         }
@@ -396,8 +367,7 @@ async fn search_huggingface(http: &reqwest::Client, query: &str, limit: usize) -
         Ok(b) => b,
         Err(e) => {
             return error_response(
-                StatusCode::BAD_GATEWAY,
-                "upstream_error",
+                ErrorCode::UpstreamError,
                 &format!("Failed to parse HuggingFace response: {}", e),
             )
         }
@@ -441,8 +411,7 @@ async fn search_modelscope(http: &reqwest::Client, query: &str, limit: usize) ->
         Ok(r) => r,
         Err(e) => {
             return error_response(
-                StatusCode::BAD_GATEWAY,
-                "upstream_error",
+                ErrorCode::UpstreamError,
                 &format!("Search API error: {}", e),
             )
         }
@@ -452,8 +421,7 @@ async fn search_modelscope(http: &reqwest::Client, query: &str, limit: usize) ->
         Ok(b) => b,
         Err(e) => {
             return error_response(
-                StatusCode::BAD_GATEWAY,
-                "upstream_error",
+                ErrorCode::UpstreamError,
                 &format!("Failed to parse search response: {}", e),
             )
         }
@@ -491,8 +459,7 @@ async fn load_model_with_request(
         Some(req) => req,
         None => {
             return error_response(
-                StatusCode::BAD_REQUEST,
-                "invalid_request",
+                ErrorCode::InvalidRequest,
                 "missing request body",
             )
         }
@@ -513,8 +480,7 @@ async fn load_model_with_request(
         Ok(val) => val,
         Err(e) => {
             return error_response(
-                StatusCode::INTERNAL_SERVER_ERROR,
-                "serialization_error",
+                ErrorCode::SerializationError,
                 &format!("serialization error: {}", e),
             )
         }
@@ -523,8 +489,7 @@ async fn load_model_with_request(
     let key = format!("/model_requests/{}", model_req.id);
     if let Err(e) = st.store.put(&key, val, None).await {
         return error_response(
-            StatusCode::INTERNAL_SERVER_ERROR,
-            "etcd_error",
+            ErrorCode::MetaStoreError,
             &format!("etcd error: {}", e),
         );
     }
@@ -561,8 +526,7 @@ async fn xtrace_proxy_get(
         XtraceAuthMode::Service => {
             if st.xtrace_token.is_empty() {
                 return error_response(
-                    StatusCode::SERVICE_UNAVAILABLE,
-                    "misconfigured",
+                    ErrorCode::ServiceUnavailable,
                     "OBSERVE_AUTH_MODE=service requires OBSERVE_TOKEN",
                 );
             }
@@ -570,8 +534,7 @@ async fn xtrace_proxy_get(
                 Ok(r) => r,
                 Err(e) => {
                     return error_response(
-                        StatusCode::BAD_GATEWAY,
-                        "xtrace_error",
+                        ErrorCode::UpstreamError,
                         &format!("xtrace request failed: {}", e),
                     )
                 }
@@ -582,8 +545,7 @@ async fn xtrace_proxy_get(
                 Ok(r) => r,
                 Err(e) => {
                     return error_response(
-                        StatusCode::BAD_GATEWAY,
-                        "xtrace_error",
+                        ErrorCode::UpstreamError,
                         &format!("xtrace request failed: {}", e),
                     )
                 }
@@ -601,8 +563,7 @@ async fn xtrace_proxy_get(
                         Ok(r) => r,
                         Err(e) => {
                             return error_response(
-                                StatusCode::BAD_GATEWAY,
-                                "xtrace_error",
+                                ErrorCode::UpstreamError,
                                 &format!("xtrace request failed: {}", e),
                             )
                         }
@@ -621,8 +582,7 @@ async fn xtrace_proxy_get(
         Ok(v) => v,
         Err(e) => {
             return error_response(
-                StatusCode::BAD_GATEWAY,
-                "xtrace_error",
+                ErrorCode::UpstreamError,
                 &format!("failed to parse xtrace response: {}", e),
             )
         }
@@ -696,8 +656,7 @@ pub async fn observe_metrics_names(
 async fn unload_model_inner(st: AppState, id: String) -> Response {
     if id.is_empty() {
         return error_response(
-            StatusCode::BAD_REQUEST,
-            "invalid_request",
+            ErrorCode::InvalidRequest,
             "request id is required",
         );
     }
@@ -707,15 +666,13 @@ async fn unload_model_inner(st: AppState, id: String) -> Response {
         Ok(Some(kv)) => kv,
         Ok(None) => {
             return error_response(
-                StatusCode::NOT_FOUND,
-                "not_found",
+                ErrorCode::NotFound,
                 "request not found",
             )
         }
         Err(e) => {
             return error_response(
-                StatusCode::INTERNAL_SERVER_ERROR,
-                "etcd_error",
+                ErrorCode::MetaStoreError,
                 &format!("etcd error: {}", e),
             )
         }
@@ -725,8 +682,7 @@ async fn unload_model_inner(st: AppState, id: String) -> Response {
         Ok(r) => r,
         Err(e) => {
             return error_response(
-                StatusCode::INTERNAL_SERVER_ERROR,
-                "deserialization_error",
+                ErrorCode::SerializationError,
                 &format!("deserialization error: {}", e),
             )
         }
@@ -737,8 +693,7 @@ async fn unload_model_inner(st: AppState, id: String) -> Response {
         Ok(val) => val,
         Err(e) => {
             return error_response(
-                StatusCode::INTERNAL_SERVER_ERROR,
-                "serialization_error",
+                ErrorCode::SerializationError,
                 &format!("serialization error: {}", e),
             )
         }
@@ -746,8 +701,7 @@ async fn unload_model_inner(st: AppState, id: String) -> Response {
 
     if let Err(e) = st.store.put(&key, val, None).await {
         return error_response(
-            StatusCode::INTERNAL_SERVER_ERROR,
-            "etcd_error",
+            ErrorCode::MetaStoreError,
             &format!("etcd error: {}", e),
         );
     }
@@ -788,8 +742,7 @@ pub async fn list_images(
         Ok(v) => v,
         Err(e) => {
             return error_response(
-                StatusCode::INTERNAL_SERVER_ERROR,
-                "etcd_error",
+                ErrorCode::MetaStoreError,
                 &format!("etcd error: {}", e),
             )
         }
@@ -814,15 +767,13 @@ pub async fn get_image(
         Ok(Some((data, _))) => match serde_json::from_slice::<nebula_common::EngineImage>(&data) {
             Ok(img) => (StatusCode::OK, Json(json!(img))).into_response(),
             Err(e) => error_response(
-                StatusCode::INTERNAL_SERVER_ERROR,
-                "deserialization_error",
+                ErrorCode::SerializationError,
                 &format!("deserialization error: {}", e),
             ),
         },
-        Ok(None) => error_response(StatusCode::NOT_FOUND, "not_found", "image not found"),
+        Ok(None) => error_response(ErrorCode::NotFound, "image not found"),
         Err(e) => error_response(
-            StatusCode::INTERNAL_SERVER_ERROR,
-            "etcd_error",
+            ErrorCode::MetaStoreError,
             &format!("etcd error: {}", e),
         ),
     }
@@ -851,8 +802,7 @@ pub async fn put_image(
         Ok(v) => v,
         Err(e) => {
             return error_response(
-                StatusCode::INTERNAL_SERVER_ERROR,
-                "serialization_error",
+                ErrorCode::SerializationError,
                 &format!("serialization error: {}", e),
             )
         }
@@ -860,8 +810,7 @@ pub async fn put_image(
     let key = format!("/images/{}", id);
     if let Err(e) = st.store.put(&key, val, None).await {
         return error_response(
-            StatusCode::INTERNAL_SERVER_ERROR,
-            "etcd_error",
+            ErrorCode::MetaStoreError,
             &format!("etcd error: {}", e),
         );
     }
@@ -879,8 +828,7 @@ pub async fn delete_image(
     let key = format!("/images/{}", id);
     if let Err(e) = st.store.delete(&key).await {
         return error_response(
-            StatusCode::INTERNAL_SERVER_ERROR,
-            "etcd_error",
+            ErrorCode::MetaStoreError,
             &format!("etcd error: {}", e),
         );
     }
@@ -906,8 +854,7 @@ pub async fn list_image_status(
         Ok(v) => v,
         Err(e) => {
             return error_response(
-                StatusCode::INTERNAL_SERVER_ERROR,
-                "etcd_error",
+                ErrorCode::MetaStoreError,
                 &format!("etcd error: {}", e),
             )
         }
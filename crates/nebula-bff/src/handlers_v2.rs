@@ -12,10 +12,13 @@ use uuid::Uuid;
 
 use crate::auth::{require_role, AuthContext, Role};
 use crate::state::AppState;
+use nebula_common::api_key::API_KEYS_PREFIX;
 use nebula_common::{
-    DesiredState, DiskAlert, DownloadPhase, DownloadProgress, EndpointInfo, EndpointStats,
-    ModelCacheEntry, ModelConfig, ModelDeployment, ModelRequest, ModelRequestStatus, ModelSource,
-    ModelSpec, ModelTemplate, NodeDiskStatus, PlacementPlan, TemplateCategory, TemplateSource,
+    ApiError, ApiKey, DesiredState, DisaggregationSpec, DiskAlert, DownloadPhase, DownloadProgress, EndpointInfo,
+    EndpointStats, ErrorCode, ModelCacheEntry, ModelConfig, ModelDeployment, ModelRequest, ModelRequestStatus,
+    ModelSource, ModelSpec, ModelTemplate, NodeDiskStatus, PlacementPlan, RequestPolicy, RoutingPolicy,
+    TemplateCategory,
+    TemplateSource,
 };
 use nebula_meta::MetaStore;
 
@@ -30,30 +33,10 @@ fn now_ms() -> u64 {
         .as_millis() as u64
 }
 
-#[derive(Serialize)]
-struct ErrorDetail {
-    code: String,
-    message: String,
-    request_id: String,
-    #[serde(skip_serializing_if = "Option::is_none")]
-    details: Option<serde_json::Value>,
-}
-
-#[derive(Serialize)]
-struct ErrorResponse {
-    error: ErrorDetail,
-}
-
-fn error_response(status: StatusCode, code: &str, message: &str) -> Response {
-    let body = ErrorResponse {
-        error: ErrorDetail {
-            code: code.to_string(),
-            message: message.to_string(),
-            request_id: format!("req_{}", Uuid::new_v4()),
-            details: None,
-        },
-    };
-    (status, Json(body)).into_response()
+fn error_response(code: ErrorCode, message: &str) -> Response {
+    ApiError::new(code, message)
+        .with_request_id(format!("req_{}", Uuid::new_v4()))
+        .into_response()
 }
 
 /// Sanitise a model name into a valid model_uid.
@@ -230,8 +213,7 @@ async fn fetch_router_metrics_text(st: &AppState) -> Result<String, Response> {
         Ok(resp) => resp,
         Err(e) => {
             return Err(error_response(
-                StatusCode::BAD_GATEWAY,
-                "upstream_error",
+                ErrorCode::UpstreamError,
                 &format!("router request failed: {e}"),
             ))
         }
@@ -239,8 +221,7 @@ async fn fetch_router_metrics_text(st: &AppState) -> Result<String, Response> {
 
     if !resp.status().is_success() {
         return Err(error_response(
-            StatusCode::BAD_GATEWAY,
-            "upstream_error",
+            ErrorCode::UpstreamError,
             &format!("router metrics responded with status {}", resp.status().as_u16()),
         ));
     }
@@ -248,8 +229,7 @@ async fn fetch_router_metrics_text(st: &AppState) -> Result<String, Response> {
     match resp.text().await {
         Ok(text) => Ok(text),
         Err(e) => Err(error_response(
-            StatusCode::BAD_GATEWAY,
-            "upstream_error",
+            ErrorCode::UpstreamError,
             &format!("failed to read router response: {e}"),
         )),
     }
@@ -509,6 +489,7 @@ pub struct CreateModelRequest {
     pub docker_image: Option<String>,
     pub config: Option<ModelConfig>,
     pub labels: Option<HashMap<String, String>>,
+    pub request_policy: Option<RequestPolicy>,
     pub auto_start: Option<bool>,
     pub replicas: Option<u32>,
     pub node_id: Option<String>,
@@ -524,6 +505,7 @@ pub struct UpdateModelRequest {
     pub docker_image: Option<String>,
     pub config: Option<ModelConfig>,
     pub labels: Option<HashMap<String, String>>,
+    pub request_policy: Option<RequestPolicy>,
 }
 
 #[derive(Deserialize)]
@@ -532,6 +514,9 @@ pub struct StartModelRequest {
     pub config_overrides: Option<ModelConfig>,
     pub node_id: Option<String>,
     pub gpu_indices: Option<Vec<u32>>,
+    pub disaggregation: Option<DisaggregationSpec>,
+    /// Overrides the spec's request policy for this deployment.
+    pub request_policy: Option<RequestPolicy>,
 }
 
 #[derive(Deserialize)]
@@ -585,6 +570,21 @@ pub struct UpdateTemplateRequest {
     pub labels: Option<HashMap<String, String>>,
 }
 
+#[derive(Deserialize)]
+pub struct CreateApiKeyRequest {
+    pub owner: String,
+    /// Defaults to `viewer`.
+    pub role: Option<Role>,
+    pub tenant: Option<String>,
+    #[serde(default)]
+    pub allowed_models: Vec<String>,
+    /// Lifetime in days; the key never expires when unset.
+    pub expires_in_days: Option<u64>,
+    pub rate_limit_per_minute: Option<u64>,
+    /// Default request timeout for clients that send no `x-request-timeout`.
+    pub request_timeout_secs: Option<u64>,
+}
+
 #[derive(Deserialize)]
 pub struct ListModelsQuery {
     pub state: Option<String>,
@@ -684,8 +684,7 @@ pub async fn create_model(
         Some(ref uid) => {
             if !is_valid_model_uid(uid) {
                 return error_response(
-                    StatusCode::BAD_REQUEST,
-                    "invalid_model_uid",
+                    ErrorCode::InvalidModelUid,
                     "model_uid must match [a-z0-9][a-z0-9-]* and be at most 63 chars",
                 );
             }
@@ -693,12 +692,14 @@ pub async fn create_model(
         }
         None => generate_model_uid(&req.model_name),
     };
+    if let Some(Err(msg)) = req.request_policy.as_ref().map(RequestPolicy::validate) {
+        return error_response(ErrorCode::InvalidRequestPolicy, &msg);
+    }
 
     // Check for conflict
     if let Ok(Some(_)) = st.store.get(&format!("/models/{uid}/spec")).await {
         return error_response(
-            StatusCode::CONFLICT,
-            "model_exists",
+            ErrorCode::ModelExists,
             &format!("model with uid '{uid}' already exists"),
         );
     }
@@ -712,6 +713,7 @@ pub async fn create_model(
         engine_type: req.engine_type,
         docker_image: req.docker_image,
         config: req.config,
+        request_policy: req.request_policy,
         labels: req.labels.unwrap_or_default(),
         created_at_ms: now,
         updated_at_ms: now,
@@ -722,8 +724,7 @@ pub async fn create_model(
         Ok(v) => v,
         Err(e) => {
             return error_response(
-                StatusCode::INTERNAL_SERVER_ERROR,
-                "serialization_error",
+                ErrorCode::SerializationError,
                 &format!("serialization error: {e}"),
             )
         }
@@ -731,8 +732,7 @@ pub async fn create_model(
 
     if let Err(e) = st.store.put(&format!("/models/{uid}/spec"), val, None).await {
         return error_response(
-            StatusCode::INTERNAL_SERVER_ERROR,
-            "etcd_error",
+            ErrorCode::MetaStoreError,
             &format!("etcd error: {e}"),
         );
     }
@@ -748,6 +748,8 @@ pub async fn create_model(
             node_affinity: req.node_id,
             gpu_affinity: req.gpu_indices,
             config_overrides: None,
+            request_policy: None,
+            disaggregation: None,
             version: 1,
             updated_at_ms: now,
         };
@@ -772,8 +774,7 @@ pub async fn list_models(
         Ok(v) => v,
         Err(e) => {
             return error_response(
-                StatusCode::INTERNAL_SERVER_ERROR,
-                "etcd_error",
+                ErrorCode::MetaStoreError,
                 &format!("etcd error: {e}"),
             )
         }
@@ -827,19 +828,17 @@ pub async fn get_model(
             Ok(s) => s,
             Err(e) => {
                 return error_response(
-                    StatusCode::INTERNAL_SERVER_ERROR,
-                    "deserialization_error",
+                    ErrorCode::SerializationError,
                     &format!("deserialization error: {e}"),
                 )
             }
         },
         Ok(None) => {
-            return error_response(StatusCode::NOT_FOUND, "not_found", "model not found")
+            return error_response(ErrorCode::NotFound, "model not found")
         }
         Err(e) => {
             return error_response(
-                StatusCode::INTERNAL_SERVER_ERROR,
-                "etcd_error",
+                ErrorCode::MetaStoreError,
                 &format!("etcd error: {e}"),
             )
         }
@@ -974,19 +973,17 @@ pub async fn update_model(
             Ok(s) => s,
             Err(e) => {
                 return error_response(
-                    StatusCode::INTERNAL_SERVER_ERROR,
-                    "deserialization_error",
+                    ErrorCode::SerializationError,
                     &format!("deserialization error: {e}"),
                 )
             }
         },
         Ok(None) => {
-            return error_response(StatusCode::NOT_FOUND, "not_found", "model not found")
+            return error_response(ErrorCode::NotFound, "model not found")
         }
         Err(e) => {
             return error_response(
-                StatusCode::INTERNAL_SERVER_ERROR,
-                "etcd_error",
+                ErrorCode::MetaStoreError,
                 &format!("etcd error: {e}"),
             )
         }
@@ -1013,14 +1010,19 @@ pub async fn update_model(
     if let Some(labels) = req.labels {
         spec.labels = labels;
     }
+    if let Some(policy) = req.request_policy {
+        if let Err(msg) = policy.validate() {
+            return error_response(ErrorCode::InvalidRequestPolicy, &msg);
+        }
+        spec.request_policy = Some(policy);
+    }
     spec.updated_at_ms = now_ms();
 
     let val = match serde_json::to_vec(&spec) {
         Ok(v) => v,
         Err(e) => {
             return error_response(
-                StatusCode::INTERNAL_SERVER_ERROR,
-                "serialization_error",
+                ErrorCode::SerializationError,
                 &format!("serialization error: {e}"),
             )
         }
@@ -1032,8 +1034,7 @@ pub async fn update_model(
         .await
     {
         return error_response(
-            StatusCode::INTERNAL_SERVER_ERROR,
-            "etcd_error",
+            ErrorCode::MetaStoreError,
             &format!("etcd error: {e}"),
         );
     }
@@ -1056,17 +1057,15 @@ pub async fn delete_model(
             Ok(s) => s,
             Err(e) => {
                 return error_response(
-                    StatusCode::INTERNAL_SERVER_ERROR,
-                    "deserialization_error",
+                    ErrorCode::SerializationError,
                     &format!("deserialization error: {e}"),
                 )
             }
         },
-        Ok(None) => return error_response(StatusCode::NOT_FOUND, "not_found", "model not found"),
+        Ok(None) => return error_response(ErrorCode::NotFound, "model not found"),
         Err(e) => {
             return error_response(
-                StatusCode::INTERNAL_SERVER_ERROR,
-                "etcd_error",
+                ErrorCode::MetaStoreError,
                 &format!("etcd error: {e}"),
             )
         }
@@ -1097,6 +1096,7 @@ pub async fn delete_model(
     let _ = st.store.delete(&format!("/models/{model_uid}/spec")).await;
     let _ = st.store.delete(&format!("/deployments/{model_uid}")).await;
     let _ = st.store.delete(&format!("/placements/{model_uid}")).await;
+    let _ = st.store.delete(&format!("/routing_policies/{model_uid}")).await;
 
     // Delete all endpoints
     if let Ok(kvs) = st
@@ -1141,9 +1141,28 @@ pub async fn start_model(
         return resp;
     }
 
+    if let Some(d) = &req.disaggregation {
+        if d.prefill_replicas == 0 || d.decode_replicas == 0 {
+            return error_response(
+                ErrorCode::InvalidDisaggregation,
+                "prefill_replicas and decode_replicas must be at least 1",
+            );
+        }
+    }
+    if let Some(Err(msg)) = req.request_policy.as_ref().map(RequestPolicy::validate) {
+        return error_response(ErrorCode::InvalidRequestPolicy, &msg);
+    }
+
     // Verify spec exists
-    if let Ok(None) | Err(_) = st.store.get(&format!("/models/{model_uid}/spec")).await {
-        return error_response(StatusCode::NOT_FOUND, "not_found", "model not found");
+    let spec = match load_json::<ModelSpec>(&st, &format!("/models/{model_uid}/spec")).await {
+        Ok(Some(spec)) => spec,
+        Ok(None) | Err(_) => return error_response(ErrorCode::NotFound, "model not found"),
+    };
+    if req.disaggregation.is_some() && !DisaggregationSpec::supports_engine(spec.engine_type.as_deref()) {
+        return error_response(
+            ErrorCode::InvalidDisaggregation,
+            "prefill/decode disaggregation is only supported by the vllm engine",
+        );
     }
 
     let now = now_ms();
@@ -1159,6 +1178,8 @@ pub async fn start_model(
                     node_affinity: None,
                     gpu_affinity: None,
                     config_overrides: None,
+                    request_policy: None,
+                    disaggregation: None,
                     version: 0,
                     updated_at_ms: 0,
                 });
@@ -1175,6 +1196,12 @@ pub async fn start_model(
             if req.gpu_indices.is_some() {
                 dep.gpu_affinity = req.gpu_indices;
             }
+            if req.disaggregation.is_some() {
+                dep.disaggregation = req.disaggregation;
+            }
+            if req.request_policy.is_some() {
+                dep.request_policy = req.request_policy;
+            }
             dep.version += 1;
             dep.updated_at_ms = now;
             dep
@@ -1188,6 +1215,8 @@ pub async fn start_model(
             node_affinity: req.node_id,
             gpu_affinity: req.gpu_indices,
             config_overrides: req.config_overrides,
+            request_policy: req.request_policy,
+            disaggregation: req.disaggregation,
             version: 1,
             updated_at_ms: now,
         },
@@ -1197,8 +1226,7 @@ pub async fn start_model(
         Ok(v) => v,
         Err(e) => {
             return error_response(
-                StatusCode::INTERNAL_SERVER_ERROR,
-                "serialization_error",
+                ErrorCode::SerializationError,
                 &format!("serialization error: {e}"),
             )
         }
@@ -1210,8 +1238,7 @@ pub async fn start_model(
         .await
     {
         return error_response(
-            StatusCode::INTERNAL_SERVER_ERROR,
-            "etcd_error",
+            ErrorCode::MetaStoreError,
             &format!("etcd error: {e}"),
         );
     }
@@ -1230,7 +1257,7 @@ pub async fn stop_model(
 
     // Verify spec exists
     if let Ok(None) | Err(_) = st.store.get(&format!("/models/{model_uid}/spec")).await {
-        return error_response(StatusCode::NOT_FOUND, "not_found", "model not found");
+        return error_response(ErrorCode::NotFound, "model not found");
     }
 
     let now = now_ms();
@@ -1240,8 +1267,7 @@ pub async fn stop_model(
                 Ok(d) => d,
                 Err(e) => {
                     return error_response(
-                        StatusCode::INTERNAL_SERVER_ERROR,
-                        "deserialization_error",
+                        ErrorCode::SerializationError,
                         &format!("deserialization error: {e}"),
                     )
                 }
@@ -1262,6 +1288,8 @@ pub async fn stop_model(
                 node_affinity: None,
                 gpu_affinity: None,
                 config_overrides: None,
+                request_policy: None,
+                disaggregation: None,
                 version: 1,
                 updated_at_ms: now,
             }
@@ -1272,8 +1300,7 @@ pub async fn stop_model(
         Ok(v) => v,
         Err(e) => {
             return error_response(
-                StatusCode::INTERNAL_SERVER_ERROR,
-                "serialization_error",
+                ErrorCode::SerializationError,
                 &format!("serialization error: {e}"),
             )
         }
@@ -1285,8 +1312,7 @@ pub async fn stop_model(
         .await
     {
         return error_response(
-            StatusCode::INTERNAL_SERVER_ERROR,
-            "etcd_error",
+            ErrorCode::MetaStoreError,
             &format!("etcd error: {e}"),
         );
     }
@@ -1309,23 +1335,20 @@ pub async fn scale_model(
             Ok(d) => d,
             Err(e) => {
                 return error_response(
-                    StatusCode::INTERNAL_SERVER_ERROR,
-                    "deserialization_error",
+                    ErrorCode::SerializationError,
                     &format!("deserialization error: {e}"),
                 )
             }
         },
         Ok(None) => {
             return error_response(
-                StatusCode::NOT_FOUND,
-                "not_found",
+                ErrorCode::NotFound,
                 "deployment not found (model may not be started)",
             )
         }
         Err(e) => {
             return error_response(
-                StatusCode::INTERNAL_SERVER_ERROR,
-                "etcd_error",
+                ErrorCode::MetaStoreError,
                 &format!("etcd error: {e}"),
             )
         }
@@ -1333,8 +1356,7 @@ pub async fn scale_model(
 
     if dep.desired_state == DesiredState::Stopped {
         return error_response(
-            StatusCode::CONFLICT,
-            "model_stopped",
+            ErrorCode::ModelStopped,
             "cannot scale a stopped model; start it first",
         );
     }
@@ -1347,8 +1369,7 @@ pub async fn scale_model(
         Ok(v) => v,
         Err(e) => {
             return error_response(
-                StatusCode::INTERNAL_SERVER_ERROR,
-                "serialization_error",
+                ErrorCode::SerializationError,
                 &format!("serialization error: {e}"),
             )
         }
@@ -1360,8 +1381,7 @@ pub async fn scale_model(
         .await
     {
         return error_response(
-            StatusCode::INTERNAL_SERVER_ERROR,
-            "etcd_error",
+            ErrorCode::MetaStoreError,
             &format!("etcd error: {e}"),
         );
     }
@@ -1369,6 +1389,409 @@ pub async fn scale_model(
     (StatusCode::OK, Json(json!(dep))).into_response()
 }
 
+// ---------------------------------------------------------------------------
+// Routing policy
+// ---------------------------------------------------------------------------
+
+const ROUTING_STRATEGIES: &[&str] = &["least_pending", "least_kv_cache", "prefix_cache_aware"];
+
+fn validate_routing_policy(policy: &RoutingPolicy) -> Result<(), String> {
+    if let Some(strategy) = policy.strategy.as_deref() {
+        if !ROUTING_STRATEGIES.contains(&strategy) {
+            return Err(format!(
+                "unknown strategy '{strategy}', available: {}",
+                ROUTING_STRATEGIES.join(", ")
+            ));
+        }
+    }
+    if let Some(t) = policy.kv_cache_overload_threshold {
+        if !(0.0..=1.0).contains(&t) {
+            return Err("kv_cache_overload_threshold must be between 0.0 and 1.0".to_string());
+        }
+    }
+    if policy.circuit_failure_threshold == Some(0) {
+        return Err("circuit_failure_threshold must be at least 1".to_string());
+    }
+    if let Some(shadow) = &policy.shadow {
+        shadow.validate(&policy.model_uid)?;
+    }
+    if let Some(hedge) = &policy.hedge {
+        hedge.validate()?;
+    }
+    Ok(())
+}
+
+pub async fn get_routing_policy(
+    State(st): State<AppState>,
+    Extension(ctx): Extension<AuthContext>,
+    Path(model_uid): Path<String>,
+) -> impl IntoResponse {
+    if let Some(resp) = require_role(&ctx, Role::Viewer) {
+        return resp;
+    }
+
+    match st.store.get(&format!("/routing_policies/{model_uid}")).await {
+        Ok(Some((data, _))) => match serde_json::from_slice::<RoutingPolicy>(&data) {
+            Ok(p) => (StatusCode::OK, Json(json!(p))).into_response(),
+            Err(e) => error_response(
+                ErrorCode::SerializationError,
+                &format!("deserialization error: {e}"),
+            ),
+        },
+        Ok(None) => error_response(
+            ErrorCode::NotFound,
+            "no routing policy for model (global defaults apply)",
+        ),
+        Err(e) => error_response(
+            ErrorCode::MetaStoreError,
+            &format!("etcd error: {e}"),
+        ),
+    }
+}
+
+pub async fn put_routing_policy(
+    State(st): State<AppState>,
+    Extension(ctx): Extension<AuthContext>,
+    Path(model_uid): Path<String>,
+    Json(mut policy): Json<RoutingPolicy>,
+) -> impl IntoResponse {
+    if let Some(resp) = require_role(&ctx, Role::Operator) {
+        return resp;
+    }
+
+    policy.model_uid = model_uid.clone();
+    if let Err(msg) = validate_routing_policy(&policy) {
+        return error_response(ErrorCode::InvalidRoutingPolicy, &msg);
+    }
+    policy.updated_at_ms = now_ms();
+
+    let val = match serde_json::to_vec(&policy) {
+        Ok(v) => v,
+        Err(e) => {
+            return error_response(
+                ErrorCode::SerializationError,
+                &format!("serialization error: {e}"),
+            )
+        }
+    };
+
+    if let Err(e) = st
+        .store
+        .put(&format!("/routing_policies/{model_uid}"), val, None)
+        .await
+    {
+        return error_response(
+            ErrorCode::MetaStoreError,
+            &format!("etcd error: {e}"),
+        );
+    }
+
+    (StatusCode::OK, Json(json!(policy))).into_response()
+}
+
+pub async fn delete_routing_policy(
+    State(st): State<AppState>,
+    Extension(ctx): Extension<AuthContext>,
+    Path(model_uid): Path<String>,
+) -> impl IntoResponse {
+    if let Some(resp) = require_role(&ctx, Role::Operator) {
+        return resp;
+    }
+
+    if let Err(e) = st
+        .store
+        .delete(&format!("/routing_policies/{model_uid}"))
+        .await
+    {
+        return error_response(
+            ErrorCode::MetaStoreError,
+            &format!("etcd error: {e}"),
+        );
+    }
+
+    StatusCode::NO_CONTENT.into_response()
+}
+
+// ===========================================================================
+// Request Policy
+// ===========================================================================
+
+#[derive(Deserialize)]
+pub struct RequestPolicyQuery {
+    /// `spec` (default) or `deployment`.
+    pub scope: Option<String>,
+}
+
+/// Where a request policy edit is stored.
+enum PolicyScope {
+    Spec,
+    Deployment,
+}
+
+impl PolicyScope {
+    fn parse(scope: Option<&str>) -> Result<Self, String> {
+        match scope.unwrap_or("spec") {
+            "spec" => Ok(Self::Spec),
+            "deployment" => Ok(Self::Deployment),
+            other => Err(format!("unknown scope '{other}', expected spec or deployment")),
+        }
+    }
+}
+
+async fn load_json<T: serde::de::DeserializeOwned>(st: &AppState, key: &str) -> Result<Option<T>, Response> {
+    match st.store.get(key).await {
+        Ok(Some((data, _))) => serde_json::from_slice(&data).map(Some).map_err(|e| {
+            error_response(
+                ErrorCode::SerializationError,
+                &format!("deserialization error: {e}"),
+            )
+        }),
+        Ok(None) => Ok(None),
+        Err(e) => Err(error_response(
+            ErrorCode::MetaStoreError,
+            &format!("etcd error: {e}"),
+        )),
+    }
+}
+
+async fn store_json<T: Serialize>(st: &AppState, key: &str, value: &T) -> Result<(), Response> {
+    let val = serde_json::to_vec(value).map_err(|e| {
+        error_response(
+            ErrorCode::SerializationError,
+            &format!("serialization error: {e}"),
+        )
+    })?;
+    st.store.put(key, val, None).await.map(|_| ()).map_err(|e| {
+        error_response(
+            ErrorCode::MetaStoreError,
+            &format!("etcd error: {e}"),
+        )
+    })
+}
+
+/// Sets (`Some`) or clears (`None`) the request policy of a spec or deployment.
+/// The deployment version is left alone: the router applies policies live, so
+/// no re-plan is needed.
+async fn write_request_policy(
+    st: &AppState,
+    model_uid: &str,
+    scope: PolicyScope,
+    policy: Option<RequestPolicy>,
+) -> Result<(), Response> {
+    let now = now_ms();
+    match scope {
+        PolicyScope::Spec => {
+            let key = format!("/models/{model_uid}/spec");
+            let Some(mut spec) = load_json::<ModelSpec>(st, &key).await? else {
+                return Err(error_response(ErrorCode::NotFound, "model not found"));
+            };
+            spec.request_policy = policy;
+            spec.updated_at_ms = now;
+            store_json(st, &key, &spec).await
+        }
+        PolicyScope::Deployment => {
+            let key = format!("/deployments/{model_uid}");
+            let Some(mut dep) = load_json::<ModelDeployment>(st, &key).await? else {
+                return Err(error_response(ErrorCode::NotFound, "model has no deployment"));
+            };
+            dep.request_policy = policy;
+            dep.updated_at_ms = now;
+            store_json(st, &key, &dep).await
+        }
+    }
+}
+
+/// The spec's policy, the deployment's override and the merged policy the
+/// router applies.
+pub async fn get_request_policy(
+    State(st): State<AppState>,
+    Extension(ctx): Extension<AuthContext>,
+    Path(model_uid): Path<String>,
+) -> impl IntoResponse {
+    if let Some(resp) = require_role(&ctx, Role::Viewer) {
+        return resp;
+    }
+
+    let spec = match load_json::<ModelSpec>(&st, &format!("/models/{model_uid}/spec")).await {
+        Ok(Some(spec)) => spec,
+        Ok(None) => return error_response(ErrorCode::NotFound, "model not found"),
+        Err(resp) => return resp,
+    };
+    let deployment = match load_json::<ModelDeployment>(&st, &format!("/deployments/{model_uid}")).await {
+        Ok(dep) => dep.and_then(|d| d.request_policy),
+        Err(resp) => return resp,
+    };
+    let effective = RequestPolicy::effective(spec.request_policy.as_ref(), deployment.as_ref());
+
+    (
+        StatusCode::OK,
+        Json(json!({
+            "model_uid": model_uid,
+            "spec": spec.request_policy,
+            "deployment": deployment,
+            "effective": effective,
+        })),
+    )
+        .into_response()
+}
+
+pub async fn put_request_policy(
+    State(st): State<AppState>,
+    Extension(ctx): Extension<AuthContext>,
+    Path(model_uid): Path<String>,
+    Query(query): Query<RequestPolicyQuery>,
+    Json(policy): Json<RequestPolicy>,
+) -> impl IntoResponse {
+    if let Some(resp) = require_role(&ctx, Role::Operator) {
+        return resp;
+    }
+
+    let scope = match PolicyScope::parse(query.scope.as_deref()) {
+        Ok(scope) => scope,
+        Err(msg) => return error_response(ErrorCode::InvalidRequest, &msg),
+    };
+    if let Err(msg) = policy.validate() {
+        return error_response(ErrorCode::InvalidRequestPolicy, &msg);
+    }
+    if let Err(resp) = write_request_policy(&st, &model_uid, scope, Some(policy.clone())).await {
+        return resp;
+    }
+
+    (StatusCode::OK, Json(json!(policy))).into_response()
+}
+
+pub async fn delete_request_policy(
+    State(st): State<AppState>,
+    Extension(ctx): Extension<AuthContext>,
+    Path(model_uid): Path<String>,
+    Query(query): Query<RequestPolicyQuery>,
+) -> impl IntoResponse {
+    if let Some(resp) = require_role(&ctx, Role::Operator) {
+        return resp;
+    }
+
+    let scope = match PolicyScope::parse(query.scope.as_deref()) {
+        Ok(scope) => scope,
+        Err(msg) => return error_response(ErrorCode::InvalidRequest, &msg),
+    };
+    if let Err(resp) = write_request_policy(&st, &model_uid, scope, None).await {
+        return resp;
+    }
+
+    StatusCode::NO_CONTENT.into_response()
+}
+
+// ---------------------------------------------------------------------------
+// API keys
+// ---------------------------------------------------------------------------
+
+async fn load_api_keys(st: &AppState) -> Result<Vec<ApiKey>, Response> {
+    match st.store.list_prefix(API_KEYS_PREFIX).await {
+        Ok(kvs) => Ok(kvs
+            .into_iter()
+            .filter_map(|(_, v, _)| serde_json::from_slice::<ApiKey>(&v).ok())
+            .collect()),
+        Err(e) => Err(error_response(
+            ErrorCode::MetaStoreError,
+            &format!("etcd error: {e}"),
+        )),
+    }
+}
+
+pub async fn list_api_keys(
+    State(st): State<AppState>,
+    Extension(ctx): Extension<AuthContext>,
+) -> impl IntoResponse {
+    if let Some(resp) = require_role(&ctx, Role::Admin) {
+        return resp;
+    }
+
+    let mut keys = match load_api_keys(&st).await {
+        Ok(k) => k,
+        Err(resp) => return resp,
+    };
+    keys.sort_by_key(|k| k.created_at_ms);
+    (StatusCode::OK, Json(keys)).into_response()
+}
+
+/// Create an API key. The secret is only ever returned here.
+pub async fn create_api_key(
+    State(st): State<AppState>,
+    Extension(ctx): Extension<AuthContext>,
+    Json(req): Json<CreateApiKeyRequest>,
+) -> impl IntoResponse {
+    if let Some(resp) = require_role(&ctx, Role::Admin) {
+        return resp;
+    }
+    if req.owner.trim().is_empty() {
+        return error_response(ErrorCode::InvalidRequest, "owner is required");
+    }
+
+    let now = now_ms();
+    let (secret, mut key) = ApiKey::generate(
+        req.owner,
+        req.role.unwrap_or(Role::Viewer),
+        req.tenant.filter(|t| !t.is_empty()),
+        req.allowed_models,
+        req.expires_in_days.map(|d| now + d * 24 * 3600 * 1000),
+        req.rate_limit_per_minute,
+        now,
+    );
+    key.request_timeout_secs = req.request_timeout_secs.filter(|&t| t > 0);
+
+    let val = match serde_json::to_vec(&key) {
+        Ok(v) => v,
+        Err(e) => {
+            return error_response(
+                ErrorCode::SerializationError,
+                &format!("serialization error: {e}"),
+            )
+        }
+    };
+    if let Err(e) = st.store.put(&key.storage_key(), val, None).await {
+        return error_response(
+            ErrorCode::MetaStoreError,
+            &format!("etcd error: {e}"),
+        );
+    }
+
+    (
+        StatusCode::CREATED,
+        Json(json!({"secret": secret, "api_key": key})),
+    )
+        .into_response()
+}
+
+pub async fn revoke_api_key(
+    State(st): State<AppState>,
+    Extension(ctx): Extension<AuthContext>,
+    Path(id): Path<String>,
+) -> impl IntoResponse {
+    if let Some(resp) = require_role(&ctx, Role::Admin) {
+        return resp;
+    }
+
+    let keys = match load_api_keys(&st).await {
+        Ok(k) => k,
+        Err(resp) => return resp,
+    };
+    let Some(key) = keys.into_iter().find(|k| k.id == id) else {
+        return error_response(ErrorCode::NotFound, "api key not found");
+    };
+    if let Err(e) = st.store.delete(&key.storage_key()).await {
+        return error_response(
+            ErrorCode::MetaStoreError,
+            &format!("etcd error: {e}"),
+        );
+    }
+
+    (
+        StatusCode::OK,
+        Json(json!({"id": id, "status": "revoked"})),
+    )
+        .into_response()
+}
+
 // ===========================================================================
 // Templates
 // ===========================================================================
@@ -1385,8 +1808,7 @@ pub async fn list_templates(
         Ok(v) => v,
         Err(e) => {
             return error_response(
-                StatusCode::INTERNAL_SERVER_ERROR,
-                "etcd_error",
+                ErrorCode::MetaStoreError,
                 &format!("etcd error: {e}"),
             )
         }
@@ -1413,15 +1835,13 @@ pub async fn get_template(
         Ok(Some((data, _))) => match serde_json::from_slice::<ModelTemplate>(&data) {
             Ok(t) => (StatusCode::OK, Json(json!(t))).into_response(),
             Err(e) => error_response(
-                StatusCode::INTERNAL_SERVER_ERROR,
-                "deserialization_error",
+                ErrorCode::SerializationError,
                 &format!("deserialization error: {e}"),
             ),
         },
-        Ok(None) => error_response(StatusCode::NOT_FOUND, "not_found", "template not found"),
+        Ok(None) => error_response(ErrorCode::NotFound, "template not found"),
         Err(e) => error_response(
-            StatusCode::INTERNAL_SERVER_ERROR,
-            "etcd_error",
+            ErrorCode::MetaStoreError,
             &format!("etcd error: {e}"),
         ),
     }
@@ -1462,8 +1882,7 @@ pub async fn create_template(
         Ok(v) => v,
         Err(e) => {
             return error_response(
-                StatusCode::INTERNAL_SERVER_ERROR,
-                "serialization_error",
+                ErrorCode::SerializationError,
                 &format!("serialization error: {e}"),
             )
         }
@@ -1471,8 +1890,7 @@ pub async fn create_template(
 
     if let Err(e) = st.store.put(&format!("/templates/{tid}"), val, None).await {
         return error_response(
-            StatusCode::INTERNAL_SERVER_ERROR,
-            "etcd_error",
+            ErrorCode::MetaStoreError,
             &format!("etcd error: {e}"),
         );
     }
@@ -1495,19 +1913,17 @@ pub async fn update_template(
             Ok(t) => t,
             Err(e) => {
                 return error_response(
-                    StatusCode::INTERNAL_SERVER_ERROR,
-                    "deserialization_error",
+                    ErrorCode::SerializationError,
                     &format!("deserialization error: {e}"),
                 )
             }
         },
         Ok(None) => {
-            return error_response(StatusCode::NOT_FOUND, "not_found", "template not found")
+            return error_response(ErrorCode::NotFound, "template not found")
         }
         Err(e) => {
             return error_response(
-                StatusCode::INTERNAL_SERVER_ERROR,
-                "etcd_error",
+                ErrorCode::MetaStoreError,
                 &format!("etcd error: {e}"),
             )
         }
@@ -1549,8 +1965,7 @@ pub async fn update_template(
         Ok(v) => v,
         Err(e) => {
             return error_response(
-                StatusCode::INTERNAL_SERVER_ERROR,
-                "serialization_error",
+                ErrorCode::SerializationError,
                 &format!("serialization error: {e}"),
             )
         }
@@ -1558,8 +1973,7 @@ pub async fn update_template(
 
     if let Err(e) = st.store.put(&format!("/templates/{id}"), val, None).await {
         return error_response(
-            StatusCode::INTERNAL_SERVER_ERROR,
-            "etcd_error",
+            ErrorCode::MetaStoreError,
             &format!("etcd error: {e}"),
         );
     }
@@ -1577,13 +1991,12 @@ pub async fn delete_template(
     }
 
     if let Ok(None) = st.store.get(&format!("/templates/{id}")).await {
-        return error_response(StatusCode::NOT_FOUND, "not_found", "template not found");
+        return error_response(ErrorCode::NotFound, "template not found");
     }
 
     if let Err(e) = st.store.delete(&format!("/templates/{id}")).await {
         return error_response(
-            StatusCode::INTERNAL_SERVER_ERROR,
-            "etcd_error",
+            ErrorCode::MetaStoreError,
             &format!("etcd error: {e}"),
         );
     }
@@ -1610,19 +2023,17 @@ pub async fn deploy_template(
             Ok(t) => t,
             Err(e) => {
                 return error_response(
-                    StatusCode::INTERNAL_SERVER_ERROR,
-                    "deserialization_error",
+                    ErrorCode::SerializationError,
                     &format!("deserialization error: {e}"),
                 )
             }
         },
         Ok(None) => {
-            return error_response(StatusCode::NOT_FOUND, "not_found", "template not found")
+            return error_response(ErrorCode::NotFound, "template not found")
         }
         Err(e) => {
             return error_response(
-                StatusCode::INTERNAL_SERVER_ERROR,
-                "etcd_error",
+                ErrorCode::MetaStoreError,
                 &format!("etcd error: {e}"),
             )
         }
@@ -1635,8 +2046,7 @@ pub async fn deploy_template(
     // Check for conflict
     if let Ok(Some(_)) = st.store.get(&format!("/models/{uid}/spec")).await {
         return error_response(
-            StatusCode::CONFLICT,
-            "model_exists",
+            ErrorCode::ModelExists,
             &format!("model with uid '{uid}' already exists"),
         );
     }
@@ -1650,6 +2060,7 @@ pub async fn deploy_template(
         engine_type: tpl.engine_type,
         docker_image: tpl.docker_image,
         config: tpl.config,
+        request_policy: None,
         labels: tpl.labels,
         created_at_ms: now,
         updated_at_ms: now,
@@ -1660,8 +2071,7 @@ pub async fn deploy_template(
         Ok(v) => v,
         Err(e) => {
             return error_response(
-                StatusCode::INTERNAL_SERVER_ERROR,
-                "serialization_error",
+                ErrorCode::SerializationError,
                 &format!("serialization error: {e}"),
             )
         }
@@ -1673,8 +2083,7 @@ pub async fn deploy_template(
         .await
     {
         return error_response(
-            StatusCode::INTERNAL_SERVER_ERROR,
-            "etcd_error",
+            ErrorCode::MetaStoreError,
             &format!("etcd error: {e}"),
         );
     }
@@ -1689,6 +2098,8 @@ pub async fn deploy_template(
         node_affinity: req.node_id,
         gpu_affinity: req.gpu_indices,
         config_overrides: req.config_overrides,
+        request_policy: None,
+        disaggregation: None,
         version: 1,
         updated_at_ms: now,
     };
@@ -1715,19 +2126,17 @@ pub async fn save_as_template(
             Ok(s) => s,
             Err(e) => {
                 return error_response(
-                    StatusCode::INTERNAL_SERVER_ERROR,
-                    "deserialization_error",
+                    ErrorCode::SerializationError,
                     &format!("deserialization error: {e}"),
                 )
             }
         },
         Ok(None) => {
-            return error_response(StatusCode::NOT_FOUND, "not_found", "model not found")
+            return error_response(ErrorCode::NotFound, "model not found")
         }
         Err(e) => {
             return error_response(
-                StatusCode::INTERNAL_SERVER_ERROR,
-                "etcd_error",
+                ErrorCode::MetaStoreError,
                 &format!("etcd error: {e}"),
             )
         }
@@ -1767,8 +2176,7 @@ pub async fn save_as_template(
         Ok(v) => v,
         Err(e) => {
             return error_response(
-                StatusCode::INTERNAL_SERVER_ERROR,
-                "serialization_error",
+                ErrorCode::SerializationError,
                 &format!("serialization error: {e}"),
             )
         }
@@ -1776,8 +2184,7 @@ pub async fn save_as_template(
 
     if let Err(e) = st.store.put(&format!("/templates/{tid}"), val, None).await {
         return error_response(
-            StatusCode::INTERNAL_SERVER_ERROR,
-            "etcd_error",
+            ErrorCode::MetaStoreError,
             &format!("etcd error: {e}"),
         );
     }
@@ -1806,8 +2213,7 @@ pub async fn node_cache(
         Ok(v) => v,
         Err(e) => {
             return error_response(
-                StatusCode::INTERNAL_SERVER_ERROR,
-                "etcd_error",
+                ErrorCode::MetaStoreError,
                 &format!("etcd error: {e}"),
             )
         }
@@ -1834,19 +2240,16 @@ pub async fn node_disk(
         Ok(Some((data, _))) => match serde_json::from_slice::<NodeDiskStatus>(&data) {
             Ok(d) => (StatusCode::OK, Json(json!(d))).into_response(),
             Err(e) => error_response(
-                StatusCode::INTERNAL_SERVER_ERROR,
-                "deserialization_error",
+                ErrorCode::SerializationError,
                 &format!("deserialization error: {e}"),
             ),
         },
         Ok(None) => error_response(
-            StatusCode::NOT_FOUND,
-            "not_found",
+            ErrorCode::NotFound,
             "disk status not found for node",
         ),
         Err(e) => error_response(
-            StatusCode::INTERNAL_SERVER_ERROR,
-            "etcd_error",
+            ErrorCode::MetaStoreError,
             &format!("etcd error: {e}"),
         ),
     }
@@ -1924,8 +2327,7 @@ pub async fn list_alerts(
         Ok(v) => v,
         Err(e) => {
             return error_response(
-                StatusCode::INTERNAL_SERVER_ERROR,
-                "etcd_error",
+                ErrorCode::MetaStoreError,
                 &format!("etcd error: {e}"),
             )
         }
@@ -1975,8 +2377,7 @@ pub async fn migrate_v1_to_v2(
         Ok(r) => r,
         Err(e) => {
             return error_response(
-                StatusCode::INTERNAL_SERVER_ERROR,
-                "etcd_error",
+                ErrorCode::MetaStoreError,
                 &format!("failed to list model_requests: {e}"),
             );
         }
@@ -2032,6 +2433,7 @@ pub async fn migrate_v1_to_v2(
             engine_type: mr.request.engine_type.clone(),
             docker_image: mr.request.docker_image.clone(),
             config: mr.request.config.clone(),
+            request_policy: None,
             labels: HashMap::new(),
             created_at_ms: mr.created_at_ms,
             updated_at_ms: now,
@@ -2089,6 +2491,8 @@ pub async fn migrate_v1_to_v2(
             node_affinity: mr.request.node_id.clone(),
             gpu_affinity,
             config_overrides: mr.request.config.clone(),
+            request_policy: None,
+            disaggregation: None,
             version: 1,
             updated_at_ms: now,
         };
@@ -2162,8 +2566,7 @@ pub async fn gateway_overview(
         Some(v) => v,
         None => {
             return error_response(
-                StatusCode::BAD_REQUEST,
-                "invalid_request",
+                ErrorCode::InvalidRequest,
                 "window must be one of: 5m, 15m, 1h, 6h, 24h",
             )
         }
@@ -2217,8 +2620,7 @@ pub async fn gateway_traffic(
         Some(v) => v,
         None => {
             return error_response(
-                StatusCode::BAD_REQUEST,
-                "invalid_request",
+                ErrorCode::InvalidRequest,
                 "window must be one of: 5m, 15m, 1h, 6h, 24h",
             )
         }
@@ -2267,8 +2669,7 @@ pub async fn gateway_reliability(
         Some(v) => v,
         None => {
             return error_response(
-                StatusCode::BAD_REQUEST,
-                "invalid_request",
+                ErrorCode::InvalidRequest,
                 "window must be one of: 5m, 15m, 1h, 6h, 24h",
             )
         }
@@ -2328,8 +2729,7 @@ pub async fn gateway_protection(
     let window = query.window.unwrap_or_else(|| "15m".to_string());
     if parse_window_seconds(&window).is_none() {
         return error_response(
-            StatusCode::BAD_REQUEST,
-            "invalid_request",
+            ErrorCode::InvalidRequest,
             "window must be one of: 5m, 15m, 1h, 6h, 24h",
         );
     }
@@ -2365,8 +2765,7 @@ pub async fn gateway_latency(
     let window = query.window.unwrap_or_else(|| "1h".to_string());
     if parse_window_seconds(&window).is_none() {
         return error_response(
-            StatusCode::BAD_REQUEST,
-            "invalid_request",
+            ErrorCode::InvalidRequest,
             "window must be one of: 5m, 15m, 1h, 6h, 24h",
         );
     }
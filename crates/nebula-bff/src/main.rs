@@ -107,6 +107,20 @@ async fn main() -> anyhow::Result<()> {
         .route("/models/:model_uid/stop", post(handlers_v2::stop_model))
         .route("/models/:model_uid/scale", put(handlers_v2::scale_model))
         .route("/models/:model_uid/save-as-template", post(handlers_v2::save_as_template))
+        .route(
+            "/models/:model_uid/routing-policy",
+            get(handlers_v2::get_routing_policy)
+                .put(handlers_v2::put_routing_policy)
+                .delete(handlers_v2::delete_routing_policy),
+        )
+        .route(
+            "/models/:model_uid/request-policy",
+            get(handlers_v2::get_request_policy)
+                .put(handlers_v2::put_request_policy)
+                .delete(handlers_v2::delete_request_policy),
+        )
+        .route("/api-keys", get(handlers_v2::list_api_keys).post(handlers_v2::create_api_key))
+        .route("/api-keys/:id", delete(handlers_v2::revoke_api_key))
         .route("/templates", get(handlers_v2::list_templates).post(handlers_v2::create_template))
         .route("/templates/:id", get(handlers_v2::get_template).put(handlers_v2::update_template).delete(handlers_v2::delete_template))
         .route("/templates/:id/deploy", post(handlers_v2::deploy_template))
@@ -47,6 +47,11 @@ pub enum Command {
         #[command(subcommand)]
         subcommand: DiskCommand,
     },
+    /// API key management
+    ApiKey {
+        #[command(subcommand)]
+        subcommand: ApiKeyCommand,
+    },
     /// Show current auth identity
     Whoami,
     /// Fetch gateway metrics
@@ -257,6 +262,40 @@ pub enum DiskCommand {
     Status,
 }
 
+#[derive(Debug, Subcommand)]
+pub enum ApiKeyCommand {
+    /// List API keys
+    List,
+    /// Create an API key (the secret is printed once)
+    Create {
+        /// Person or service the key is issued to
+        #[arg(long)]
+        owner: String,
+        /// Role: admin, operator, viewer
+        #[arg(long, default_value = "viewer")]
+        role: String,
+        /// Tenant the key's usage is accounted to
+        #[arg(long)]
+        tenant: Option<String>,
+        /// Comma-separated allowed models; a trailing '*' matches a prefix
+        #[arg(long, value_delimiter = ',')]
+        models: Vec<String>,
+        /// Expire the key after this many days
+        #[arg(long)]
+        expires_days: Option<u64>,
+        /// Requests per minute for this key
+        #[arg(long)]
+        rate_limit: Option<u64>,
+        /// Default request timeout in seconds for this key
+        #[arg(long)]
+        timeout_secs: Option<u64>,
+    },
+    /// Revoke an API key
+    Revoke {
+        /// API key ID (key_...)
+        id: String,
+    },
+}
 
 #[derive(Debug, Subcommand)]
 pub enum AdminCommand {
@@ -12,13 +12,13 @@ use reqwest::Client;
 use nebula_common::{ClusterStatus, ModelLoadRequest};
 
 use crate::args::{
-    AdminCommand, Args, CacheCommand, ClusterCommand, Command, DiskCommand, ModelCommand,
+    AdminCommand, ApiKeyCommand, Args, CacheCommand, ClusterCommand, Command, DiskCommand, ModelCommand,
     TemplateCommand,
 };
 use crate::client::auth;
 use crate::config::build_config;
 use crate::output::{
-    print_cache_summary, print_cluster_status, print_disk_status, print_model_detail_v2,
+    print_api_keys, print_cache_summary, print_cluster_status, print_disk_status, print_model_detail_v2,
     print_models_v2, print_node_cache, print_templates,
 };
 
@@ -337,6 +337,63 @@ async fn main() -> Result<()> {
                 }
             }
         },
+        Command::ApiKey { subcommand } => match subcommand {
+            ApiKeyCommand::List => {
+                let url = v2_url(&args.gateway_url, "/api-keys");
+                let resp = auth(client.get(&url), token.as_ref()).send().await?;
+                if resp.status().is_success() {
+                    let keys: Vec<serde_json::Value> = resp.json().await?;
+                    print_api_keys(&keys);
+                } else {
+                    eprintln!("✗ Failed to list API keys: {}", resp.text().await?);
+                }
+            }
+            ApiKeyCommand::Create {
+                owner,
+                role,
+                tenant,
+                models,
+                expires_days,
+                rate_limit,
+                timeout_secs,
+            } => {
+                let url = v2_url(&args.gateway_url, "/api-keys");
+                let body = serde_json::json!({
+                    "owner": owner,
+                    "role": role,
+                    "tenant": tenant,
+                    "allowed_models": models,
+                    "expires_in_days": expires_days,
+                    "rate_limit_per_minute": rate_limit,
+                    "request_timeout_secs": timeout_secs,
+                });
+                let resp = auth(client.post(&url), token.as_ref())
+                    .json(&body)
+                    .send()
+                    .await?;
+                if resp.status().is_success() {
+                    let created: serde_json::Value = resp.json().await?;
+                    println!(
+                        "✓ API key '{}' created for '{}'",
+                        created["api_key"]["id"].as_str().unwrap_or(""),
+                        owner
+                    );
+                    println!("  Secret: {}", created["secret"].as_str().unwrap_or(""));
+                    println!("  Store it now; it cannot be shown again.");
+                } else {
+                    eprintln!("✗ Failed to create API key: {}", resp.text().await?);
+                }
+            }
+            ApiKeyCommand::Revoke { id } => {
+                let url = v2_url(&args.gateway_url, &format!("/api-keys/{}", id));
+                let resp = auth(client.delete(&url), token.as_ref()).send().await?;
+                if resp.status().is_success() {
+                    println!("✓ API key '{}' revoked", id);
+                } else {
+                    eprintln!("✗ Failed to revoke API key: {}", resp.text().await?);
+                }
+            }
+        },
         Command::Whoami => {
             let url = format!("{}/v1/admin/whoami", args.gateway_url.trim_end_matches('/'));
             let resp = auth(client.get(&url), token.as_ref()).send().await?;
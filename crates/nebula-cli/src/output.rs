@@ -169,6 +169,40 @@ pub fn print_templates(templates: &[Value]) {
     println!();
 }
 
+pub fn print_api_keys(keys: &[Value]) {
+    println!("\n=== Nebula API Keys ===\n");
+    if keys.is_empty() {
+        println!("No API keys found.");
+        return;
+    }
+    println!(
+        "{:<22} {:<12} {:<20} {:<10} {:<15} {:<25}",
+        "ID", "Prefix", "Owner", "Role", "Tenant", "Models"
+    );
+    println!("{:-<109}", "");
+    for k in keys {
+        let id = k["id"].as_str().unwrap_or("");
+        let prefix = k["prefix"].as_str().unwrap_or("");
+        let owner = k["owner"].as_str().unwrap_or("");
+        let role = k["role"].as_str().unwrap_or("");
+        let tenant = k["tenant"].as_str().unwrap_or("-");
+        let models = k["allowed_models"]
+            .as_array()
+            .map(|m| {
+                m.iter()
+                    .filter_map(|v| v.as_str())
+                    .collect::<Vec<_>>()
+                    .join(",")
+            })
+            .unwrap_or_else(|| "*".to_string());
+        println!(
+            "{:<22} {:<12} {:<20} {:<10} {:<15} {:<25}",
+            id, prefix, owner, role, tenant, models
+        );
+    }
+    println!();
+}
+
 pub fn print_cache_summary(data: &Value) {
     println!("\n=== Cache Summary ===\n");
     if let Some(nodes) = data.get("nodes").and_then(|n| n.as_array()) {
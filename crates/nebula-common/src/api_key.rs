@@ -0,0 +1,182 @@
+use std::collections::HashMap;
+use std::sync::{Arc, RwLock};
+
+use serde::{Deserialize, Serialize};
+use sha2::{Digest, Sha256};
+use uuid::Uuid;
+
+use crate::auth::Role;
+
+pub const API_KEYS_PREFIX: &str = "/api_keys/";
+
+/// Prefix of generated API key secrets.
+const SECRET_PREFIX: &str = "nbk-";
+
+/// An API key's metadata.
+///
+/// Stored in etcd under `/api_keys/{key_hash}`. The secret itself is never
+/// stored; it is shown once, when the key is created.
+#[derive(Debug, Clone, Serialize, Deserialize, PartialEq)]
+pub struct ApiKey {
+    /// Public identifier (`key_…`), used to list and revoke the key and as its
+    /// principal label in metrics and quotas.
+    pub id: String,
+
+    /// Hex SHA-256 of the secret.
+    pub key_hash: String,
+
+    /// Leading characters of the secret, so owners can recognise their keys.
+    pub prefix: String,
+
+    /// Person or service the key was issued to.
+    pub owner: String,
+
+    pub role: Role,
+
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub tenant: Option<String>,
+
+    /// Models or aliases the key may call. A trailing `*` matches any suffix;
+    /// an empty list allows every model.
+    #[serde(default, skip_serializing_if = "Vec::is_empty")]
+    pub allowed_models: Vec<String>,
+
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub expires_at_ms: Option<u64>,
+
+    /// Requests per minute; falls back to `NEBULA_AUTH_RATE_LIMIT_PER_MINUTE`.
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub rate_limit_per_minute: Option<u64>,
+
+    /// Request timeout when the client sends no `x-request-timeout`.
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub request_timeout_secs: Option<u64>,
+
+    pub created_at_ms: u64,
+}
+
+impl ApiKey {
+    /// A new random secret and the key record for it.
+    pub fn generate(
+        owner: String,
+        role: Role,
+        tenant: Option<String>,
+        allowed_models: Vec<String>,
+        expires_at_ms: Option<u64>,
+        rate_limit_per_minute: Option<u64>,
+        now_ms: u64,
+    ) -> (String, Self) {
+        let secret = format!(
+            "{SECRET_PREFIX}{}{}",
+            Uuid::new_v4().simple(),
+            Uuid::new_v4().simple()
+        );
+        let key = Self {
+            id: format!("key_{}", &Uuid::new_v4().simple().to_string()[..16]),
+            key_hash: hash_secret(&secret),
+            prefix: secret[..SECRET_PREFIX.len() + 6].to_string(),
+            owner,
+            role,
+            tenant,
+            allowed_models,
+            expires_at_ms,
+            rate_limit_per_minute,
+            request_timeout_secs: None,
+            created_at_ms: now_ms,
+        };
+        (secret, key)
+    }
+
+    pub fn storage_key(&self) -> String {
+        format!("{API_KEYS_PREFIX}{}", self.key_hash)
+    }
+
+    pub fn is_expired(&self, now_ms: u64) -> bool {
+        self.expires_at_ms.is_some_and(|t| t <= now_ms)
+    }
+
+    pub fn allows_model(&self, model: &str) -> bool {
+        self.allowed_models.is_empty()
+            || self.allowed_models.iter().any(|m| match m.strip_suffix('*') {
+                Some(prefix) => model.starts_with(prefix),
+                None => m == model,
+            })
+    }
+}
+
+/// Hex SHA-256 of an API key secret.
+pub fn hash_secret(secret: &str) -> String {
+    Sha256::digest(secret.as_bytes())
+        .iter()
+        .map(|b| format!("{b:02x}"))
+        .collect()
+}
+
+/// API keys loaded from the meta store, by secret hash. Kept current by a
+/// watch on `/api_keys/`.
+#[derive(Debug, Clone, Default)]
+pub struct ApiKeyCache(Arc<RwLock<HashMap<String, Arc<ApiKey>>>>);
+
+impl ApiKeyCache {
+    pub fn lookup(&self, secret: &str) -> Option<Arc<ApiKey>> {
+        let keys = self.0.read().ok()?;
+        keys.get(&hash_secret(secret)).cloned()
+    }
+
+    pub fn replace_all(&self, keys: Vec<ApiKey>) {
+        if let Ok(mut map) = self.0.write() {
+            *map = keys
+                .into_iter()
+                .map(|k| (k.key_hash.clone(), Arc::new(k)))
+                .collect();
+        }
+    }
+
+    pub fn upsert(&self, key: ApiKey) {
+        if let Ok(mut map) = self.0.write() {
+            map.insert(key.key_hash.clone(), Arc::new(key));
+        }
+    }
+
+    /// Remove the key stored under `storage_key`.
+    pub fn remove(&self, storage_key: &str) {
+        let Some(hash) = storage_key.strip_prefix(API_KEYS_PREFIX) else {
+            return;
+        };
+        if let Ok(mut map) = self.0.write() {
+            map.remove(hash);
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_generate_and_lookup() {
+        let (secret, key) = ApiKey::generate(
+            "alice".into(),
+            Role::Viewer,
+            Some("team-a".into()),
+            vec!["qwen".into(), "llama*".into()],
+            Some(2_000),
+            None,
+            1_000,
+        );
+        assert!(secret.starts_with(&key.prefix));
+        assert_eq!(key.key_hash, hash_secret(&secret));
+        assert!(!key.is_expired(1_999));
+        assert!(key.is_expired(2_000));
+        assert!(key.allows_model("qwen"));
+        assert!(key.allows_model("llama-3-8b"));
+        assert!(!key.allows_model("qwen2"));
+
+        let cache = ApiKeyCache::default();
+        cache.upsert(key.clone());
+        assert_eq!(cache.lookup(&secret).as_deref(), Some(&key));
+        assert!(cache.lookup("nbk-wrong").is_none());
+        cache.remove(&key.storage_key());
+        assert!(cache.lookup(&secret).is_none());
+    }
+}
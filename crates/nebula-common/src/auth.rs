@@ -1,6 +1,5 @@
 use std::collections::HashMap;
 use std::sync::Arc;
-use std::time::Instant;
 
 use axum::{
     body::Body,
@@ -8,13 +7,17 @@ use axum::{
     http::Request,
     middleware::Next,
     response::{IntoResponse, Response},
-    Json,
 };
-use tokio::sync::Mutex;
+use serde::{Deserialize, Serialize};
+
+use crate::api_key::{ApiKey, ApiKeyCache};
+use crate::error::{ApiError, ErrorCode};
+use crate::ratelimit::{LocalRateLimiter, RateLimitDecision, RateLimiter};
 
 // ── Role ────────────────────────────────────────────────────────────
 
-#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(rename_all = "lowercase")]
 pub enum Role {
     Viewer,
     Operator,
@@ -38,6 +41,33 @@ impl Role {
 pub struct AuthContext {
     pub principal: String,
     pub role: Role,
+    /// Tenant the credential belongs to, if any.
+    pub tenant: Option<String>,
+    /// The API key used, when the request authenticated with one.
+    pub api_key: Option<Arc<ApiKey>>,
+}
+
+impl AuthContext {
+    /// Non-secret label for the principal, safe for metric labels. Bearer
+    /// tokens are reduced to a stable FNV-1a fingerprint.
+    pub fn principal_label(&self) -> String {
+        if let Some(key) = self.api_key.as_ref() {
+            return key.id.clone();
+        }
+        if self.principal == "guest" {
+            return self.principal.clone();
+        }
+        let hash = self
+            .principal
+            .bytes()
+            .fold(0xcbf2_9ce4_8422_2325u64, |h, b| (h ^ b as u64).wrapping_mul(0x0100_0000_01b3));
+        format!("token-{:08x}", hash >> 32)
+    }
+
+    /// Whether the credential may call `model`.
+    pub fn allows_model(&self, model: &str) -> bool {
+        self.api_key.as_ref().is_none_or(|k| k.allows_model(model))
+    }
 }
 
 // ── AuthConfig (was AuthState in gateway) ───────────────────────────
@@ -46,33 +76,60 @@ pub struct AuthContext {
 pub struct AuthConfig {
     pub enabled: bool,
     pub tokens: Arc<HashMap<String, Role>>,
-    pub rate_limits: Arc<Mutex<HashMap<String, RateWindow>>>,
+    /// Tenant of each token that names one.
+    pub tenants: Arc<HashMap<String, String>>,
+    /// Request buckets; in-process unless the binary installs a shared backend.
+    pub rate_limiter: Arc<dyn RateLimiter>,
+    /// Requests per minute per principal; 0 disables the limit.
     pub limit_per_minute: u64,
+    /// Requests per minute per tenant; 0 disables the limit.
+    pub tenant_limit_per_minute: u64,
+    /// Requests per minute per principal and model; 0 disables the limit.
+    pub model_limit_per_minute: u64,
+    /// API keys from the meta store, accepted alongside `tokens` when
+    /// `NEBULA_AUTH_API_KEYS` is set. Filled by the binary's watch loop.
+    pub api_keys: ApiKeyCache,
+    pub api_keys_enabled: bool,
 }
 
-#[derive(Debug, Clone)]
-pub struct RateWindow {
-    pub window_start: Instant,
-    pub count: u64,
+impl AuthConfig {
+    /// Take one request from the caller's bucket for `model`. `None` when
+    /// per-model limits are off.
+    pub async fn acquire_model(&self, ctx: &AuthContext, model: &str) -> Option<RateLimitDecision> {
+        if !self.enabled || self.model_limit_per_minute == 0 {
+            return None;
+        }
+        let key = format!("model/{}/{model}", ctx.principal_label());
+        Some(self.rate_limiter.acquire(&key, self.model_limit_per_minute).await)
+    }
 }
 
 // ── Environment parsing ─────────────────────────────────────────────
 
 pub fn parse_auth_from_env() -> AuthConfig {
     let tokens_raw = std::env::var("NEBULA_AUTH_TOKENS").ok();
-    let enabled = tokens_raw.is_some();
+    let api_keys_enabled = std::env::var("NEBULA_AUTH_API_KEYS")
+        .map(|v| matches!(v.to_ascii_lowercase().as_str(), "1" | "true" | "yes"))
+        .unwrap_or(false);
+    let enabled = tokens_raw.is_some() || api_keys_enabled;
 
     let mut tokens = HashMap::new();
+    let mut tenants = HashMap::new();
     if let Some(raw) = tokens_raw {
         for entry in raw.split(',') {
             let trimmed = entry.trim();
             if trimmed.is_empty() {
                 continue;
             }
-            let Some((token, role_raw)) = trimmed.split_once(':') else {
-                tracing::warn!(entry=%trimmed, "invalid NEBULA_AUTH_TOKENS entry, expected token:role");
+            let Some((token, rest)) = trimmed.split_once(':') else {
+                tracing::warn!(entry=%trimmed, "invalid NEBULA_AUTH_TOKENS entry, expected token:role[:tenant]");
                 continue;
             };
+            let (role_raw, tenant) = match rest.split_once(':') {
+                Some((role, tenant)) if !tenant.is_empty() => (role, Some(tenant)),
+                Some((role, _)) => (role, None),
+                None => (rest, None),
+            };
             let role = match role_raw.to_ascii_lowercase().as_str() {
                 "admin" => Role::Admin,
                 "operator" => Role::Operator,
@@ -83,23 +140,36 @@ pub fn parse_auth_from_env() -> AuthConfig {
                 }
             };
             tokens.insert(token.to_string(), role);
+            if let Some(tenant) = tenant {
+                tenants.insert(token.to_string(), tenant.to_string());
+            }
         }
     }
 
-    let limit_per_minute = std::env::var("NEBULA_AUTH_RATE_LIMIT_PER_MINUTE")
-        .ok()
-        .and_then(|v| v.parse::<u64>().ok())
-        .unwrap_or(120);
+    let env_limit = |name: &str, default: u64| {
+        std::env::var(name)
+            .ok()
+            .and_then(|v| v.parse::<u64>().ok())
+            .unwrap_or(default)
+    };
+    let limit_per_minute = env_limit("NEBULA_AUTH_RATE_LIMIT_PER_MINUTE", 120);
+    let tenant_limit_per_minute = env_limit("NEBULA_AUTH_TENANT_RATE_LIMIT_PER_MINUTE", 0);
+    let model_limit_per_minute = env_limit("NEBULA_AUTH_MODEL_RATE_LIMIT_PER_MINUTE", 0);
 
     if !enabled {
-        tracing::warn!("auth disabled: NEBULA_AUTH_TOKENS not set");
+        tracing::warn!("auth disabled: neither NEBULA_AUTH_TOKENS nor NEBULA_AUTH_API_KEYS set");
     }
 
     AuthConfig {
         enabled,
         tokens: Arc::new(tokens),
-        rate_limits: Arc::new(Mutex::new(HashMap::new())),
+        tenants: Arc::new(tenants),
+        rate_limiter: Arc::new(LocalRateLimiter::default()),
         limit_per_minute,
+        tenant_limit_per_minute,
+        model_limit_per_minute,
+        api_keys: ApiKeyCache::default(),
+        api_keys_enabled,
     }
 }
 
@@ -121,6 +191,8 @@ where
         let ctx = AuthContext {
             principal: "guest".into(),
             role: Role::Admin,
+            tenant: None,
+            api_key: None,
         };
         req.extensions_mut().insert(ctx);
         return Ok(next.run(req).await);
@@ -132,34 +204,69 @@ where
         return Ok(unauthorized("missing token"));
     };
 
-    let Some(role) = auth.tokens.get(&token).copied() else {
-        return Ok(forbidden("invalid token"));
+    let api_key = match auth.tokens.get(&token) {
+        Some(_) => None,
+        None if auth.api_keys_enabled => auth.api_keys.lookup(&token),
+        None => None,
     };
-
-    if auth.limit_per_minute > 0 {
-        let mut guard = auth.rate_limits.lock().await;
-        let entry = guard.entry(token.clone()).or_insert(RateWindow {
-            window_start: Instant::now(),
-            count: 0,
-        });
-        let now = Instant::now();
-        if now.duration_since(entry.window_start) >= std::time::Duration::from_secs(60) {
-            entry.window_start = now;
-            entry.count = 0;
-        }
-        if entry.count >= auth.limit_per_minute {
-            return Ok(too_many_requests());
+    let (role, limit_per_minute) = match (auth.tokens.get(&token), api_key.as_ref()) {
+        (Some(role), _) => (*role, auth.limit_per_minute),
+        (None, Some(key)) => {
+            let now_ms = std::time::SystemTime::now()
+                .duration_since(std::time::UNIX_EPOCH)
+                .unwrap_or_default()
+                .as_millis() as u64;
+            if key.is_expired(now_ms) {
+                return Ok(ApiError::new(ErrorCode::ApiKeyExpired, "api key expired").into_response());
+            }
+            (key.role, key.rate_limit_per_minute.unwrap_or(auth.limit_per_minute))
         }
-        entry.count += 1;
-    }
+        (None, None) => return Ok(unauthorized("invalid token")),
+    };
 
-    let ctx = AuthContext {
-        principal: token,
-        role,
+    let ctx = match api_key {
+        Some(key) => AuthContext {
+            principal: key.owner.clone(),
+            role,
+            tenant: key.tenant.clone(),
+            api_key: Some(key),
+        },
+        None => AuthContext {
+            tenant: auth.tenants.get(&token).cloned(),
+            principal: token,
+            role,
+            api_key: None,
+        },
     };
+
+    // Buckets are keyed by the principal label so secrets never reach a
+    // shared backend.
+    let mut buckets = Vec::new();
+    if limit_per_minute > 0 {
+        buckets.push((format!("principal/{}", ctx.principal_label()), limit_per_minute));
+    }
+    if let Some(tenant) = ctx.tenant.as_ref().filter(|_| auth.tenant_limit_per_minute > 0) {
+        buckets.push((format!("tenant/{tenant}"), auth.tenant_limit_per_minute));
+    }
+    let mut decision: Option<RateLimitDecision> = None;
+    for (key, limit) in buckets {
+        let d = auth.rate_limiter.acquire(&key, limit).await;
+        decision = Some(decision.map_or(d, |prev| prev.tighter(d)));
+    }
+    if let Some(d) = decision.filter(|d| !d.allowed) {
+        return Ok(rate_limited(&d));
+    }
+
     req.extensions_mut().insert(ctx);
 
-    Ok(next.run(req).await)
+    let mut resp = next.run(req).await;
+    // Handlers that apply a tighter per-model limit set their own headers.
+    if let Some(d) = decision {
+        if !resp.headers().contains_key("x-ratelimit-limit-requests") {
+            d.apply_headers(resp.headers_mut());
+        }
+    }
+    Ok(resp)
 }
 
 fn extract_token(req: &Request<Body>) -> Option<String> {
@@ -191,25 +298,21 @@ pub fn require_role(ctx: &AuthContext, required: Role) -> Option<Response> {
 // ── Error helpers ───────────────────────────────────────────────────
 
 pub fn unauthorized(msg: &str) -> Response {
-    (
-        axum::http::StatusCode::UNAUTHORIZED,
-        Json(serde_json::json!({"error": {"message": msg}})),
-    )
-        .into_response()
+    ApiError::new(ErrorCode::InvalidApiKey, msg).into_response()
 }
 
 pub fn forbidden(msg: &str) -> Response {
-    (
-        axum::http::StatusCode::FORBIDDEN,
-        Json(serde_json::json!({"error": {"message": msg}})),
-    )
-        .into_response()
+    ApiError::new(ErrorCode::PermissionDenied, msg).into_response()
 }
 
-pub fn too_many_requests() -> Response {
-    (
-        axum::http::StatusCode::TOO_MANY_REQUESTS,
-        Json(serde_json::json!({"error": {"message": "rate limited"}})),
+/// 429 for a rejected rate limit decision, with its `x-ratelimit-*` and
+/// `retry-after` headers.
+pub fn rate_limited(decision: &RateLimitDecision) -> Response {
+    let mut resp = ApiError::new(
+        ErrorCode::RateLimitExceeded,
+        format!("Rate limit reached: {} requests per minute.", decision.limit),
     )
-        .into_response()
+    .into_response();
+    decision.apply_headers(resp.headers_mut());
+    resp
 }
\ No newline at end of file
@@ -0,0 +1,341 @@
+//! Incremental extraction of the `model` field from JSON and multipart request
+//! bodies.
+//!
+//! Scanners are fed the growing prefix of the body and report where the model
+//! value lives once it has been seen, so the router can rewrite just that span
+//! of a streamed body and forward the remainder untouched. The gateway uses
+//! them to find the model of multipart uploads.
+
+/// Result of scanning a body prefix for the `model` field.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum ModelScan {
+    /// The prefix ends before the model field (or its absence) can be decided.
+    NeedMore,
+    /// The raw model value occupies `start..end` of the prefix.
+    Found { start: usize, end: usize, model: String },
+    /// The body has no model field. For JSON, a field may be inserted at `insert_at`.
+    Absent { insert_at: Option<usize>, needs_comma: bool },
+    /// The body can't be rewritten in-flight (not an object, non-string model, ...).
+    Unsupported,
+}
+
+/// Resumable scanner for the top-level `"model"` string of a JSON object.
+#[derive(Debug, Default)]
+pub struct JsonModelScanner {
+    pos: usize,
+    depth: u32,
+    in_string: bool,
+    escape: bool,
+    string_start: usize,
+    /// At depth 1, whether the next string is an object key.
+    expect_key: bool,
+    /// The last key seen at depth 1 was `model`.
+    model_key: bool,
+    object_start: Option<usize>,
+    has_members: bool,
+}
+
+impl JsonModelScanner {
+    pub fn feed(&mut self, buf: &[u8]) -> ModelScan {
+        while self.pos < buf.len() {
+            let b = buf[self.pos];
+            let at = self.pos;
+            self.pos += 1;
+
+            if self.in_string {
+                if self.escape {
+                    self.escape = false;
+                } else if b == b'\\' {
+                    self.escape = true;
+                } else if b == b'"' {
+                    self.in_string = false;
+                    if self.depth == 1 {
+                        if self.expect_key {
+                            self.model_key = &buf[self.string_start + 1..at] == b"model";
+                        } else if self.model_key {
+                            let raw = &buf[self.string_start..=at];
+                            return match serde_json::from_slice::<String>(raw) {
+                                Ok(model) => ModelScan::Found {
+                                    start: self.string_start,
+                                    end: at + 1,
+                                    model,
+                                },
+                                Err(_) => ModelScan::Unsupported,
+                            };
+                        }
+                    }
+                }
+                continue;
+            }
+
+            match b {
+                b' ' | b'\t' | b'\r' | b'\n' => {}
+                _ if self.object_start.is_none() => {
+                    if b != b'{' {
+                        return ModelScan::Unsupported;
+                    }
+                    self.object_start = Some(at);
+                    self.depth = 1;
+                    self.expect_key = true;
+                }
+                b'"' => {
+                    self.in_string = true;
+                    self.string_start = at;
+                    if self.depth == 1 && self.expect_key {
+                        self.has_members = true;
+                    }
+                }
+                b'{' | b'[' => {
+                    if self.depth == 1 && self.model_key {
+                        return ModelScan::Unsupported;
+                    }
+                    self.depth += 1;
+                }
+                b'}' | b']' => {
+                    self.depth = self.depth.saturating_sub(1);
+                    if self.depth == 0 {
+                        return ModelScan::Absent {
+                            insert_at: self.object_start.map(|p| p + 1),
+                            needs_comma: self.has_members,
+                        };
+                    }
+                }
+                b':' if self.depth == 1 => self.expect_key = false,
+                b',' if self.depth == 1 => {
+                    self.expect_key = true;
+                    self.model_key = false;
+                }
+                _ => {
+                    // Literal value (number, bool, null) for the model key.
+                    if self.depth == 1 && self.model_key && !self.expect_key {
+                        return ModelScan::Unsupported;
+                    }
+                }
+            }
+        }
+        ModelScan::NeedMore
+    }
+}
+
+/// Resumable scanner for a `model` form field in a `multipart/form-data` body.
+#[derive(Debug)]
+pub struct MultipartModelScanner {
+    /// `\r\n--boundary`, the delimiter that terminates each part body.
+    delimiter: Vec<u8>,
+    /// Start of the next part's headers, once the first delimiter was seen.
+    part_start: Option<usize>,
+    /// Where to resume searching for the current part's terminating delimiter.
+    search_from: usize,
+}
+
+fn find(haystack: &[u8], needle: &[u8], from: usize) -> Option<usize> {
+    if from >= haystack.len() {
+        return None;
+    }
+    haystack[from..]
+        .windows(needle.len())
+        .position(|w| w == needle)
+        .map(|p| p + from)
+}
+
+impl MultipartModelScanner {
+    /// Build a scanner from a `Content-Type` header value, if it is multipart.
+    pub fn from_content_type(content_type: &str) -> Option<Self> {
+        let lower = content_type.to_ascii_lowercase();
+        if !lower.starts_with("multipart/form-data") {
+            return None;
+        }
+        let boundary = content_type.split(';').find_map(|p| {
+            let p = p.trim();
+            p.get(..9)
+                .filter(|k| k.eq_ignore_ascii_case("boundary="))
+                .map(|_| p[9..].trim_matches('"').to_string())
+        })?;
+        if boundary.is_empty() {
+            return None;
+        }
+        Some(Self {
+            delimiter: format!("\r\n--{boundary}").into_bytes(),
+            part_start: None,
+            search_from: 0,
+        })
+    }
+
+    pub fn feed(&mut self, buf: &[u8]) -> ModelScan {
+        let dash_boundary = &self.delimiter[2..];
+        loop {
+            let part_start = match self.part_start {
+                Some(p) => p,
+                None => {
+                    // The first boundary may appear without a leading CRLF.
+                    let Some(p) = find(buf, dash_boundary, 0) else {
+                        return ModelScan::NeedMore;
+                    };
+                    let p = p + dash_boundary.len();
+                    self.part_start = Some(p);
+                    self.search_from = p;
+                    p
+                }
+            };
+
+            // After a delimiter: `--` closes the body, otherwise CRLF and headers follow.
+            if buf.len() < part_start + 2 {
+                return ModelScan::NeedMore;
+            }
+            if &buf[part_start..part_start + 2] == b"--" {
+                return ModelScan::Absent { insert_at: None, needs_comma: false };
+            }
+            let Some(headers_end) = find(buf, b"\r\n\r\n", part_start) else {
+                return ModelScan::NeedMore;
+            };
+            let body_start = headers_end + 4;
+            let headers = String::from_utf8_lossy(&buf[part_start..headers_end]).to_ascii_lowercase();
+            let is_model = headers.lines().any(|l| {
+                l.starts_with("content-disposition:")
+                    && l.split(';').any(|p| p.trim() == "name=\"model\"" || p.trim() == "name=model")
+            });
+
+            let from = self
+                .search_from
+                .max(body_start)
+                .saturating_sub(self.delimiter.len())
+                .max(body_start);
+            let Some(end) = find(buf, &self.delimiter, from) else {
+                self.search_from = buf.len();
+                return ModelScan::NeedMore;
+            };
+
+            if is_model {
+                return match std::str::from_utf8(&buf[body_start..end]) {
+                    Ok(model) => ModelScan::Found {
+                        start: body_start,
+                        end,
+                        model: model.trim().to_string(),
+                    },
+                    Err(_) => ModelScan::Unsupported,
+                };
+            }
+
+            let next = end + self.delimiter.len();
+            self.part_start = Some(next);
+            self.search_from = next;
+        }
+    }
+}
+
+/// Replace the scanned model value in `prefix` with `model_name`.
+/// `json` selects JSON string encoding (vs. a raw multipart field value).
+pub fn rewrite_model(prefix: &[u8], scan: &ModelScan, model_name: &str, json: bool) -> Vec<u8> {
+    match scan {
+        ModelScan::Found { start, end, .. } => {
+            let value = if json {
+                serde_json::Value::String(model_name.to_string()).to_string()
+            } else {
+                model_name.to_string()
+            };
+            let mut out = Vec::with_capacity(prefix.len() + value.len());
+            out.extend_from_slice(&prefix[..*start]);
+            out.extend_from_slice(value.as_bytes());
+            out.extend_from_slice(&prefix[*end..]);
+            out
+        }
+        ModelScan::Absent { insert_at: Some(at), needs_comma } => {
+            let mut field = format!(
+                "\"model\":{}",
+                serde_json::Value::String(model_name.to_string())
+            );
+            if *needs_comma {
+                field.push(',');
+            }
+            let mut out = Vec::with_capacity(prefix.len() + field.len());
+            out.extend_from_slice(&prefix[..*at]);
+            out.extend_from_slice(field.as_bytes());
+            out.extend_from_slice(&prefix[*at..]);
+            out
+        }
+        _ => prefix.to_vec(),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn scan_in_chunks(body: &[u8], chunk: usize) -> (ModelScan, usize) {
+        let mut scanner = JsonModelScanner::default();
+        let mut len = 0;
+        loop {
+            len = (len + chunk).min(body.len());
+            let scan = scanner.feed(&body[..len]);
+            if scan != ModelScan::NeedMore || len == body.len() {
+                return (scan, len);
+            }
+        }
+    }
+
+    #[test]
+    fn test_json_model_found_across_chunks() {
+        let body = br#"{"model" : "qwen\"x", "messages":[{"role":"user","content":"model"}]}"#;
+        for chunk in [1, 3, 7, body.len()] {
+            let (scan, _) = scan_in_chunks(body, chunk);
+            let ModelScan::Found { start, end, model } = scan else {
+                panic!("expected model for chunk size {chunk}");
+            };
+            assert_eq!(model, "qwen\"x");
+            assert_eq!(&body[start..end], br#""qwen\"x""#);
+        }
+    }
+
+    #[test]
+    fn test_json_nested_model_key_ignored() {
+        let body = br#"{"messages":[{"model":"nested"}],"model":"top"}"#;
+        let (scan, _) = scan_in_chunks(body, 4);
+        assert!(matches!(scan, ModelScan::Found { ref model, .. } if model == "top"));
+
+        let body = br#"{"metadata":{"model":"nested"},"stream":true}"#;
+        let (scan, _) = scan_in_chunks(body, 4);
+        let rewritten = rewrite_model(body, &scan, "m", true);
+        assert_eq!(
+            rewritten,
+            br#"{"model":"m","metadata":{"model":"nested"},"stream":true}"#.to_vec()
+        );
+    }
+
+    #[test]
+    fn test_json_rewrite_and_unsupported() {
+        let body = br#"{"model":"alias","input":"x"}"#;
+        let (scan, len) = scan_in_chunks(body, 5);
+        let mut out = rewrite_model(&body[..len], &scan, "real-name", true);
+        out.extend_from_slice(&body[len..]);
+        assert_eq!(out, br#"{"model":"real-name","input":"x"}"#.to_vec());
+
+        assert_eq!(scan_in_chunks(b"{}", 1).0, ModelScan::Absent { insert_at: Some(1), needs_comma: false });
+        assert_eq!(scan_in_chunks(b"[1,2]", 1).0, ModelScan::Unsupported);
+        assert_eq!(scan_in_chunks(br#"{"model":null}"#, 1).0, ModelScan::Unsupported);
+    }
+
+    #[test]
+    fn test_multipart_model_field() {
+        let ct = "multipart/form-data; boundary=XyZ";
+        let body = b"--XyZ\r\nContent-Disposition: form-data; name=\"file\"; filename=\"a.wav\"\r\n\r\nRIFF----\r\n--XyZ\r\nContent-Disposition: form-data; name=\"model\"\r\n\r\nwhisper\r\n--XyZ--\r\n";
+        for chunk in [1, 5, body.len()] {
+            let mut scanner = MultipartModelScanner::from_content_type(ct).unwrap();
+            let mut len = 0;
+            let scan = loop {
+                len = (len + chunk).min(body.len());
+                let scan = scanner.feed(&body[..len]);
+                if scan != ModelScan::NeedMore || len == body.len() {
+                    break scan;
+                }
+            };
+            assert!(matches!(scan, ModelScan::Found { ref model, .. } if model == "whisper"));
+            let out = rewrite_model(&body[..len], &scan, "whisper-large-v3", false);
+            assert!(String::from_utf8_lossy(&out).contains("\r\n\r\nwhisper-large-v3\r\n--XyZ"));
+        }
+
+        let no_model = b"--XyZ\r\nContent-Disposition: form-data; name=\"file\"\r\n\r\nabc\r\n--XyZ--\r\n";
+        let mut scanner = MultipartModelScanner::from_content_type(ct).unwrap();
+        assert!(matches!(scanner.feed(no_model), ModelScan::Absent { insert_at: None, .. }));
+        assert!(MultipartModelScanner::from_content_type("application/json").is_none());
+    }
+}
@@ -16,6 +16,33 @@ pub enum EndpointStatus {
     Draining,
 }
 
+/// Which phase of a request a replica serves under prefill/decode disaggregation.
+#[derive(Debug, Clone, Copy, Default, Serialize, Deserialize, PartialEq, Eq, Hash)]
+#[serde(rename_all = "snake_case")]
+pub enum ServingRole {
+    /// Monolithic engine serving whole requests.
+    #[default]
+    Both,
+    /// Computes the prompt KV cache and hands it off to a decode replica.
+    Prefill,
+    /// Generates tokens from a KV cache received from a prefill replica.
+    Decode,
+}
+
+impl ServingRole {
+    pub fn is_both(&self) -> bool {
+        *self == ServingRole::Both
+    }
+
+    pub fn as_str(&self) -> &'static str {
+        match self {
+            ServingRole::Both => "both",
+            ServingRole::Prefill => "prefill",
+            ServingRole::Decode => "decode",
+        }
+    }
+}
+
 #[derive(Debug, Clone, Serialize, Deserialize, PartialEq, Eq)]
 pub struct EndpointInfo {
     pub model_uid: String,
@@ -31,6 +58,18 @@ pub struct EndpointInfo {
 
     pub grpc_target: Option<String>,
     pub base_url: Option<String>,
+
+    /// Effective context window (prompt + completion tokens) of the engine
+    /// behind this endpoint, when known.
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub max_model_len: Option<u32>,
+
+    /// LoRA adapters currently loaded by the engine, served under these model names.
+    #[serde(default, skip_serializing_if = "Vec::is_empty")]
+    pub lora_adapters: Vec<String>,
+
+    #[serde(default, skip_serializing_if = "ServingRole::is_both")]
+    pub role: ServingRole,
 }
 
 #[derive(Debug, Clone, Serialize, Deserialize, PartialEq)]
@@ -0,0 +1,340 @@
+//! OpenAI-compatible API errors.
+//!
+//! Every HTTP error Nebula returns uses the OpenAI error envelope:
+//!
+//! ```json
+//! {"error": {"message": "...", "type": "invalid_request_error", "param": null, "code": "not_found"}}
+//! ```
+//!
+//! `code` comes from the [`ErrorCode`] catalog, which also fixes the HTTP
+//! status and the OpenAI error `type`, so SDKs can rely on both.
+
+use axum::http::{HeaderValue, StatusCode};
+use axum::response::{IntoResponse, Response};
+use axum::Json;
+use serde_json::{json, Value};
+
+/// Nebula error codes.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash)]
+pub enum ErrorCode {
+    InvalidRequest,
+    InvalidModelUid,
+    InvalidRoutingPolicy,
+    InvalidRequestPolicy,
+    InvalidDisaggregation,
+    ContextLengthExceeded,
+    PreviousResponseNotFound,
+    ContentPolicyViolation,
+    InvalidApiKey,
+    ApiKeyExpired,
+    InvalidCredentials,
+    PermissionDenied,
+    ModelNotAllowed,
+    NotFound,
+    ModelNotFound,
+    ResponseNotFound,
+    MethodNotAllowed,
+    ModelExists,
+    ModelStopped,
+    UserExists,
+    RequestTooLarge,
+    RateLimitExceeded,
+    InsufficientQuota,
+    ModelOverloaded,
+    InternalError,
+    MetaStoreError,
+    SerializationError,
+    NotImplemented,
+    UpstreamError,
+    NoReadyEndpoint,
+    ServiceUnavailable,
+    UpstreamTimeout,
+    DeadlineExceeded,
+}
+
+impl ErrorCode {
+    pub fn as_str(self) -> &'static str {
+        match self {
+            Self::InvalidRequest => "invalid_request",
+            Self::InvalidModelUid => "invalid_model_uid",
+            Self::InvalidRoutingPolicy => "invalid_routing_policy",
+            Self::InvalidRequestPolicy => "invalid_request_policy",
+            Self::InvalidDisaggregation => "invalid_disaggregation",
+            Self::ContextLengthExceeded => "context_length_exceeded",
+            Self::PreviousResponseNotFound => "previous_response_not_found",
+            Self::ContentPolicyViolation => "content_policy_violation",
+            Self::InvalidApiKey => "invalid_api_key",
+            Self::ApiKeyExpired => "api_key_expired",
+            Self::InvalidCredentials => "invalid_credentials",
+            Self::PermissionDenied => "permission_denied",
+            Self::ModelNotAllowed => "model_not_allowed",
+            Self::NotFound => "not_found",
+            Self::ModelNotFound => "model_not_found",
+            Self::ResponseNotFound => "response_not_found",
+            Self::MethodNotAllowed => "method_not_allowed",
+            Self::ModelExists => "model_exists",
+            Self::ModelStopped => "model_stopped",
+            Self::UserExists => "user_exists",
+            Self::RequestTooLarge => "request_too_large",
+            Self::RateLimitExceeded => "rate_limit_exceeded",
+            Self::InsufficientQuota => "insufficient_quota",
+            Self::ModelOverloaded => "model_overloaded",
+            Self::InternalError => "internal_error",
+            Self::MetaStoreError => "meta_store_error",
+            Self::SerializationError => "serialization_error",
+            Self::NotImplemented => "not_implemented",
+            Self::UpstreamError => "upstream_error",
+            Self::NoReadyEndpoint => "no_ready_endpoint",
+            Self::ServiceUnavailable => "service_unavailable",
+            Self::UpstreamTimeout => "upstream_timeout",
+            Self::DeadlineExceeded => "deadline_exceeded",
+        }
+    }
+
+    pub fn status(self) -> StatusCode {
+        match self {
+            Self::InvalidRequest
+            | Self::InvalidModelUid
+            | Self::InvalidRoutingPolicy
+            | Self::InvalidRequestPolicy
+            | Self::InvalidDisaggregation
+            | Self::ContextLengthExceeded
+            | Self::PreviousResponseNotFound
+            | Self::ContentPolicyViolation => StatusCode::BAD_REQUEST,
+            Self::InvalidApiKey | Self::ApiKeyExpired | Self::InvalidCredentials => StatusCode::UNAUTHORIZED,
+            Self::PermissionDenied | Self::ModelNotAllowed => StatusCode::FORBIDDEN,
+            Self::NotFound | Self::ModelNotFound | Self::ResponseNotFound => StatusCode::NOT_FOUND,
+            Self::MethodNotAllowed => StatusCode::METHOD_NOT_ALLOWED,
+            Self::ModelExists | Self::ModelStopped | Self::UserExists => StatusCode::CONFLICT,
+            Self::RequestTooLarge => StatusCode::PAYLOAD_TOO_LARGE,
+            Self::RateLimitExceeded | Self::InsufficientQuota | Self::ModelOverloaded => {
+                StatusCode::TOO_MANY_REQUESTS
+            }
+            Self::InternalError | Self::MetaStoreError | Self::SerializationError => {
+                StatusCode::INTERNAL_SERVER_ERROR
+            }
+            Self::NotImplemented => StatusCode::NOT_IMPLEMENTED,
+            Self::UpstreamError => StatusCode::BAD_GATEWAY,
+            Self::NoReadyEndpoint | Self::ServiceUnavailable => StatusCode::SERVICE_UNAVAILABLE,
+            Self::UpstreamTimeout | Self::DeadlineExceeded => StatusCode::GATEWAY_TIMEOUT,
+        }
+    }
+
+    /// The OpenAI error `type`.
+    pub fn error_type(self) -> &'static str {
+        match self {
+            Self::InvalidApiKey | Self::ApiKeyExpired | Self::InvalidCredentials => "authentication_error",
+            Self::PermissionDenied | Self::ModelNotAllowed => "permission_error",
+            Self::NotFound | Self::ModelNotFound | Self::ResponseNotFound => "not_found_error",
+            Self::RateLimitExceeded => "requests",
+            Self::InsufficientQuota => "insufficient_quota",
+            c if c.status().is_server_error() || c == Self::ModelOverloaded => "server_error",
+            _ => "invalid_request_error",
+        }
+    }
+
+    /// The code for an upstream error response that carried no usable code.
+    pub fn for_status(status: u16) -> Self {
+        match status {
+            401 => Self::InvalidApiKey,
+            403 => Self::PermissionDenied,
+            404 => Self::NotFound,
+            405 => Self::MethodNotAllowed,
+            413 => Self::RequestTooLarge,
+            429 => Self::RateLimitExceeded,
+            400..=499 => Self::InvalidRequest,
+            501 => Self::NotImplemented,
+            503 => Self::ServiceUnavailable,
+            504 => Self::UpstreamTimeout,
+            _ => Self::UpstreamError,
+        }
+    }
+}
+
+/// An error rendered as the OpenAI error envelope.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct ApiError {
+    pub code: ErrorCode,
+    pub message: String,
+    pub param: Option<String>,
+    /// Overrides the code's status, e.g. to pass through an upstream status.
+    pub status: Option<StatusCode>,
+    pub request_id: Option<String>,
+    pub retry_after_secs: Option<u64>,
+}
+
+impl ApiError {
+    pub fn new(code: ErrorCode, message: impl Into<String>) -> Self {
+        Self {
+            code,
+            message: message.into(),
+            param: None,
+            status: None,
+            request_id: None,
+            retry_after_secs: None,
+        }
+    }
+
+    pub fn with_param(mut self, param: impl Into<String>) -> Self {
+        self.param = Some(param.into());
+        self
+    }
+
+    pub fn with_status(mut self, status: StatusCode) -> Self {
+        self.status = Some(status);
+        self
+    }
+
+    pub fn with_request_id(mut self, request_id: impl Into<String>) -> Self {
+        self.request_id = Some(request_id.into());
+        self
+    }
+
+    pub fn with_retry_after(mut self, secs: u64) -> Self {
+        self.retry_after_secs = Some(secs);
+        self
+    }
+
+    /// An upstream error response, keeping its status. JSON bodies with an
+    /// `error.message` keep their message; anything else becomes the message.
+    pub fn from_upstream(status: u16, body: &[u8]) -> Self {
+        let parsed = serde_json::from_slice::<Value>(body).ok();
+        let message = parsed
+            .as_ref()
+            .and_then(|v| v["error"]["message"].as_str().or_else(|| v["error"].as_str()).map(str::to_string))
+            .unwrap_or_else(|| String::from_utf8_lossy(body).trim().to_string());
+        let mut err = Self::new(ErrorCode::for_status(status), message);
+        if let Ok(s) = StatusCode::from_u16(status) {
+            if s.is_client_error() || s.is_server_error() {
+                err.status = Some(s);
+            }
+        }
+        err
+    }
+
+    pub fn status(&self) -> StatusCode {
+        self.status.unwrap_or(self.code.status())
+    }
+
+    pub fn to_json(&self) -> Value {
+        let mut error = json!({
+            "message": self.message,
+            "type": self.code.error_type(),
+            "param": self.param,
+            "code": self.code.as_str(),
+        });
+        if let Some(id) = self.request_id.as_ref() {
+            error["request_id"] = json!(id);
+        }
+        json!({ "error": error })
+    }
+
+    /// The error as an SSE `data:` event, for failures after a stream started.
+    pub fn sse_event(&self) -> String {
+        format!("data: {}\n\n", self.to_json())
+    }
+}
+
+impl std::fmt::Display for ApiError {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        write!(f, "{}: {}", self.code.as_str(), self.message)
+    }
+}
+
+impl std::error::Error for ApiError {}
+
+impl IntoResponse for ApiError {
+    fn into_response(self) -> Response {
+        let mut resp = (self.status(), Json(self.to_json())).into_response();
+        if let Some(secs) = self.retry_after_secs {
+            resp.headers_mut().insert("retry-after", HeaderValue::from(secs));
+        }
+        resp
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_error_envelope() {
+        let err = ApiError::new(ErrorCode::ModelNotAllowed, "nope").with_param("model");
+        assert_eq!(err.status(), StatusCode::FORBIDDEN);
+        assert_eq!(
+            err.to_json(),
+            json!({"error": {"message": "nope", "type": "permission_error", "param": "model", "code": "model_not_allowed"}})
+        );
+        assert_eq!(ErrorCode::NoReadyEndpoint.error_type(), "server_error");
+        assert_eq!(ErrorCode::RequestTooLarge.error_type(), "invalid_request_error");
+
+        let upstream = ApiError::from_upstream(422, br#"{"error": {"message": "bad field"}}"#);
+        assert_eq!(upstream.status(), StatusCode::UNPROCESSABLE_ENTITY);
+        assert_eq!(upstream.code, ErrorCode::InvalidRequest);
+        assert_eq!(upstream.message, "bad field");
+        assert_eq!(ApiError::from_upstream(500, b"boom").code, ErrorCode::UpstreamError);
+    }
+
+    #[tokio::test]
+    async fn test_error_code_table() {
+        use ErrorCode::*;
+        let table = [
+            (InvalidRequest, 400, "invalid_request_error", "invalid_request"),
+            (InvalidModelUid, 400, "invalid_request_error", "invalid_model_uid"),
+            (InvalidRoutingPolicy, 400, "invalid_request_error", "invalid_routing_policy"),
+            (InvalidRequestPolicy, 400, "invalid_request_error", "invalid_request_policy"),
+            (InvalidDisaggregation, 400, "invalid_request_error", "invalid_disaggregation"),
+            (ContextLengthExceeded, 400, "invalid_request_error", "context_length_exceeded"),
+            (PreviousResponseNotFound, 400, "invalid_request_error", "previous_response_not_found"),
+            (ContentPolicyViolation, 400, "invalid_request_error", "content_policy_violation"),
+            (InvalidApiKey, 401, "authentication_error", "invalid_api_key"),
+            (ApiKeyExpired, 401, "authentication_error", "api_key_expired"),
+            (InvalidCredentials, 401, "authentication_error", "invalid_credentials"),
+            (PermissionDenied, 403, "permission_error", "permission_denied"),
+            (ModelNotAllowed, 403, "permission_error", "model_not_allowed"),
+            (NotFound, 404, "not_found_error", "not_found"),
+            (ModelNotFound, 404, "not_found_error", "model_not_found"),
+            (ResponseNotFound, 404, "not_found_error", "response_not_found"),
+            (MethodNotAllowed, 405, "invalid_request_error", "method_not_allowed"),
+            (ModelExists, 409, "invalid_request_error", "model_exists"),
+            (ModelStopped, 409, "invalid_request_error", "model_stopped"),
+            (UserExists, 409, "invalid_request_error", "user_exists"),
+            (RequestTooLarge, 413, "invalid_request_error", "request_too_large"),
+            (RateLimitExceeded, 429, "requests", "rate_limit_exceeded"),
+            (InsufficientQuota, 429, "insufficient_quota", "insufficient_quota"),
+            (ModelOverloaded, 429, "server_error", "model_overloaded"),
+            (InternalError, 500, "server_error", "internal_error"),
+            (MetaStoreError, 500, "server_error", "meta_store_error"),
+            (SerializationError, 500, "server_error", "serialization_error"),
+            (NotImplemented, 501, "server_error", "not_implemented"),
+            (UpstreamError, 502, "server_error", "upstream_error"),
+            (NoReadyEndpoint, 503, "server_error", "no_ready_endpoint"),
+            (ServiceUnavailable, 503, "server_error", "service_unavailable"),
+            (UpstreamTimeout, 504, "server_error", "upstream_timeout"),
+            (DeadlineExceeded, 504, "server_error", "deadline_exceeded"),
+        ];
+        for (code, status, error_type, name) in table {
+            let resp = ApiError::new(code, "boom").with_param("p").into_response();
+            assert_eq!(resp.status().as_u16(), status, "{name}");
+            let body = axum::body::to_bytes(resp.into_body(), usize::MAX).await.unwrap();
+            assert_eq!(
+                serde_json::from_slice::<Value>(&body).unwrap(),
+                json!({"error": {"message": "boom", "type": error_type, "param": "p", "code": name}}),
+            );
+        }
+
+        // Overrides keep the code's type and name.
+        let err = ApiError::new(InvalidRequest, "gone")
+            .with_status(StatusCode::NOT_FOUND)
+            .with_request_id("req-1")
+            .with_retry_after(3);
+        let resp = err.clone().into_response();
+        assert_eq!(resp.status(), StatusCode::NOT_FOUND);
+        assert_eq!(resp.headers()["retry-after"], "3");
+        assert_eq!(
+            err.to_json(),
+            json!({"error": {"message": "gone", "type": "invalid_request_error", "param": null,
+                             "code": "invalid_request", "request_id": "req-1"}})
+        );
+    }
+}
@@ -1,11 +1,124 @@
+use std::time::Duration;
+
+use axum::http::HeaderMap;
 use serde::{Deserialize, Serialize};
 
+use crate::ratelimit::now_ms;
+
+/// Client header with a timeout for the whole request, in seconds.
+pub const REQUEST_TIMEOUT_HEADER: &str = "x-request-timeout";
+/// Absolute deadline in Unix milliseconds, set by the gateway for the router.
+pub const DEADLINE_HEADER: &str = "x-nebula-deadline-ms";
+/// Upper bound on completion tokens for the request.
+pub const BUDGET_TOKENS_HEADER: &str = "x-nebula-budget-tokens";
+/// Scheduling priority; negative values mark background work (such as batch
+/// jobs) that yields capacity to interactive traffic.
+pub const PRIORITY_HEADER: &str = "x-nebula-priority";
+
 #[derive(Debug, Clone, Serialize, Deserialize, PartialEq, Eq, Hash)]
 pub struct ExecutionContext {
     pub request_id: String,
     pub session_id: Option<String>,
     pub tenant_id: Option<String>,
     pub priority: Option<i32>,
+    /// Unix milliseconds after which the request is abandoned.
     pub deadline_ms: Option<u64>,
+    /// Completion tokens the request may generate; `max_tokens` is clamped to it.
     pub budget_tokens: Option<u32>,
+    /// Estimated prompt tokens plus requested completion tokens. Endpoints
+    /// whose `max_model_len` is smaller are skipped when routing.
+    #[serde(default)]
+    pub required_context_tokens: Option<u32>,
+    /// LoRA adapter requested as the model; replicas with it loaded are preferred.
+    #[serde(default)]
+    pub lora_adapter: Option<String>,
+}
+
+impl ExecutionContext {
+    /// Read `deadline_ms`, `budget_tokens` and `priority` from
+    /// [`DEADLINE_HEADER`], [`BUDGET_TOKENS_HEADER`] and [`PRIORITY_HEADER`].
+    pub fn read_limit_headers(&mut self, headers: &HeaderMap) {
+        let parse = |name: &str| headers.get(name).and_then(|v| v.to_str().ok()).map(str::trim);
+        if let Some(deadline) = parse(DEADLINE_HEADER).and_then(|v| v.parse().ok()) {
+            self.deadline_ms = Some(deadline);
+        }
+        if let Some(budget) = parse(BUDGET_TOKENS_HEADER).and_then(|v| v.parse().ok()) {
+            self.budget_tokens = Some(budget);
+        }
+        if let Some(priority) = parse(PRIORITY_HEADER).and_then(|v| v.parse().ok()) {
+            self.priority = Some(priority);
+        }
+    }
+
+    /// Whether this is background work that should be shed first.
+    pub fn is_low_priority(&self) -> bool {
+        self.priority.is_some_and(|p| p < 0)
+    }
+
+    /// Time left before the deadline at `now_ms`; zero once it has passed.
+    pub fn remaining_at(&self, now_ms: u64) -> Option<Duration> {
+        self.deadline_ms
+            .map(|d| Duration::from_millis(d.saturating_sub(now_ms)))
+    }
+
+    pub fn remaining(&self) -> Option<Duration> {
+        self.remaining_at(now_ms())
+    }
+
+    pub fn is_expired(&self) -> bool {
+        self.remaining().is_some_and(|r| r.is_zero())
+    }
+
+    /// `timeout` shortened to the time left before the deadline.
+    pub fn bound_timeout(&self, timeout: Option<Duration>) -> Option<Duration> {
+        match (timeout, self.remaining()) {
+            (Some(t), Some(r)) => Some(t.min(r)),
+            (t, r) => t.or(r),
+        }
+    }
+}
+
+/// Time left before the [`DEADLINE_HEADER`] deadline, if `headers` carry one.
+pub fn deadline_remaining(headers: &HeaderMap) -> Option<Duration> {
+    let deadline = headers.get(DEADLINE_HEADER)?.to_str().ok()?.trim().parse::<u64>().ok()?;
+    Some(Duration::from_millis(deadline.saturating_sub(now_ms())))
+}
+
+/// Parse a [`REQUEST_TIMEOUT_HEADER`] value: seconds, possibly fractional.
+pub fn parse_timeout_secs(value: &str) -> Option<Duration> {
+    let secs = value.trim().parse::<f64>().ok()?;
+    (secs.is_finite() && secs > 0.0).then(|| Duration::from_secs_f64(secs.min(86_400.0)))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_deadline_headers() {
+        let mut headers = HeaderMap::new();
+        headers.insert(DEADLINE_HEADER, "10000".parse().unwrap());
+        headers.insert(BUDGET_TOKENS_HEADER, "64".parse().unwrap());
+        headers.insert(PRIORITY_HEADER, "-1".parse().unwrap());
+        let mut ctx = ExecutionContext {
+            request_id: "req_1".to_string(),
+            session_id: None,
+            tenant_id: None,
+            priority: None,
+            deadline_ms: None,
+            budget_tokens: None,
+            required_context_tokens: None,
+            lora_adapter: None,
+        };
+        ctx.read_limit_headers(&headers);
+        assert_eq!(ctx.deadline_ms, Some(10_000));
+        assert_eq!(ctx.budget_tokens, Some(64));
+        assert!(ctx.is_low_priority());
+        assert_eq!(ctx.remaining_at(7_500), Some(Duration::from_millis(2_500)));
+        assert_eq!(ctx.remaining_at(12_000), Some(Duration::ZERO));
+
+        assert_eq!(parse_timeout_secs("2.5"), Some(Duration::from_millis(2_500)));
+        assert_eq!(parse_timeout_secs("0"), None);
+        assert_eq!(parse_timeout_secs("soon"), None);
+    }
 }
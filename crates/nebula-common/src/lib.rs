@@ -1,6 +1,9 @@
+pub mod api_key;
+pub mod body;
 pub mod cluster;
 pub mod endpoint;
 pub mod engine_image;
+pub mod error;
 pub mod execution_context;
 pub mod model_cache;
 pub mod model_deployment;
@@ -9,18 +12,28 @@ pub mod model_spec;
 pub mod model_template;
 pub mod node_status;
 pub mod placement;
+pub mod ratelimit;
+pub mod request_policy;
+pub mod routing_policy;
+pub mod tokens;
+pub mod usage;
 
+pub use api_key::ApiKey;
 pub use cluster::ClusterStatus;
-pub use endpoint::{EndpointInfo, EndpointKind, EndpointStats, EndpointStatus};
+pub use endpoint::{EndpointInfo, EndpointKind, EndpointStats, EndpointStatus, ServingRole};
+pub use error::{ApiError, ErrorCode};
 pub use engine_image::{EngineImage, ImagePullStatus, NodeImageStatus, VersionPolicy};
 pub use execution_context::ExecutionContext;
 pub use model_cache::{AlertType, DiskAlert, DownloadPhase, DownloadProgress, ModelCacheEntry, NodeDiskStatus};
-pub use model_deployment::{DesiredState, ModelDeployment};
+pub use model_deployment::{DesiredState, DisaggregationSpec, ModelDeployment};
 pub use model_request::*;
 pub use model_spec::{ModelSource, ModelSpec};
 pub use model_template::{ModelTemplate, TemplateCategory, TemplateSource};
 pub use node_status::{GpuStatus, NodeStatus};
 pub use placement::{PlacementAssignment, PlacementPlan};
+pub use request_policy::RequestPolicy;
+pub use routing_policy::{HedgePolicy, RoutingPolicy, ShadowPolicy};
+pub use usage::TokenUsage;
 
 pub mod auth;
 pub mod telemetry;
@@ -1,6 +1,8 @@
 use serde::{Deserialize, Serialize};
 
+use crate::endpoint::ServingRole;
 use crate::model_request::ModelConfig;
+use crate::request_policy::RequestPolicy;
 
 /// Desired runtime state for a model deployment.
 #[derive(Debug, Clone, Serialize, Deserialize, PartialEq, Eq)]
@@ -45,6 +47,15 @@ pub struct ModelDeployment {
     #[serde(default, skip_serializing_if = "Option::is_none")]
     pub config_overrides: Option<ModelConfig>,
 
+    /// Overrides for ModelSpec.request_policy (merged, see `RequestPolicy::merged`).
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub request_policy: Option<RequestPolicy>,
+
+    /// Run as separate prefill and decode replica groups instead of `replicas`
+    /// monolithic engines.
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub disaggregation: Option<DisaggregationSpec>,
+
     /// Monotonically increasing version; bumped on every change.
     /// Scheduler uses this to detect whether a re-plan is needed.
     #[serde(default)]
@@ -59,3 +70,61 @@ fn default_replicas() -> u32 {
     1
 }
 
+/// Prefill/decode disaggregated serving layout for a deployment.
+#[derive(Debug, Clone, Serialize, Deserialize, PartialEq, Eq)]
+pub struct DisaggregationSpec {
+    #[serde(default = "default_replicas")]
+    pub prefill_replicas: u32,
+
+    #[serde(default = "default_replicas")]
+    pub decode_replicas: u32,
+
+    /// Engine KV connector used for the hand-off (vLLM `--kv-transfer-config`).
+    #[serde(default = "default_kv_connector")]
+    pub kv_connector: String,
+}
+
+fn default_kv_connector() -> String {
+    "NixlConnector".to_string()
+}
+
+impl DisaggregationSpec {
+    /// Replicas planned for a role. Each group has at least one replica; the
+    /// planner and reconciler both size groups with this.
+    pub fn replicas_for(&self, role: ServingRole) -> u32 {
+        match role {
+            ServingRole::Prefill => self.prefill_replicas.max(1),
+            ServingRole::Decode => self.decode_replicas.max(1),
+            ServingRole::Both => 0,
+        }
+    }
+
+    pub fn total_replicas(&self) -> u32 {
+        self.replicas_for(ServingRole::Prefill) + self.replicas_for(ServingRole::Decode)
+    }
+
+    /// Whether an engine can serve prefill/decode replicas: only vLLM (the
+    /// default engine) understands `--kv-transfer-config`.
+    pub fn supports_engine(engine_type: Option<&str>) -> bool {
+        matches!(engine_type, None | Some("vllm"))
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_disaggregation_replicas() {
+        let spec: DisaggregationSpec =
+            serde_json::from_str(r#"{"prefill_replicas": 0, "decode_replicas": 2}"#).unwrap();
+        assert_eq!(spec.replicas_for(ServingRole::Prefill), 1);
+        assert_eq!(spec.replicas_for(ServingRole::Decode), 2);
+        assert_eq!(spec.total_replicas(), 3);
+        assert_eq!(spec.kv_connector, "NixlConnector");
+
+        assert!(DisaggregationSpec::supports_engine(None));
+        assert!(DisaggregationSpec::supports_engine(Some("vllm")));
+        assert!(!DisaggregationSpec::supports_engine(Some("sglang")));
+    }
+}
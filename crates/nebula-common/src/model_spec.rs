@@ -3,6 +3,7 @@ use std::collections::HashMap;
 use serde::{Deserialize, Serialize};
 
 use crate::model_request::ModelConfig;
+use crate::request_policy::RequestPolicy;
 
 /// Source of model files — determines how the Node downloads the model.
 #[derive(Debug, Clone, Serialize, Deserialize, PartialEq, Eq)]
@@ -50,6 +51,10 @@ pub struct ModelSpec {
     #[serde(default, skip_serializing_if = "Option::is_none")]
     pub config: Option<ModelConfig>,
 
+    /// Defaults and limits for request parameters.
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub request_policy: Option<RequestPolicy>,
+
     /// Free-form key-value labels for grouping and filtering.
     #[serde(default)]
     pub labels: HashMap<String, String>,
@@ -1,5 +1,7 @@
 use serde::{Deserialize, Serialize};
 
+use crate::endpoint::ServingRole;
+
 #[derive(Debug, Clone, Serialize, Deserialize, PartialEq, Eq)]
 pub struct PlacementAssignment {
     pub replica_id: u32,
@@ -26,6 +28,9 @@ pub struct PlacementAssignment {
     /// the node-level engine docker_image CLI arg.
     #[serde(default, skip_serializing_if = "Option::is_none")]
     pub docker_image: Option<String>,
+    /// Prefill/decode role for disaggregated deployments. None means a monolithic replica.
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub role: Option<ServingRole>,
 }
 
 impl PlacementAssignment {
@@ -38,6 +43,62 @@ impl PlacementAssignment {
         }
         self.gpu_index.map(|i| vec![i])
     }
+
+    /// Adapter names declared via `--lora-modules name=path ...` in `extra_args`.
+    pub fn lora_adapter_names(&self) -> Vec<String> {
+        let Some(args) = self.extra_args.as_deref() else {
+            return Vec::new();
+        };
+        let mut names = Vec::new();
+        let mut in_lora = false;
+        for arg in args {
+            if arg == "--lora-modules" {
+                in_lora = true;
+                continue;
+            }
+            if arg.starts_with("--") {
+                in_lora = false;
+                continue;
+            }
+            if in_lora {
+                // Accept both space- and comma-separated `name=path` lists.
+                for module in arg.split(',') {
+                    let name = module.split('=').next().unwrap_or("").trim();
+                    if !name.is_empty() && !names.iter().any(|n| n == name) {
+                        names.push(name.to_string());
+                    }
+                }
+            }
+        }
+        names
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_lora_adapter_names() {
+        let a = PlacementAssignment {
+            replica_id: 0,
+            node_id: "n1".to_string(),
+            engine_config_path: String::new(),
+            port: 8000,
+            gpu_index: None,
+            gpu_indices: None,
+            extra_args: Some(
+                ["--max-model-len", "4096", "--enable-lora", "--lora-modules", "sql=/a,chat=/b", "x=/c", "--seed", "1"]
+                    .iter()
+                    .map(|s| s.to_string())
+                    .collect(),
+            ),
+            engine_type: None,
+            docker_image: None,
+            role: None,
+        };
+        assert_eq!(a.lora_adapter_names(), vec!["sql", "chat", "x"]);
+    }
 }
 
 #[derive(Debug, Clone, Serialize, Deserialize, PartialEq, Eq)]
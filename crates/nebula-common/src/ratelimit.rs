@@ -0,0 +1,200 @@
+//! Request rate limiting.
+//!
+//! Limits are token buckets holding up to one minute's worth of requests and
+//! refilling continuously, so a client can never burst past its per-minute
+//! limit at a window boundary. Buckets live behind a [`RateLimiter`]: the
+//! in-process [`LocalRateLimiter`], or a backend shared between replicas.
+
+use std::collections::HashMap;
+use std::sync::Mutex;
+use std::time::{SystemTime, UNIX_EPOCH};
+
+use async_trait::async_trait;
+use axum::http::{HeaderMap, HeaderValue};
+use serde::{Deserialize, Serialize};
+
+pub fn now_ms() -> u64 {
+    SystemTime::now()
+        .duration_since(UNIX_EPOCH)
+        .unwrap_or_default()
+        .as_millis() as u64
+}
+
+/// A bucket of request tokens with capacity `limit`, refilled at `limit` per
+/// minute.
+#[derive(Debug, Clone, Copy, PartialEq, Serialize, Deserialize)]
+pub struct TokenBucket {
+    pub tokens: f64,
+    pub updated_ms: u64,
+}
+
+impl TokenBucket {
+    pub fn full(limit: u64, now_ms: u64) -> Self {
+        Self {
+            tokens: limit as f64,
+            updated_ms: now_ms,
+        }
+    }
+
+    pub fn refill(&mut self, limit: u64, now_ms: u64) {
+        let elapsed = now_ms.saturating_sub(self.updated_ms) as f64;
+        self.tokens = (self.tokens + elapsed * limit as f64 / 60_000.0).min(limit as f64);
+        self.updated_ms = self.updated_ms.max(now_ms);
+    }
+
+    /// Take up to `n` whole tokens; returns how many were taken.
+    pub fn take(&mut self, n: u64) -> u64 {
+        let taken = (self.tokens.floor().max(0.0) as u64).min(n);
+        self.tokens -= taken as f64;
+        taken
+    }
+
+    /// Milliseconds until the bucket holds `n` tokens.
+    pub fn ms_until(&self, limit: u64, n: f64) -> u64 {
+        if limit == 0 || self.tokens >= n {
+            return 0;
+        }
+        ((n - self.tokens) * 60_000.0 / limit as f64).ceil() as u64
+    }
+}
+
+/// Outcome of one rate limit check.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct RateLimitDecision {
+    pub allowed: bool,
+    pub limit: u64,
+    pub remaining: u64,
+    /// Milliseconds until the bucket is full again.
+    pub reset_ms: u64,
+    /// Milliseconds until a rejected request may be retried.
+    pub retry_after_ms: u64,
+}
+
+impl RateLimitDecision {
+    pub fn from_bucket(allowed: bool, limit: u64, bucket: &TokenBucket) -> Self {
+        Self {
+            allowed,
+            limit,
+            remaining: bucket.tokens.floor().max(0.0) as u64,
+            reset_ms: bucket.ms_until(limit, limit as f64),
+            retry_after_ms: if allowed { 0 } else { bucket.ms_until(limit, 1.0) },
+        }
+    }
+
+    /// The more restrictive of two decisions: a rejection, else the one with
+    /// fewer requests left.
+    pub fn tighter(self, other: Self) -> Self {
+        match (self.allowed, other.allowed) {
+            (true, false) => other,
+            (false, true) => self,
+            _ if other.remaining < self.remaining => other,
+            _ => self,
+        }
+    }
+
+    /// Set the `x-ratelimit-*` headers, and `retry-after` on rejections.
+    pub fn apply_headers(&self, headers: &mut HeaderMap) {
+        let values = [
+            ("x-ratelimit-limit-requests", self.limit.to_string()),
+            ("x-ratelimit-remaining-requests", self.remaining.to_string()),
+            ("x-ratelimit-reset-requests", format_duration_ms(self.reset_ms)),
+        ];
+        for (name, value) in values {
+            if let Ok(v) = HeaderValue::from_str(&value) {
+                headers.insert(name, v);
+            }
+        }
+        if !self.allowed {
+            headers.insert("retry-after", HeaderValue::from(self.retry_after_ms.div_ceil(1000).max(1)));
+        }
+    }
+}
+
+/// Durations in the form OpenAI uses for rate limit headers, e.g. `1m30s`,
+/// `6s`, `250ms`.
+fn format_duration_ms(ms: u64) -> String {
+    if ms < 1000 {
+        return format!("{ms}ms");
+    }
+    let secs = ms.div_ceil(1000);
+    match (secs / 60, secs % 60) {
+        (0, s) => format!("{s}s"),
+        (m, 0) => format!("{m}m"),
+        (m, s) => format!("{m}m{s}s"),
+    }
+}
+
+/// Where rate limit buckets are kept.
+#[async_trait]
+pub trait RateLimiter: Send + Sync + std::fmt::Debug {
+    /// Take one request from the bucket `key`, refilled at `limit_per_minute`.
+    async fn acquire(&self, key: &str, limit_per_minute: u64) -> RateLimitDecision;
+}
+
+/// Buckets in process memory; each replica enforces the full limit on its own.
+#[derive(Debug, Default)]
+pub struct LocalRateLimiter {
+    buckets: Mutex<HashMap<String, TokenBucket>>,
+}
+
+impl LocalRateLimiter {
+    pub fn acquire_at(&self, key: &str, limit: u64, now_ms: u64) -> RateLimitDecision {
+        let Ok(mut buckets) = self.buckets.lock() else {
+            return RateLimitDecision::from_bucket(true, limit, &TokenBucket::full(limit, now_ms));
+        };
+        // Buckets idle for over a minute are full; drop them to bound memory.
+        if buckets.len() > 10_000 {
+            buckets.retain(|_, b| now_ms.saturating_sub(b.updated_ms) < 60_000);
+        }
+        let bucket = buckets
+            .entry(key.to_string())
+            .or_insert_with(|| TokenBucket::full(limit, now_ms));
+        bucket.refill(limit, now_ms);
+        let allowed = bucket.take(1) == 1;
+        RateLimitDecision::from_bucket(allowed, limit, bucket)
+    }
+}
+
+#[async_trait]
+impl RateLimiter for LocalRateLimiter {
+    async fn acquire(&self, key: &str, limit_per_minute: u64) -> RateLimitDecision {
+        self.acquire_at(key, limit_per_minute, now_ms())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_token_bucket_refills_without_boundary_bursts() {
+        let limiter = LocalRateLimiter::default();
+        for i in 0..3 {
+            let d = limiter.acquire_at("k", 3, 1_000);
+            assert!(d.allowed);
+            assert_eq!(d.remaining, 2 - i);
+        }
+        let d = limiter.acquire_at("k", 3, 1_000);
+        assert!(!d.allowed);
+        assert_eq!(d.retry_after_ms, 20_000);
+
+        // One token per 20s: still rejected just before, allowed after.
+        assert!(!limiter.acquire_at("k", 3, 20_000).allowed);
+        assert!(limiter.acquire_at("k", 3, 21_500).allowed);
+        assert!(!limiter.acquire_at("k", 3, 21_500).allowed);
+    }
+
+    #[test]
+    fn test_decision_headers() {
+        let a = RateLimitDecision { allowed: true, limit: 10, remaining: 4, reset_ms: 36_000, retry_after_ms: 0 };
+        let b = RateLimitDecision { allowed: false, limit: 5, remaining: 0, reset_ms: 60_000, retry_after_ms: 1_500 };
+        assert_eq!(a.tighter(b), b);
+        let mut headers = HeaderMap::new();
+        b.apply_headers(&mut headers);
+        assert_eq!(headers["x-ratelimit-remaining-requests"], "0");
+        assert_eq!(headers["x-ratelimit-reset-requests"], "1m");
+        assert_eq!(headers["retry-after"], "2");
+        assert_eq!(format_duration_ms(90_000), "1m30s");
+        assert_eq!(format_duration_ms(250), "250ms");
+    }
+}
@@ -0,0 +1,235 @@
+use std::collections::BTreeMap;
+
+use serde::{Deserialize, Serialize};
+use serde_json::{Map, Value};
+
+/// Request parameters that bound the number of generated tokens; a bound on
+/// either applies to both.
+const TOKEN_LIMIT_PARAMS: [&str; 2] = ["max_tokens", "max_completion_tokens"];
+
+/// Per-model rules for request parameters, applied by the router to chat and
+/// completion requests before they are forwarded to an engine.
+///
+/// Set on `ModelSpec.request_policy`; `ModelDeployment.request_policy`
+/// overrides it field by field (see [`RequestPolicy::merged`]).
+#[derive(Debug, Clone, Default, Serialize, Deserialize, PartialEq)]
+pub struct RequestPolicy {
+    /// Values for parameters the request leaves unset (e.g. `temperature`).
+    #[serde(default, skip_serializing_if = "BTreeMap::is_empty")]
+    pub defaults: BTreeMap<String, Value>,
+
+    /// Lower bounds for numeric parameters; smaller values are raised.
+    #[serde(default, skip_serializing_if = "BTreeMap::is_empty")]
+    pub min: BTreeMap<String, f64>,
+
+    /// Upper bounds for numeric parameters; larger values are lowered. A bound
+    /// on `max_tokens` is also set on requests that leave it unset.
+    #[serde(default, skip_serializing_if = "BTreeMap::is_empty")]
+    pub max: BTreeMap<String, f64>,
+
+    /// Parameters clients may not set; requests that do are rejected. A
+    /// default for a forbidden parameter still applies, fixing its value.
+    #[serde(default, skip_serializing_if = "Vec::is_empty")]
+    pub forbidden_params: Vec<String>,
+
+    /// System prompt placed before the conversation (chat requests only). An
+    /// existing leading system message is kept after it.
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub system_prompt: Option<String>,
+
+    /// Stop sequences added to every request's `stop`.
+    #[serde(default, skip_serializing_if = "Vec::is_empty")]
+    pub stop: Vec<String>,
+}
+
+impl RequestPolicy {
+    pub fn is_empty(&self) -> bool {
+        *self == Self::default()
+    }
+
+    pub fn validate(&self) -> Result<(), String> {
+        for (param, min) in &self.min {
+            if let Some(max) = self.max.get(param) {
+                if min > max {
+                    return Err(format!("min.{param} must not exceed max.{param}"));
+                }
+            }
+        }
+        if self.defaults.values().any(Value::is_null) {
+            return Err("defaults must not be null".to_string());
+        }
+        if self.defaults.contains_key("model") || self.forbidden_params.iter().any(|p| p == "model") {
+            return Err("the model parameter can't be set by a request policy".to_string());
+        }
+        if self.stop.iter().any(String::is_empty) {
+            return Err("stop sequences must not be empty".to_string());
+        }
+        Ok(())
+    }
+
+    /// This policy with `overrides` laid over it: maps are merged key by key,
+    /// forbidden parameters and stop sequences are combined, and a set system
+    /// prompt replaces this one.
+    pub fn merged(&self, overrides: &RequestPolicy) -> RequestPolicy {
+        let mut out = self.clone();
+        out.defaults
+            .extend(overrides.defaults.iter().map(|(k, v)| (k.clone(), v.clone())));
+        out.min.extend(overrides.min.iter().map(|(k, v)| (k.clone(), *v)));
+        out.max.extend(overrides.max.iter().map(|(k, v)| (k.clone(), *v)));
+        for param in &overrides.forbidden_params {
+            if !out.forbidden_params.contains(param) {
+                out.forbidden_params.push(param.clone());
+            }
+        }
+        for stop in &overrides.stop {
+            if !out.stop.contains(stop) {
+                out.stop.push(stop.clone());
+            }
+        }
+        if overrides.system_prompt.is_some() {
+            out.system_prompt = overrides.system_prompt.clone();
+        }
+        out
+    }
+
+    /// The policy in force for a model, from its spec and deployment.
+    pub fn effective(spec: Option<&RequestPolicy>, deployment: Option<&RequestPolicy>) -> Option<RequestPolicy> {
+        let policy = match (spec, deployment) {
+            (Some(spec), Some(deployment)) => spec.merged(deployment),
+            (Some(policy), None) | (None, Some(policy)) => policy.clone(),
+            (None, None) => return None,
+        };
+        (!policy.is_empty()).then_some(policy)
+    }
+
+    /// Applies the policy to a chat (`messages`) or completion (`prompt`)
+    /// request body. Returns the offending parameter if the request sets a
+    /// forbidden one.
+    pub fn apply(&self, body: &mut Value) -> Result<(), String> {
+        let Some(request) = body.as_object_mut() else {
+            return Ok(());
+        };
+        if let Some(param) = self.forbidden_params.iter().find(|p| request.contains_key(p.as_str())) {
+            return Err(param.clone());
+        }
+        for (param, value) in &self.defaults {
+            if !request.contains_key(param) {
+                request.insert(param.clone(), value.clone());
+            }
+        }
+        for (param, min) in &self.min {
+            for param in bound_params(param) {
+                clamp(request, param, |v| v.max(*min), min.ceil());
+            }
+        }
+        for (param, max) in &self.max {
+            let token_limit = TOKEN_LIMIT_PARAMS.contains(&param.as_str());
+            if token_limit && !TOKEN_LIMIT_PARAMS.iter().any(|p| request.contains_key(*p)) {
+                request.insert("max_tokens".to_string(), Value::from(max.floor() as u64));
+            }
+            for param in bound_params(param) {
+                clamp(request, param, |v| v.min(*max), max.floor());
+            }
+        }
+        if !self.stop.is_empty() {
+            let mut stop: Vec<Value> = match request.remove("stop") {
+                Some(Value::String(s)) => vec![Value::String(s)],
+                Some(Value::Array(items)) => items,
+                _ => Vec::new(),
+            };
+            for s in &self.stop {
+                let s = Value::String(s.clone());
+                if !stop.contains(&s) {
+                    stop.push(s);
+                }
+            }
+            request.insert("stop".to_string(), Value::Array(stop));
+        }
+        if let (Some(prompt), Some(Value::Array(messages))) = (&self.system_prompt, request.get_mut("messages")) {
+            messages.insert(0, serde_json::json!({ "role": "system", "content": prompt }));
+        }
+        Ok(())
+    }
+}
+
+fn bound_params(param: &str) -> Vec<&str> {
+    if TOKEN_LIMIT_PARAMS.contains(&param) {
+        TOKEN_LIMIT_PARAMS.to_vec()
+    } else {
+        vec![param]
+    }
+}
+
+/// Clamps a numeric parameter, keeping integers integral (`integral` is the
+/// bound rounded inward).
+fn clamp(request: &mut Map<String, Value>, param: &str, bound: impl Fn(f64) -> f64, integral: f64) {
+    let Some(value) = request.get_mut(param) else {
+        return;
+    };
+    let Some(n) = value.as_f64() else {
+        return;
+    };
+    let clamped = bound(n);
+    if clamped == n {
+        return;
+    }
+    *value = if value.is_f64() {
+        Value::from(clamped)
+    } else {
+        Value::from(integral as i64)
+    };
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use serde_json::json;
+
+    #[test]
+    fn test_apply_request_policy() {
+        let spec: RequestPolicy = serde_json::from_value(json!({
+            "defaults": {"temperature": 0.2},
+            "min": {"temperature": 0.0},
+            "max": {"max_tokens": 4096, "temperature": 1.0},
+            "forbidden_params": ["logit_bias"],
+            "system_prompt": "Be brief.",
+            "stop": ["<|end|>"]
+        }))
+        .unwrap();
+        let deployment = RequestPolicy {
+            max: BTreeMap::from([("max_tokens".to_string(), 2048.0)]),
+            ..Default::default()
+        };
+        let policy = RequestPolicy::effective(Some(&spec), Some(&deployment)).unwrap();
+        policy.validate().unwrap();
+
+        let mut body = json!({
+            "model": "m",
+            "messages": [{"role": "system", "content": "You are a bot."}, {"role": "user", "content": "hi"}],
+            "max_completion_tokens": 100000,
+            "stop": "###"
+        });
+        policy.apply(&mut body).unwrap();
+        assert_eq!(body["temperature"], json!(0.2));
+        assert_eq!(body["max_completion_tokens"], json!(2048));
+        assert!(body.get("max_tokens").is_none());
+        assert_eq!(body["stop"], json!(["###", "<|end|>"]));
+        assert_eq!(body["messages"][0], json!({"role": "system", "content": "Be brief."}));
+        assert_eq!(body["messages"][1]["content"], json!("You are a bot."));
+
+        let mut body = json!({"model": "m", "prompt": "x", "temperature": 1.5});
+        policy.apply(&mut body).unwrap();
+        assert_eq!(body["temperature"], json!(1.0));
+        assert_eq!(body["max_tokens"], json!(2048));
+
+        let mut body = json!({"model": "m", "prompt": "x", "logit_bias": {}});
+        assert_eq!(policy.apply(&mut body), Err("logit_bias".to_string()));
+
+        let invalid = RequestPolicy {
+            min: BTreeMap::from([("top_p".to_string(), 0.9)]),
+            max: BTreeMap::from([("top_p".to_string(), 0.5)]),
+            ..Default::default()
+        };
+        assert!(invalid.validate().is_err());
+    }
+}
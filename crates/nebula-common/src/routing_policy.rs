@@ -0,0 +1,204 @@
+use std::collections::BTreeMap;
+
+use serde::{Deserialize, Serialize};
+
+/// Per-model routing behaviour, applied by the router at runtime.
+///
+/// Stored in etcd under `/routing_policies/{model_uid}`. Every field is
+/// optional; unset fields fall back to the router's global settings
+/// (CLI flags and `NEBULA_ROUTE_*` / `NEBULA_ROUTER_*` env vars).
+#[derive(Debug, Clone, Default, Serialize, Deserialize, PartialEq)]
+pub struct RoutingPolicy {
+    pub model_uid: String,
+
+    /// Strategy name: `least_pending`, `least_kv_cache` or `prefix_cache_aware`.
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub strategy: Option<String>,
+
+    /// Strategy-specific numeric parameters (e.g. `hit_rate_threshold` for
+    /// `prefix_cache_aware`).
+    #[serde(default, skip_serializing_if = "BTreeMap::is_empty")]
+    pub strategy_params: BTreeMap<String, f64>,
+
+    /// Extra upstream attempts after the first one fails.
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub retry_max: Option<u32>,
+
+    /// Delay between upstream attempts.
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub retry_backoff_ms: Option<u64>,
+
+    /// Consecutive failures before an endpoint's circuit opens.
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub circuit_failure_threshold: Option<u32>,
+
+    /// How long an opened circuit keeps the endpoint out of rotation.
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub circuit_open_ms: Option<u64>,
+
+    /// Routing stats older than this are ignored.
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub stats_max_age_ms: Option<u64>,
+
+    /// KV cache usage fraction (0.0–1.0) above which an endpoint counts as overloaded.
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub kv_cache_overload_threshold: Option<f64>,
+
+    /// Per-attempt upstream request timeout.
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub request_timeout_ms: Option<u64>,
+
+    /// Mirror a sample of this model's traffic to a candidate model.
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub shadow: Option<ShadowPolicy>,
+
+    /// Duplicate slow non-streaming requests to a second replica.
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub hedge: Option<HedgePolicy>,
+
+    /// Last update timestamp (ms since epoch).
+    #[serde(default)]
+    pub updated_at_ms: u64,
+}
+
+/// Shadow traffic rule: copies of sampled requests are sent to `target_model`
+/// in the background. Shadow responses never reach the client; only their
+/// latency, status and output are recorded.
+#[derive(Debug, Clone, Serialize, Deserialize, PartialEq)]
+pub struct ShadowPolicy {
+    /// Model uid or name receiving the mirrored requests.
+    pub target_model: String,
+
+    /// Fraction of requests to mirror (0.0–1.0).
+    pub sample_rate: f64,
+
+    /// Mirrored requests allowed in flight at once; further samples are dropped.
+    #[serde(default = "default_shadow_max_concurrency")]
+    pub max_concurrency: u32,
+
+    /// Compare the shadow output with the primary response (non-streaming
+    /// primary responses only).
+    #[serde(default)]
+    pub compare_output: bool,
+}
+
+fn default_shadow_max_concurrency() -> u32 {
+    4
+}
+
+impl ShadowPolicy {
+    pub fn validate(&self, model_uid: &str) -> Result<(), String> {
+        if self.target_model.is_empty() {
+            return Err("shadow.target_model must not be empty".to_string());
+        }
+        if self.target_model == model_uid {
+            return Err("shadow.target_model must differ from the source model".to_string());
+        }
+        if !(0.0..=1.0).contains(&self.sample_rate) {
+            return Err("shadow.sample_rate must be between 0 and 1".to_string());
+        }
+        if self.max_concurrency == 0 {
+            return Err("shadow.max_concurrency must be at least 1".to_string());
+        }
+        Ok(())
+    }
+}
+
+/// Request hedging rule for non-streaming calls: when the first replica hasn't
+/// answered within the model's recent `percentile` latency, the router sends a
+/// duplicate to another replica and returns whichever finishes first.
+#[derive(Debug, Clone, Serialize, Deserialize, PartialEq)]
+pub struct HedgePolicy {
+    /// Latency percentile (0.0–1.0, exclusive) used as the hedge delay.
+    #[serde(default = "default_hedge_percentile")]
+    pub percentile: f64,
+
+    /// Lower bound on the hedge delay.
+    #[serde(default = "default_hedge_min_delay_ms")]
+    pub min_delay_ms: u64,
+
+    /// Hedged requests allowed as a fraction of the model's requests (0.0–1.0).
+    #[serde(default = "default_hedge_max_extra_load")]
+    pub max_extra_load: f64,
+}
+
+fn default_hedge_percentile() -> f64 {
+    0.95
+}
+
+fn default_hedge_min_delay_ms() -> u64 {
+    20
+}
+
+fn default_hedge_max_extra_load() -> f64 {
+    0.1
+}
+
+impl HedgePolicy {
+    pub fn validate(&self) -> Result<(), String> {
+        if !(self.percentile > 0.0 && self.percentile < 1.0) {
+            return Err("hedge.percentile must be between 0 and 1 (exclusive)".to_string());
+        }
+        if !(0.0..=1.0).contains(&self.max_extra_load) {
+            return Err("hedge.max_extra_load must be between 0 and 1".to_string());
+        }
+        Ok(())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use serde_json::json;
+
+    #[test]
+    fn test_unset_fields_stay_unset() {
+        let policy: RoutingPolicy = serde_json::from_value(json!({
+            "model_uid": "m",
+            "retry_max": 1,
+            "hedge": {"percentile": 0.9}
+        }))
+        .unwrap();
+        assert_eq!(policy.retry_max, Some(1));
+        assert_eq!(policy.strategy, None);
+        assert_eq!(policy.circuit_failure_threshold, None);
+        assert_eq!(policy.kv_cache_overload_threshold, None);
+        let hedge = policy.hedge.as_ref().unwrap();
+        assert_eq!((hedge.min_delay_ms, hedge.max_extra_load), (20, 0.1));
+        hedge.validate().unwrap();
+
+        // Only the fields that were set are written back.
+        let written = serde_json::to_value(&policy).unwrap();
+        assert_eq!(
+            written,
+            json!({
+                "model_uid": "m",
+                "retry_max": 1,
+                "hedge": {"percentile": 0.9, "min_delay_ms": 20, "max_extra_load": 0.1},
+                "updated_at_ms": 0
+            })
+        );
+    }
+
+    #[test]
+    fn test_validate_shadow_and_hedge() {
+        let shadow = ShadowPolicy {
+            target_model: "m".to_string(),
+            sample_rate: 0.1,
+            max_concurrency: 4,
+            compare_output: false,
+        };
+        assert!(shadow.validate("m").is_err());
+        assert!(shadow.validate("n").is_ok());
+        assert!(ShadowPolicy { sample_rate: 1.5, ..shadow.clone() }.validate("n").is_err());
+        assert!(ShadowPolicy { max_concurrency: 0, ..shadow }.validate("n").is_err());
+
+        let hedge = HedgePolicy {
+            percentile: 1.0,
+            min_delay_ms: 20,
+            max_extra_load: 0.1,
+        };
+        assert!(hedge.validate().is_err());
+        assert!(HedgePolicy { percentile: 0.5, max_extra_load: 2.0, ..hedge }.validate().is_err());
+    }
+}
@@ -0,0 +1,126 @@
+//! Heuristic token counts for requests whose tokenizer isn't at hand.
+//!
+//! The estimate is deliberately low: it is used to pick engines with enough
+//! context and to charge streams that ended before the engine reported usage,
+//! and neither should ever overcount.
+
+use serde_json::Value;
+
+/// Fixed per-message overhead of chat templates (role markers, separators).
+const CHAT_TOKENS_PER_MESSAGE: u32 = 4;
+/// Tokens the chat template adds to prime the assistant reply.
+const CHAT_REPLY_PRIMING_TOKENS: u32 = 3;
+
+/// Lower-bound estimate without a vocabulary: ~4 ASCII bytes per token and
+/// ~1.5 non-ASCII characters (mostly CJK) per token.
+pub fn heuristic_count(text: &str) -> u32 {
+    let ascii = text.bytes().filter(|b| b.is_ascii()).count();
+    let other = text.chars().filter(|c| !c.is_ascii()).count();
+    (ascii / 4 + other * 2 / 3) as u32
+}
+
+/// Token requirements of one OpenAI request.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct TokenEstimate {
+    pub prompt_tokens: u32,
+    /// `max_tokens` / `max_completion_tokens` as requested, if any.
+    pub completion_tokens: Option<u32>,
+}
+
+impl TokenEstimate {
+    /// Context tokens the engine must accommodate; at least one completion token.
+    pub fn required_context_tokens(&self) -> u32 {
+        self.prompt_tokens
+            .saturating_add(self.completion_tokens.unwrap_or(1).max(1))
+    }
+}
+
+fn collect_text(content: &Value, out: &mut Vec<String>) {
+    match content {
+        Value::String(s) => out.push(s.clone()),
+        Value::Array(parts) => {
+            for part in parts {
+                match part {
+                    Value::String(s) => out.push(s.clone()),
+                    _ => {
+                        if let Some(t) = part.get("text").and_then(|t| t.as_str()) {
+                            out.push(t.to_string());
+                        }
+                    }
+                }
+            }
+        }
+        _ => {}
+    }
+}
+
+/// Estimate prompt and completion tokens for a chat or completion request body.
+/// Returns `None` for bodies without `messages` or `prompt`.
+pub fn estimate_request(body: &Value, count: &dyn Fn(&str) -> u32) -> Option<TokenEstimate> {
+    let completion_tokens = body
+        .get("max_completion_tokens")
+        .or_else(|| body.get("max_tokens"))
+        .and_then(|v| v.as_u64())
+        .map(|v| v.min(u32::MAX as u64) as u32);
+
+    let prompt_tokens = if let Some(messages) = body.get("messages").and_then(|m| m.as_array()) {
+        let mut total = CHAT_REPLY_PRIMING_TOKENS;
+        for msg in messages {
+            let mut texts = Vec::new();
+            if let Some(content) = msg.get("content") {
+                collect_text(content, &mut texts);
+            }
+            if let Some(calls) = msg.get("tool_calls") {
+                texts.push(calls.to_string());
+            }
+            total = total.saturating_add(CHAT_TOKENS_PER_MESSAGE);
+            for t in texts {
+                total = total.saturating_add(count(&t));
+            }
+        }
+        if let Some(tools) = body.get("tools") {
+            total = total.saturating_add(count(&tools.to_string()));
+        }
+        total
+    } else if let Some(prompt) = body.get("prompt") {
+        let mut texts = Vec::new();
+        collect_text(prompt, &mut texts);
+        texts.iter().map(|t| count(t)).fold(0u32, u32::saturating_add)
+    } else {
+        return None;
+    };
+
+    Some(TokenEstimate {
+        prompt_tokens,
+        completion_tokens,
+    })
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use serde_json::json;
+
+    #[test]
+    fn test_estimate_request() {
+        let words = |s: &str| s.split_whitespace().count() as u32;
+        let chat = json!({
+            "messages": [
+                {"role": "system", "content": "be brief"},
+                {"role": "user", "content": [{"type": "text", "text": "one two three"}]}
+            ],
+            "max_tokens": 100
+        });
+        let est = estimate_request(&chat, &words).unwrap();
+        assert_eq!(est.prompt_tokens, 3 + 2 * 4 + 2 + 3);
+        assert_eq!(est.required_context_tokens(), est.prompt_tokens + 100);
+
+        let completion = json!({"prompt": ["a b", "c"]});
+        let est = estimate_request(&completion, &words).unwrap();
+        assert_eq!(est.prompt_tokens, 3);
+        assert_eq!(est.required_context_tokens(), 4);
+
+        assert!(estimate_request(&json!({"input": "x"}), &words).is_none());
+        assert_eq!(heuristic_count("abcdefgh"), 2);
+    }
+}
@@ -0,0 +1,300 @@
+//! Token usage reported by OpenAI-compatible engines.
+
+use serde::{Deserialize, Serialize};
+use serde_json::{json, Value};
+
+/// Prompt and completion tokens of one request.
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq, Serialize, Deserialize)]
+pub struct TokenUsage {
+    pub prompt_tokens: u64,
+    pub completion_tokens: u64,
+}
+
+impl TokenUsage {
+    /// Usage from a response body or stream chunk: chat/completions
+    /// (`prompt_tokens` / `completion_tokens`) or Responses
+    /// (`input_tokens` / `output_tokens`) naming, at the top level or under
+    /// `response` for Responses stream events.
+    pub fn from_json(v: &Value) -> Option<Self> {
+        let usage = v
+            .get("usage")
+            .or_else(|| v.pointer("/response/usage"))
+            .filter(|u| u.is_object())?;
+        let tokens = |a: &str, b: &str| {
+            usage
+                .get(a)
+                .or_else(|| usage.get(b))
+                .and_then(|x| x.as_u64())
+        };
+        let prompt_tokens = tokens("prompt_tokens", "input_tokens");
+        let completion_tokens = tokens("completion_tokens", "output_tokens");
+        if prompt_tokens.is_none() && completion_tokens.is_none() {
+            return None;
+        }
+        Some(Self {
+            prompt_tokens: prompt_tokens.unwrap_or(0),
+            completion_tokens: completion_tokens.unwrap_or(0),
+        })
+    }
+
+    pub fn total_tokens(&self) -> u64 {
+        self.prompt_tokens + self.completion_tokens
+    }
+
+    /// The chat/completions `usage` object.
+    pub fn to_json(&self) -> Value {
+        json!({
+            "prompt_tokens": self.prompt_tokens,
+            "completion_tokens": self.completion_tokens,
+            "total_tokens": self.total_tokens()
+        })
+    }
+}
+
+/// Ask the engine to report usage at the end of a streamed chat/completions
+/// request. Returns `true` when the body was changed, i.e. the client didn't
+/// ask for usage and the usage-only chunk should be kept from it.
+pub fn request_stream_usage(body: &mut Value) -> bool {
+    if body.get("stream").and_then(|s| s.as_bool()) != Some(true) {
+        return false;
+    }
+    if body.pointer("/stream_options/include_usage").and_then(|u| u.as_bool()) == Some(true) {
+        return false;
+    }
+    match body.get_mut("stream_options").and_then(|o| o.as_object_mut()) {
+        Some(opts) => {
+            opts.insert("include_usage".to_string(), Value::Bool(true));
+        }
+        None => body["stream_options"] = json!({"include_usage": true}),
+    }
+    true
+}
+
+/// Whether `v` is the usage-only chunk (`choices: []` with a `usage`) that
+/// ends a stream requested with `include_usage`.
+pub fn is_usage_only_chunk(v: &Value) -> bool {
+    v.get("choices")
+        .and_then(|c| c.as_array())
+        .is_some_and(|c| c.is_empty())
+        && TokenUsage::from_json(v).is_some()
+}
+
+/// Drops the usage-only chunk from an SSE stream, for clients that didn't
+/// ask for it. Complete events pass through as they arrive; a partial event
+/// is held back until its blank line.
+#[derive(Debug, Default)]
+pub struct UsageChunkFilter {
+    pending: Vec<u8>,
+}
+
+impl UsageChunkFilter {
+    /// Feed upstream bytes. Returns the bytes that may be forwarded.
+    pub fn push(&mut self, chunk: &[u8]) -> Vec<u8> {
+        self.pending.extend_from_slice(chunk);
+        let mut out = Vec::with_capacity(self.pending.len());
+        let mut start = 0;
+        while let Some(len) = event_len(&self.pending[start..]) {
+            let event = &self.pending[start..start + len];
+            if !is_usage_only_event(event) {
+                out.extend_from_slice(event);
+            }
+            start += len;
+        }
+        self.pending.drain(..start);
+        out
+    }
+
+    /// Bytes of an unterminated last event, once the stream has ended.
+    pub fn finish(self) -> Vec<u8> {
+        self.pending
+    }
+}
+
+/// Length of the first complete event in `buf`, including its blank line.
+fn event_len(buf: &[u8]) -> Option<usize> {
+    (0..buf.len()).find_map(|i| {
+        if buf[i] != b'\n' {
+            return None;
+        }
+        match &buf[i + 1..] {
+            [b'\n', ..] => Some(i + 2),
+            [b'\r', b'\n', ..] => Some(i + 3),
+            _ => None,
+        }
+    })
+}
+
+fn is_usage_only_event(event: &[u8]) -> bool {
+    if !event.windows(7).any(|w| w == b"\"usage\"") {
+        return false;
+    }
+    event.split(|&b| b == b'\n').any(|line| {
+        line.strip_prefix(b"data:")
+            .and_then(|d| serde_json::from_slice::<Value>(d.trim_ascii()).ok())
+            .is_some_and(|v| is_usage_only_chunk(&v))
+    })
+}
+
+/// Incrementally scans an OpenAI SSE stream for its usage chunk, collecting
+/// the generated text for a fallback count when the engine reports none.
+#[derive(Debug, Default)]
+pub struct SseUsageScanner {
+    line: Vec<u8>,
+    usage: Option<TokenUsage>,
+    text: String,
+    /// `id`, `created` and `model` of the last chunk, for a synthesized usage chunk.
+    last_chunk: Option<Value>,
+}
+
+impl SseUsageScanner {
+    pub fn push(&mut self, chunk: &[u8]) {
+        for &b in chunk {
+            if b == b'\n' {
+                let line = std::mem::take(&mut self.line);
+                self.scan_line(&line);
+            } else {
+                self.line.push(b);
+            }
+        }
+    }
+
+    fn scan_line(&mut self, line: &[u8]) {
+        let Some(data) = line.strip_prefix(b"data:") else {
+            return;
+        };
+        let Ok(v) = serde_json::from_slice::<Value>(data.trim_ascii()) else {
+            return;
+        };
+        if let Some(usage) = TokenUsage::from_json(&v) {
+            self.usage = Some(usage);
+        }
+        for c in v.get("choices").and_then(|c| c.as_array()).into_iter().flatten() {
+            let text = c
+                .pointer("/delta/content")
+                .or_else(|| c.get("text"))
+                .and_then(|t| t.as_str());
+            if let Some(text) = text {
+                self.text.push_str(text);
+            }
+            if let Some(calls) = c.pointer("/delta/tool_calls").and_then(|t| t.as_array()) {
+                for call in calls {
+                    if let Some(args) = call.pointer("/function/arguments").and_then(|a| a.as_str()) {
+                        self.text.push_str(args);
+                    }
+                }
+            }
+        }
+        if let Some(delta) = v.get("delta").and_then(|d| d.as_str()) {
+            // Responses `*.delta` events.
+            self.text.push_str(delta);
+        }
+        if v.get("id").is_some() {
+            let last = self.last_chunk.get_or_insert_with(|| json!({}));
+            for key in ["id", "object", "created", "model"] {
+                if let Some(x) = v.get(key) {
+                    last[key] = x.clone();
+                }
+            }
+        }
+    }
+
+    /// Usage reported by the stream, if any.
+    pub fn usage(&self) -> Option<TokenUsage> {
+        self.usage
+    }
+
+    /// Text generated so far.
+    pub fn text(&self) -> &str {
+        &self.text
+    }
+
+    /// A usage-only SSE event (`choices: []`) in the shape of the stream's chunks.
+    pub fn usage_event(&self, usage: TokenUsage) -> String {
+        let mut chunk = self
+            .last_chunk
+            .clone()
+            .unwrap_or_else(|| json!({"object": "chat.completion.chunk"}));
+        chunk["choices"] = json!([]);
+        chunk["usage"] = usage.to_json();
+        format!("data: {chunk}\n\n")
+    }
+}
+
+/// Generated text of a non-streamed chat/completions response.
+pub fn completion_text(v: &Value) -> String {
+    let mut out = String::new();
+    for c in v.get("choices").and_then(|c| c.as_array()).into_iter().flatten() {
+        let text = c
+            .pointer("/message/content")
+            .or_else(|| c.get("text"))
+            .and_then(|t| t.as_str());
+        if let Some(text) = text {
+            out.push_str(text);
+        }
+        if let Some(calls) = c.pointer("/message/tool_calls").and_then(|t| t.as_array()) {
+            for call in calls {
+                if let Some(args) = call.pointer("/function/arguments").and_then(|a| a.as_str()) {
+                    out.push_str(args);
+                }
+            }
+        }
+    }
+    out
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_usage_from_json_and_stream() {
+        let chat = json!({"usage": {"prompt_tokens": 12, "completion_tokens": 5, "total_tokens": 17}});
+        assert_eq!(
+            TokenUsage::from_json(&chat),
+            Some(TokenUsage { prompt_tokens: 12, completion_tokens: 5 })
+        );
+        let responses = json!({"type": "response.completed", "response": {"usage": {"input_tokens": 3, "output_tokens": 4}}});
+        assert_eq!(TokenUsage::from_json(&responses).map(|u| u.total_tokens()), Some(7));
+        assert_eq!(TokenUsage::from_json(&json!({"usage": null})), None);
+
+        let mut body = json!({"stream": true});
+        assert!(request_stream_usage(&mut body));
+        assert_eq!(body["stream_options"]["include_usage"], true);
+        assert!(!request_stream_usage(&mut body));
+        assert!(!request_stream_usage(&mut json!({"stream": false})));
+
+        let mut s = SseUsageScanner::default();
+        s.push(b"data: {\"id\":\"c1\",\"model\":\"m\",\"choices\":[{\"delta\":{\"content\":\"Hel\"}}]}\n\ndata: {\"id\":\"c1\",\"choices\":[{\"de");
+        s.push(b"lta\":{\"content\":\"lo\"}}]}\n\n");
+        assert_eq!(s.text(), "Hello");
+        assert_eq!(s.usage(), None);
+        let ev = s.usage_event(TokenUsage { prompt_tokens: 1, completion_tokens: 2 });
+        assert!(ev.starts_with("data: ") && ev.contains("\"total_tokens\":3"));
+        assert!(ev.contains("\"id\":\"c1\"") && ev.contains("\"model\":\"m\""));
+        s.push(b"data: {\"id\":\"c1\",\"choices\":[],\"usage\":{\"prompt_tokens\":9,\"completion_tokens\":2}}\n\ndata: [DONE]\n\n");
+        assert_eq!(s.usage(), Some(TokenUsage { prompt_tokens: 9, completion_tokens: 2 }));
+    }
+
+    #[test]
+    fn test_usage_chunk_filter() {
+        assert!(is_usage_only_chunk(&json!({"choices": [], "usage": {"prompt_tokens": 1}})));
+        assert!(!is_usage_only_chunk(&json!({"choices": [{"delta": {}}], "usage": {"prompt_tokens": 1}})));
+        assert!(!is_usage_only_chunk(&json!({"choices": [], "usage": null})));
+
+        let mut f = UsageChunkFilter::default();
+        let content = "data: {\"choices\":[{\"delta\":{\"content\":\"hi\"}}],\"usage\":null}\n\n";
+        let usage = "data: {\"choices\":[],\"usage\":{\"prompt_tokens\":9,\"completion_tokens\":2}}\r\n\r\n";
+        let mut out = f.push(&content.as_bytes()[..10]);
+        assert!(out.is_empty());
+        out.extend(f.push(&content.as_bytes()[10..]));
+        out.extend(f.push(&usage.as_bytes()[..20]));
+        out.extend(f.push(&usage.as_bytes()[20..]));
+        out.extend(f.push(b"data: [DONE]\n\n"));
+        out.extend(f.push(b"data: trailing"));
+        out.extend(f.finish());
+        assert_eq!(
+            String::from_utf8(out).unwrap(),
+            format!("{content}data: [DONE]\n\ndata: trailing")
+        );
+    }
+}
@@ -15,6 +15,7 @@ pub struct Args {
     #[arg(long, env = "NEBULA_GATEWAY_LOG_PATH", default_value = "/tmp/nebula-gateway.log")]
     pub log_path: String,
 
+    /// Model for `/v1/responses` requests that don't name one.
     #[arg(long, env = "NEBULA_ENGINE_MODEL")]
     pub engine_model: Option<String>,
 
@@ -30,6 +31,91 @@ pub struct Args {
     #[arg(long, env = "NEBULA_LOG_FORMAT", default_value = "text")]
     pub log_format: String,
 
+    /// Backend for stored Responses (`store: true`): "etcd" (default) or "memory".
+    #[arg(long, env = "NEBULA_GATEWAY_RESPONSE_STORE", default_value = "etcd")]
+    pub response_store: String,
+
+    /// How long stored Responses are kept, in seconds (0 = forever).
+    #[arg(long, env = "NEBULA_GATEWAY_RESPONSE_TTL_SECS", default_value_t = 30 * 24 * 3600)]
+    pub response_ttl_secs: u64,
+
+    /// How often token usage is flushed to the quota ledger and quota totals
+    /// are refreshed, in milliseconds.
+    #[arg(long, env = "NEBULA_GATEWAY_QUOTA_SYNC_MS", default_value_t = 1000)]
+    pub quota_sync_ms: u64,
+
+    /// Where request rate limit buckets are kept: `meta` (shared by all
+    /// replicas) or `local` (per replica).
+    #[arg(long, env = "NEBULA_GATEWAY_RATE_LIMIT_BACKEND", default_value = "meta")]
+    pub rate_limit_backend: String,
+
+    /// Audit sink: "file", "xtrace", "both" or "none". Defaults to "both" when
+    /// xtrace is configured and "file" otherwise.
+    #[arg(long, env = "NEBULA_GATEWAY_AUDIT_SINK")]
+    pub audit_sink: Option<String>,
+
+    /// Directory for the audit file sink and the sinks' disk spools.
+    #[arg(long, env = "NEBULA_GATEWAY_AUDIT_DIR", default_value = "/tmp/nebula-audit")]
+    pub audit_dir: String,
+
+    /// Size at which the audit file is rotated, in MiB.
+    #[arg(long, env = "NEBULA_GATEWAY_AUDIT_MAX_FILE_MB", default_value_t = 64)]
+    pub audit_max_file_mb: u64,
+
+    /// Rotated audit files to keep.
+    #[arg(long, env = "NEBULA_GATEWAY_AUDIT_MAX_FILES", default_value_t = 10)]
+    pub audit_max_files: usize,
+
+    /// Longest a request may run, in seconds. Caps `x-request-timeout` and API
+    /// key defaults, and is the timeout for requests that set neither.
+    #[arg(long, env = "NEBULA_GATEWAY_MAX_REQUEST_TIMEOUT_SECS", default_value_t = 300)]
+    pub max_request_timeout_secs: u64,
+
+    /// Models whose `temperature: 0` responses are cached, comma-separated; a
+    /// trailing `*` matches a prefix. Empty disables the cache.
+    #[arg(long, env = "NEBULA_GATEWAY_CACHE_MODELS", default_value = "")]
+    pub cache_models: String,
+
+    /// How long cached responses are served, in seconds.
+    #[arg(long, env = "NEBULA_GATEWAY_CACHE_TTL_SECS", default_value_t = 300)]
+    pub cache_ttl_secs: u64,
+
+    /// Cached responses kept per replica; the oldest are evicted first.
+    #[arg(long, env = "NEBULA_GATEWAY_CACHE_MAX_ENTRIES", default_value_t = 10_000)]
+    pub cache_max_entries: usize,
+
+    /// Embeddings model (served through the router) for semantic cache
+    /// lookups. Unset keeps the cache exact-match only.
+    #[arg(long, env = "NEBULA_GATEWAY_CACHE_SEMANTIC_MODEL")]
+    pub cache_semantic_model: Option<String>,
+
+    /// Minimum cosine similarity for a semantic cache hit.
+    #[arg(long, env = "NEBULA_GATEWAY_CACHE_SIMILARITY_THRESHOLD", default_value_t = 0.95)]
+    pub cache_similarity_threshold: f32,
+
+    /// Directory for `/v1/files` uploads and batch results. Replicas must
+    /// share it, e.g. on a network mount.
+    #[arg(long, env = "NEBULA_GATEWAY_FILES_DIR", default_value = "/tmp/nebula-files")]
+    pub files_dir: String,
+
+    /// Largest file accepted by `/v1/files`, in MiB.
+    #[arg(long, env = "NEBULA_GATEWAY_MAX_FILE_MB", default_value_t = 200)]
+    pub max_file_mb: usize,
+
+    /// Requests a running batch keeps in flight.
+    #[arg(long, env = "NEBULA_GATEWAY_BATCH_CONCURRENCY", default_value_t = 8)]
+    pub batch_concurrency: usize,
+
+    /// Bearer token for the gateway's own router calls (batch requests), when
+    /// the router requires auth.
+    #[arg(long, env = "NEBULA_GATEWAY_ROUTER_TOKEN")]
+    pub router_token: Option<String>,
+
+    /// JSON file of guardrail policies applied to requests and responses.
+    /// Unset disables guardrails.
+    #[arg(long, env = "NEBULA_GATEWAY_GUARDRAILS_FILE")]
+    pub guardrails_file: Option<String>,
+
     /// BFF service URL for v2 API proxy.
     #[arg(long, env = "NEBULA_BFF_URL", default_value = "http://127.0.0.1:18090")]
     pub bff_url: String,
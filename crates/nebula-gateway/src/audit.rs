@@ -1,132 +1,279 @@
+use std::net::SocketAddr;
+use std::path::PathBuf;
+use std::sync::atomic::{AtomicBool, Ordering};
 use std::sync::Arc;
-use std::time::{Duration, Instant};
+use std::time::Instant;
 
+use anyhow::Result;
 use axum::{
     body::Body,
-    extract::State,
-    http::Request,
+    extract::{ConnectInfo, State},
+    http::{HeaderValue, Request},
     middleware::Next,
     response::Response,
 };
-use chrono::Utc;
-use serde::Deserialize;
-use tokio::sync::mpsc;
+use chrono::{DateTime, Utc};
+use futures_util::StreamExt;
+use nebula_common::TokenUsage;
+use serde::{Deserialize, Serialize};
 use uuid::Uuid;
 
+use crate::audit_sink::{FileSink, SinkHandle, XtraceSink};
 use crate::auth::AuthContext;
+use crate::guardrails::GuardrailEvent;
+use crate::metrics::Metrics;
 use crate::state::AppState;
 
+pub const REQUEST_ID: &str = "x-request-id";
+
 /// A single audit log entry capturing who/when/what/result.
-#[derive(Debug, Clone, serde::Serialize)]
+#[derive(Debug, Clone, Serialize, Deserialize)]
 pub struct AuditEntry {
     pub id: String,
+    /// `x-request-id` of the request, generated when the client sent none.
+    #[serde(default)]
+    pub request_id: String,
     pub timestamp: chrono::DateTime<Utc>,
+    /// API key owner, or the non-secret label of a bearer token.
     pub principal: String,
     pub role: String,
+    #[serde(default)]
+    pub tenant: Option<String>,
+    #[serde(default)]
+    pub client_ip: Option<String>,
     pub method: String,
     pub path: String,
     pub status: u16,
+    /// Why the request failed: the error code or message of an error
+    /// response, or `client_disconnected` for abandoned streams.
+    #[serde(default)]
+    pub reason: Option<String>,
     pub latency_ms: u64,
+    /// Model of inference requests, and the token usage of those that reported it.
+    pub model: Option<String>,
+    pub prompt_tokens: Option<u64>,
+    pub completion_tokens: Option<u64>,
+    /// Guardrail actions taken on the request or its response.
+    #[serde(default, skip_serializing_if = "Vec::is_empty")]
+    pub guardrails: Vec<GuardrailEvent>,
+}
+
+impl AuditEntry {
+    /// Fills in the model, token usage and guardrail actions left in `usage`.
+    pub fn record(&mut self, usage: &UsageRecorder) {
+        let recorded = usage.take();
+        self.model = recorded.model;
+        if let Some((model, usage)) = recorded.usage {
+            self.model = Some(model);
+            self.prompt_tokens = Some(usage.prompt_tokens);
+            self.completion_tokens = Some(usage.completion_tokens);
+        }
+        self.guardrails = recorded.guardrails;
+    }
+}
+
+/// Request extension where inference handlers leave the model, token usage
+/// and guardrail actions for the request's audit entry. Streaming handlers
+/// fill it when the stream ends.
+#[derive(Debug, Clone, Default)]
+pub struct UsageRecorder(Arc<std::sync::Mutex<Recorded>>);
+
+#[derive(Debug, Default)]
+struct Recorded {
+    model: Option<String>,
+    usage: Option<(String, TokenUsage)>,
+    guardrails: Vec<GuardrailEvent>,
 }
 
-/// Background writer that batches audit entries and sends them to xtrace.
+impl UsageRecorder {
+    /// The model of a request that may not report token usage (e.g. audio).
+    pub fn record_model(&self, model: &str) {
+        if let Ok(mut slot) = self.0.lock() {
+            slot.model = Some(model.to_string());
+        }
+    }
+
+    pub fn record(&self, model: &str, usage: TokenUsage) {
+        if let Ok(mut slot) = self.0.lock() {
+            slot.usage = Some((model.to_string(), usage));
+        }
+    }
+
+    pub fn record_guardrails(&self, events: &[GuardrailEvent]) {
+        if let Ok(mut slot) = self.0.lock() {
+            slot.guardrails.extend_from_slice(events);
+        }
+    }
+
+    fn take(&self) -> Recorded {
+        self.0
+            .lock()
+            .map(|mut slot| std::mem::take(&mut *slot))
+            .unwrap_or_default()
+    }
+}
+
+/// Sends the audit entry when dropped, i.e. once the response body has been
+/// fully sent or the client went away.
+struct PendingEntry {
+    writer: Arc<AuditWriter>,
+    entry: Option<AuditEntry>,
+    usage: UsageRecorder,
+    start: Instant,
+    /// Cleared while a streamed body is still being sent.
+    completed: Arc<AtomicBool>,
+}
+
+impl Drop for PendingEntry {
+    fn drop(&mut self) {
+        let Some(mut entry) = self.entry.take() else {
+            return;
+        };
+        entry.latency_ms = self.start.elapsed().as_millis() as u64;
+        entry.record(&self.usage);
+        if !self.completed.load(Ordering::Relaxed) && entry.reason.is_none() {
+            entry.reason = Some("client_disconnected".to_string());
+        }
+        self.writer.send(entry);
+    }
+}
+
+/// Where audit entries go.
+#[derive(Debug, Clone)]
+pub struct AuditConfig {
+    /// `file`, `xtrace`, `both` or `none`. Defaults to `both` when xtrace is
+    /// configured and `file` otherwise.
+    pub sink: Option<String>,
+    pub dir: PathBuf,
+    pub max_file_bytes: u64,
+    pub max_files: usize,
+    pub xtrace_url: Option<String>,
+    pub xtrace_token: Option<String>,
+}
+
+/// Fans audit entries out to the configured sinks.
 pub struct AuditWriter {
-    tx: mpsc::Sender<AuditEntry>,
+    sinks: Vec<SinkHandle>,
+    file: Option<Arc<FileSink>>,
 }
 
 impl AuditWriter {
-    /// Spawn a background task that drains audit entries and writes them to xtrace.
-    /// Returns `None` if xtrace is not configured.
-    pub fn spawn(xtrace_url: Option<&str>, xtrace_token: Option<&str>) -> Option<Arc<Self>> {
-        let (url, token) = match (xtrace_url, xtrace_token) {
-            (Some(u), Some(t)) if !u.is_empty() && !t.is_empty() => (u.to_string(), t.to_string()),
-            _ => return None,
+    /// Spawn a worker per configured sink. Returns `None` when auditing is
+    /// turned off.
+    pub fn spawn(config: &AuditConfig, metrics: Arc<Metrics>) -> Result<Option<Arc<Self>>> {
+        let xtrace = match (config.xtrace_url.as_deref(), config.xtrace_token.as_deref()) {
+            (Some(u), Some(t)) if !u.is_empty() && !t.is_empty() => Some((u, t)),
+            _ => None,
+        };
+        let sink = config
+            .sink
+            .as_deref()
+            .unwrap_or(if xtrace.is_some() { "both" } else { "file" });
+        let (use_file, use_xtrace) = match sink {
+            "none" => return Ok(None),
+            "file" => (true, false),
+            "xtrace" => (false, true),
+            "both" => (true, true),
+            other => anyhow::bail!("unknown audit sink '{other}', expected file, xtrace, both or none"),
         };
 
-        let http = reqwest::Client::builder()
-            .timeout(Duration::from_secs(10))
-            .build()
-            .ok()?;
+        let mut sinks = Vec::new();
+        let mut file = None;
+        if use_file {
+            let sink = Arc::new(FileSink::new(config.dir.clone(), config.max_file_bytes, config.max_files));
+            sinks.push(SinkHandle::spawn(sink.clone(), &config.dir, metrics.clone()));
+            file = Some(sink);
+        }
+        if use_xtrace {
+            let Some((url, token)) = xtrace else {
+                anyhow::bail!("audit sink '{sink}' needs OBSERVE_URL and OBSERVE_TOKEN");
+            };
+            let sink = Arc::new(XtraceSink::new(url, token)?);
+            sinks.push(SinkHandle::spawn(sink, &config.dir, metrics.clone()));
+        }
 
-        let (tx, rx) = mpsc::channel::<AuditEntry>(4096);
+        tracing::info!(sink=%sink, dir=%config.dir.display(), "audit logger enabled");
+        Ok(Some(Arc::new(Self { sinks, file })))
+    }
 
-        tokio::spawn(audit_worker(http, url, token, rx));
+    pub fn send(&self, entry: AuditEntry) {
+        for sink in &self.sinks {
+            sink.send(entry.clone());
+        }
+    }
 
-        tracing::info!("audit logger enabled, writing to xtrace");
-        Some(Arc::new(Self { tx }))
+    /// The local file sink, if configured; it backs the audit log listing.
+    pub fn file(&self) -> Option<&FileSink> {
+        self.file.as_deref()
     }
+}
 
-    pub fn send(&self, entry: AuditEntry) {
-        // Fire-and-forget; drop if channel is full.
-        let _ = self.tx.try_send(entry);
+/// Client address: the first `x-forwarded-for` hop, else the peer address.
+fn client_ip(req: &Request<Body>) -> Option<String> {
+    req.headers()
+        .get("x-forwarded-for")
+        .and_then(|v| v.to_str().ok())
+        .and_then(|v| v.split(',').next())
+        .map(|ip| ip.trim().to_string())
+        .filter(|ip| !ip.is_empty())
+        .or_else(|| {
+            req.extensions()
+                .get::<ConnectInfo<SocketAddr>>()
+                .map(|ConnectInfo(addr)| addr.ip().to_string())
+        })
+}
+
+/// Error responses are small; larger bodies are not inspected.
+const MAX_ERROR_BODY_BYTES: usize = 64 * 1024;
+
+/// The reason carried by an error response body: its OpenAI/Anthropic error
+/// code, type or message, or the text of a plain error.
+fn error_reason(body: &[u8]) -> Option<String> {
+    match serde_json::from_slice::<serde_json::Value>(body) {
+        Ok(v) => json_error_reason(&v),
+        Err(_) => std::str::from_utf8(body).ok().and_then(|s| truncated_reason(s.trim())),
     }
 }
 
-async fn audit_worker(
-    http: reqwest::Client,
-    base_url: String,
-    token: String,
-    mut rx: mpsc::Receiver<AuditEntry>,
-) {
-    let ingest_url = format!("{}/v1/l/batch", base_url.trim_end_matches('/'));
-    let mut buf: Vec<AuditEntry> = Vec::with_capacity(64);
-
-    loop {
-        // Wait for the first entry.
-        let entry = match rx.recv().await {
-            Some(e) => e,
-            None => break, // channel closed
-        };
-        buf.push(entry);
+/// The reason carried by the `error` object of a JSON error body.
+pub fn json_error_reason(body: &serde_json::Value) -> Option<String> {
+    let err = &body["error"];
+    let reason = ["code", "type", "message"]
+        .iter()
+        .find_map(|k| err[*k].as_str())
+        .or_else(|| err.as_str())?;
+    truncated_reason(reason)
+}
 
-        // Drain any additional entries already in the channel (up to 64).
-        while buf.len() < 64 {
-            match rx.try_recv() {
-                Ok(e) => buf.push(e),
-                Err(_) => break,
-            }
-        }
+fn truncated_reason(reason: &str) -> Option<String> {
+    (!reason.is_empty()).then(|| reason.chars().take(200).collect())
+}
 
-        // Send each entry to xtrace as a trace via batch ingest.
-        for entry in buf.drain(..) {
-            let body = serde_json::json!({
-                "trace": {
-                    "id": entry.id,
-                    "timestamp": entry.timestamp.to_rfc3339(),
-                    "name": format!("{} {}", entry.method, entry.path),
-                    "input": { "method": entry.method, "path": entry.path },
-                    "output": { "status": entry.status },
-                    "userId": entry.principal,
-                    "metadata": {
-                        "role": entry.role,
-                        "latency_ms": entry.latency_ms,
-                        "status": entry.status,
-                    },
-                    "tags": ["audit", format!("role:{}", entry.role)],
-                    "environment": "production",
-                    "latency": entry.latency_ms as f64 / 1000.0,
-                },
-                "observations": [],
-            });
-
-            let res = http
-                .post(&ingest_url)
-                .bearer_auth(&token)
-                .json(&body)
-                .send()
-                .await;
-
-            if let Err(e) = res {
-                tracing::warn!(error=%e, "failed to send audit log to xtrace");
-            }
+/// Buffer a small error response to read its reason.
+async fn with_error_reason(resp: Response) -> (Response, Option<String>) {
+    let small = resp
+        .headers()
+        .get(axum::http::header::CONTENT_LENGTH)
+        .and_then(|v| v.to_str().ok())
+        .and_then(|v| v.parse::<usize>().ok())
+        .is_some_and(|n| n <= MAX_ERROR_BODY_BYTES);
+    if resp.status().as_u16() < 400 || !small {
+        return (resp, None);
+    }
+    let (parts, body) = resp.into_parts();
+    match axum::body::to_bytes(body, MAX_ERROR_BODY_BYTES).await {
+        Ok(bytes) => {
+            let reason = error_reason(&bytes);
+            (Response::from_parts(parts, Body::from(bytes)), reason)
         }
+        Err(e) => (Response::from_parts(parts, Body::empty()), Some(e.to_string())),
     }
 }
 
 /// Axum middleware that records audit log entries after each request.
 pub async fn audit_middleware(
     State(st): State<AppState>,
-    req: Request<Body>,
+    mut req: Request<Body>,
     next: Next,
 ) -> Response {
     let writer = match &st.audit {
@@ -134,40 +281,97 @@ pub async fn audit_middleware(
         None => return next.run(req).await,
     };
 
+    let request_id = match req.headers().get(REQUEST_ID).and_then(|v| v.to_str().ok()) {
+        Some(id) if !id.is_empty() => id.to_string(),
+        _ => {
+            let id = format!("req_{}", Uuid::new_v4());
+            if let Ok(v) = HeaderValue::from_str(&id) {
+                req.headers_mut().insert(REQUEST_ID, v);
+            }
+            id
+        }
+    };
     let method = req.method().to_string();
     let path = req.uri().path().to_string();
+    let client_ip = client_ip(&req);
     let ctx = req.extensions().get::<AuthContext>().cloned();
+    let usage = UsageRecorder::default();
+    req.extensions_mut().insert(usage.clone());
 
     let start = Instant::now();
     let resp = next.run(req).await;
-    let elapsed = start.elapsed();
+    let (mut resp, reason) = with_error_reason(resp).await;
+    if let Ok(v) = HeaderValue::from_str(&request_id) {
+        resp.headers_mut().insert(REQUEST_ID, v);
+    }
 
-    let (principal, role) = match ctx {
+    let (principal, role, tenant) = match ctx {
         Some(c) => {
             let role_str = match c.role {
                 crate::auth::Role::Admin => "admin",
                 crate::auth::Role::Operator => "operator",
                 crate::auth::Role::Viewer => "viewer",
             };
-            (c.principal, role_str.to_string())
+            let principal = match c.api_key.as_ref() {
+                Some(_) => c.principal.clone(),
+                None => c.principal_label(),
+            };
+            (principal, role_str.to_string(), c.tenant)
         }
-        None => ("anonymous".to_string(), "none".to_string()),
+        None => ("anonymous".to_string(), "none".to_string(), None),
     };
 
     let entry = AuditEntry {
         id: Uuid::new_v4().to_string(),
+        request_id,
         timestamp: Utc::now(),
         principal,
         role,
+        tenant,
+        client_ip,
         method,
         path,
         status: resp.status().as_u16(),
-        latency_ms: elapsed.as_millis() as u64,
+        reason,
+        latency_ms: 0,
+        model: None,
+        prompt_tokens: None,
+        completion_tokens: None,
+        guardrails: Vec::new(),
+    };
+    let completed = Arc::new(AtomicBool::new(true));
+    let pending = PendingEntry {
+        writer,
+        entry: Some(entry),
+        usage,
+        start,
+        completed: completed.clone(),
     };
 
-    writer.send(entry);
+    let is_sse = resp
+        .headers()
+        .get(axum::http::header::CONTENT_TYPE)
+        .and_then(|v| v.to_str().ok())
+        .is_some_and(|s| s.contains("text/event-stream"));
+    if !is_sse {
+        drop(pending);
+        return resp;
+    }
 
-    resp
+    // Streams report usage at the end; hold the entry until the body is done.
+    completed.store(false, Ordering::Relaxed);
+    let (parts, body) = resp.into_parts();
+    let stream = body
+        .into_data_stream()
+        .map(move |chunk| {
+            let _ = &pending;
+            chunk
+        })
+        .chain(futures_util::stream::once(async move {
+            completed.store(true, Ordering::Relaxed);
+        })
+        .filter_map(|()| async { None }));
+    Response::from_parts(parts, Body::from_stream(stream))
 }
 
 /// Query parameters for the audit log listing endpoint.
@@ -179,3 +383,19 @@ pub struct AuditLogQuery {
     pub from: Option<String>,
     pub to: Option<String>,
 }
+
+/// Optional lower and upper timestamp bounds.
+pub type TimeRange = (Option<DateTime<Utc>>, Option<DateTime<Utc>>);
+
+impl AuditLogQuery {
+    /// The `from`/`to` bounds, which must be RFC 3339 timestamps.
+    pub fn time_range(&self) -> Result<TimeRange, String> {
+        let parse = |s: &Option<String>| {
+            s.as_deref()
+                .map(|t| DateTime::parse_from_rfc3339(t).map(|t| t.with_timezone(&Utc)))
+                .transpose()
+                .map_err(|e| format!("invalid timestamp: {e}; expected RFC 3339"))
+        };
+        Ok((parse(&self.from)?, parse(&self.to)?))
+    }
+}
@@ -0,0 +1,547 @@
+//! Destinations for audit entries.
+//!
+//! Every sink gets its own queue and worker. An entry that doesn't fit in a
+//! sink's queue, or that the sink fails to write, is appended to that sink's
+//! spool file on disk and retried by the worker later, so a slow or
+//! unreachable sink delays entries instead of losing them. Only when the spool
+//! itself is full are entries dropped.
+
+use std::fs::OpenOptions;
+use std::io::Write;
+use std::path::{Path, PathBuf};
+use std::sync::atomic::Ordering;
+use std::sync::{Arc, Mutex};
+use std::time::Duration;
+
+use anyhow::{Context, Result};
+use async_trait::async_trait;
+use chrono::{DateTime, Utc};
+use tokio::io::AsyncWriteExt;
+use tokio::sync::mpsc;
+
+use crate::audit::{AuditEntry, AuditLogQuery};
+use crate::metrics::Metrics;
+
+const QUEUE_CAPACITY: usize = 4096;
+const BATCH_SIZE: usize = 64;
+/// How often spooled entries are retried when no new entries arrive.
+const RETRY_INTERVAL: Duration = Duration::from_secs(5);
+const MAX_SPOOL_BYTES: u64 = 256 * 1024 * 1024;
+const CURRENT_FILE: &str = "audit.jsonl";
+
+#[async_trait]
+pub trait AuditSink: Send + Sync {
+    fn name(&self) -> &'static str;
+
+    /// Write a batch of entries. Entries of a failed batch are retried, so a
+    /// sink may see an entry more than once.
+    async fn write(&self, entries: &[AuditEntry]) -> Result<()>;
+}
+
+/// Sends entries to xtrace as traces. Retried entries keep their trace id.
+pub struct XtraceSink {
+    http: reqwest::Client,
+    ingest_url: String,
+    token: String,
+}
+
+impl XtraceSink {
+    pub fn new(base_url: &str, token: &str) -> Result<Self> {
+        let http = reqwest::Client::builder()
+            .timeout(Duration::from_secs(10))
+            .build()?;
+        Ok(Self {
+            http,
+            ingest_url: format!("{}/v1/l/batch", base_url.trim_end_matches('/')),
+            token: token.to_string(),
+        })
+    }
+}
+
+#[async_trait]
+impl AuditSink for XtraceSink {
+    fn name(&self) -> &'static str {
+        "xtrace"
+    }
+
+    async fn write(&self, entries: &[AuditEntry]) -> Result<()> {
+        for entry in entries {
+            let body = serde_json::json!({
+                "trace": {
+                    "id": entry.id,
+                    "timestamp": entry.timestamp.to_rfc3339(),
+                    "name": format!("{} {}", entry.method, entry.path),
+                    "input": { "method": entry.method, "path": entry.path },
+                    "output": {
+                        "status": entry.status,
+                        "reason": entry.reason,
+                        "prompt_tokens": entry.prompt_tokens,
+                        "completion_tokens": entry.completion_tokens,
+                    },
+                    "userId": entry.principal,
+                    "metadata": {
+                        "request_id": entry.request_id,
+                        "role": entry.role,
+                        "tenant": entry.tenant,
+                        "client_ip": entry.client_ip,
+                        "latency_ms": entry.latency_ms,
+                        "status": entry.status,
+                        "reason": entry.reason,
+                        "model": entry.model,
+                        "prompt_tokens": entry.prompt_tokens,
+                        "completion_tokens": entry.completion_tokens,
+                        "guardrails": entry.guardrails,
+                    },
+                    "tags": ["audit", format!("role:{}", entry.role)],
+                    "environment": "production",
+                    "latency": entry.latency_ms as f64 / 1000.0,
+                },
+                "observations": [],
+            });
+
+            self.http
+                .post(&self.ingest_url)
+                .bearer_auth(&self.token)
+                .json(&body)
+                .send()
+                .await?
+                .error_for_status()?;
+        }
+        Ok(())
+    }
+}
+
+/// Appends entries as JSON lines to `audit.jsonl` in a directory, rotating it
+/// to `audit-{unix_ms}.jsonl` when it grows past `max_file_bytes` and keeping
+/// at most `max_files` rotated files.
+pub struct FileSink {
+    dir: PathBuf,
+    max_file_bytes: u64,
+    max_files: usize,
+    file: tokio::sync::Mutex<Option<(tokio::fs::File, u64)>>,
+}
+
+impl FileSink {
+    pub fn new(dir: PathBuf, max_file_bytes: u64, max_files: usize) -> Self {
+        Self {
+            dir,
+            max_file_bytes,
+            max_files,
+            file: tokio::sync::Mutex::new(None),
+        }
+    }
+
+    async fn open(&self) -> Result<(tokio::fs::File, u64)> {
+        tokio::fs::create_dir_all(&self.dir).await?;
+        let path = self.dir.join(CURRENT_FILE);
+        let file = tokio::fs::OpenOptions::new()
+            .create(true)
+            .append(true)
+            .open(&path)
+            .await
+            .with_context(|| format!("open {}", path.display()))?;
+        let size = file.metadata().await?.len();
+        Ok((file, size))
+    }
+
+    async fn rotate(&self) -> Result<()> {
+        // Names must sort by age even for rotations within one millisecond, so
+        // never reuse or go below the newest rotated file's timestamp.
+        let newest = self.rotated_files().await?.first().and_then(|p| rotated_ms(p));
+        let now = Utc::now().timestamp_millis();
+        let ms = newest.map_or(now, |n| now.max(n + 1));
+        let rotated = self.dir.join(format!("audit-{ms:013}.jsonl"));
+        tokio::fs::rename(self.dir.join(CURRENT_FILE), &rotated).await?;
+        let mut old = self.rotated_files().await?;
+        while old.len() > self.max_files {
+            let oldest = old.remove(old.len() - 1);
+            tokio::fs::remove_file(&oldest).await?;
+        }
+        Ok(())
+    }
+
+    /// Rotated files, newest first.
+    async fn rotated_files(&self) -> Result<Vec<PathBuf>> {
+        let mut files = Vec::new();
+        let mut dir = match tokio::fs::read_dir(&self.dir).await {
+            Ok(d) => d,
+            Err(e) if e.kind() == std::io::ErrorKind::NotFound => return Ok(files),
+            Err(e) => return Err(e.into()),
+        };
+        while let Some(entry) = dir.next_entry().await? {
+            let name = entry.file_name();
+            let name = name.to_string_lossy();
+            if name.starts_with("audit-") && name.ends_with(".jsonl") {
+                files.push(entry.path());
+            }
+        }
+        files.sort_by(|a, b| b.cmp(a));
+        Ok(files)
+    }
+
+    /// Entries matching `query`, newest first, paged like the xtrace listing.
+    pub async fn query(
+        &self,
+        query: &AuditLogQuery,
+        from: Option<DateTime<Utc>>,
+        to: Option<DateTime<Utc>>,
+    ) -> Result<serde_json::Value> {
+        let page = query.page.unwrap_or(1).max(1) as usize;
+        let limit = query.limit.unwrap_or(50).clamp(1, 200) as usize;
+        let mut skip = (page - 1) * limit;
+
+        let mut files = vec![self.dir.join(CURRENT_FILE)];
+        files.extend(self.rotated_files().await?);
+
+        let mut data = Vec::new();
+        'files: for path in files {
+            let content = match tokio::fs::read_to_string(&path).await {
+                Ok(c) => c,
+                Err(e) if e.kind() == std::io::ErrorKind::NotFound => continue,
+                Err(e) => return Err(e.into()),
+            };
+            for line in content.lines().rev() {
+                let Ok(entry) = serde_json::from_str::<AuditEntry>(line) else {
+                    continue;
+                };
+                let matches = query.user_id.as_ref().is_none_or(|u| *u == entry.principal)
+                    && from.is_none_or(|f| entry.timestamp >= f)
+                    && to.is_none_or(|t| entry.timestamp <= t);
+                if !matches {
+                    continue;
+                }
+                if skip > 0 {
+                    skip -= 1;
+                    continue;
+                }
+                data.push(entry);
+                if data.len() == limit {
+                    break 'files;
+                }
+            }
+        }
+
+        Ok(serde_json::json!({
+            "data": data,
+            "meta": { "page": page, "limit": limit, "source": "file" },
+        }))
+    }
+}
+
+/// The timestamp in a rotated file's name.
+fn rotated_ms(path: &Path) -> Option<i64> {
+    path.file_stem()?.to_str()?.strip_prefix("audit-")?.parse().ok()
+}
+
+#[async_trait]
+impl AuditSink for FileSink {
+    fn name(&self) -> &'static str {
+        "file"
+    }
+
+    async fn write(&self, entries: &[AuditEntry]) -> Result<()> {
+        let mut buf = Vec::new();
+        for entry in entries {
+            serde_json::to_writer(&mut buf, entry)?;
+            buf.push(b'\n');
+        }
+
+        let mut guard = self.file.lock().await;
+        if let Some((_, size)) = guard.as_ref() {
+            if *size > 0 && size + buf.len() as u64 > self.max_file_bytes {
+                *guard = None;
+                self.rotate().await?;
+            }
+        }
+        if guard.is_none() {
+            *guard = Some(self.open().await?);
+        }
+        let Some((file, size)) = guard.as_mut() else {
+            return Ok(());
+        };
+        if let Err(e) = file.write_all(&buf).await {
+            // Reopen on the next write.
+            *guard = None;
+            return Err(e.into());
+        }
+        file.flush().await?;
+        *size += buf.len() as u64;
+        Ok(())
+    }
+}
+
+/// On-disk overflow queue for one sink.
+struct Spool {
+    path: PathBuf,
+    lock: Mutex<()>,
+}
+
+impl Spool {
+    fn new(dir: &Path, sink: &str) -> Self {
+        Self {
+            path: dir.join("spool").join(format!("{sink}.jsonl")),
+            lock: Mutex::new(()),
+        }
+    }
+
+    /// Append entries; `false` when they couldn't be kept.
+    fn append(&self, entries: &[AuditEntry]) -> bool {
+        let Ok(_guard) = self.lock.lock() else {
+            return false;
+        };
+        let size = std::fs::metadata(&self.path).map(|m| m.len()).unwrap_or(0);
+        if size >= MAX_SPOOL_BYTES {
+            return false;
+        }
+        let write = || -> std::io::Result<()> {
+            if let Some(parent) = self.path.parent() {
+                std::fs::create_dir_all(parent)?;
+            }
+            let mut file = OpenOptions::new().create(true).append(true).open(&self.path)?;
+            let mut buf = Vec::new();
+            for entry in entries {
+                serde_json::to_writer(&mut buf, entry)?;
+                buf.push(b'\n');
+            }
+            file.write_all(&buf)
+        };
+        match write() {
+            Ok(()) => true,
+            Err(e) => {
+                tracing::warn!(error=%e, path=%self.path.display(), "failed to spool audit entries");
+                false
+            }
+        }
+    }
+
+    /// Remove and return everything spooled so far.
+    fn take(&self) -> Vec<AuditEntry> {
+        let Ok(_guard) = self.lock.lock() else {
+            return Vec::new();
+        };
+        let Ok(content) = std::fs::read_to_string(&self.path) else {
+            return Vec::new();
+        };
+        if let Err(e) = std::fs::remove_file(&self.path) {
+            tracing::warn!(error=%e, path=%self.path.display(), "failed to clear audit spool");
+            return Vec::new();
+        }
+        content
+            .lines()
+            .filter_map(|l| serde_json::from_str(l).ok())
+            .collect()
+    }
+}
+
+/// A sink's queue and spool, held by the writer.
+pub struct SinkHandle {
+    tx: mpsc::Sender<AuditEntry>,
+    spool: Arc<Spool>,
+    metrics: Arc<Metrics>,
+}
+
+impl SinkHandle {
+    pub fn spawn(sink: Arc<dyn AuditSink>, spool_dir: &Path, metrics: Arc<Metrics>) -> Self {
+        let (tx, rx) = mpsc::channel(QUEUE_CAPACITY);
+        let spool = Arc::new(Spool::new(spool_dir, sink.name()));
+        tokio::spawn(sink_worker(sink, spool.clone(), metrics.clone(), rx));
+        Self { tx, spool, metrics }
+    }
+
+    /// Queue an entry, spooling it to disk when the queue is full.
+    pub fn send(&self, entry: AuditEntry) {
+        if let Err(mpsc::error::TrySendError::Full(entry)) = self.tx.try_send(entry) {
+            spool_or_drop(&self.spool, &self.metrics, &[entry]);
+        }
+    }
+}
+
+fn spool_or_drop(spool: &Spool, metrics: &Metrics, entries: &[AuditEntry]) {
+    let counter = if spool.append(entries) {
+        &metrics.audit_spooled
+    } else {
+        &metrics.audit_dropped
+    };
+    counter.fetch_add(entries.len() as u64, Ordering::Relaxed);
+}
+
+async fn deliver(sink: &dyn AuditSink, spool: &Spool, metrics: &Metrics, entries: &[AuditEntry]) -> bool {
+    match sink.write(entries).await {
+        Ok(()) => true,
+        Err(e) => {
+            tracing::warn!(error=%e, sink=%sink.name(), entries=entries.len(), "failed to write audit entries, spooling");
+            spool_or_drop(spool, metrics, entries);
+            false
+        }
+    }
+}
+
+async fn sink_worker(
+    sink: Arc<dyn AuditSink>,
+    spool: Arc<Spool>,
+    metrics: Arc<Metrics>,
+    mut rx: mpsc::Receiver<AuditEntry>,
+) {
+    let mut retry = tokio::time::interval(RETRY_INTERVAL);
+    let mut buf: Vec<AuditEntry> = Vec::with_capacity(BATCH_SIZE);
+
+    loop {
+        tokio::select! {
+            entry = rx.recv() => match entry {
+                Some(e) => buf.push(e),
+                None => break,
+            },
+            _ = retry.tick() => {}
+        }
+        while buf.len() < BATCH_SIZE {
+            match rx.try_recv() {
+                Ok(e) => buf.push(e),
+                Err(_) => break,
+            }
+        }
+
+        let healthy = buf.is_empty() || deliver(sink.as_ref(), &spool, &metrics, &buf).await;
+        buf.clear();
+        if !healthy {
+            continue;
+        }
+        let spooled = spool.take();
+        let mut rest = spooled.as_slice();
+        while !rest.is_empty() {
+            let (batch, tail) = rest.split_at(BATCH_SIZE.min(rest.len()));
+            if !deliver(sink.as_ref(), &spool, &metrics, batch).await {
+                spool_or_drop(&spool, &metrics, tail);
+                break;
+            }
+            rest = tail;
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::sync::atomic::AtomicBool;
+
+    fn entry(i: u32, principal: &str) -> AuditEntry {
+        AuditEntry {
+            id: format!("e{i}"),
+            request_id: format!("req-{i}"),
+            timestamp: Utc::now(),
+            principal: principal.to_string(),
+            role: "operator".to_string(),
+            tenant: None,
+            client_ip: None,
+            method: "POST".to_string(),
+            path: "/v1/chat/completions".to_string(),
+            status: 200,
+            reason: None,
+            latency_ms: 5,
+            model: Some("m".to_string()),
+            prompt_tokens: Some(1),
+            completion_tokens: Some(2),
+            guardrails: Vec::new(),
+        }
+    }
+
+    fn temp_dir() -> PathBuf {
+        std::env::temp_dir().join(format!("nebula-audit-{}", uuid::Uuid::new_v4()))
+    }
+
+    fn query(page: i64, limit: i64, user_id: Option<&str>) -> AuditLogQuery {
+        AuditLogQuery {
+            page: Some(page),
+            limit: Some(limit),
+            user_id: user_id.map(str::to_string),
+            from: None,
+            to: None,
+        }
+    }
+
+    fn ids(page: &serde_json::Value) -> Vec<&str> {
+        page["data"]
+            .as_array()
+            .unwrap()
+            .iter()
+            .map(|e| e["id"].as_str().unwrap())
+            .collect()
+    }
+
+    #[tokio::test]
+    async fn test_file_sink_rotation_and_query() {
+        let dir = temp_dir();
+        // Every write after the first rotates; two rotated files are kept.
+        let sink = FileSink::new(dir.clone(), 1, 2);
+        for i in 0..5 {
+            let principal = if i % 2 == 0 { "alice" } else { "bob" };
+            sink.write(&[entry(i, principal)]).await.unwrap();
+        }
+        assert_eq!(sink.rotated_files().await.unwrap().len(), 2);
+
+        let newest = sink.query(&query(1, 2, None), None, None).await.unwrap();
+        assert_eq!(ids(&newest), ["e4", "e3"]);
+        let older = sink.query(&query(2, 2, None), None, None).await.unwrap();
+        assert_eq!(ids(&older), ["e2"]);
+        let alice = sink.query(&query(1, 10, Some("alice")), None, None).await.unwrap();
+        assert_eq!(ids(&alice), ["e4", "e2"]);
+        let none = sink.query(&query(1, 10, None), Some(Utc::now()), None).await.unwrap();
+        assert!(ids(&none).is_empty());
+
+        std::fs::remove_dir_all(dir).unwrap();
+    }
+
+    /// Fails every write while `failing` is set.
+    #[derive(Default)]
+    struct FlakySink {
+        failing: AtomicBool,
+        written: Mutex<Vec<String>>,
+    }
+
+    #[async_trait]
+    impl AuditSink for FlakySink {
+        fn name(&self) -> &'static str {
+            "flaky"
+        }
+
+        async fn write(&self, entries: &[AuditEntry]) -> Result<()> {
+            if self.failing.load(Ordering::Relaxed) {
+                anyhow::bail!("sink down");
+            }
+            self.written.lock().unwrap().extend(entries.iter().map(|e| e.id.clone()));
+            Ok(())
+        }
+    }
+
+    #[tokio::test]
+    async fn test_failed_writes_are_spooled_and_retried() {
+        let dir = temp_dir();
+        let sink = Arc::new(FlakySink::default());
+        sink.failing.store(true, Ordering::Relaxed);
+        let metrics = Arc::new(Metrics::default());
+        let handle = SinkHandle::spawn(sink.clone(), &dir, metrics.clone());
+
+        handle.send(entry(0, "alice"));
+        handle.send(entry(1, "alice"));
+        let deadline = std::time::Instant::now() + Duration::from_secs(5);
+        while metrics.audit_spooled.load(Ordering::Relaxed) < 2 {
+            assert!(std::time::Instant::now() < deadline, "entries were not spooled");
+            tokio::time::sleep(Duration::from_millis(10)).await;
+        }
+        assert!(dir.join("spool").join("flaky.jsonl").exists());
+
+        // The next successful write drains the spool.
+        sink.failing.store(false, Ordering::Relaxed);
+        handle.send(entry(2, "alice"));
+        while sink.written.lock().unwrap().len() < 3 {
+            assert!(std::time::Instant::now() < deadline, "spool was not retried");
+            tokio::time::sleep(Duration::from_millis(10)).await;
+        }
+        let mut written = sink.written.lock().unwrap().clone();
+        written.sort();
+        assert_eq!(written, ["e0", "e1", "e2"]);
+        assert_eq!(metrics.audit_dropped.load(Ordering::Relaxed), 0);
+        assert!(!dir.join("spool").join("flaky.jsonl").exists());
+
+        std::fs::remove_dir_all(dir).unwrap();
+    }
+}
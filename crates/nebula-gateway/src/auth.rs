@@ -1,7 +1,15 @@
 // Re-export shared auth types from nebula-common.
 pub use nebula_common::auth::{AuthConfig, AuthContext, Role};
 
+use std::sync::Arc;
+use std::time::Duration;
+
 use axum::response::Response;
+use futures_util::StreamExt;
+
+use nebula_common::api_key::{ApiKeyCache, API_KEYS_PREFIX};
+use nebula_common::ApiKey;
+use nebula_meta::MetaStore;
 
 use crate::metrics::Metrics;
 
@@ -16,3 +24,195 @@ pub fn require_role(metrics: &Metrics, ctx: &AuthContext, required: Role) -> Opt
     }
     resp
 }
+
+/// Keep the auth middleware's API keys in step with `/api_keys/`.
+pub async fn api_keys_sync_loop(store: Arc<dyn MetaStore>, keys: ApiKeyCache) {
+    loop {
+        match store.list_prefix(API_KEYS_PREFIX).await {
+            Ok(items) => {
+                let snapshot = items
+                    .into_iter()
+                    .filter_map(|(_k, v, _rev)| serde_json::from_slice::<ApiKey>(&v).ok())
+                    .collect();
+                keys.replace_all(snapshot);
+            }
+            Err(e) => {
+                tracing::warn!(error=%e, "failed to list api keys, will retry");
+                tokio::time::sleep(Duration::from_secs(1)).await;
+                continue;
+            }
+        }
+
+        let mut stream = match store.watch_prefix(API_KEYS_PREFIX, None).await {
+            Ok(s) => s,
+            Err(e) => {
+                tracing::warn!(error=%e, "failed to watch api keys, will retry");
+                tokio::time::sleep(Duration::from_secs(1)).await;
+                continue;
+            }
+        };
+
+        while let Some(ev) = stream.next().await {
+            match ev.value {
+                Some(v) => match serde_json::from_slice::<ApiKey>(&v) {
+                    Ok(key) => keys.upsert(key),
+                    Err(e) => tracing::warn!(key=%ev.key, error=%e, "failed to parse api key"),
+                },
+                None => keys.remove(&ev.key),
+            }
+        }
+
+        tracing::warn!("api keys watch stream ended, reconnecting");
+        tokio::time::sleep(Duration::from_secs(1)).await;
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    use std::collections::HashMap;
+
+    use axum::extract::State;
+    use axum::response::IntoResponse;
+    use axum::routing::get;
+    use axum::{middleware, Extension, Json};
+    use nebula_meta::MemoryMetaStore;
+    use serde_json::{json, Value};
+
+    use crate::state::AppState;
+
+    fn key(owner: &str, role: Role, expires_at_ms: Option<u64>) -> (String, ApiKey) {
+        ApiKey::generate(
+            owner.to_string(),
+            role,
+            Some("acme".to_string()),
+            vec!["llama-*".to_string()],
+            expires_at_ms,
+            None,
+            0,
+        )
+    }
+
+    /// Operator-only endpoint describing the caller.
+    async fn whoami(State(st): State<AppState>, Extension(ctx): Extension<AuthContext>) -> Response {
+        if let Some(resp) = require_role(&st.metrics, &ctx, Role::Operator) {
+            return resp;
+        }
+        Json(json!({
+            "principal": ctx.principal,
+            "tenant": ctx.tenant,
+            "llama": ctx.allows_model("llama-8b"),
+            "qwen": ctx.allows_model("qwen-7b"),
+        }))
+        .into_response()
+    }
+
+    #[tokio::test]
+    async fn test_api_keys_authenticate_by_hash_with_expiry_and_scopes() {
+        let (operator, operator_key) = key("alice", Role::Operator, None);
+        let (viewer, viewer_key) = key("bob", Role::Viewer, None);
+        let (expired, expired_key) = key("carol", Role::Admin, Some(1));
+        let stored_hash = operator_key.key_hash.clone();
+        let keys = ApiKeyCache::default();
+        keys.replace_all(vec![operator_key, viewer_key, expired_key]);
+
+        let mut st = AppState::for_tests("http://127.0.0.1:1");
+        st.auth = AuthConfig {
+            enabled: true,
+            tokens: Arc::new(HashMap::from([("static-token".to_string(), Role::Admin)])),
+            limit_per_minute: 0,
+            api_keys: keys,
+            api_keys_enabled: true,
+            ..st.auth.clone()
+        };
+        let app = axum::Router::new()
+            .route("/v1/whoami", get(whoami))
+            .layer(middleware::from_fn_with_state(
+                st.clone(),
+                nebula_common::auth::auth_middleware::<AppState>,
+            ))
+            .with_state(st.clone());
+        let listener = tokio::net::TcpListener::bind("127.0.0.1:0").await.unwrap();
+        let url = format!("http://{}/v1/whoami", listener.local_addr().unwrap());
+        tokio::spawn(async move { axum::serve(listener, app).await });
+        let client = reqwest::Client::new();
+        let call = |header: &'static str, value: String| {
+            let request = client.get(&url).header(header, value);
+            async move {
+                let resp = request.send().await.unwrap();
+                (resp.status().as_u16(), resp.json::<Value>().await.unwrap())
+            }
+        };
+
+        // The secret is looked up by its hash, from either header.
+        let (status, body) = call("authorization", format!("Bearer {operator}")).await;
+        assert_eq!(status, 200);
+        assert_eq!(body, json!({"principal": "alice", "tenant": "acme", "llama": true, "qwen": false}));
+        assert_eq!(call("x-api-key", operator.clone()).await.0, 200);
+        let (status, body) = call("authorization", format!("Bearer {stored_hash}")).await;
+        assert_eq!((status, body["error"]["code"].as_str()), (401, Some("invalid_api_key")));
+
+        // Static tokens keep working alongside keys, without an allow-list.
+        let (status, body) = call("authorization", "Bearer static-token".to_string()).await;
+        assert_eq!((status, body["qwen"].as_bool()), (200, Some(true)));
+
+        let (status, body) = call("authorization", format!("Bearer {expired}")).await;
+        assert_eq!((status, body["error"]["code"].as_str()), (401, Some("api_key_expired")));
+
+        let (status, body) = call("authorization", format!("Bearer {viewer}")).await;
+        assert_eq!((status, body["error"]["code"].as_str()), (403, Some("permission_denied")));
+        assert_eq!(st.metrics.auth_forbidden.load(std::sync::atomic::Ordering::Relaxed), 1);
+    }
+
+    /// Whether `check` passes within a second.
+    async fn eventually(check: impl Fn() -> bool) -> bool {
+        for _ in 0..100 {
+            if check() {
+                return true;
+            }
+            tokio::time::sleep(Duration::from_millis(10)).await;
+        }
+        false
+    }
+
+    #[tokio::test]
+    async fn test_api_keys_reload_from_the_meta_store() {
+        let store = Arc::new(MemoryMetaStore::new());
+        let put = |key: ApiKey| {
+            let store = store.clone();
+            async move {
+                let value = serde_json::to_vec(&key).unwrap();
+                store.put(&key.storage_key(), value, None).await.unwrap();
+            }
+        };
+        let (first, first_key) = key("alice", Role::Operator, None);
+        let first_storage_key = first_key.storage_key();
+        put(first_key).await;
+        let stored = store.get(&first_storage_key).await.unwrap().unwrap().0;
+        assert!(!String::from_utf8(stored).unwrap().contains(&first));
+
+        let keys = ApiKeyCache::default();
+        tokio::spawn(api_keys_sync_loop(store.clone(), keys.clone()));
+        let owner = |secret: &str| keys.lookup(secret).map(|k| k.owner.clone());
+
+        // Keys present at startup are listed; later ones arrive through the watch.
+        assert!(eventually(|| owner(&first).is_some()).await);
+        let (second, second_key) = key("bob", Role::Viewer, None);
+        let mut added = false;
+        for _ in 0..10 {
+            put(second_key.clone()).await;
+            if eventually(|| owner(&second).is_some()).await {
+                added = true;
+                break;
+            }
+        }
+        assert!(added, "new key was not picked up");
+
+        // Edits and revocations apply without a restart.
+        put(ApiKey { owner: "bobby".to_string(), ..second_key }).await;
+        assert!(eventually(|| owner(&second).as_deref() == Some("bobby")).await);
+        store.delete(&first_storage_key).await.unwrap();
+        assert!(eventually(|| owner(&first).is_none()).await);
+    }
+}
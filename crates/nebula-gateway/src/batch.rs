@@ -0,0 +1,1498 @@
+//! OpenAI Batch API: uploaded JSONL files and the batch jobs that run them.
+//!
+//! File metadata and batch state live in the meta store; file contents are
+//! kept under the files directory, which all replicas must share (a network
+//! mount stands in for an object store). Any replica may run a batch. The
+//! executor claims it with a lease in its meta record and renews the lease
+//! while running, so a batch left behind by a stopped replica is picked up by
+//! another once the lease lapses. Results are appended to the output and
+//! error files as they complete, and a resumed batch skips the `custom_id`s
+//! already written there.
+//!
+//! Requests go to the router with `x-nebula-priority: -1`, so they are shed
+//! before interactive traffic when endpoints are busy, and are retried with
+//! backoff while the router reports it is overloaded. Every request sent is
+//! audited like a direct one, with the principal of the batch's creator and
+//! role `batch`. Each request is admitted
+//! against the creator's token quotas: per-minute limits are waited out, and
+//! once a daily or monthly one is used up the remaining requests fail with
+//! `insufficient_quota` without being sent. Guardrails check each
+//! request and its response as they would the same call made directly.
+//!
+//! A batch created with an API key runs under that key: the key is looked up
+//! again at every heartbeat, and lines stop being sent once it is revoked,
+//! expires or no longer allows the line's model.
+
+use std::cmp::Reverse;
+use std::collections::HashSet;
+use std::path::PathBuf;
+use std::sync::atomic::{AtomicBool, Ordering};
+use std::sync::{Arc, Mutex};
+use std::time::{Duration, Instant};
+
+use anyhow::Result;
+use futures_util::StreamExt;
+use serde::{Deserialize, Serialize};
+use serde_json::{json, Value};
+use tokio::io::AsyncWriteExt;
+
+use nebula_common::api_key::{ApiKey, API_KEYS_PREFIX};
+use nebula_common::execution_context::PRIORITY_HEADER;
+use nebula_common::ratelimit::now_ms;
+use nebula_common::{ApiError, ErrorCode, TokenUsage};
+use nebula_meta::MetaStore;
+
+use crate::audit::{json_error_reason, AuditEntry, AuditWriter, UsageRecorder};
+use crate::guardrails::Guardrails;
+use crate::metrics::Metrics;
+use crate::quota::{QuotaExceeded, QuotaScope, Quotas, Window};
+
+const FILES_PREFIX: &str = "/batches/files/";
+const JOBS_PREFIX: &str = "/batches/jobs/";
+
+/// Endpoints a batch may target.
+pub const BATCH_ENDPOINTS: &[&str] = &["/v1/chat/completions", "/v1/completions", "/v1/embeddings"];
+/// The only supported `completion_window`.
+pub const COMPLETION_WINDOW: &str = "24h";
+const COMPLETION_WINDOW_SECS: u64 = 24 * 3600;
+/// Most requests one input file may hold.
+const MAX_BATCH_REQUESTS: usize = 50_000;
+
+/// How long a claim on a batch lasts without being renewed.
+const LEASE_MS: u64 = 30_000;
+/// How often the running replica renews its lease and publishes progress.
+const HEARTBEAT_INTERVAL: Duration = Duration::from_secs(5);
+/// How often replicas look for batches to run.
+const POLL_INTERVAL: Duration = Duration::from_secs(2);
+/// Attempts per request while the router is overloaded or unreachable.
+const MAX_ATTEMPTS: u32 = 8;
+
+pub fn now_secs() -> u64 {
+    now_ms() / 1000
+}
+
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct FileObject {
+    pub id: String,
+    pub object: String,
+    pub bytes: u64,
+    pub created_at: u64,
+    pub filename: String,
+    pub purpose: String,
+}
+
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct StoredFile {
+    pub file: FileObject,
+    /// Principal label of the uploader.
+    pub owner: String,
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(rename_all = "snake_case")]
+pub enum BatchStatus {
+    Validating,
+    Failed,
+    InProgress,
+    Finalizing,
+    Completed,
+    Expired,
+    Cancelling,
+    Cancelled,
+}
+
+impl BatchStatus {
+    /// Whether the batch still needs an executor.
+    pub fn is_active(self) -> bool {
+        matches!(self, Self::Validating | Self::InProgress | Self::Finalizing | Self::Cancelling)
+    }
+
+    pub fn as_str(self) -> &'static str {
+        match self {
+            Self::Validating => "validating",
+            Self::Failed => "failed",
+            Self::InProgress => "in_progress",
+            Self::Finalizing => "finalizing",
+            Self::Completed => "completed",
+            Self::Expired => "expired",
+            Self::Cancelling => "cancelling",
+            Self::Cancelled => "cancelled",
+        }
+    }
+}
+
+#[derive(Debug, Clone, Copy, Default, Serialize, Deserialize)]
+pub struct RequestCounts {
+    pub total: u64,
+    pub completed: u64,
+    pub failed: u64,
+}
+
+/// The OpenAI Batch object.
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct Batch {
+    pub id: String,
+    pub object: String,
+    pub endpoint: String,
+    pub errors: Option<Value>,
+    pub input_file_id: String,
+    pub completion_window: String,
+    pub status: BatchStatus,
+    pub output_file_id: Option<String>,
+    pub error_file_id: Option<String>,
+    pub created_at: u64,
+    pub in_progress_at: Option<u64>,
+    pub expires_at: Option<u64>,
+    pub finalizing_at: Option<u64>,
+    pub completed_at: Option<u64>,
+    pub failed_at: Option<u64>,
+    pub expired_at: Option<u64>,
+    pub cancelling_at: Option<u64>,
+    pub cancelled_at: Option<u64>,
+    pub request_counts: RequestCounts,
+    pub metadata: Option<Value>,
+}
+
+impl Batch {
+    pub fn new(input_file_id: String, endpoint: String, metadata: Option<Value>) -> Self {
+        let created_at = now_secs();
+        Self {
+            id: format!("batch_{}", uuid::Uuid::new_v4().simple()),
+            object: "batch".to_string(),
+            endpoint,
+            errors: None,
+            input_file_id,
+            completion_window: COMPLETION_WINDOW.to_string(),
+            status: BatchStatus::Validating,
+            output_file_id: None,
+            error_file_id: None,
+            created_at,
+            in_progress_at: None,
+            expires_at: Some(created_at + COMPLETION_WINDOW_SECS),
+            finalizing_at: None,
+            completed_at: None,
+            failed_at: None,
+            expired_at: None,
+            cancelling_at: None,
+            cancelled_at: None,
+            request_counts: RequestCounts::default(),
+            metadata,
+        }
+    }
+
+    /// Output and error file ids, fixed per batch so a resumed run appends
+    /// to the files an earlier run started.
+    fn result_file_ids(&self) -> (String, String) {
+        let suffix = self.id.trim_start_matches("batch_");
+        (format!("file-{suffix}-output"), format!("file-{suffix}-errors"))
+    }
+}
+
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct StoredBatch {
+    pub batch: Batch,
+    /// Principal label of the creator; usage is accounted to it.
+    pub owner: String,
+    pub tenant: Option<String>,
+    /// Id of the API key the batch was created with, if any.
+    #[serde(default)]
+    pub api_key_id: Option<String>,
+    /// Replica running the batch, and when its claim lapses.
+    #[serde(default)]
+    pub worker: Option<String>,
+    #[serde(default)]
+    pub lease_until_ms: u64,
+}
+
+/// Uploaded files and batch jobs.
+pub struct BatchStore {
+    meta: Arc<dyn MetaStore>,
+    dir: PathBuf,
+    pub max_file_bytes: usize,
+}
+
+impl BatchStore {
+    pub fn new(meta: Arc<dyn MetaStore>, dir: PathBuf, max_file_bytes: usize) -> Self {
+        Self { meta, dir, max_file_bytes }
+    }
+
+    pub fn file_path(&self, id: &str) -> PathBuf {
+        self.dir.join(format!("{id}.jsonl"))
+    }
+
+    pub async fn create_file(&self, owner: &str, filename: &str, purpose: &str, data: &[u8]) -> Result<FileObject> {
+        let file = FileObject {
+            id: format!("file-{}", uuid::Uuid::new_v4().simple()),
+            object: "file".to_string(),
+            bytes: data.len() as u64,
+            created_at: now_secs(),
+            filename: filename.to_string(),
+            purpose: purpose.to_string(),
+        };
+        tokio::fs::create_dir_all(&self.dir).await?;
+        tokio::fs::write(self.file_path(&file.id), data).await?;
+        self.put_file(&StoredFile {
+            file: file.clone(),
+            owner: owner.to_string(),
+        })
+        .await?;
+        Ok(file)
+    }
+
+    async fn put_file(&self, stored: &StoredFile) -> Result<()> {
+        let key = format!("{FILES_PREFIX}{}", stored.file.id);
+        self.meta.put(&key, serde_json::to_vec(stored)?, None).await?;
+        Ok(())
+    }
+
+    pub async fn get_file(&self, id: &str) -> Result<Option<StoredFile>> {
+        match self.meta.get(&format!("{FILES_PREFIX}{id}")).await? {
+            Some((raw, _)) => Ok(Some(serde_json::from_slice(&raw)?)),
+            None => Ok(None),
+        }
+    }
+
+    pub async fn list_files(&self) -> Result<Vec<StoredFile>> {
+        let mut files: Vec<StoredFile> = self
+            .meta
+            .list_prefix(FILES_PREFIX)
+            .await?
+            .into_iter()
+            .filter_map(|(_, raw, _)| serde_json::from_slice(&raw).ok())
+            .collect();
+        files.sort_by_key(|f| Reverse(f.file.created_at));
+        Ok(files)
+    }
+
+    /// Delete a file and its contents; `false` when it did not exist.
+    pub async fn delete_file(&self, id: &str) -> Result<bool> {
+        let key = format!("{FILES_PREFIX}{id}");
+        if self.meta.get(&key).await?.is_none() {
+            return Ok(false);
+        }
+        self.meta.delete(&key).await?;
+        if let Err(e) = tokio::fs::remove_file(self.file_path(id)).await {
+            if e.kind() != std::io::ErrorKind::NotFound {
+                return Err(e.into());
+            }
+        }
+        Ok(true)
+    }
+
+    pub async fn create_batch(&self, stored: &StoredBatch) -> Result<()> {
+        let key = format!("{JOBS_PREFIX}{}", stored.batch.id);
+        self.meta.put(&key, serde_json::to_vec(stored)?, None).await?;
+        Ok(())
+    }
+
+    pub async fn get_batch(&self, id: &str) -> Result<Option<StoredBatch>> {
+        match self.meta.get(&format!("{JOBS_PREFIX}{id}")).await? {
+            Some((raw, _)) => Ok(Some(serde_json::from_slice(&raw)?)),
+            None => Ok(None),
+        }
+    }
+
+    /// Batches, newest first.
+    pub async fn list_batches(&self) -> Result<Vec<StoredBatch>> {
+        let mut batches: Vec<StoredBatch> = self
+            .meta
+            .list_prefix(JOBS_PREFIX)
+            .await?
+            .into_iter()
+            .filter_map(|(_, raw, _)| serde_json::from_slice(&raw).ok())
+            .collect();
+        batches.sort_by_key(|b| Reverse((b.batch.created_at, b.batch.id.clone())));
+        Ok(batches)
+    }
+
+    /// The API key with public id `id`, as currently stored; `None` once it
+    /// has been revoked.
+    pub async fn api_key(&self, id: &str) -> Result<Option<ApiKey>> {
+        Ok(self
+            .meta
+            .list_prefix(API_KEYS_PREFIX)
+            .await?
+            .into_iter()
+            .filter_map(|(_, raw, _)| serde_json::from_slice::<ApiKey>(&raw).ok())
+            .find(|k| k.id == id))
+    }
+
+    /// Applies `update` to a batch with compare-and-swap, retrying on
+    /// conflicts. `update` returns `false` to leave the batch unchanged, in
+    /// which case `None` is returned, as it is for a missing batch.
+    pub async fn update_batch(
+        &self,
+        id: &str,
+        update: impl Fn(&mut StoredBatch) -> bool,
+    ) -> Result<Option<StoredBatch>> {
+        let key = format!("{JOBS_PREFIX}{id}");
+        loop {
+            let Some((raw, rev)) = self.meta.get(&key).await? else {
+                return Ok(None);
+            };
+            let mut stored: StoredBatch = serde_json::from_slice(&raw)?;
+            if !update(&mut stored) {
+                return Ok(None);
+            }
+            if self.meta.compare_and_swap(&key, rev, serde_json::to_vec(&stored)?).await?.0 {
+                return Ok(Some(stored));
+            }
+        }
+    }
+}
+
+/// One request of an input file.
+#[derive(Debug, Clone, Deserialize)]
+struct BatchLine {
+    custom_id: String,
+    method: String,
+    url: String,
+    body: Value,
+}
+
+/// The model of every request in an input file that parses, with its line
+/// number. Requests without one are reported as `unknown`.
+pub fn input_models(data: &[u8]) -> Vec<(usize, String)> {
+    let text = String::from_utf8_lossy(data);
+    text.lines()
+        .enumerate()
+        .filter_map(|(i, raw)| {
+            let line = serde_json::from_str::<Value>(raw).ok()?;
+            let model = line.pointer("/body/model").and_then(Value::as_str).unwrap_or("unknown");
+            Some((i + 1, model.to_string()))
+        })
+        .collect()
+}
+
+/// Why a batch's API key may no longer send a request for `model`: `key` is
+/// the key as currently stored, `None` once revoked.
+fn key_denial(key: Option<&ApiKey>, model: &str, now_ms: u64) -> Option<(ErrorCode, String)> {
+    match key {
+        None => Some((ErrorCode::InvalidApiKey, "The batch's API key was revoked.".to_string())),
+        Some(k) if k.is_expired(now_ms) => Some((ErrorCode::ApiKeyExpired, "The batch's API key expired.".to_string())),
+        Some(k) if !k.allows_model(model) => Some((
+            ErrorCode::ModelNotAllowed,
+            format!("This API key is not allowed to use model '{model}'."),
+        )),
+        Some(_) => None,
+    }
+}
+
+/// Parses an input file, returning every invalid line as a batch error.
+fn parse_input(data: &[u8], endpoint: &str) -> Result<Vec<BatchLine>, Vec<Value>> {
+    let error = |code: &str, message: String, line: Option<usize>| {
+        json!({ "code": code, "message": message, "param": null, "line": line })
+    };
+    let text = match std::str::from_utf8(data) {
+        Ok(text) => text,
+        Err(_) => return Err(vec![error("invalid_file_format", "input file is not UTF-8".into(), None)]),
+    };
+
+    let mut lines = Vec::new();
+    let mut errors = Vec::new();
+    let mut seen = HashSet::new();
+    for (i, raw) in text.lines().enumerate().filter(|(_, l)| !l.trim().is_empty()) {
+        let n = Some(i + 1);
+        let line: BatchLine = match serde_json::from_str(raw) {
+            Ok(line) => line,
+            Err(e) => {
+                errors.push(error("invalid_json_line", format!("invalid request: {e}"), n));
+                continue;
+            }
+        };
+        if !line.method.eq_ignore_ascii_case("POST") {
+            errors.push(error("invalid_method", "method must be POST".into(), n));
+        } else if line.url != endpoint {
+            errors.push(error(
+                "mismatched_endpoint",
+                format!("url '{}' does not match the batch endpoint '{endpoint}'", line.url),
+                n,
+            ));
+        } else if line.body.get("stream").and_then(Value::as_bool) == Some(true) {
+            errors.push(error("invalid_request", "streaming is not supported in batches".into(), n));
+        } else if !seen.insert(line.custom_id.clone()) {
+            errors.push(error("duplicate_custom_id", format!("duplicate custom_id '{}'", line.custom_id), n));
+        } else {
+            lines.push(line);
+        }
+    }
+    if lines.is_empty() && errors.is_empty() {
+        errors.push(error("empty_file", "input file has no requests".into(), None));
+    }
+    if lines.len() > MAX_BATCH_REQUESTS {
+        errors.push(error(
+            "too_many_requests",
+            format!("input file has more than {MAX_BATCH_REQUESTS} requests"),
+            None,
+        ));
+    }
+    if errors.is_empty() {
+        Ok(lines)
+    } else {
+        Err(errors)
+    }
+}
+
+/// Append-only output and error files of a running batch.
+struct ResultFiles {
+    output: tokio::fs::File,
+    errors: tokio::fs::File,
+    done: HashSet<String>,
+    completed: u64,
+    failed: u64,
+}
+
+impl ResultFiles {
+    async fn open(store: &BatchStore, batch: &Batch) -> Result<Self> {
+        tokio::fs::create_dir_all(&store.dir).await?;
+        let (output_id, error_id) = batch.result_file_ids();
+        let mut done = HashSet::new();
+        let (output, completed) = open_results(store.file_path(&output_id), &mut done).await?;
+        let (errors, failed) = open_results(store.file_path(&error_id), &mut done).await?;
+        Ok(Self {
+            output,
+            errors,
+            done,
+            completed,
+            failed,
+        })
+    }
+
+    async fn write(&mut self, result: &LineResult) -> Result<()> {
+        let mut line = serde_json::to_vec(&result.record)?;
+        line.push(b'\n');
+        if result.ok {
+            self.output.write_all(&line).await?;
+            self.output.flush().await?;
+            self.completed += 1;
+        } else {
+            self.errors.write_all(&line).await?;
+            self.errors.flush().await?;
+            self.failed += 1;
+        }
+        Ok(())
+    }
+}
+
+/// Opens a result file for appending and collects the `custom_id`s it holds.
+/// A line cut short by a crash is dropped.
+async fn open_results(path: PathBuf, done: &mut HashSet<String>) -> Result<(tokio::fs::File, u64)> {
+    let mut count = 0;
+    match tokio::fs::read(&path).await {
+        Ok(data) => {
+            let complete = data.iter().rposition(|b| *b == b'\n').map_or(0, |i| i + 1);
+            if complete < data.len() {
+                tokio::fs::OpenOptions::new()
+                    .write(true)
+                    .open(&path)
+                    .await?
+                    .set_len(complete as u64)
+                    .await?;
+            }
+            for line in data[..complete].split(|b| *b == b'\n') {
+                let Ok(record) = serde_json::from_slice::<Value>(line) else {
+                    continue;
+                };
+                if let Some(id) = record.get("custom_id").and_then(Value::as_str) {
+                    done.insert(id.to_string());
+                    count += 1;
+                }
+            }
+        }
+        Err(e) if e.kind() == std::io::ErrorKind::NotFound => {}
+        Err(e) => return Err(e.into()),
+    }
+    let file = tokio::fs::OpenOptions::new()
+        .create(true)
+        .append(true)
+        .open(&path)
+        .await?;
+    Ok((file, count))
+}
+
+/// A line of the output or error file.
+struct LineResult {
+    ok: bool,
+    record: Value,
+}
+
+impl LineResult {
+    fn response(custom_id: &str, request_id: &str, status: u16, body: Value) -> Self {
+        Self {
+            ok: (200..300).contains(&status),
+            record: json!({
+                "id": request_id,
+                "custom_id": custom_id,
+                "response": { "status_code": status, "request_id": request_id, "body": body },
+                "error": null,
+            }),
+        }
+    }
+
+    fn error(custom_id: &str, request_id: &str, code: &str, message: &str) -> Self {
+        Self {
+            ok: false,
+            record: json!({
+                "id": request_id,
+                "custom_id": custom_id,
+                "response": null,
+                "error": { "code": code, "message": message },
+            }),
+        }
+    }
+}
+
+/// Runs batches claimed by this replica.
+pub struct BatchExecutor {
+    store: Arc<BatchStore>,
+    http: reqwest::Client,
+    router_base_url: String,
+    router_token: Option<String>,
+    replica_id: String,
+    concurrency: usize,
+    quotas: Arc<Quotas>,
+    metrics: Arc<Metrics>,
+    guardrails: Option<Arc<Guardrails>>,
+    audit: Option<Arc<AuditWriter>>,
+    running: Mutex<HashSet<String>>,
+}
+
+impl BatchExecutor {
+    #[allow(clippy::too_many_arguments)]
+    pub fn new(
+        store: Arc<BatchStore>,
+        http: reqwest::Client,
+        router_base_url: String,
+        router_token: Option<String>,
+        replica_id: String,
+        concurrency: usize,
+        quotas: Arc<Quotas>,
+        metrics: Arc<Metrics>,
+        guardrails: Option<Arc<Guardrails>>,
+        audit: Option<Arc<AuditWriter>>,
+    ) -> Self {
+        Self {
+            store,
+            http,
+            router_base_url,
+            router_token,
+            replica_id,
+            concurrency: concurrency.max(1),
+            quotas,
+            metrics,
+            guardrails,
+            audit,
+            running: Mutex::new(HashSet::new()),
+        }
+    }
+
+    pub fn spawn(self: Arc<Self>) {
+        tokio::spawn(async move {
+            loop {
+                if let Err(e) = self.poll().await {
+                    tracing::warn!(error=%e, "batch poll failed");
+                }
+                tokio::time::sleep(POLL_INTERVAL).await;
+            }
+        });
+    }
+
+    fn is_running(&self, id: &str) -> bool {
+        self.running.lock().map(|r| r.contains(id)).unwrap_or(true)
+    }
+
+    /// Claims active batches that no live replica holds and starts them.
+    async fn poll(self: &Arc<Self>) -> Result<()> {
+        for stored in self.store.list_batches().await? {
+            let id = stored.batch.id.clone();
+            if !stored.batch.status.is_active() || self.is_running(&id) {
+                continue;
+            }
+            let now = now_ms();
+            if stored.worker.as_deref() != Some(self.replica_id.as_str()) && stored.lease_until_ms > now {
+                continue;
+            }
+            let claimed = self
+                .store
+                .update_batch(&id, |b| {
+                    let free = b.worker.as_deref() == Some(self.replica_id.as_str()) || b.lease_until_ms <= now;
+                    if !b.batch.status.is_active() || !free {
+                        return false;
+                    }
+                    b.worker = Some(self.replica_id.clone());
+                    b.lease_until_ms = now + LEASE_MS;
+                    true
+                })
+                .await?;
+            let Some(claimed) = claimed else {
+                continue;
+            };
+            if let Ok(mut running) = self.running.lock() {
+                running.insert(id.clone());
+            }
+            tracing::info!(batch_id=%id, status=%claimed.batch.status.as_str(), "running batch");
+            let executor = self.clone();
+            tokio::spawn(async move {
+                if let Err(e) = executor.run(claimed).await {
+                    tracing::error!(error=%e, batch_id=%id, "batch run failed; it will be retried");
+                }
+                if let Ok(mut running) = executor.running.lock() {
+                    running.remove(&id);
+                }
+            });
+        }
+        Ok(())
+    }
+
+    async fn run(&self, stored: StoredBatch) -> Result<()> {
+        let batch = &stored.batch;
+        let data = match tokio::fs::read(self.store.file_path(&batch.input_file_id)).await {
+            Ok(data) => data,
+            Err(e) => {
+                let error = json!({ "code": "file_not_found", "message": format!("input file unreadable: {e}") });
+                return self.fail(&batch.id, vec![error]).await;
+            }
+        };
+        let lines = match parse_input(&data, &batch.endpoint) {
+            Ok(lines) => lines,
+            Err(errors) => return self.fail(&batch.id, errors).await,
+        };
+
+        let total = lines.len() as u64;
+        let Some(stored) = self
+            .store
+            .update_batch(&batch.id, |b| {
+                if !self.holds(b) {
+                    return false;
+                }
+                if b.batch.status == BatchStatus::Validating {
+                    b.batch.status = BatchStatus::InProgress;
+                    b.batch.in_progress_at = Some(now_secs());
+                }
+                b.batch.request_counts.total = total;
+                true
+            })
+            .await?
+        else {
+            return Ok(());
+        };
+
+        let results = ResultFiles::open(&self.store, &stored.batch).await?;
+        let pending: Vec<BatchLine> = lines
+            .into_iter()
+            .filter(|l| !results.done.contains(&l.custom_id))
+            .collect();
+        let results = tokio::sync::Mutex::new(results);
+        let stop = AtomicBool::new(stored.batch.status == BatchStatus::Cancelling);
+        let scopes = {
+            let mut scopes = vec![QuotaScope::Key(stored.owner.clone())];
+            scopes.extend(stored.tenant.clone().map(QuotaScope::Tenant));
+            scopes
+        };
+        let key = match stored.api_key_id.as_deref() {
+            Some(id) => Some(std::sync::RwLock::new(self.store.api_key(id).await?)),
+            None => None,
+        };
+
+        let work = futures_util::stream::iter(pending).for_each_concurrent(self.concurrency, |line| {
+            let (results, stop, scopes, stored, key) = (&results, &stop, &scopes, &stored, &key);
+            async move {
+                if stop.load(Ordering::Relaxed) {
+                    return;
+                }
+                let model = line.body.get("model").and_then(Value::as_str).unwrap_or("unknown");
+                let denied = key
+                    .as_ref()
+                    .and_then(|k| key_denial(k.read().ok()?.as_ref(), model, now_ms()));
+                let result = if stored.batch.expires_at.is_some_and(|t| now_secs() >= t) {
+                    let request_id = format!("batch_req_{}", uuid::Uuid::new_v4().simple());
+                    LineResult::error(&line.custom_id, &request_id, "batch_expired", "batch expired before the request ran")
+                } else if let Some((code, message)) = denied {
+                    let request_id = format!("batch_req_{}", uuid::Uuid::new_v4().simple());
+                    LineResult::error(&line.custom_id, &request_id, code.as_str(), &message)
+                } else if let Err(exceeded) = self.admit(scopes, stop).await {
+                    if exceeded.window == Window::Minute {
+                        // Cancelled while waiting for the minute to pass.
+                        return;
+                    }
+                    self.metrics.record_quota_rejected(exceeded.window);
+                    let error = exceeded.error();
+                    let request_id = format!("batch_req_{}", uuid::Uuid::new_v4().simple());
+                    LineResult::error(&line.custom_id, &request_id, error.code.as_str(), &error.message)
+                } else {
+                    let usage = UsageRecorder::default();
+                    let start = Instant::now();
+                    let result = self.execute(&stored.batch.endpoint, &line, scopes, stored, &usage).await;
+                    self.audit(stored, &result, &usage, start);
+                    result
+                };
+                self.metrics.record_batch_request(result.ok);
+                if let Err(e) = results.lock().await.write(&result).await {
+                    tracing::error!(error=%e, batch_id=%stored.batch.id, "failed to write batch result");
+                    stop.store(true, Ordering::Relaxed);
+                }
+            }
+        });
+        let heartbeat = async {
+            loop {
+                tokio::time::sleep(HEARTBEAT_INTERVAL).await;
+                let (completed, failed) = {
+                    let r = results.lock().await;
+                    (r.completed, r.failed)
+                };
+                match self.renew(&stored.batch.id, completed, failed).await {
+                    Ok(Some(current)) if current.batch.status == BatchStatus::Cancelling => {
+                        stop.store(true, Ordering::Relaxed);
+                    }
+                    Ok(Some(_)) => {}
+                    Ok(None) => return,
+                    Err(e) => tracing::warn!(error=%e, batch_id=%stored.batch.id, "batch heartbeat failed"),
+                }
+                if let (Some(id), Some(key)) = (stored.api_key_id.as_deref(), key.as_ref()) {
+                    match self.store.api_key(id).await {
+                        Ok(current) => {
+                            if let Ok(mut key) = key.write() {
+                                *key = current;
+                            }
+                        }
+                        Err(e) => {
+                            tracing::warn!(error=%e, batch_id=%stored.batch.id, "failed to reload the batch's api key")
+                        }
+                    }
+                }
+            }
+        };
+        tokio::select! {
+            _ = work => {}
+            _ = heartbeat => {
+                tracing::warn!(batch_id=%stored.batch.id, "lost the batch lease; another replica resumes it");
+                return Ok(());
+            }
+        }
+
+        let results = results.into_inner();
+        if stop.load(Ordering::Relaxed) {
+            // Cancelled, or a result could not be written and the run is retried.
+            let current = self.store.get_batch(&stored.batch.id).await?;
+            if current.is_none_or(|b| b.batch.status != BatchStatus::Cancelling) {
+                anyhow::bail!("batch stopped before finishing");
+            }
+        }
+        self.finish(&stored, &results).await
+    }
+
+    /// Audits a request sent for a batch. Requests that got no response from
+    /// the router are recorded as 502s.
+    fn audit(&self, stored: &StoredBatch, result: &LineResult, usage: &UsageRecorder, start: Instant) {
+        let Some(audit) = self.audit.as_ref() else {
+            return;
+        };
+        let record = &result.record;
+        let status = record.pointer("/response/status_code").and_then(Value::as_u64).unwrap_or(502);
+        let mut entry = AuditEntry {
+            id: uuid::Uuid::new_v4().to_string(),
+            request_id: record["id"].as_str().unwrap_or_default().to_string(),
+            timestamp: chrono::Utc::now(),
+            principal: stored.owner.clone(),
+            role: "batch".to_string(),
+            tenant: stored.tenant.clone(),
+            client_ip: None,
+            method: "POST".to_string(),
+            path: stored.batch.endpoint.clone(),
+            status: status as u16,
+            reason: json_error_reason(record.pointer("/response/body").unwrap_or(record)),
+            latency_ms: start.elapsed().as_millis() as u64,
+            model: None,
+            prompt_tokens: None,
+            completion_tokens: None,
+            guardrails: Vec::new(),
+        };
+        entry.record(usage);
+        audit.send(entry);
+    }
+
+    /// Waits until `scopes` are under their per-minute token limits, or the
+    /// batch is cancelled. Daily and monthly limits are not waited out.
+    async fn admit(&self, scopes: &[QuotaScope], stop: &AtomicBool) -> Result<(), QuotaExceeded> {
+        loop {
+            match self.quotas.check(scopes) {
+                Err(e) if e.window == Window::Minute && !stop.load(Ordering::Relaxed) => {
+                    tokio::time::sleep(Duration::from_secs(e.retry_after_secs)).await;
+                }
+                admitted => return admitted,
+            }
+        }
+    }
+
+    /// Whether this replica still holds the batch's lease.
+    fn holds(&self, stored: &StoredBatch) -> bool {
+        stored.worker.as_deref() == Some(self.replica_id.as_str()) && stored.batch.status.is_active()
+    }
+
+    /// Renews the lease and publishes progress; `None` once the lease is lost.
+    async fn renew(&self, id: &str, completed: u64, failed: u64) -> Result<Option<StoredBatch>> {
+        self.store
+            .update_batch(id, |b| {
+                if !self.holds(b) {
+                    return false;
+                }
+                b.lease_until_ms = now_ms() + LEASE_MS;
+                b.batch.request_counts.completed = completed;
+                b.batch.request_counts.failed = failed;
+                true
+            })
+            .await
+    }
+
+    async fn fail(&self, id: &str, errors: Vec<Value>) -> Result<()> {
+        tracing::warn!(batch_id=%id, errors=errors.len(), "batch input failed validation");
+        self.store
+            .update_batch(id, |b| {
+                if !self.holds(b) {
+                    return false;
+                }
+                b.batch.status = BatchStatus::Failed;
+                b.batch.failed_at = Some(now_secs());
+                b.batch.errors = Some(json!({ "object": "list", "data": errors }));
+                b.worker = None;
+                true
+            })
+            .await?;
+        Ok(())
+    }
+
+    /// Registers the result files and moves the batch to its final status.
+    async fn finish(&self, stored: &StoredBatch, results: &ResultFiles) -> Result<()> {
+        let id = &stored.batch.id;
+        let cancelled = self
+            .store
+            .update_batch(id, |b| {
+                if !self.holds(b) {
+                    return false;
+                }
+                if b.batch.status != BatchStatus::Cancelling {
+                    b.batch.status = BatchStatus::Finalizing;
+                    b.batch.finalizing_at = Some(now_secs());
+                }
+                true
+            })
+            .await?
+            .map(|b| b.batch.status == BatchStatus::Cancelling);
+        let Some(cancelled) = cancelled else {
+            return Ok(());
+        };
+
+        let (output_id, error_id) = stored.batch.result_file_ids();
+        let output_file_id = self.register_result(stored, &output_id, "output").await?;
+        let error_file_id = self.register_result(stored, &error_id, "errors").await?;
+        let expired = stored.batch.expires_at.is_some_and(|t| now_secs() >= t);
+        let (completed, failed) = (results.completed, results.failed);
+        self.store
+            .update_batch(id, |b| {
+                if !self.holds(b) {
+                    return false;
+                }
+                let now = Some(now_secs());
+                if cancelled {
+                    b.batch.status = BatchStatus::Cancelled;
+                    b.batch.cancelled_at = now;
+                } else if expired {
+                    b.batch.status = BatchStatus::Expired;
+                    b.batch.expired_at = now;
+                } else {
+                    b.batch.status = BatchStatus::Completed;
+                    b.batch.completed_at = now;
+                }
+                b.batch.output_file_id = output_file_id.clone();
+                b.batch.error_file_id = error_file_id.clone();
+                b.batch.request_counts.completed = completed;
+                b.batch.request_counts.failed = failed;
+                b.worker = None;
+                b.lease_until_ms = 0;
+                true
+            })
+            .await?;
+        tracing::info!(batch_id=%id, completed, failed, cancelled, "batch finished");
+        Ok(())
+    }
+
+    /// Records a non-empty result file as a `batch_output` file owned by the
+    /// batch's creator.
+    async fn register_result(&self, stored: &StoredBatch, file_id: &str, kind: &str) -> Result<Option<String>> {
+        let bytes = match tokio::fs::metadata(self.store.file_path(file_id)).await {
+            Ok(m) => m.len(),
+            Err(e) if e.kind() == std::io::ErrorKind::NotFound => 0,
+            Err(e) => return Err(e.into()),
+        };
+        if bytes == 0 {
+            return Ok(None);
+        }
+        let file = FileObject {
+            id: file_id.to_string(),
+            object: "file".to_string(),
+            bytes,
+            created_at: now_secs(),
+            filename: format!("{}_{kind}.jsonl", stored.batch.id),
+            purpose: "batch_output".to_string(),
+        };
+        self.store
+            .put_file(&StoredFile {
+                file,
+                owner: stored.owner.clone(),
+            })
+            .await?;
+        Ok(Some(file_id.to_string()))
+    }
+
+    /// Sends one request to the router, retrying while it is overloaded or
+    /// unreachable, and accounts its token usage to the batch's owner.
+    /// Sends one request, leaving its model, token usage and guardrail
+    /// actions in `usage` for its audit entry.
+    async fn execute(
+        &self,
+        endpoint: &str,
+        line: &BatchLine,
+        scopes: &[QuotaScope],
+        stored: &StoredBatch,
+        usage: &UsageRecorder,
+    ) -> LineResult {
+        let request_id = format!("batch_req_{}", uuid::Uuid::new_v4().simple());
+        let model = line.body.get("model").and_then(Value::as_str).unwrap_or("unknown");
+        usage.record_model(model);
+        let guard = self.guardrails.as_ref().and_then(|g| g.select(model, stored.tenant.as_deref()));
+        let mut redacted = None;
+        if let Some(guard) = guard.as_ref() {
+            let mut body = line.body.clone();
+            let mut events = Vec::new();
+            let checked = guard.check_request(&mut body, &mut events).await;
+            events.extend(checked.as_ref().err().map(|b| b.0.clone()));
+            usage.record_guardrails(&events);
+            match checked {
+                Ok(changed) => redacted = changed.then_some(body),
+                Err(blocked) => {
+                    let err = ApiError::new(ErrorCode::ContentPolicyViolation, blocked.message());
+                    return LineResult::response(&line.custom_id, &request_id, err.status().as_u16(), err.to_json());
+                }
+            }
+        }
+        let url = format!("{}{endpoint}", self.router_base_url.trim_end_matches('/'));
+        let mut backoff = Duration::from_secs(1);
+        for attempt in 1..=MAX_ATTEMPTS {
+            let mut request = self
+                .http
+                .post(&url)
+                .header(PRIORITY_HEADER, "-1")
+                .header("x-request-id", &request_id)
+                .json(redacted.as_ref().unwrap_or(&line.body));
+            if let Some(token) = self.router_token.as_deref() {
+                request = request.bearer_auth(token);
+            }
+            match request.send().await {
+                Ok(resp) => {
+                    let status = resp.status().as_u16();
+                    if matches!(status, 429 | 503) && attempt < MAX_ATTEMPTS {
+                        tokio::time::sleep(backoff).await;
+                        backoff = (backoff * 2).min(Duration::from_secs(30));
+                        continue;
+                    }
+                    let mut body = resp.json::<Value>().await.unwrap_or(Value::Null);
+                    if (200..300).contains(&status) {
+                        if let Some(reported) = TokenUsage::from_json(&body) {
+                            self.metrics.record_tokens(model, &stored.owner, reported);
+                            self.quotas.record(scopes, reported);
+                            usage.record(model, reported);
+                        }
+                        if let Some(guard) = guard.as_ref() {
+                            let mut events = Vec::new();
+                            guard.check_response(&mut body, &mut events).await;
+                            usage.record_guardrails(&events);
+                        }
+                    }
+                    return LineResult::response(&line.custom_id, &request_id, status, body);
+                }
+                Err(e) if e.is_connect() && attempt < MAX_ATTEMPTS => {
+                    tokio::time::sleep(backoff).await;
+                    backoff = (backoff * 2).min(Duration::from_secs(30));
+                }
+                Err(e) => {
+                    let code = if e.is_timeout() { "timeout" } else { "upstream_error" };
+                    return LineResult::error(&line.custom_id, &request_id, code, &e.to_string());
+                }
+            }
+        }
+        LineResult::error(&line.custom_id, &request_id, "upstream_error", "router unavailable")
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::sync::atomic::AtomicUsize;
+
+    use axum::{extract::State, http::StatusCode, routing::post, Json};
+    use nebula_common::auth::Role;
+    use nebula_meta::MemoryMetaStore;
+
+    use super::*;
+    use crate::guardrails::{Action, Direction};
+    use crate::quota::QuotaLimits;
+
+    const ENDPOINT: &str = "/v1/chat/completions";
+
+    fn line(custom_id: &str, model: &str) -> String {
+        line_saying(custom_id, model, "hi")
+    }
+
+    fn line_saying(custom_id: &str, model: &str, content: &str) -> String {
+        json!({
+            "custom_id": custom_id,
+            "method": "POST",
+            "url": ENDPOINT,
+            "body": {"model": model, "messages": [{"role": "user", "content": content}]},
+        })
+        .to_string()
+    }
+
+    /// A router that answers model `bad` with a 400 and everything else with a
+    /// completion echoing the first message, using 3 prompt and 2 completion
+    /// tokens. Returns its base URL and the number of requests it received.
+    async fn fake_router() -> (String, Arc<AtomicUsize>) {
+        async fn complete(State(hits): State<Arc<AtomicUsize>>, Json(body): Json<Value>) -> (StatusCode, Json<Value>) {
+            hits.fetch_add(1, Ordering::SeqCst);
+            if body["model"] == "bad" {
+                return (StatusCode::BAD_REQUEST, Json(json!({"error": {"message": "unknown model"}})));
+            }
+            let completion = json!({
+                "object": "chat.completion",
+                "model": body["model"],
+                "choices": [{
+                    "index": 0,
+                    "message": {"role": "assistant", "content": body["messages"][0]["content"]},
+                    "finish_reason": "stop",
+                }],
+                "usage": {"prompt_tokens": 3, "completion_tokens": 2, "total_tokens": 5},
+            });
+            (StatusCode::OK, Json(completion))
+        }
+        let hits = Arc::new(AtomicUsize::new(0));
+        let app = axum::Router::new().route(ENDPOINT, post(complete)).with_state(hits.clone());
+        let listener = tokio::net::TcpListener::bind("127.0.0.1:0").await.unwrap();
+        let url = format!("http://{}", listener.local_addr().unwrap());
+        tokio::spawn(async move { axum::serve(listener, app).await });
+        (url, hits)
+    }
+
+    struct Fixture {
+        store: Arc<BatchStore>,
+        quotas: Arc<Quotas>,
+        metrics: Arc<Metrics>,
+        guardrails: Option<Arc<Guardrails>>,
+        audit: Option<Arc<AuditWriter>>,
+        router: String,
+        hits: Arc<AtomicUsize>,
+    }
+
+    impl Fixture {
+        /// With guardrails that block `forbidden` in requests, redact e-mail
+        /// addresses in requests and redact `secret` in responses.
+        async fn with_guardrails() -> Self {
+            let mut fx = Self::new().await;
+            let config = json!({"policies": [{
+                "name": "batch",
+                "stages": [
+                    {"type": "keywords", "terms": ["forbidden"], "action": "block", "applies_to": "input"},
+                    {"type": "pii", "kinds": ["email"], "applies_to": "input"},
+                    {"type": "keywords", "terms": ["secret"], "action": "redact", "applies_to": "output"},
+                ],
+            }]});
+            let path = fx.store.dir.with_extension("guardrails.json");
+            tokio::fs::write(&path, config.to_string()).await.unwrap();
+            let loaded = Guardrails::load(path.to_str().unwrap(), reqwest::Client::new(), fx.metrics.clone());
+            tokio::fs::remove_file(&path).await.unwrap();
+            fx.guardrails = Some(Arc::new(loaded.unwrap()));
+            fx
+        }
+
+        /// Audits to a file sink in the fixture's directory.
+        fn with_audit(mut self) -> Self {
+            let config = crate::audit::AuditConfig {
+                sink: Some("file".to_string()),
+                dir: self.store.dir.join("audit"),
+                max_file_bytes: 1 << 20,
+                max_files: 1,
+                xtrace_url: None,
+                xtrace_token: None,
+            };
+            self.audit = AuditWriter::spawn(&config, self.metrics.clone()).unwrap();
+            self
+        }
+
+        /// Waits for `n` audit entries to reach the file sink.
+        async fn audit_entries(&self, n: usize) -> Vec<AuditEntry> {
+            let path = self.store.dir.join("audit").join("audit.jsonl");
+            for _ in 0..100 {
+                let content = tokio::fs::read_to_string(&path).await.unwrap_or_default();
+                let entries: Vec<AuditEntry> = content.lines().map(|l| serde_json::from_str(l).unwrap()).collect();
+                if entries.len() >= n {
+                    return entries;
+                }
+                tokio::time::sleep(Duration::from_millis(20)).await;
+            }
+            panic!("audit entries were not written");
+        }
+
+        async fn new() -> Self {
+            let meta: Arc<dyn MetaStore> = Arc::new(MemoryMetaStore::new());
+            let dir = std::env::temp_dir().join(format!("nebula-batch-{}", uuid::Uuid::new_v4()));
+            let (router, hits) = fake_router().await;
+            Self {
+                store: Arc::new(BatchStore::new(meta.clone(), dir, 1 << 20)),
+                quotas: Arc::new(Quotas::new(meta, "gw-1".to_string())),
+                metrics: Arc::new(Metrics::default()),
+                guardrails: None,
+                audit: None,
+                router,
+                hits,
+            }
+        }
+
+        fn executor(&self, replica_id: &str) -> Arc<BatchExecutor> {
+            Arc::new(BatchExecutor::new(
+                self.store.clone(),
+                reqwest::Client::new(),
+                self.router.clone(),
+                None,
+                replica_id.to_string(),
+                4,
+                self.quotas.clone(),
+                self.metrics.clone(),
+                self.guardrails.clone(),
+                self.audit.clone(),
+            ))
+        }
+
+        /// A batch over `lines`, created by `alice` of tenant `acme` and held
+        /// by replica `worker`.
+        async fn batch(&self, lines: &[String], worker: Option<&str>) -> StoredBatch {
+            self.batch_for_key(lines, worker, None).await
+        }
+
+        /// As [`Fixture::batch`], created with the API key `api_key_id`.
+        async fn batch_for_key(&self, lines: &[String], worker: Option<&str>, api_key_id: Option<&str>) -> StoredBatch {
+            let data = lines.join("\n");
+            let file = self.store.create_file("alice", "in.jsonl", "batch", data.as_bytes()).await.unwrap();
+            let stored = StoredBatch {
+                batch: Batch::new(file.id, ENDPOINT.to_string(), None),
+                owner: "alice".to_string(),
+                tenant: Some("acme".to_string()),
+                api_key_id: api_key_id.map(str::to_string),
+                worker: worker.map(str::to_string),
+                lease_until_ms: worker.map_or(0, |_| now_ms() + LEASE_MS),
+            };
+            self.store.create_batch(&stored).await.unwrap();
+            stored
+        }
+
+        async fn result_lines(&self, file_id: Option<&str>) -> Vec<Value> {
+            let Some(id) = file_id else {
+                return Vec::new();
+            };
+            let data = tokio::fs::read(self.store.file_path(id)).await.unwrap();
+            data.split(|b| *b == b'\n')
+                .filter(|l| !l.is_empty())
+                .map(|l| serde_json::from_slice(l).unwrap())
+                .collect()
+        }
+    }
+
+    impl Drop for Fixture {
+        fn drop(&mut self) {
+            let _ = std::fs::remove_dir_all(&self.store.dir);
+        }
+    }
+
+    fn error_codes(errors: &[Value]) -> Vec<(&str, Option<u64>)> {
+        errors
+            .iter()
+            .map(|e| (e["code"].as_str().unwrap(), e["line"].as_u64()))
+            .collect()
+    }
+
+    #[test]
+    fn test_parse_input() {
+        let data = [line("a", "m"), line("b", "m")].join("\n\n");
+        let lines = parse_input(data.as_bytes(), ENDPOINT).unwrap();
+        assert_eq!(lines.iter().map(|l| l.custom_id.as_str()).collect::<Vec<_>>(), ["a", "b"]);
+
+        let mut get = serde_json::from_str::<Value>(&line("c", "m")).unwrap();
+        get["method"] = json!("GET");
+        let mut embeddings = serde_json::from_str::<Value>(&line("d", "m")).unwrap();
+        embeddings["url"] = json!("/v1/embeddings");
+        let mut stream = serde_json::from_str::<Value>(&line("e", "m")).unwrap();
+        stream["body"]["stream"] = json!(true);
+        let data = [
+            line("a", "m"),
+            "{not json".to_string(),
+            get.to_string(),
+            embeddings.to_string(),
+            stream.to_string(),
+            line("a", "m"),
+        ]
+        .join("\n");
+        let errors = parse_input(data.as_bytes(), ENDPOINT).unwrap_err();
+        assert_eq!(
+            error_codes(&errors),
+            [
+                ("invalid_json_line", Some(2)),
+                ("invalid_method", Some(3)),
+                ("mismatched_endpoint", Some(4)),
+                ("invalid_request", Some(5)),
+                ("duplicate_custom_id", Some(6)),
+            ]
+        );
+
+        let errors = parse_input(b"\n  \n", ENDPOINT).unwrap_err();
+        assert_eq!(error_codes(&errors), [("empty_file", None)]);
+        let errors = parse_input(&[0xff, 0xfe], ENDPOINT).unwrap_err();
+        assert_eq!(error_codes(&errors), [("invalid_file_format", None)]);
+    }
+
+    #[tokio::test]
+    async fn test_batch_writes_results_and_charges_the_owner() {
+        let fx = Fixture::new().await;
+        let stored = fx.batch(&[line("a", "m"), line("b", "bad"), line("c", "m")], Some("gw-1")).await;
+        fx.executor("gw-1").run(stored.clone()).await.unwrap();
+
+        let done = fx.store.get_batch(&stored.batch.id).await.unwrap().unwrap();
+        assert_eq!(done.batch.status, BatchStatus::Completed);
+        assert!(done.batch.completed_at.is_some());
+        assert_eq!(done.worker, None);
+        let counts = done.batch.request_counts;
+        assert_eq!((counts.total, counts.completed, counts.failed), (3, 2, 1));
+
+        let output = fx.result_lines(done.batch.output_file_id.as_deref()).await;
+        let mut ids: Vec<&str> = output.iter().map(|r| r["custom_id"].as_str().unwrap()).collect();
+        ids.sort();
+        assert_eq!(ids, ["a", "c"]);
+        assert!(output.iter().all(|r| r["response"]["status_code"] == 200 && r["error"].is_null()));
+        let errors = fx.result_lines(done.batch.error_file_id.as_deref()).await;
+        assert_eq!(errors.len(), 1);
+        assert_eq!(errors[0]["custom_id"], "b");
+        assert_eq!(errors[0]["response"]["status_code"], 400);
+
+        // Result files belong to the batch's creator.
+        let output_file = fx.store.get_file(done.batch.output_file_id.as_deref().unwrap()).await.unwrap().unwrap();
+        assert_eq!(output_file.owner, "alice");
+        assert_eq!(output_file.file.purpose, "batch_output");
+
+        // Only successful requests are charged, to the owner's key and tenant.
+        let usage = TokenUsage { prompt_tokens: 6, completion_tokens: 4 };
+        assert_eq!(fx.metrics.tokens.lock().unwrap()[&("m".to_string(), "alice".to_string())], usage);
+        fx.quotas.sync().await.unwrap();
+        for scope in [QuotaScope::Key("alice".to_string()), QuotaScope::Tenant("acme".to_string())] {
+            assert_eq!(fx.quotas.usage(&scope).await.unwrap().day, usage);
+        }
+        assert_eq!(fx.metrics.batch_requests_completed.load(Ordering::Relaxed), 2);
+        assert_eq!(fx.metrics.batch_requests_failed.load(Ordering::Relaxed), 1);
+    }
+
+    #[tokio::test]
+    async fn test_batch_lines_run_under_the_creating_key() {
+        let fx = Fixture::new().await;
+        let allowed = vec!["m".to_string()];
+        let (_, key) = ApiKey::generate("alice".to_string(), Role::Operator, None, allowed, None, None, 0);
+        fx.store.meta.put(&key.storage_key(), serde_json::to_vec(&key).unwrap(), None).await.unwrap();
+
+        let lines = [line("a", "m"), line("b", "other")];
+        let stored = fx.batch_for_key(&lines, Some("gw-1"), Some(&key.id)).await;
+        fx.executor("gw-1").run(stored.clone()).await.unwrap();
+        let done = fx.store.get_batch(&stored.batch.id).await.unwrap().unwrap();
+        let errors = fx.result_lines(done.batch.error_file_id.as_deref()).await;
+        assert_eq!(errors.len(), 1);
+        assert_eq!(errors[0]["custom_id"], "b");
+        assert_eq!(errors[0]["error"]["code"], "model_not_allowed");
+        assert_eq!(fx.hits.load(Ordering::SeqCst), 1);
+
+        // Once the key expires or is revoked, none of its lines are sent.
+        let expired = ApiKey { expires_at_ms: Some(1), ..key.clone() };
+        fx.store.meta.put(&key.storage_key(), serde_json::to_vec(&expired).unwrap(), None).await.unwrap();
+        let stored = fx.batch_for_key(&lines[..1], Some("gw-1"), Some(&key.id)).await;
+        fx.executor("gw-1").run(stored.clone()).await.unwrap();
+        let done = fx.store.get_batch(&stored.batch.id).await.unwrap().unwrap();
+        let errors = fx.result_lines(done.batch.error_file_id.as_deref()).await;
+        assert_eq!(errors[0]["error"]["code"], "api_key_expired");
+
+        fx.store.meta.delete(&key.storage_key()).await.unwrap();
+        let stored = fx.batch_for_key(&lines, Some("gw-1"), Some(&key.id)).await;
+        fx.executor("gw-1").run(stored.clone()).await.unwrap();
+        let done = fx.store.get_batch(&stored.batch.id).await.unwrap().unwrap();
+        assert_eq!(done.batch.request_counts.failed, 2);
+        let errors = fx.result_lines(done.batch.error_file_id.as_deref()).await;
+        assert!(errors.iter().all(|e| e["error"]["code"] == "invalid_api_key"));
+        assert_eq!(fx.hits.load(Ordering::SeqCst), 1);
+    }
+
+    #[tokio::test]
+    async fn test_batch_stops_sending_once_the_quota_is_used_up() {
+        let fx = Fixture::new().await;
+        let limits = QuotaLimits { tokens_per_day: Some(5), ..Default::default() };
+        fx.quotas.set_limits(&QuotaScope::Tenant("acme".to_string()), limits).await.unwrap();
+
+        let stored = fx.batch(&[line("a", "m")], Some("gw-1")).await;
+        fx.executor("gw-1").run(stored).await.unwrap();
+        assert_eq!(fx.hits.load(Ordering::SeqCst), 1);
+
+        // The first batch used the tenant's 5 tokens for the day.
+        let stored = fx.batch(&[line("b", "m"), line("c", "m")], Some("gw-1")).await;
+        fx.executor("gw-1").run(stored.clone()).await.unwrap();
+        let done = fx.store.get_batch(&stored.batch.id).await.unwrap().unwrap();
+        assert_eq!(done.batch.status, BatchStatus::Completed);
+        assert_eq!(done.batch.request_counts.failed, 2);
+        let errors = fx.result_lines(done.batch.error_file_id.as_deref()).await;
+        assert!(errors.iter().all(|e| e["error"]["code"] == "insufficient_quota"));
+        assert_eq!(fx.hits.load(Ordering::SeqCst), 1);
+    }
+
+    #[tokio::test]
+    async fn test_guardrails_check_batch_requests() {
+        let fx = Fixture::with_guardrails().await.with_audit();
+        let lines = [
+            line_saying("mail", "m", "mail bob@example.com"),
+            line_saying("blocked", "m", "something forbidden"),
+            line_saying("leak", "m", "the secret plan"),
+        ];
+        let stored = fx.batch(&lines, Some("gw-1")).await;
+        fx.executor("gw-1").run(stored.clone()).await.unwrap();
+
+        // The blocked request never reaches the router.
+        assert_eq!(fx.hits.load(Ordering::SeqCst), 2);
+        let done = fx.store.get_batch(&stored.batch.id).await.unwrap().unwrap();
+        let errors = fx.result_lines(done.batch.error_file_id.as_deref()).await;
+        assert_eq!(errors.len(), 1);
+        assert_eq!(errors[0]["custom_id"], "blocked");
+        assert_eq!(errors[0]["response"]["status_code"], 400);
+        assert_eq!(errors[0]["response"]["body"]["error"]["code"], "content_policy_violation");
+
+        let output = fx.result_lines(done.batch.output_file_id.as_deref()).await;
+        let reply = |id: &str| {
+            let record = output.iter().find(|r| r["custom_id"] == id).unwrap();
+            record["response"]["body"]["choices"][0]["message"]["content"].clone()
+        };
+        // The router echoes the redacted request; the response is redacted on the way back.
+        assert_eq!(reply("mail"), "mail [REDACTED]");
+        assert_eq!(reply("leak"), "the [REDACTED] plan");
+
+        // Each request is audited with the guardrail actions taken on it.
+        let entries = fx.audit_entries(3).await;
+        let audited = |id: &str| {
+            let record = errors.iter().chain(&output).find(|r| r["custom_id"] == id).unwrap();
+            entries.iter().find(|e| e.request_id == record["id"]).unwrap()
+        };
+        let blocked = audited("blocked");
+        assert_eq!((blocked.status, blocked.reason.as_deref()), (400, Some("content_policy_violation")));
+        assert_eq!(blocked.guardrails.iter().map(|g| g.action).collect::<Vec<_>>(), [Action::Block]);
+        let leak = audited("leak");
+        assert_eq!((leak.principal.as_str(), leak.role.as_str(), leak.status), ("alice", "batch", 200));
+        assert_eq!(leak.model.as_deref(), Some("m"));
+        assert_eq!((leak.prompt_tokens, leak.completion_tokens), (Some(3), Some(2)));
+        let actions: Vec<_> = leak.guardrails.iter().map(|g| (g.direction, g.action)).collect();
+        assert_eq!(actions, [(Direction::Output, Action::Redact)]);
+        assert_eq!(audited("mail").guardrails[0].direction, Direction::Input);
+        assert_eq!(fx.metrics.guardrail_redacted.load(Ordering::Relaxed), 2);
+    }
+
+    #[tokio::test]
+    async fn test_invalid_input_fails_the_batch() {
+        let fx = Fixture::new().await;
+        let stored = fx.batch(&[line("a", "m"), line("a", "m")], Some("gw-1")).await;
+        fx.executor("gw-1").run(stored.clone()).await.unwrap();
+
+        let failed = fx.store.get_batch(&stored.batch.id).await.unwrap().unwrap();
+        assert_eq!(failed.batch.status, BatchStatus::Failed);
+        assert_eq!(failed.batch.errors.unwrap()["data"][0]["code"], "duplicate_custom_id");
+        assert_eq!(fx.hits.load(Ordering::SeqCst), 0);
+    }
+
+    #[tokio::test]
+    async fn test_resumed_batch_skips_written_results() {
+        let fx = Fixture::new().await;
+        let stored = fx.batch(&[line("a", "m"), line("b", "m")], Some("gw-1")).await;
+        // An earlier run wrote `a` and crashed halfway through `b`.
+        let (output_id, _) = stored.batch.result_file_ids();
+        let written = LineResult::response("a", "batch_req_1", 200, json!({}));
+        let data = format!("{}\n{{\"custom_id\": \"b\"", written.record);
+        tokio::fs::write(fx.store.file_path(&output_id), data).await.unwrap();
+
+        fx.executor("gw-1").run(stored.clone()).await.unwrap();
+        assert_eq!(fx.hits.load(Ordering::SeqCst), 1);
+        let done = fx.store.get_batch(&stored.batch.id).await.unwrap().unwrap();
+        assert_eq!(done.batch.request_counts.completed, 2);
+        let output = fx.result_lines(Some(&output_id)).await;
+        let ids: Vec<&str> = output.iter().map(|r| r["custom_id"].as_str().unwrap()).collect();
+        assert_eq!(ids, ["a", "b"]);
+    }
+
+    #[tokio::test]
+    async fn test_cancelling_batch_stops_without_sending() {
+        let fx = Fixture::new().await;
+        let mut stored = fx.batch(&[line("a", "m"), line("b", "m")], Some("gw-1")).await;
+        stored = fx
+            .store
+            .update_batch(&stored.batch.id, |b| {
+                b.batch.status = BatchStatus::Cancelling;
+                b.batch.cancelling_at = Some(now_secs());
+                true
+            })
+            .await
+            .unwrap()
+            .unwrap();
+        fx.executor("gw-1").run(stored.clone()).await.unwrap();
+
+        let done = fx.store.get_batch(&stored.batch.id).await.unwrap().unwrap();
+        assert_eq!(done.batch.status, BatchStatus::Cancelled);
+        assert!(done.batch.cancelled_at.is_some());
+        assert_eq!(done.batch.output_file_id, None);
+        assert_eq!(done.batch.request_counts.completed, 0);
+        assert_eq!(fx.hits.load(Ordering::SeqCst), 0);
+    }
+
+    #[tokio::test]
+    async fn test_replicas_claim_only_lapsed_leases() {
+        let fx = Fixture::new().await;
+        let (a, b) = (fx.executor("gw-a"), fx.executor("gw-b"));
+        let stored = fx.batch(&[line("a", "m")], Some("gw-a")).await;
+        let id = stored.batch.id.clone();
+
+        // gw-a's lease is live: gw-b leaves the batch alone and can't renew it.
+        b.poll().await.unwrap();
+        let current = fx.store.get_batch(&id).await.unwrap().unwrap();
+        assert_eq!(current.worker.as_deref(), Some("gw-a"));
+        assert_eq!(current.batch.status, BatchStatus::Validating);
+        assert!(b.renew(&id, 0, 0).await.unwrap().is_none());
+        assert!(a.renew(&id, 0, 0).await.unwrap().is_some());
+
+        // Once the lease lapses gw-b takes over and runs the batch, and gw-a
+        // learns it lost the lease at its next heartbeat.
+        fx.store
+            .update_batch(&id, |b| {
+                b.lease_until_ms = 0;
+                true
+            })
+            .await
+            .unwrap();
+        b.poll().await.unwrap();
+        assert!(a.renew(&id, 0, 0).await.unwrap().is_none());
+        let done = tokio::time::timeout(Duration::from_secs(5), async {
+            loop {
+                let current = fx.store.get_batch(&id).await.unwrap().unwrap();
+                if !current.batch.status.is_active() {
+                    return current;
+                }
+                tokio::time::sleep(Duration::from_millis(10)).await;
+            }
+        })
+        .await
+        .unwrap();
+        assert_eq!(done.batch.status, BatchStatus::Completed);
+        assert_eq!(fx.hits.load(Ordering::SeqCst), 1);
+    }
+}
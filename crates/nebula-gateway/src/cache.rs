@@ -0,0 +1,537 @@
+//! Response cache for deterministic completions.
+//!
+//! Only non-streaming `/v1/chat/completions` and `/v1/completions` requests
+//! that set `temperature` to 0 for an opted-in model are cached. Entries are
+//! keyed by a SHA-256 of the normalized request JSON and the caller's tenant
+//! (or principal), so a cached answer is never served across tenants.
+//!
+//! In semantic mode an exact miss embeds the prompt with a deployed embeddings
+//! model through the router, and the response of the most similar cached
+//! prompt is reused when every other request parameter matches and the cosine
+//! similarity reaches the threshold. Only the newest entries of a group are
+//! compared. Entries live in process memory, so each gateway replica keeps
+//! its own cache.
+
+use std::collections::{HashMap, VecDeque};
+use std::sync::{Arc, Mutex};
+use std::time::{Duration, Instant};
+
+use axum::{
+    body::Body,
+    http::{HeaderMap, HeaderValue, StatusCode},
+    response::Response,
+};
+use bytes::Bytes;
+use serde_json::{json, Value};
+use sha2::{Digest, Sha256};
+
+use nebula_common::execution_context::deadline_remaining;
+
+use crate::auth::AuthContext;
+use crate::metrics::Metrics;
+
+/// Request header `bypass` skips the cache; the response header reports the
+/// lookup result (`hit`, `semantic-hit` or `miss`).
+pub const CACHE_HEADER: &str = "x-nebula-cache";
+
+/// Request fields that don't change the completion.
+const IGNORED_FIELDS: &[&str] = &["stream", "stream_options", "user", "metadata", "store"];
+/// Cached prompts compared per semantic lookup, newest first.
+const MAX_SEMANTIC_CANDIDATES: usize = 256;
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum CacheResult {
+    Hit,
+    SemanticHit,
+    Miss,
+    Bypass,
+}
+
+#[derive(Debug, Clone)]
+pub struct CacheConfig {
+    /// Models whose responses may be cached; a trailing `*` matches a prefix.
+    pub models: Vec<String>,
+    pub ttl: Duration,
+    pub max_entries: usize,
+    /// Embeddings model used for semantic lookups; `None` keeps exact-match only.
+    pub semantic_model: Option<String>,
+    /// Minimum cosine similarity for a semantic hit.
+    pub similarity_threshold: f32,
+}
+
+impl CacheConfig {
+    /// Parses a comma-separated model list. Returns `None` when no model opts in.
+    pub fn parse_models(raw: &str) -> Option<Vec<String>> {
+        let models: Vec<String> = raw
+            .split(',')
+            .map(str::trim)
+            .filter(|m| !m.is_empty())
+            .map(str::to_string)
+            .collect();
+        (!models.is_empty()).then_some(models)
+    }
+
+    fn caches_model(&self, model: &str) -> bool {
+        self.models.iter().any(|m| match m.strip_suffix('*') {
+            Some(prefix) => model.starts_with(prefix),
+            None => m == model,
+        })
+    }
+}
+
+/// Identifies a cacheable request.
+pub struct CacheKey {
+    /// Hash of the scope and the whole normalized request.
+    exact: String,
+    /// Hash of the scope and the request without its prompt; semantic matches
+    /// are only considered within a group.
+    group: String,
+    /// Prompt text to embed, when the request has one that can be compared.
+    prompt: Option<String>,
+}
+
+/// What a lookup found.
+pub enum Lookup {
+    Hit(Response),
+    /// Nothing cached; carries the prompt embedding for [`ResponseCache::store`].
+    Miss(Option<Vec<f32>>),
+}
+
+struct Entry {
+    body: Bytes,
+    group: String,
+    embedding: Option<Vec<f32>>,
+    stored_at: Instant,
+}
+
+/// Cached entries, indexed so that no operation scans the whole cache.
+#[derive(Default)]
+struct Entries {
+    by_key: HashMap<String, Entry>,
+    /// Exact keys with their store time, oldest first. Keys stored again
+    /// leave a stale record behind, skipped when it reaches the front.
+    order: VecDeque<(Instant, String)>,
+    /// Exact keys of each group, oldest first.
+    groups: HashMap<String, VecDeque<String>>,
+}
+
+impl Entries {
+    fn remove(&mut self, exact: &str) {
+        let Some(entry) = self.by_key.remove(exact) else {
+            return;
+        };
+        if let Some(keys) = self.groups.get_mut(&entry.group) {
+            if keys.front().is_some_and(|k| k == exact) {
+                keys.pop_front();
+            } else {
+                keys.retain(|k| k != exact);
+            }
+            if keys.is_empty() {
+                self.groups.remove(&entry.group);
+            }
+        }
+    }
+
+    /// Drop the oldest entry. `false` when the cache is empty.
+    fn pop_oldest(&mut self) -> bool {
+        while let Some((stored_at, exact)) = self.order.pop_front() {
+            if self.by_key.get(&exact).is_some_and(|e| e.stored_at == stored_at) {
+                self.remove(&exact);
+                return true;
+            }
+        }
+        false
+    }
+
+    fn expire(&mut self, ttl: Duration) {
+        while self.order.front().is_some_and(|(t, _)| t.elapsed() >= ttl) {
+            self.pop_oldest();
+        }
+    }
+
+    fn insert(&mut self, exact: String, entry: Entry) {
+        self.remove(&exact);
+        self.order.push_back((entry.stored_at, exact.clone()));
+        self.groups
+            .entry(entry.group.clone())
+            .or_default()
+            .push_back(exact.clone());
+        self.by_key.insert(exact, entry);
+        // Bound the stale records left by keys stored more than once.
+        if self.order.len() > 2 * self.by_key.len() + 16 {
+            let by_key = &self.by_key;
+            self.order
+                .retain(|(t, k)| by_key.get(k).is_some_and(|e| e.stored_at == *t));
+        }
+    }
+}
+
+pub struct ResponseCache {
+    config: CacheConfig,
+    http: reqwest::Client,
+    router_base_url: String,
+    metrics: Arc<Metrics>,
+    entries: Mutex<Entries>,
+}
+
+impl ResponseCache {
+    pub fn new(config: CacheConfig, http: reqwest::Client, router_base_url: String, metrics: Arc<Metrics>) -> Self {
+        Self {
+            config,
+            http,
+            router_base_url,
+            metrics,
+            entries: Mutex::new(Entries::default()),
+        }
+    }
+
+    /// Returns the key for a cacheable request, or `None` when the request
+    /// must go upstream uncached.
+    pub fn key(&self, path: &str, headers: &HeaderMap, auth: Option<&AuthContext>, body: &[u8]) -> Option<CacheKey> {
+        if !matches!(path, "/v1/chat/completions" | "/v1/completions") {
+            return None;
+        }
+        let Ok(Value::Object(mut request)) = serde_json::from_slice::<Value>(body) else {
+            return None;
+        };
+        let model = request.get("model").and_then(Value::as_str)?;
+        if !self.config.caches_model(model) {
+            return None;
+        }
+        if request.get("stream").and_then(Value::as_bool) == Some(true)
+            || request.get("temperature").and_then(Value::as_f64) != Some(0.0)
+        {
+            return None;
+        }
+        let bypass = headers
+            .get(CACHE_HEADER)
+            .and_then(|v| v.to_str().ok())
+            .is_some_and(|v| v.eq_ignore_ascii_case("bypass"));
+        if bypass {
+            self.metrics.record_cache(CacheResult::Bypass);
+            return None;
+        }
+
+        for field in IGNORED_FIELDS {
+            request.remove(*field);
+        }
+        let scope = match auth {
+            Some(AuthContext { tenant: Some(t), .. }) => format!("tenant/{t}"),
+            Some(c) => format!("principal/{}", c.principal_label()),
+            None => "anonymous".to_string(),
+        };
+        let exact = digest(&scope, path, &Value::Object(request.clone()));
+        let prompt = prompt_text(&request);
+        request.remove("messages");
+        request.remove("prompt");
+        let group = digest(&scope, path, &Value::Object(request));
+        Some(CacheKey { exact, group, prompt })
+    }
+
+    /// Looks up an exact match, then a semantic one when enabled.
+    pub async fn lookup(&self, key: &CacheKey, headers: &HeaderMap) -> Lookup {
+        if let Some(body) = self.get_exact(&key.exact) {
+            self.metrics.record_cache(CacheResult::Hit);
+            return Lookup::Hit(hit_response(body, "hit"));
+        }
+        let embedding = match (self.config.semantic_model.as_deref(), key.prompt.as_deref()) {
+            (Some(model), Some(prompt)) => self.embed(model, prompt, headers).await,
+            _ => None,
+        };
+        if let Some(body) = embedding.as_deref().and_then(|e| self.get_similar(&key.group, e)) {
+            self.metrics.record_cache(CacheResult::SemanticHit);
+            return Lookup::Hit(hit_response(body, "semantic-hit"));
+        }
+        self.metrics.record_cache(CacheResult::Miss);
+        Lookup::Miss(embedding)
+    }
+
+    /// Caches a successful upstream response.
+    pub fn store(&self, key: CacheKey, body: Bytes, embedding: Option<Vec<f32>>) {
+        let Ok(mut entries) = self.entries.lock() else {
+            return;
+        };
+        entries.expire(self.config.ttl);
+        while entries.by_key.len() >= self.config.max_entries.max(1) && entries.pop_oldest() {}
+        entries.insert(
+            key.exact,
+            Entry {
+                body,
+                group: key.group,
+                embedding,
+                stored_at: Instant::now(),
+            },
+        );
+    }
+
+    fn get_exact(&self, exact: &str) -> Option<Bytes> {
+        let entries = self.entries.lock().ok()?;
+        entries
+            .by_key
+            .get(exact)
+            .filter(|e| e.stored_at.elapsed() < self.config.ttl)
+            .map(|e| e.body.clone())
+    }
+
+    fn get_similar(&self, group: &str, embedding: &[f32]) -> Option<Bytes> {
+        let entries = self.entries.lock().ok()?;
+        entries
+            .groups
+            .get(group)?
+            .iter()
+            .rev()
+            .take(MAX_SEMANTIC_CANDIDATES)
+            .filter_map(|k| entries.by_key.get(k))
+            .filter(|e| e.stored_at.elapsed() < self.config.ttl)
+            .filter_map(|e| Some((cosine(e.embedding.as_deref()?, embedding), e)))
+            .filter(|(sim, _)| *sim >= self.config.similarity_threshold)
+            .max_by(|a, b| a.0.total_cmp(&b.0))
+            .map(|(_, e)| e.body.clone())
+    }
+
+    /// Embeds `prompt` through the router with the caller's credentials, so the
+    /// lookup is authorized and metered like any other embeddings request.
+    async fn embed(&self, model: &str, prompt: &str, headers: &HeaderMap) -> Option<Vec<f32>> {
+        let url = format!("{}/v1/embeddings", self.router_base_url.trim_end_matches('/'));
+        let mut request = self.http.post(url).json(&json!({ "model": model, "input": prompt }));
+        if let Some(auth) = headers.get(axum::http::header::AUTHORIZATION) {
+            request = request.header(reqwest::header::AUTHORIZATION, auth.clone());
+        }
+        if let Some(remaining) = deadline_remaining(headers) {
+            request = request.timeout(remaining);
+        }
+        let resp = match request.send().await {
+            Ok(resp) if resp.status().is_success() => resp,
+            Ok(resp) => {
+                tracing::warn!(status=%resp.status(), model=%model, "cache embedding request failed");
+                return None;
+            }
+            Err(e) => {
+                tracing::warn!(error=%e, model=%model, "cache embedding request failed");
+                return None;
+            }
+        };
+        let body: Value = resp.json().await.ok()?;
+        let embedding = body.pointer("/data/0/embedding")?.as_array()?;
+        embedding.iter().map(|x| x.as_f64().map(|x| x as f32)).collect()
+    }
+}
+
+fn digest(scope: &str, path: &str, request: &Value) -> String {
+    let mut hasher = Sha256::new();
+    hasher.update(scope.as_bytes());
+    hasher.update([0]);
+    hasher.update(path.as_bytes());
+    hasher.update([0]);
+    // serde_json maps are sorted, so this is canonical.
+    hasher.update(request.to_string().as_bytes());
+    hasher.finalize().iter().map(|b| format!("{b:02x}")).collect()
+}
+
+/// The text a semantic lookup compares. Requests with non-text content (such
+/// as images) have none and are only ever matched exactly.
+fn prompt_text(request: &serde_json::Map<String, Value>) -> Option<String> {
+    if let Some(messages) = request.get("messages").and_then(Value::as_array) {
+        let mut lines = Vec::with_capacity(messages.len());
+        for message in messages {
+            let role = message.get("role").and_then(Value::as_str).unwrap_or("user");
+            let text = match message.get("content") {
+                Some(Value::String(s)) => s.clone(),
+                Some(Value::Array(parts)) => {
+                    let mut text = String::new();
+                    for part in parts {
+                        if part.get("type").and_then(Value::as_str) != Some("text") {
+                            return None;
+                        }
+                        text.push_str(part.get("text").and_then(Value::as_str).unwrap_or(""));
+                    }
+                    text
+                }
+                Some(Value::Null) | None => String::new(),
+                Some(_) => return None,
+            };
+            lines.push(format!("{role}: {text}"));
+        }
+        return Some(lines.join("\n"));
+    }
+    request.get("prompt").and_then(Value::as_str).map(str::to_string)
+}
+
+fn cosine(a: &[f32], b: &[f32]) -> f32 {
+    if a.len() != b.len() || a.is_empty() {
+        return 0.0;
+    }
+    let (mut dot, mut na, mut nb) = (0.0f32, 0.0f32, 0.0f32);
+    for (x, y) in a.iter().zip(b) {
+        dot += x * y;
+        na += x * x;
+        nb += y * y;
+    }
+    if na == 0.0 || nb == 0.0 {
+        return 0.0;
+    }
+    dot / (na.sqrt() * nb.sqrt())
+}
+
+fn hit_response(body: Bytes, result: &'static str) -> Response {
+    let mut out = Response::new(Body::from(body));
+    *out.status_mut() = StatusCode::OK;
+    out.headers_mut()
+        .insert(axum::http::header::CONTENT_TYPE, HeaderValue::from_static("application/json"));
+    out.headers_mut()
+        .insert(CACHE_HEADER, HeaderValue::from_static(result));
+    out
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::auth::Role;
+
+    fn cache(max_entries: usize, ttl: Duration) -> ResponseCache {
+        let config = CacheConfig {
+            models: vec!["llama*".to_string()],
+            ttl,
+            max_entries,
+            semantic_model: None,
+            similarity_threshold: 0.9,
+        };
+        ResponseCache::new(
+            config,
+            reqwest::Client::new(),
+            "http://router".to_string(),
+            Arc::new(Metrics::default()),
+        )
+    }
+
+    fn caller(principal: &str, tenant: Option<&str>) -> AuthContext {
+        AuthContext {
+            principal: principal.to_string(),
+            role: Role::Operator,
+            tenant: tenant.map(str::to_string),
+            api_key: None,
+        }
+    }
+
+    fn chat(prompt: &str, extra: Value) -> Vec<u8> {
+        let mut body = json!({
+            "model": "llama-8b",
+            "messages": [{"role": "user", "content": prompt}],
+            "temperature": 0
+        });
+        if let (Some(b), Value::Object(extra)) = (body.as_object_mut(), extra) {
+            b.extend(extra);
+        }
+        serde_json::to_vec(&body).unwrap()
+    }
+
+    fn key(c: &ResponseCache, auth: Option<&AuthContext>, body: &[u8]) -> Option<CacheKey> {
+        c.key("/v1/chat/completions", &HeaderMap::new(), auth, body)
+    }
+
+    #[test]
+    fn test_only_deterministic_requests_are_cached() {
+        let c = cache(16, Duration::from_secs(60));
+        assert!(key(&c, None, &chat("hi", json!({}))).is_some());
+        assert!(key(&c, None, &chat("hi", json!({"temperature": 0.0}))).is_some());
+        assert!(key(&c, None, &chat("hi", json!({"temperature": 0.2}))).is_none());
+        assert!(key(&c, None, &chat("hi", json!({"temperature": null}))).is_none());
+        assert!(key(&c, None, &chat("hi", json!({"stream": true}))).is_none());
+        assert!(key(&c, None, &chat("hi", json!({"model": "qwen"}))).is_none());
+        assert!(c
+            .key("/v1/embeddings", &HeaderMap::new(), None, &chat("hi", json!({})))
+            .is_none());
+        let mut bypass = HeaderMap::new();
+        bypass.insert(CACHE_HEADER, HeaderValue::from_static("bypass"));
+        assert!(c
+            .key("/v1/chat/completions", &bypass, None, &chat("hi", json!({})))
+            .is_none());
+
+        // Fields that don't change the output don't change the key.
+        let plain = key(&c, None, &chat("hi", json!({}))).unwrap();
+        let tagged = key(&c, None, &chat("hi", json!({"user": "u1", "stream": false}))).unwrap();
+        assert_eq!(plain.exact, tagged.exact);
+        let longer = key(&c, None, &chat("hi", json!({"max_tokens": 5}))).unwrap();
+        assert_ne!(plain.exact, longer.exact);
+    }
+
+    #[test]
+    fn test_keys_are_scoped_to_tenant_or_principal() {
+        let c = cache(16, Duration::from_secs(60));
+        let body = chat("hi", json!({}));
+        let exact = |auth: Option<&AuthContext>| key(&c, auth, &body).unwrap().exact;
+
+        let alice = caller("alice-token", None);
+        let bob = caller("bob-token", None);
+        assert_ne!(exact(Some(&alice)), exact(Some(&bob)));
+        assert_ne!(exact(Some(&alice)), exact(None));
+
+        // Principals of one tenant share entries; tenants never do.
+        let acme_a = caller("alice-token", Some("acme"));
+        let acme_b = caller("bob-token", Some("acme"));
+        let other = caller("alice-token", Some("globex"));
+        assert_eq!(exact(Some(&acme_a)), exact(Some(&acme_b)));
+        assert_ne!(exact(Some(&acme_a)), exact(Some(&other)));
+
+        // A tenant named after a principal label is still a different scope.
+        let lookalike = caller("carol-token", Some(&alice.principal_label()));
+        assert_ne!(exact(Some(&lookalike)), exact(Some(&alice)));
+        // Groups are scoped the same way.
+        let group = |auth: Option<&AuthContext>| key(&c, auth, &body).unwrap().group;
+        assert_ne!(group(Some(&alice)), group(Some(&bob)));
+    }
+
+    #[tokio::test]
+    async fn test_store_lookup_and_eviction() {
+        let c = cache(2, Duration::from_secs(60));
+        let headers = HeaderMap::new();
+        let store = |prompt: &str| {
+            let k = key(&c, None, &chat(prompt, json!({}))).unwrap();
+            c.store(k, Bytes::from(prompt.to_string()), None);
+        };
+        let hit = |prompt: &str| {
+            let k = key(&c, None, &chat(prompt, json!({}))).unwrap();
+            c.get_exact(&k.exact)
+        };
+
+        store("a");
+        store("b");
+        store("a");
+        store("c");
+        // "b" is the oldest once "a" was stored again.
+        assert_eq!(hit("a").as_deref(), Some(&b"a"[..]));
+        assert_eq!(hit("b"), None);
+        assert_eq!(hit("c").as_deref(), Some(&b"c"[..]));
+        assert_eq!(c.entries.lock().unwrap().by_key.len(), 2);
+
+        let k = key(&c, None, &chat("c", json!({}))).unwrap();
+        match c.lookup(&k, &headers).await {
+            Lookup::Hit(resp) => assert_eq!(resp.headers()[CACHE_HEADER], "hit"),
+            Lookup::Miss(_) => panic!("expected a hit"),
+        }
+
+        let expired = cache(2, Duration::ZERO);
+        let k = key(&expired, None, &chat("a", json!({}))).unwrap();
+        let exact = k.exact.clone();
+        expired.store(k, Bytes::from_static(b"a"), None);
+        assert_eq!(expired.get_exact(&exact), None);
+    }
+
+    #[test]
+    fn test_semantic_matches_stay_in_group() {
+        let c = cache(16, Duration::from_secs(60));
+        let a = key(&c, None, &chat("what is rust", json!({}))).unwrap();
+        let group = a.group.clone();
+        c.store(a, Bytes::from_static(b"rust"), Some(vec![1.0, 0.0]));
+        let other = key(&c, None, &chat("what is go", json!({"max_tokens": 5}))).unwrap();
+        let other_group = other.group.clone();
+        c.store(other, Bytes::from_static(b"go"), Some(vec![0.0, 1.0]));
+
+        assert_eq!(c.get_similar(&group, &[0.99, 0.05]).as_deref(), Some(&b"rust"[..]));
+        assert_eq!(c.get_similar(&group, &[0.0, 1.0]), None);
+        assert_eq!(c.get_similar(&other_group, &[0.99, 0.05]), None);
+        assert_eq!(c.get_similar("unknown", &[1.0, 0.0]), None);
+        assert!((cosine(&[1.0, 2.0], &[2.0, 4.0]) - 1.0).abs() < 1e-6);
+        assert_eq!(cosine(&[1.0], &[1.0, 0.0]), 0.0);
+    }
+}
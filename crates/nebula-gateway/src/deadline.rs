@@ -0,0 +1,155 @@
+//! Request deadlines.
+//!
+//! Clients bound a request with `x-request-timeout` (seconds); API keys may
+//! set a default, and every request is capped at the gateway's maximum
+//! timeout. The timeout becomes an absolute `x-nebula-deadline-ms` forwarded
+//! to the router, which bounds its retries and upstream calls by it.
+
+use std::time::Duration;
+
+use axum::{
+    body::Body,
+    extract::State,
+    http::{HeaderValue, Request},
+    middleware::Next,
+    response::{IntoResponse, Response},
+};
+
+use nebula_common::execution_context::{
+    parse_timeout_secs, BUDGET_TOKENS_HEADER, DEADLINE_HEADER, REQUEST_TIMEOUT_HEADER,
+};
+use nebula_common::ratelimit::now_ms;
+use nebula_common::{ApiError, ErrorCode};
+
+use crate::auth::AuthContext;
+use crate::state::AppState;
+
+/// Sets the deadline header on API requests, replacing any sent by the client.
+pub async fn deadline_middleware(State(st): State<AppState>, mut req: Request<Body>, next: Next) -> Response {
+    let path = req.uri().path();
+    if !path.starts_with("/v1/") || path.starts_with("/v1/admin/") {
+        return next.run(req).await;
+    }
+    if let Err(e) = set_deadline(&mut req, st.max_request_timeout) {
+        return e.into_response();
+    }
+    next.run(req).await
+}
+
+/// Validates the timeout headers and writes the absolute deadline, capped at
+/// `max_timeout` from now.
+fn set_deadline(req: &mut Request<Body>, max_timeout: Duration) -> Result<(), ApiError> {
+    let requested = match req.headers().get(REQUEST_TIMEOUT_HEADER) {
+        Some(v) => match v.to_str().ok().and_then(parse_timeout_secs) {
+            Some(timeout) => Some(timeout),
+            None => {
+                return Err(ApiError::new(
+                    ErrorCode::InvalidRequest,
+                    format!("{REQUEST_TIMEOUT_HEADER} must be a positive number of seconds"),
+                ))
+            }
+        },
+        None => req
+            .extensions()
+            .get::<AuthContext>()
+            .and_then(|ctx| ctx.api_key.as_ref())
+            .and_then(|key| key.request_timeout_secs)
+            .map(Duration::from_secs),
+    };
+    let budget = req.headers().get(BUDGET_TOKENS_HEADER);
+    if budget.is_some_and(|v| v.to_str().ok().and_then(|s| s.trim().parse::<u32>().ok()).is_none()) {
+        return Err(ApiError::new(
+            ErrorCode::InvalidRequest,
+            format!("{BUDGET_TOKENS_HEADER} must be a non-negative integer"),
+        ));
+    }
+
+    let timeout = requested.map_or(max_timeout, |t| t.min(max_timeout));
+    let deadline = now_ms() + timeout.as_millis() as u64;
+    req.headers_mut().insert(DEADLINE_HEADER, HeaderValue::from(deadline));
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::sync::Arc;
+
+    use nebula_common::api_key::ApiKey;
+
+    use crate::auth::Role;
+
+    const MAX: Duration = Duration::from_secs(600);
+
+    fn request(headers: &[(&str, &str)]) -> Request<Body> {
+        let mut builder = Request::post("/v1/chat/completions");
+        for (name, value) in headers {
+            builder = builder.header(*name, *value);
+        }
+        builder.body(Body::empty()).unwrap()
+    }
+
+    /// Milliseconds from now until the deadline written on `req`.
+    fn remaining_ms(req: &Request<Body>) -> u64 {
+        let deadline: u64 = req.headers()[DEADLINE_HEADER].to_str().unwrap().parse().unwrap();
+        deadline.saturating_sub(now_ms())
+    }
+
+    #[test]
+    fn test_request_timeout_becomes_the_deadline() {
+        let mut req = request(&[(REQUEST_TIMEOUT_HEADER, "2.5"), (DEADLINE_HEADER, "1")]);
+        set_deadline(&mut req, MAX).unwrap();
+        let remaining = remaining_ms(&req);
+        assert!((2_000..=2_500).contains(&remaining), "remaining {remaining}ms");
+    }
+
+    #[test]
+    fn test_timeouts_are_capped_at_the_maximum() {
+        let mut req = request(&[(REQUEST_TIMEOUT_HEADER, "3600")]);
+        set_deadline(&mut req, MAX).unwrap();
+        assert!(remaining_ms(&req) <= 600_000);
+
+        let mut req = request(&[]);
+        set_deadline(&mut req, Duration::from_secs(30)).unwrap();
+        let remaining = remaining_ms(&req);
+        assert!((29_000..=30_000).contains(&remaining), "remaining {remaining}ms");
+    }
+
+    #[test]
+    fn test_api_key_timeout_applies_without_a_header() {
+        let (_, mut key) = ApiKey::generate("alice".to_string(), Role::Operator, None, vec![], None, None, 0);
+        key.request_timeout_secs = Some(5);
+        let ctx = AuthContext {
+            principal: "alice".to_string(),
+            role: Role::Operator,
+            tenant: None,
+            api_key: Some(Arc::new(key)),
+        };
+
+        let mut req = request(&[]);
+        req.extensions_mut().insert(ctx.clone());
+        set_deadline(&mut req, MAX).unwrap();
+        let remaining = remaining_ms(&req);
+        assert!((4_000..=5_000).contains(&remaining), "remaining {remaining}ms");
+
+        let mut req = request(&[(REQUEST_TIMEOUT_HEADER, "60")]);
+        req.extensions_mut().insert(ctx);
+        set_deadline(&mut req, MAX).unwrap();
+        assert!(remaining_ms(&req) > 5_000);
+    }
+
+    #[test]
+    fn test_invalid_headers_are_rejected() {
+        for headers in [
+            [(REQUEST_TIMEOUT_HEADER, "soon")],
+            [(REQUEST_TIMEOUT_HEADER, "0")],
+            [(REQUEST_TIMEOUT_HEADER, "-1")],
+            [(BUDGET_TOKENS_HEADER, "lots")],
+        ] {
+            let mut req = request(&headers);
+            let err = set_deadline(&mut req, MAX).unwrap_err();
+            assert_eq!(err.code, ErrorCode::InvalidRequest, "{headers:?}");
+            assert!(!req.headers().contains_key(DEADLINE_HEADER));
+        }
+    }
+}
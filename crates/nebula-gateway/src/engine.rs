@@ -1,30 +1,161 @@
+use std::future::Future;
 use std::pin::Pin;
 use std::time::Duration;
 
 use futures_core::Stream;
 use futures_util::StreamExt;
+use nebula_common::execution_context::deadline_remaining;
+use reqwest::header::HeaderMap;
 use serde_json::Value;
 use tokio::sync::mpsc;
 use tokio_stream::wrappers::ReceiverStream;
 
-pub type EngineDeltaStream = Pin<Box<dyn Stream<Item = String> + Send>>;
+/// Run `task` until it completes or the receiving side of `tx` is dropped.
+/// Returns `None` when the receiver went away first; `task` is then dropped,
+/// which closes its upstream connection and stops generation.
+pub async fn until_closed<T, R>(tx: mpsc::Sender<T>, task: impl Future<Output = R>) -> Option<R> {
+    tokio::select! {
+        r = task => Some(r),
+        _ = tx.closed() => None,
+    }
+}
+
+/// One piece of a streamed chat completion.
+#[derive(Debug, Clone, PartialEq)]
+pub enum EngineEvent {
+    /// Assistant text delta.
+    Text(String),
+    /// Tool call delta; `id` and `name` arrive with the first delta of a call.
+    ToolCall {
+        index: u32,
+        id: Option<String>,
+        name: Option<String>,
+        arguments: String,
+    },
+    /// Why generation stopped (`stop`, `length`, `tool_calls`, ...).
+    /// `stop_sequence` is the matched stop string when the engine reports it
+    /// (vLLM's `stop_reason`).
+    Finish {
+        reason: String,
+        stop_sequence: Option<String>,
+    },
+    /// Token usage reported by the engine.
+    Usage { input_tokens: u32, output_tokens: u32 },
+    /// The request failed; no further events follow. `status` is the upstream
+    /// HTTP status, 504 when the request deadline passed, or 502 when the
+    /// upstream could not be reached or the stream broke off.
+    Error { status: u16, message: String },
+}
+
+impl EngineEvent {
+    fn failed(e: &reqwest::Error, message: &str) -> Self {
+        if e.is_timeout() {
+            return Self::Error {
+                status: 504,
+                message: "request deadline exceeded".to_string(),
+            };
+        }
+        Self::Error {
+            status: 502,
+            message: message.to_string(),
+        }
+    }
+}
+
+pub type EngineEventStream = Pin<Box<dyn Stream<Item = EngineEvent> + Send>>;
 
 pub trait EngineClient: Send + Sync {
-    fn stream_text(&self, input: String) -> EngineDeltaStream;
+    /// Stream an OpenAI chat/completions request. The client fills in `stream`
+    /// and `stream_options`, and `model` when the body has none. `headers` are
+    /// forwarded upstream (auth, `x-session-id`).
+    fn stream_chat(&self, body: Value, headers: HeaderMap) -> EngineEventStream;
 }
 
+/// Message of an OpenAI error body, or the raw body text.
+fn error_message(body: &str) -> String {
+    serde_json::from_str::<Value>(body)
+        .ok()
+        .and_then(|v| {
+            v.pointer("/error/message")
+                .or_else(|| v.get("message"))
+                .and_then(|m| m.as_str())
+                .map(str::to_string)
+        })
+        .unwrap_or_else(|| body.trim().to_string())
+}
+
+/// Engine events carried by one chat/completions SSE chunk.
+fn chunk_events(v: &Value) -> Vec<EngineEvent> {
+    let mut out = Vec::new();
+    if let Some(c0) = v.get("choices").and_then(|c| c.get(0)) {
+        let text = c0
+            .get("delta")
+            .and_then(|d| d.get("content"))
+            .and_then(|t| t.as_str())
+            .or_else(|| c0.get("text").and_then(|t| t.as_str()))
+            .unwrap_or("");
+        if !text.is_empty() {
+            out.push(EngineEvent::Text(text.to_string()));
+        }
+        let tool_calls = c0
+            .get("delta")
+            .and_then(|d| d.get("tool_calls"))
+            .and_then(|t| t.as_array());
+        for (i, tc) in tool_calls.into_iter().flatten().enumerate() {
+            let function = tc.get("function");
+            out.push(EngineEvent::ToolCall {
+                index: tc
+                    .get("index")
+                    .and_then(|x| x.as_u64())
+                    .unwrap_or(i as u64) as u32,
+                id: tc.get("id").and_then(|x| x.as_str()).map(str::to_string),
+                name: function
+                    .and_then(|f| f.get("name"))
+                    .and_then(|x| x.as_str())
+                    .map(str::to_string),
+                arguments: function
+                    .and_then(|f| f.get("arguments"))
+                    .and_then(|x| x.as_str())
+                    .unwrap_or("")
+                    .to_string(),
+            });
+        }
+        if let Some(reason) = c0.get("finish_reason").and_then(|r| r.as_str()) {
+            out.push(EngineEvent::Finish {
+                reason: reason.to_string(),
+                stop_sequence: c0
+                    .get("stop_reason")
+                    .and_then(|r| r.as_str())
+                    .map(str::to_string),
+            });
+        }
+    }
+    if let Some(usage) = v.get("usage").filter(|u| u.is_object()) {
+        let tokens = |k: &str| usage.get(k).and_then(|x| x.as_u64()).unwrap_or(0) as u32;
+        out.push(EngineEvent::Usage {
+            input_tokens: tokens("prompt_tokens"),
+            output_tokens: tokens("completion_tokens"),
+        });
+    }
+    out
+}
+
+/// Chat client for an OpenAI-compatible upstream. The gateway points it at the
+/// router, which resolves `model` and applies session affinity, retries and
+/// per-model metrics.
 #[derive(Debug, Clone)]
 pub struct OpenAIEngineClient {
     base_url: String,
-    model: String,
+    /// Used when a request names no model.
+    default_model: String,
     http: reqwest::Client,
 }
 
 impl OpenAIEngineClient {
-    pub fn new(base_url: String, model: String) -> Self {
+    pub fn new(base_url: String, default_model: String, timeout: Duration) -> Self {
         let http = reqwest::Client::builder()
             .connect_timeout(Duration::from_secs(3))
-            .timeout(Duration::from_secs(300))
+            .timeout(timeout)
             .build()
             .unwrap_or_else(|e| {
                 tracing::error!(error=%e, "failed to build reqwest client");
@@ -33,32 +164,38 @@ impl OpenAIEngineClient {
 
         Self {
             base_url,
-            model,
+            default_model,
             http,
         }
     }
 }
 
 impl EngineClient for OpenAIEngineClient {
-    fn stream_text(&self, input: String) -> EngineDeltaStream {
-        let (tx, rx) = mpsc::channel::<String>(256);
+    fn stream_chat(&self, body: Value, headers: HeaderMap) -> EngineEventStream {
+        let (tx, rx) = mpsc::channel::<EngineEvent>(256);
         let http = self.http.clone();
         let base = self.base_url.trim_end_matches('/').to_string();
-        let model = self.model.clone();
+        let default_model = self.default_model.clone();
 
-        tokio::spawn(async move {
+        let done = tx.clone();
+        let forward = async move {
             let url = format!("{base}/v1/chat/completions");
-            let body = serde_json::json!({
-                "model": model,
-                "messages": [{"role": "user", "content": input}],
-                "stream": true,
-                "max_tokens": 512,
-            });
+            let mut body = body;
+            if body.get("model").and_then(|m| m.as_str()).is_none_or(str::is_empty) {
+                body["model"] = Value::String(default_model);
+            }
+            body["stream"] = Value::Bool(true);
+            body["stream_options"] = serde_json::json!({"include_usage": true});
 
-            let resp = match http.post(url).json(&body).send().await {
+            let mut request = http.post(url);
+            if let Some(remaining) = deadline_remaining(&headers) {
+                request = request.timeout(remaining);
+            }
+            let resp = match request.headers(headers).json(&body).send().await {
                 Ok(r) => r,
                 Err(e) => {
                     tracing::error!(error=%e, "engine request failed");
+                    let _ = tx.send(EngineEvent::failed(&e, "upstream request failed")).await;
                     return;
                 }
             };
@@ -73,6 +210,12 @@ impl EngineClient for OpenAIEngineClient {
                     }
                 };
                 tracing::error!(%status, body=%text, "engine returned error");
+                let _ = tx
+                    .send(EngineEvent::Error {
+                        status: status.as_u16(),
+                        message: error_message(&text),
+                    })
+                    .await;
                 return;
             }
 
@@ -83,6 +226,7 @@ impl EngineClient for OpenAIEngineClient {
                     Ok(c) => c,
                     Err(e) => {
                         tracing::error!(error=%e, "engine stream read failed");
+                        let _ = tx.send(EngineEvent::failed(&e, "upstream stream interrupted")).await;
                         return;
                     }
                 };
@@ -112,23 +256,15 @@ impl EngineClient for OpenAIEngineClient {
                         Err(_) => continue,
                     };
 
-                    let delta = v
-                        .get("choices")
-                        .and_then(|c| c.get(0))
-                        .and_then(|c0| {
-                            c0.get("delta")
-                                .and_then(|d| d.get("content"))
-                                .and_then(|t| t.as_str())
-                                .or_else(|| c0.get("text").and_then(|t| t.as_str()))
-                        })
-                        .unwrap_or("");
-
-                    if !delta.is_empty() {
-                        let _ = tx.send(delta.to_string()).await;
+                    for ev in chunk_events(&v) {
+                        if tx.send(ev).await.is_err() {
+                            return;
+                        }
                     }
                 }
             }
-        });
+        };
+        tokio::spawn(until_closed(done, forward));
 
         Box::pin(ReceiverStream::new(rx))
     }
@@ -0,0 +1,1111 @@
+//! Guardrails: policy stages run on requests before they reach a model and on
+//! the completions that come back.
+//!
+//! Policies are loaded from a JSON file and apply to the models and tenants
+//! they list (all of them when a list is empty). Stages see the text of a
+//! request (messages, prompt, embeddings input, rerank query and documents)
+//! or of a completion, and block, redact or flag it. Regex, keyword, PII and
+//! prompt-injection stages are built in, `max_length` bounds the amount of
+//! text, and `webhook` asks an external classifier. Built-in stages run
+//! before webhook stages.
+//!
+//! Streamed completions are filtered as they pass through: the last
+//! `stream_holdback_chars` characters of each choice are held back until more
+//! text arrives, so a match split across chunks is caught before it is sent.
+//! A streamed completion reaches webhook stages only once it has finished and
+//! can no longer be withheld, so their verdicts are recorded as flags.
+//! `/v1/responses` and `/v1/messages` completions arrive from the router as a
+//! stream whether or not the client streams, and are filtered the same way.
+//!
+//! Every action is counted in metrics and recorded in the request's audit
+//! entry.
+
+use std::collections::BTreeMap;
+use std::sync::Arc;
+use std::time::Duration;
+
+use anyhow::{Context, Result};
+use axum::response::{IntoResponse, Response};
+use bytes::Bytes;
+use regex::Regex;
+use serde::{Deserialize, Serialize};
+use serde_json::{json, Value};
+use tokio::sync::mpsc;
+use tokio_stream::{wrappers::ReceiverStream, StreamExt};
+
+use nebula_common::{ApiError, ErrorCode};
+
+use crate::audit::UsageRecorder;
+use crate::engine::{until_closed, EngineEvent, EngineEventStream};
+use crate::metrics::Metrics;
+
+const DEFAULT_REPLACEMENT: &str = "[REDACTED]";
+
+/// Built-in PII patterns, by kind.
+const PII_PATTERNS: &[(&str, &str)] = &[
+    ("email", r"[A-Za-z0-9._%+-]+@[A-Za-z0-9.-]+\.[A-Za-z]{2,}"),
+    ("phone", r"(?:\+\d{1,3}[ .-]?)?(?:\(\d{3}\)|\b\d{3})[ .-]?\d{3}[ .-]?\d{4}\b|\b1[3-9]\d{9}\b"),
+    ("credit_card", r"\b\d{4}[ -]?\d{4}[ -]?\d{4}[ -]?\d{1,4}\b"),
+    ("ssn", r"\b\d{3}-\d{2}-\d{4}\b"),
+    ("id_card", r"\b\d{17}[\dXx]\b"),
+    ("ipv4", r"\b(?:\d{1,3}\.){3}\d{1,3}\b"),
+];
+
+/// Phrases typical of attempts to override a model's instructions.
+const PROMPT_INJECTION_PATTERNS: &[&str] = &[
+    r"(?:ignore|disregard|forget)\s+(?:all\s+|any\s+)?(?:the\s+)?(?:previous|prior|above|earlier)\s+(?:instructions|prompts|rules|messages)",
+    r"(?:reveal|print|show|repeat|output)\s+(?:your\s+|the\s+)?(?:system|hidden|initial)\s+(?:prompt|instructions)",
+    r"you\s+are\s+now\s+(?:in\s+)?(?:developer\s+mode|dan\b|jailbroken)",
+    r"(?:忽略|无视|忘记)(?:之前|以上|上面|前面)的?(?:所有)?(?:指令|提示|规则|要求)",
+];
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(rename_all = "snake_case")]
+pub enum Action {
+    Block,
+    Redact,
+    Flag,
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(rename_all = "snake_case")]
+pub enum Direction {
+    Input,
+    Output,
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Deserialize)]
+#[serde(rename_all = "snake_case")]
+enum AppliesTo {
+    Input,
+    Output,
+    Both,
+}
+
+impl AppliesTo {
+    fn includes(self, direction: Direction) -> bool {
+        matches!(
+            (self, direction),
+            (Self::Both, _) | (Self::Input, Direction::Input) | (Self::Output, Direction::Output)
+        )
+    }
+}
+
+/// An action a guardrail took, as recorded in the audit entry. Matched text
+/// is never included.
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+pub struct GuardrailEvent {
+    pub policy: String,
+    pub stage: String,
+    pub direction: Direction,
+    pub action: Action,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub reason: Option<String>,
+}
+
+#[derive(Debug, Deserialize)]
+struct FileConfig {
+    #[serde(default = "default_holdback")]
+    stream_holdback_chars: usize,
+    policies: Vec<PolicyConfig>,
+}
+
+fn default_holdback() -> usize {
+    64
+}
+
+#[derive(Debug, Deserialize)]
+struct PolicyConfig {
+    name: String,
+    #[serde(default)]
+    models: Vec<String>,
+    #[serde(default)]
+    tenants: Vec<String>,
+    stages: Vec<StageConfig>,
+}
+
+#[derive(Debug, Deserialize)]
+struct StageConfig {
+    #[serde(default)]
+    name: Option<String>,
+    #[serde(default)]
+    applies_to: Option<AppliesTo>,
+    #[serde(flatten)]
+    kind: StageKind,
+}
+
+#[derive(Debug, Deserialize)]
+#[serde(tag = "type", rename_all = "snake_case")]
+enum StageKind {
+    Regex {
+        patterns: Vec<String>,
+        action: Action,
+        #[serde(default)]
+        replacement: Option<String>,
+    },
+    Keywords {
+        terms: Vec<String>,
+        action: Action,
+        #[serde(default)]
+        replacement: Option<String>,
+    },
+    Pii {
+        /// Kinds from [`PII_PATTERNS`]; all of them when empty.
+        #[serde(default)]
+        kinds: Vec<String>,
+        #[serde(default)]
+        action: Option<Action>,
+        #[serde(default)]
+        replacement: Option<String>,
+    },
+    PromptInjection {
+        #[serde(default)]
+        action: Option<Action>,
+    },
+    MaxLength {
+        max_chars: usize,
+    },
+    Webhook {
+        url: String,
+        #[serde(default = "default_webhook_timeout_ms")]
+        timeout_ms: u64,
+        /// Let text through when the classifier can't be reached.
+        #[serde(default)]
+        fail_open: bool,
+    },
+}
+
+fn default_webhook_timeout_ms() -> u64 {
+    1000
+}
+
+struct Policy {
+    name: String,
+    models: Vec<String>,
+    tenants: Vec<String>,
+    stages: Vec<Stage>,
+}
+
+impl Policy {
+    fn applies(&self, model: &str, tenant: Option<&str>) -> bool {
+        let model_ok = self.models.is_empty()
+            || self.models.iter().any(|m| match m.strip_suffix('*') {
+                Some(prefix) => model.starts_with(prefix),
+                None => m == model,
+            });
+        let tenant_ok = self.tenants.is_empty() || tenant.is_some_and(|t| self.tenants.iter().any(|x| x == t));
+        model_ok && tenant_ok
+    }
+}
+
+struct Stage {
+    name: String,
+    applies_to: AppliesTo,
+    check: Check,
+}
+
+enum Check {
+    Pattern {
+        regex: Regex,
+        action: Action,
+        replacement: String,
+    },
+    MaxLength {
+        max_chars: usize,
+    },
+    Webhook {
+        url: String,
+        timeout: Duration,
+        fail_open: bool,
+    },
+}
+
+impl Stage {
+    fn compile(config: StageConfig) -> Result<Self> {
+        let replacement = |r: Option<String>| r.unwrap_or_else(|| DEFAULT_REPLACEMENT.to_string());
+        let (kind, default_applies, check) = match config.kind {
+            StageKind::Regex {
+                patterns,
+                action,
+                replacement: r,
+            } => {
+                let patterns: Vec<String> = patterns.iter().map(|p| format!("(?:{p})")).collect();
+                let regex = Regex::new(&patterns.join("|")).context("invalid regex stage pattern")?;
+                ("regex", AppliesTo::Both, Check::Pattern {
+                    regex,
+                    action,
+                    replacement: replacement(r),
+                })
+            }
+            StageKind::Keywords {
+                terms,
+                action,
+                replacement: r,
+            } => {
+                let terms: Vec<String> = terms.iter().map(|t| keyword_pattern(t)).collect();
+                let regex = Regex::new(&format!("(?i){}", terms.join("|")))?;
+                ("keywords", AppliesTo::Both, Check::Pattern {
+                    regex,
+                    action,
+                    replacement: replacement(r),
+                })
+            }
+            StageKind::Pii {
+                kinds,
+                action,
+                replacement: r,
+            } => {
+                let mut patterns = Vec::new();
+                for (kind, pattern) in PII_PATTERNS {
+                    if kinds.is_empty() || kinds.iter().any(|k| k == kind) {
+                        patterns.push(*pattern);
+                    }
+                }
+                if let Some(unknown) = kinds.iter().find(|k| !PII_PATTERNS.iter().any(|(p, _)| p == k)) {
+                    anyhow::bail!("unknown pii kind '{unknown}'");
+                }
+                ("pii", AppliesTo::Both, Check::Pattern {
+                    regex: Regex::new(&patterns.join("|"))?,
+                    action: action.unwrap_or(Action::Redact),
+                    replacement: replacement(r),
+                })
+            }
+            StageKind::PromptInjection { action } => {
+                let regex = Regex::new(&format!("(?i){}", PROMPT_INJECTION_PATTERNS.join("|")))?;
+                ("prompt_injection", AppliesTo::Input, Check::Pattern {
+                    regex,
+                    action: action.unwrap_or(Action::Flag),
+                    replacement: DEFAULT_REPLACEMENT.to_string(),
+                })
+            }
+            StageKind::MaxLength { max_chars } => ("max_length", AppliesTo::Both, Check::MaxLength { max_chars }),
+            StageKind::Webhook {
+                url,
+                timeout_ms,
+                fail_open,
+            } => ("webhook", AppliesTo::Both, Check::Webhook {
+                url,
+                timeout: Duration::from_millis(timeout_ms),
+                fail_open,
+            }),
+        };
+        Ok(Self {
+            name: config.name.unwrap_or_else(|| kind.to_string()),
+            applies_to: config.applies_to.unwrap_or(default_applies),
+            check,
+        })
+    }
+}
+
+/// A guardrail blocked the request.
+#[derive(Debug)]
+pub struct Blocked(pub GuardrailEvent);
+
+impl Blocked {
+    pub fn message(&self) -> String {
+        let message = format!(
+            "Request blocked by guardrail policy '{}' ({}).",
+            self.0.policy, self.0.stage
+        );
+        match self.0.reason.as_deref() {
+            Some(reason) => format!("{message} {reason}"),
+            None => message,
+        }
+    }
+}
+
+impl IntoResponse for Blocked {
+    fn into_response(self) -> Response {
+        ApiError::new(ErrorCode::ContentPolicyViolation, self.message()).into_response()
+    }
+}
+
+/// What a webhook classifier answers.
+#[derive(Debug, Deserialize)]
+struct WebhookVerdict {
+    #[serde(default)]
+    action: Option<String>,
+    #[serde(default)]
+    reason: Option<String>,
+    /// Replacement texts, in request order, for `redact`.
+    #[serde(default)]
+    texts: Option<Vec<String>>,
+}
+
+pub struct Guardrails {
+    policies: Vec<Policy>,
+    holdback: usize,
+    http: reqwest::Client,
+    metrics: Arc<Metrics>,
+}
+
+impl Guardrails {
+    pub fn load(path: &str, http: reqwest::Client, metrics: Arc<Metrics>) -> Result<Self> {
+        let raw = std::fs::read_to_string(path).with_context(|| format!("failed to read {path}"))?;
+        let config: FileConfig = serde_json::from_str(&raw).with_context(|| format!("invalid guardrails file {path}"))?;
+        let mut policies = Vec::with_capacity(config.policies.len());
+        for p in config.policies {
+            let stages = p
+                .stages
+                .into_iter()
+                .map(Stage::compile)
+                .collect::<Result<Vec<_>>>()
+                .with_context(|| format!("guardrail policy '{}'", p.name))?;
+            policies.push(Policy {
+                name: p.name,
+                models: p.models,
+                tenants: p.tenants,
+                stages,
+            });
+        }
+        Ok(Self {
+            policies,
+            holdback: config.stream_holdback_chars,
+            http,
+            metrics,
+        })
+    }
+
+    pub fn policy_count(&self) -> usize {
+        self.policies.len()
+    }
+
+    /// The policies that apply to a request, or `None` when none do.
+    pub fn select(self: &Arc<Self>, model: &str, tenant: Option<&str>) -> Option<Selection> {
+        let policies: Vec<usize> = (0..self.policies.len())
+            .filter(|&i| self.policies[i].applies(model, tenant))
+            .collect();
+        (!policies.is_empty()).then(|| Selection {
+            guard: self.clone(),
+            policies,
+            model: model.to_string(),
+            tenant: tenant.map(str::to_string),
+        })
+    }
+
+    fn count(&self, event: &GuardrailEvent) {
+        self.metrics.record_guardrail(event.action);
+    }
+}
+
+/// The guardrail policies for one request.
+#[derive(Clone)]
+pub struct Selection {
+    guard: Arc<Guardrails>,
+    policies: Vec<usize>,
+    model: String,
+    tenant: Option<String>,
+}
+
+impl Selection {
+    fn stages(&self, direction: Direction) -> impl Iterator<Item = (&Policy, &Stage)> {
+        self.policies
+            .iter()
+            .map(|&i| &self.guard.policies[i])
+            .flat_map(|p| p.stages.iter().map(move |s| (p, s)))
+            .filter(move |(_, s)| s.applies_to.includes(direction))
+    }
+
+    fn event(&self, policy: &Policy, stage: &Stage, direction: Direction, action: Action) -> GuardrailEvent {
+        GuardrailEvent {
+            policy: policy.name.clone(),
+            stage: stage.name.clone(),
+            direction,
+            action,
+            reason: None,
+        }
+    }
+
+    fn push(&self, events: &mut Vec<GuardrailEvent>, event: GuardrailEvent) {
+        if !events.contains(&event) {
+            self.guard.count(&event);
+            events.push(event);
+        }
+    }
+
+    /// Runs the built-in stages. `prior_chars` is text already let through
+    /// that still counts toward `max_length`.
+    fn run_builtin(
+        &self,
+        direction: Direction,
+        texts: &mut [&mut String],
+        prior_chars: usize,
+        events: &mut Vec<GuardrailEvent>,
+    ) -> Result<(), GuardrailEvent> {
+        for (policy, stage) in self.stages(direction) {
+            match &stage.check {
+                Check::Pattern {
+                    regex,
+                    action,
+                    replacement,
+                } => {
+                    let mut hit = false;
+                    for text in texts.iter_mut() {
+                        if !regex.is_match(text) {
+                            continue;
+                        }
+                        hit = true;
+                        if *action == Action::Redact {
+                            **text = regex.replace_all(text, replacement.as_str()).into_owned();
+                        }
+                    }
+                    if hit {
+                        let event = self.event(policy, stage, direction, *action);
+                        if *action == Action::Block {
+                            self.guard.count(&event);
+                            return Err(event);
+                        }
+                        self.push(events, event);
+                    }
+                }
+                Check::MaxLength { max_chars } => {
+                    let total = prior_chars + texts.iter().map(|t| t.chars().count()).sum::<usize>();
+                    if total > *max_chars {
+                        let mut event = self.event(policy, stage, direction, Action::Block);
+                        event.reason = Some(format!("Text is {total} characters; the limit is {max_chars}."));
+                        self.guard.count(&event);
+                        return Err(event);
+                    }
+                }
+                Check::Webhook { .. } => {}
+            }
+        }
+        Ok(())
+    }
+
+    /// Runs the webhook stages. With `flag_only`, verdicts are recorded but
+    /// neither block nor redact.
+    async fn run_webhooks(
+        &self,
+        direction: Direction,
+        texts: &mut [&mut String],
+        flag_only: bool,
+        events: &mut Vec<GuardrailEvent>,
+    ) -> Result<(), GuardrailEvent> {
+        for (policy, stage) in self.stages(direction) {
+            let Check::Webhook { url, timeout, fail_open } = &stage.check else {
+                continue;
+            };
+            let body = json!({
+                "policy": policy.name,
+                "direction": direction,
+                "model": self.model,
+                "tenant": self.tenant,
+                "texts": texts.iter().map(|t| t.as_str()).collect::<Vec<_>>(),
+            });
+            let verdict = async {
+                let resp = self.guard.http.post(url).timeout(*timeout).json(&body).send().await?;
+                resp.error_for_status()?.json::<WebhookVerdict>().await
+            }
+            .await;
+            let (action, reason) = match verdict {
+                Ok(v) => {
+                    let action = match v.action.as_deref() {
+                        Some("block") => Action::Block,
+                        Some("redact") => Action::Redact,
+                        Some("flag") => Action::Flag,
+                        _ => continue,
+                    };
+                    if action == Action::Redact && !flag_only {
+                        match v.texts.filter(|r| r.len() == texts.len()) {
+                            Some(replacements) => {
+                                for (text, replacement) in texts.iter_mut().zip(replacements) {
+                                    **text = replacement;
+                                }
+                            }
+                            None => {
+                                tracing::warn!(url=%url, "guardrail webhook redacted without matching texts");
+                                continue;
+                            }
+                        }
+                    }
+                    (action, v.reason)
+                }
+                Err(e) => {
+                    tracing::warn!(error=%e, url=%url, "guardrail webhook failed");
+                    let action = if *fail_open { Action::Flag } else { Action::Block };
+                    (action, Some("Guardrail classifier unavailable.".to_string()))
+                }
+            };
+            let mut event = self.event(policy, stage, direction, action);
+            event.reason = reason;
+            if flag_only && action != Action::Flag {
+                event.action = Action::Flag;
+                event.reason = Some(format!(
+                    "{} after the stream was sent{}",
+                    if action == Action::Block { "blocked" } else { "redacted" },
+                    event.reason.map(|r| format!(": {r}")).unwrap_or_default()
+                ));
+            }
+            if event.action == Action::Block {
+                self.guard.count(&event);
+                return Err(event);
+            }
+            self.push(events, event);
+        }
+        Ok(())
+    }
+
+    /// Checks a request body, redacting it in place. Returns whether the body
+    /// changed, or the event that blocked it.
+    pub async fn check_request(&self, body: &mut Value, events: &mut Vec<GuardrailEvent>) -> Result<bool, Blocked> {
+        let original = body.clone();
+        let mut texts = request_texts(body);
+        self.run_builtin(Direction::Input, &mut texts, 0, events).map_err(Blocked)?;
+        self.run_webhooks(Direction::Input, &mut texts, false, events).await.map_err(Blocked)?;
+        Ok(*body != original)
+    }
+
+    /// Checks a non-streamed completion, redacting it in place. A blocked
+    /// completion has its text removed and `finish_reason: content_filter`.
+    /// Returns whether the body changed.
+    pub async fn check_response(&self, body: &mut Value, events: &mut Vec<GuardrailEvent>) -> bool {
+        let original = body.clone();
+        let mut texts = response_texts(body);
+        let mut result = self.run_builtin(Direction::Output, &mut texts, 0, events);
+        if result.is_ok() {
+            result = self.run_webhooks(Direction::Output, &mut texts, false, events).await;
+        }
+        if let Err(event) = result {
+            events.push(event);
+            filter_choices(body);
+        }
+        *body != original
+    }
+
+    /// A filter for a streamed completion, when any stage checks output.
+    pub fn stream_filter(&self) -> Option<StreamFilter> {
+        self.stages(Direction::Output).next()?;
+        Some(StreamFilter {
+            selection: self.clone(),
+            buf: Vec::new(),
+            choices: BTreeMap::new(),
+            template: None,
+            events: Vec::new(),
+            done: false,
+        })
+    }
+}
+
+/// Text a streamed choice has produced so far.
+#[derive(Default)]
+struct ChoiceText {
+    /// Checked text not yet sent.
+    pending: String,
+    /// Characters already sent.
+    sent_chars: usize,
+    /// Everything sent, for webhook stages at the end.
+    sent: String,
+}
+
+/// Filters an OpenAI-style SSE completion stream.
+pub struct StreamFilter {
+    selection: Selection,
+    buf: Vec<u8>,
+    choices: BTreeMap<u64, ChoiceText>,
+    /// Last chunk seen, used to build chunks the filter adds.
+    template: Option<Value>,
+    events: Vec<GuardrailEvent>,
+    done: bool,
+}
+
+impl StreamFilter {
+    /// Whether a stage blocked the stream; the caller stops reading upstream.
+    pub fn is_blocked(&self) -> bool {
+        self.done
+    }
+
+    /// Feeds upstream bytes and returns the bytes to send on.
+    pub fn push(&mut self, chunk: &[u8]) -> Bytes {
+        let mut out = Vec::new();
+        if self.done {
+            return Bytes::new();
+        }
+        self.buf.extend_from_slice(chunk);
+        while let Some(end) = self.buf.windows(2).position(|w| w == b"\n\n") {
+            let event: Vec<u8> = self.buf.drain(..end + 2).collect();
+            self.filter_event(&event, &mut out);
+            if self.done {
+                break;
+            }
+        }
+        Bytes::from(out)
+    }
+
+    /// Flushes held-back text once upstream has ended and runs the webhook
+    /// stages on the whole completion. Returns the remaining bytes to send
+    /// and the actions taken on the stream.
+    pub async fn finish(mut self) -> (Bytes, Vec<GuardrailEvent>) {
+        let mut out = Vec::new();
+        if !self.done {
+            if !self.buf.is_empty() {
+                let rest = std::mem::take(&mut self.buf);
+                self.filter_event(&rest, &mut out);
+            }
+            self.flush(&mut out);
+        }
+        let mut sent: Vec<String> = self.choices.values().map(|c| c.sent.clone()).collect();
+        let mut texts: Vec<&mut String> = sent.iter_mut().collect();
+        if let Err(event) = self
+            .selection
+            .run_webhooks(Direction::Output, &mut texts, true, &mut self.events)
+            .await
+        {
+            self.events.push(event);
+        }
+        (Bytes::from(out), self.events)
+    }
+
+    fn filter_event(&mut self, event: &[u8], out: &mut Vec<u8>) {
+        let data = std::str::from_utf8(event)
+            .ok()
+            .and_then(|s| s.lines().find_map(|l| l.strip_prefix("data:")))
+            .map(str::trim);
+        let Some(data) = data else {
+            out.extend_from_slice(event);
+            return;
+        };
+        if data == "[DONE]" {
+            self.flush(out);
+            out.extend_from_slice(event);
+            return;
+        }
+        let Ok(mut chunk) = serde_json::from_str::<Value>(data) else {
+            out.extend_from_slice(event);
+            return;
+        };
+
+        if let Some(choices) = chunk.get_mut("choices").and_then(Value::as_array_mut) {
+            for choice in choices.iter_mut() {
+                let index = choice.get("index").and_then(Value::as_u64).unwrap_or(0);
+                let finished = choice.get("finish_reason").is_some_and(|f| !f.is_null());
+                let Some(content) = choice.pointer_mut("/delta/content") else {
+                    if finished {
+                        let emitted = self.release(index, true);
+                        if !emitted.is_empty() {
+                            choice["delta"]["content"] = Value::String(emitted);
+                        }
+                    }
+                    continue;
+                };
+                let Some(text) = content.as_str() else {
+                    continue;
+                };
+                match self.filter_text(index, text, finished) {
+                    Ok(text) => *content = Value::String(text),
+                    Err(event) => {
+                        self.events.push(event);
+                        self.block(out);
+                        return;
+                    }
+                }
+            }
+        }
+        self.template = Some(chunk.clone());
+        out.extend_from_slice(format!("data: {chunk}\n\n").as_bytes());
+    }
+
+    /// Checks a choice's new text together with what it holds back, and
+    /// returns the text that is safe to send, or the event that blocked it.
+    fn filter_text(&mut self, index: u64, text: &str, finished: bool) -> Result<String, GuardrailEvent> {
+        let state = self.choices.entry(index).or_default();
+        state.pending.push_str(text);
+        let prior = state.sent_chars;
+        let mut pending = std::mem::take(&mut state.pending);
+        let result = self
+            .selection
+            .run_builtin(Direction::Output, &mut [&mut pending], prior, &mut self.events);
+        self.choices.entry(index).or_default().pending = pending;
+        result?;
+        Ok(self.release(index, finished))
+    }
+
+    /// Filters the text of a completion streamed as engine events, as served
+    /// by `/v1/responses` and `/v1/messages`. A blocked completion ends with
+    /// `finish_reason: content_filter`. The actions taken are recorded once
+    /// the stream ends.
+    pub fn filter_events(mut self, mut stream: EngineEventStream, recorder: Option<UsageRecorder>) -> EngineEventStream {
+        let (tx, rx) = mpsc::channel(64);
+        let done = tx.clone();
+        let forward = async move {
+            while let Some(ev) = stream.next().await {
+                let mut out = Vec::new();
+                match ev {
+                    EngineEvent::Text(text) => match self.filter_text(0, &text, false) {
+                        Ok(text) => out.push(EngineEvent::Text(text)),
+                        Err(event) => {
+                            self.events.push(event);
+                            self.done = true;
+                            out.push(EngineEvent::Finish {
+                                reason: "content_filter".to_string(),
+                                stop_sequence: None,
+                            });
+                        }
+                    },
+                    EngineEvent::Finish { .. } => {
+                        out.push(EngineEvent::Text(self.release(0, true)));
+                        out.push(ev);
+                    }
+                    ev => out.push(ev),
+                }
+                for ev in out {
+                    if matches!(&ev, EngineEvent::Text(t) if t.is_empty()) {
+                        continue;
+                    }
+                    if tx.send(ev).await.is_err() {
+                        return;
+                    }
+                }
+                if self.done {
+                    break;
+                }
+            }
+            drop(stream);
+            if !self.done {
+                let rest = self.release(0, true);
+                if !rest.is_empty() && tx.send(EngineEvent::Text(rest)).await.is_err() {
+                    return;
+                }
+            }
+            let (_, events) = self.finish().await;
+            if let Some(recorder) = recorder {
+                recorder.record_guardrails(&events);
+            }
+        };
+        tokio::spawn(until_closed(done, forward));
+        Box::pin(ReceiverStream::new(rx))
+    }
+
+    /// Takes the text of a choice that is safe to send: all of it when the
+    /// choice has finished, otherwise all but the held-back tail.
+    fn release(&mut self, index: u64, all: bool) -> String {
+        let holdback = self.selection.guard.holdback;
+        let state = self.choices.entry(index).or_default();
+        let chars = state.pending.chars().count();
+        let keep = if all { 0 } else { holdback.min(chars) };
+        let cut = state
+            .pending
+            .char_indices()
+            .nth(chars - keep)
+            .map_or(state.pending.len(), |(i, _)| i);
+        let emitted: String = state.pending.drain(..cut).collect();
+        state.sent_chars += chars - keep;
+        state.sent.push_str(&emitted);
+        emitted
+    }
+
+    /// Sends the held-back text of every choice in one added chunk.
+    fn flush(&mut self, out: &mut Vec<u8>) {
+        let indexes: Vec<u64> = self
+            .choices
+            .iter()
+            .filter(|(_, c)| !c.pending.is_empty())
+            .map(|(i, _)| *i)
+            .collect();
+        if indexes.is_empty() {
+            return;
+        }
+        let choices: Vec<Value> = indexes
+            .into_iter()
+            .map(|i| json!({ "index": i, "delta": { "content": self.release(i, true) }, "finish_reason": null }))
+            .collect();
+        out.extend_from_slice(format!("data: {}\n\n", self.added_chunk(choices)).as_bytes());
+    }
+
+    /// Ends the stream with `finish_reason: content_filter`.
+    fn block(&mut self, out: &mut Vec<u8>) {
+        self.done = true;
+        let mut indexes: Vec<u64> = self.choices.keys().copied().collect();
+        if indexes.is_empty() {
+            indexes.push(0);
+        }
+        let choices: Vec<Value> = indexes
+            .into_iter()
+            .map(|i| json!({ "index": i, "delta": {}, "finish_reason": "content_filter" }))
+            .collect();
+        out.extend_from_slice(format!("data: {}\n\ndata: [DONE]\n\n", self.added_chunk(choices)).as_bytes());
+    }
+
+    fn added_chunk(&self, choices: Vec<Value>) -> Value {
+        let mut chunk = self
+            .template
+            .clone()
+            .unwrap_or_else(|| json!({ "object": "chat.completion.chunk", "model": self.selection.model }));
+        if let Value::Object(map) = &mut chunk {
+            map.remove("usage");
+            map.insert("choices".to_string(), Value::Array(choices));
+        }
+        chunk
+    }
+}
+
+/// Matches `term` as a whole word. Word boundaries are only required next to
+/// ASCII letters and digits, so terms in scripts written without spaces
+/// still match inside a sentence.
+fn keyword_pattern(term: &str) -> String {
+    let edge = |c: Option<char>| if c.is_some_and(|c| c.is_ascii_alphanumeric()) { r"\b" } else { "" };
+    format!("{}{}{}", edge(term.chars().next()), regex::escape(term), edge(term.chars().last()))
+}
+
+/// The texts of a request: chat messages, completion prompts, embeddings
+/// input and rerank query and documents.
+fn request_texts(body: &mut Value) -> Vec<&mut String> {
+    let mut out = Vec::new();
+    let Value::Object(map) = body else {
+        return out;
+    };
+    for (key, value) in map.iter_mut() {
+        match key.as_str() {
+            "messages" => {
+                for message in value.as_array_mut().into_iter().flatten() {
+                    if let Some(content) = message.get_mut("content") {
+                        content_texts(content, &mut out);
+                    }
+                }
+            }
+            "prompt" | "input" | "query" | "documents" => content_texts(value, &mut out),
+            _ => {}
+        }
+    }
+    out
+}
+
+/// Strings, and the `text` of parts, in a string or array value.
+fn content_texts<'a>(value: &'a mut Value, out: &mut Vec<&'a mut String>) {
+    match value {
+        Value::String(s) => out.push(s),
+        Value::Array(items) => {
+            for item in items.iter_mut() {
+                match item {
+                    Value::String(s) => out.push(s),
+                    Value::Object(part) => {
+                        if let Some(Value::String(s)) = part.get_mut("text") {
+                            out.push(s);
+                        }
+                    }
+                    _ => {}
+                }
+            }
+        }
+        _ => {}
+    }
+}
+
+/// The completion texts of a chat or text completion response.
+fn response_texts(body: &mut Value) -> Vec<&mut String> {
+    let mut out = Vec::new();
+    let Some(choices) = body.get_mut("choices").and_then(Value::as_array_mut) else {
+        return out;
+    };
+    for choice in choices.iter_mut() {
+        let Value::Object(choice) = choice else {
+            continue;
+        };
+        for (key, value) in choice.iter_mut() {
+            match (key.as_str(), value) {
+                ("text", Value::String(s)) => out.push(s),
+                ("message", message) => {
+                    if let Some(Value::String(s)) = message.get_mut("content") {
+                        out.push(s);
+                    }
+                }
+                _ => {}
+            }
+        }
+    }
+    out
+}
+
+/// Removes the text of every choice and marks it filtered.
+fn filter_choices(body: &mut Value) {
+    for choice in body.get_mut("choices").and_then(Value::as_array_mut).into_iter().flatten() {
+        if let Some(Value::String(s)) = choice.get_mut("text") {
+            s.clear();
+        }
+        if let Some(message) = choice.get_mut("message") {
+            message["content"] = Value::String(String::new());
+        }
+        choice["finish_reason"] = Value::String("content_filter".to_string());
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    /// Blocks `forbidden`, redacts e-mail addresses and checks both ways.
+    fn guardrails(metrics: &Arc<Metrics>, holdback: usize) -> Arc<Guardrails> {
+        let config = json!({
+            "stream_holdback_chars": holdback,
+            "policies": [{
+                "name": "default",
+                "models": ["chat-*"],
+                "stages": [
+                    {"type": "keywords", "terms": ["forbidden"], "action": "block"},
+                    {"type": "pii", "kinds": ["email"]},
+                ],
+            }],
+        });
+        let path = std::env::temp_dir().join(format!("nebula-guardrails-{}.json", uuid::Uuid::new_v4()));
+        std::fs::write(&path, config.to_string()).unwrap();
+        let loaded = Guardrails::load(path.to_str().unwrap(), reqwest::Client::new(), metrics.clone());
+        std::fs::remove_file(&path).unwrap();
+        Arc::new(loaded.unwrap())
+    }
+
+    fn chunk(text: &str, finish_reason: Option<&str>) -> String {
+        let chunk = json!({
+            "object": "chat.completion.chunk",
+            "choices": [{"index": 0, "delta": {"content": text}, "finish_reason": finish_reason}],
+        });
+        format!("data: {chunk}\n\n")
+    }
+
+    /// The text and final `finish_reason` of an SSE completion stream.
+    fn stream_text(sse: &[u8]) -> (String, Option<String>) {
+        let mut text = String::new();
+        let mut finish = None;
+        for data in std::str::from_utf8(sse).unwrap().lines().filter_map(|l| l.strip_prefix("data: ")) {
+            let Ok(chunk) = serde_json::from_str::<Value>(data) else {
+                continue;
+            };
+            for c in chunk["choices"].as_array().into_iter().flatten() {
+                text.push_str(c["delta"]["content"].as_str().unwrap_or(""));
+                if let Some(f) = c["finish_reason"].as_str() {
+                    finish = Some(f.to_string());
+                }
+            }
+        }
+        (text, finish)
+    }
+
+    #[tokio::test]
+    async fn test_request_block_and_redact() {
+        let metrics = Arc::new(Metrics::default());
+        let guard = guardrails(&metrics, 64);
+        assert!(guard.select("other", None).is_none());
+        let selection = guard.select("chat-1", Some("acme")).unwrap();
+
+        let mut body = json!({"messages": [
+            {"role": "system", "content": "be brief"},
+            {"role": "user", "content": [{"type": "text", "text": "mail bob@example.com"}]},
+        ]});
+        let mut events = Vec::new();
+        assert!(selection.check_request(&mut body, &mut events).await.unwrap());
+        assert_eq!(body["messages"][1]["content"][0]["text"], "mail [REDACTED]");
+        assert_eq!(body["messages"][0]["content"], "be brief");
+        assert_eq!(events.len(), 1);
+        assert_eq!((events[0].stage.as_str(), events[0].action), ("pii", Action::Redact));
+
+        let mut body = json!({"prompt": "This is FORBIDDEN."});
+        let blocked = selection.check_request(&mut body, &mut Vec::new()).await.unwrap_err();
+        assert_eq!((blocked.0.stage.as_str(), blocked.0.direction), ("keywords", Direction::Input));
+        assert_eq!(blocked.message(), "Request blocked by guardrail policy 'default' (keywords).");
+        assert_eq!(blocked.into_response().status(), axum::http::StatusCode::BAD_REQUEST);
+
+        let mut clean = json!({"input": ["hello"]});
+        assert!(!selection.check_request(&mut clean, &mut Vec::new()).await.unwrap());
+        assert_eq!(metrics.guardrail_redacted.load(std::sync::atomic::Ordering::Relaxed), 1);
+        assert_eq!(metrics.guardrail_blocked.load(std::sync::atomic::Ordering::Relaxed), 1);
+    }
+
+    #[tokio::test]
+    async fn test_response_block_and_redact() {
+        let metrics = Arc::new(Metrics::default());
+        let selection = guardrails(&metrics, 64).select("chat-1", None).unwrap();
+
+        let mut body = json!({"choices": [
+            {"index": 0, "message": {"role": "assistant", "content": "ask bob@example.com"}, "finish_reason": "stop"},
+        ]});
+        assert!(selection.check_response(&mut body, &mut Vec::new()).await);
+        assert_eq!(body["choices"][0]["message"]["content"], "ask [REDACTED]");
+        assert_eq!(body["choices"][0]["finish_reason"], "stop");
+
+        let mut body = json!({"choices": [{"index": 0, "text": "forbidden words", "finish_reason": "stop"}]});
+        let mut events = Vec::new();
+        assert!(selection.check_response(&mut body, &mut events).await);
+        assert_eq!(body["choices"][0]["text"], "");
+        assert_eq!(body["choices"][0]["finish_reason"], "content_filter");
+        assert_eq!((events[0].direction, events[0].action), (Direction::Output, Action::Block));
+    }
+
+    #[tokio::test]
+    async fn test_stream_filter_catches_matches_split_across_chunks() {
+        let metrics = Arc::new(Metrics::default());
+        let selection = guardrails(&metrics, 16).select("chat-1", None).unwrap();
+
+        let mut filter = selection.stream_filter().unwrap();
+        let mut out = Vec::new();
+        for c in [chunk("Write to bob@exa", None), chunk("mple.com today", None), chunk("", Some("stop"))] {
+            out.extend_from_slice(&filter.push(c.as_bytes()));
+        }
+        out.extend_from_slice(&filter.push(b"data: [DONE]\n\n"));
+        assert!(!filter.is_blocked());
+        let (rest, events) = filter.finish().await;
+        out.extend_from_slice(&rest);
+        assert_eq!(stream_text(&out), ("Write to [REDACTED] today".to_string(), Some("stop".to_string())));
+        assert_eq!(events.len(), 1);
+        assert_eq!(events[0].action, Action::Redact);
+
+        // A blocked stream ends at once; held-back text is never sent.
+        let mut filter = selection.stream_filter().unwrap();
+        let mut out = filter.push(chunk("some safe text, then forb", None).as_bytes()).to_vec();
+        out.extend_from_slice(&filter.push(chunk("idden", None).as_bytes()));
+        assert!(filter.is_blocked());
+        assert!(filter.push(chunk("more", None).as_bytes()).is_empty());
+        let (text, finish) = stream_text(&out);
+        assert!(!text.contains("forb"), "{text}");
+        assert_eq!(finish.as_deref(), Some("content_filter"));
+        assert!(out.ends_with(b"data: [DONE]\n\n"));
+        let (rest, events) = filter.finish().await;
+        assert!(rest.is_empty());
+        assert_eq!(events[0].action, Action::Block);
+    }
+
+    #[tokio::test]
+    async fn test_filter_events() {
+        let metrics = Arc::new(Metrics::default());
+        let selection = guardrails(&metrics, 16).select("chat-1", None).unwrap();
+        let run = |events: Vec<EngineEvent>| {
+            let filter = selection.stream_filter().unwrap();
+            filter.filter_events(Box::pin(tokio_stream::iter(events)), None).collect::<Vec<_>>()
+        };
+        let counted = |counter: &std::sync::atomic::AtomicU64| counter.load(std::sync::atomic::Ordering::Relaxed);
+        let text = |events: &[EngineEvent]| -> String {
+            events
+                .iter()
+                .filter_map(|e| match e {
+                    EngineEvent::Text(t) => Some(t.as_str()),
+                    _ => None,
+                })
+                .collect()
+        };
+        let finish = |reason: &str| EngineEvent::Finish {
+            reason: reason.to_string(),
+            stop_sequence: None,
+        };
+        let usage = EngineEvent::Usage { input_tokens: 5, output_tokens: 7 };
+
+        let out = run(vec![
+            EngineEvent::Text("Write to bob@exa".to_string()),
+            EngineEvent::Text("mple.com today".to_string()),
+            finish("stop"),
+            usage.clone(),
+        ])
+        .await;
+        assert_eq!(text(&out), "Write to [REDACTED] today");
+        assert_eq!(&out[out.len() - 2..], [finish("stop"), usage]);
+        assert_eq!((counted(&metrics.guardrail_redacted), counted(&metrics.guardrail_blocked)), (1, 0));
+
+        let out = run(vec![
+            EngineEvent::Text("safe, then forbidden".to_string()),
+            EngineEvent::Text(" and more".to_string()),
+            finish("stop"),
+        ])
+        .await;
+        assert!(!text(&out).contains("forbidden"));
+        assert_eq!(out.last(), Some(&finish("content_filter")));
+        assert!(!out.contains(&finish("stop")));
+        assert_eq!(counted(&metrics.guardrail_blocked), 1);
+    }
+}
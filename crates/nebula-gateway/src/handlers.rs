@@ -1,9 +1,11 @@
+use std::collections::HashMap;
 use std::convert::Infallible;
+use std::sync::Arc;
 use std::time::Duration;
 
 use axum::{
     body::Body,
-    extract::{Path, State},
+    extract::{rejection::JsonRejection, Path, State},
     http::{HeaderMap, HeaderName, HeaderValue, Request, StatusCode},
     response::{sse::Event, IntoResponse, Response, Sse},
     Extension, Json,
@@ -16,76 +18,711 @@ use tokio::sync::mpsc;
 use tokio_stream::{wrappers::ReceiverStream, StreamExt};
 use uuid::Uuid;
 
+use nebula_common::auth::rate_limited;
+use nebula_common::body::{ModelScan, MultipartModelScanner};
+use nebula_common::execution_context::deadline_remaining;
+use nebula_common::tokens::{estimate_request, heuristic_count};
+use nebula_common::usage::{request_stream_usage, SseUsageScanner, UsageChunkFilter};
 use nebula_common::{
-    ClusterStatus, EndpointInfo, ExecutionContext, ModelLoadRequest, ModelRequest,
-    ModelRequestStatus, NodeStatus, PlacementPlan,
+    ApiError, ClusterStatus, DesiredState, EndpointInfo, EndpointStatus, ErrorCode, ExecutionContext, ModelDeployment, ModelLoadRequest,
+    ModelRequest, ModelRequestStatus, ModelSpec, NodeStatus, PlacementPlan, RequestPolicy, TokenUsage,
 };
 use nebula_meta::MetaStore;
 
+use crate::audit::UsageRecorder;
 use crate::auth::{require_role, AuthContext, Role};
-use crate::responses::{
-    build_non_stream_json, build_response, CreateResponseRequest, ResponseStreamBuilder,
+use crate::batch::{
+    input_models, now_secs, Batch, BatchStatus, FileObject, StoredBatch, BATCH_ENDPOINTS, COMPLETION_WINDOW,
 };
+use crate::cache::{Lookup, CACHE_HEADER};
+use crate::engine::{until_closed, EngineEvent};
+use crate::guardrails::{Blocked, Selection};
+use crate::messages::{self, CreateMessageRequest, MessageBuilder};
+use crate::metrics::Metrics;
+use crate::quota::{QuotaLimits, QuotaScope, Quotas, Window};
+use crate::response_store::{ResponseStore, StoredResponse};
+use crate::responses::{CreateResponseRequest, ResponseBuilder};
 use crate::state::AppState;
+use crate::util::parse_multipart;
 
 #[derive(Debug, serde::Deserialize)]
 pub(crate) struct LogsQuery {
     lines: Option<usize>,
 }
 
+fn model_not_allowed(model: &str) -> Response {
+    ApiError::new(
+        ErrorCode::ModelNotAllowed,
+        format!("This API key is not allowed to use model '{model}'."),
+    )
+    .with_param("model")
+    .into_response()
+}
+
+fn response_not_found(id: &str) -> Response {
+    ApiError::new(ErrorCode::ResponseNotFound, format!("Response with id '{id}' not found.")).into_response()
+}
+
 pub async fn create_responses(
     State(st): State<AppState>,
     headers: HeaderMap,
+    auth: Option<Extension<AuthContext>>,
+    recorder: Option<Extension<UsageRecorder>>,
     Json(req): Json<CreateResponseRequest>,
 ) -> Response {
-    let _ctx = build_execution_context(&headers);
+    if let Some(Extension(ctx)) = auth.as_ref() {
+        let model = req.model.as_deref().unwrap_or("");
+        if !ctx.allows_model(model) {
+            return model_not_allowed(model);
+        }
+        if let Some(d) = st.auth.acquire_model(ctx, model).await.filter(|d| !d.allowed) {
+            return rate_limited(&d);
+        }
+    }
+    let recorder = recorder.map(|Extension(r)| r);
+    let usage = UsageSink::new(
+        &st,
+        auth.as_ref().map(|Extension(c)| c),
+        recorder.clone(),
+        req.model.as_deref().unwrap_or("unknown"),
+    );
+
+    let (input, tools) = match (req.input_messages(), req.chat_tools()) {
+        (Ok(input), Ok(tools)) => (input, tools),
+        (Err(e), _) | (_, Err(e)) => {
+            return ApiError::new(ErrorCode::InvalidRequest, e.message)
+                .with_param(e.param)
+                .into_response();
+        }
+    };
 
-    let input_text = req.extract_input_text();
+    let owner = owner_label(auth.as_deref());
+    let tenant = auth.as_ref().and_then(|Extension(c)| c.tenant.clone());
+    let history = match req.previous_response_id.as_deref() {
+        Some(id) => match owned_response(&st.responses, id, auth.as_deref()).await {
+            Ok(Some(prev)) => prev.messages,
+            Ok(None) => {
+                return ApiError::new(
+                    ErrorCode::PreviousResponseNotFound,
+                    format!("Previous response with id '{id}' not found."),
+                )
+                .with_param("previous_response_id")
+                .into_response();
+            }
+            Err(e) => {
+                tracing::error!(error=%e, response_id=%id, "failed to load previous response");
+                return ApiError::new(ErrorCode::InternalError, "response store error").into_response();
+            }
+        },
+        None => Vec::new(),
+    };
+
+    let mut chat_body = req.chat_request(&history, &input, tools);
+    let guard = select_guardrails(&st, auth.as_deref(), req.model.as_deref().unwrap_or(""));
+    if let Some(guard) = guard.as_ref() {
+        if let Err(blocked) = check_request(guard, &mut chat_body, recorder.as_ref()).await {
+            return blocked.into_response();
+        }
+    }
+    let mut builder = ResponseBuilder::new(&req);
+    let mut conversation = history;
+    conversation.extend(input);
+    let store = req.store.unwrap_or(true).then(|| st.responses.clone());
+
+    let prompt_tokens = prompt_estimate(&chat_body);
+    // The router resolves `model` and applies session affinity (`x-session-id`),
+    // retries and per-model metrics.
+    let mut stream = st.engine.stream_chat(chat_body, to_reqwest_headers(&headers));
+    if let Some(filter) = guard.as_ref().and_then(|g| g.stream_filter()) {
+        stream = filter.filter_events(stream, recorder);
+    }
 
     if req.stream.unwrap_or(false) {
         let (tx, rx) = mpsc::channel::<Result<Event, Infallible>>(256);
-        let engine = st.engine.clone();
-
-        let builder = ResponseStreamBuilder::new(&req);
 
+        let done = tx.clone();
+        let metrics = st.metrics.clone();
         tokio::spawn(async move {
-            let mut stream = engine.stream_text(input_text);
-            let mut builder = builder;
-
-            let _ = tx
-                .send(Ok(
-                    Event::default().data(builder.created_event().to_string())
-                ))
-                .await;
-
-            while let Some(delta) = stream.next().await {
-                if delta.is_empty() {
-                    continue;
+            let mut generated = String::new();
+            let forward = async {
+                for ev in builder.start_events() {
+                    let _ = tx.send(Ok(Event::default().data(ev.to_string()))).await;
+                }
+                while let Some(delta) = stream.next().await {
+                    push_generated(&mut generated, &delta);
+                    for ev in builder.push(delta) {
+                        let _ = tx.send(Ok(Event::default().data(ev.to_string()))).await;
+                    }
+                }
+                if builder.error().is_some() {
+                    for ev in builder.fail_events() {
+                        let _ = tx.send(Ok(Event::default().data(ev.to_string()))).await;
+                    }
+                    return;
+                }
+                for ev in builder.finish_events() {
+                    let _ = tx.send(Ok(Event::default().data(ev.to_string()))).await;
+                }
+                if let Some(store) = store {
+                    store_response(&store, &builder, conversation, owner, tenant).await;
                 }
-                let ev = builder.push_delta(delta);
-                let _ = tx.send(Ok(Event::default().data(ev.to_string()))).await;
+                // Recorded last: nothing after it can be cut short by a
+                // disconnect, so the stream is never charged twice.
+                if let Some(u) = builder.token_usage() {
+                    usage.record(u);
+                }
+            };
+            if until_closed(done, forward).await.is_none() {
+                metrics.record_client_cancelled();
+                usage.record_abandoned(builder.token_usage(), prompt_tokens, &generated);
             }
-
-            let completed = builder.completed_event();
-            let _ = tx
-                .send(Ok(Event::default().data(completed.to_string())))
-                .await;
         });
 
         Sse::new(ReceiverStream::new(rx))
             .keep_alive(axum::response::sse::KeepAlive::new().interval(Duration::from_secs(15)))
             .into_response()
     } else {
-        let mut out = String::new();
-        let mut stream = st.engine.stream_text(input_text);
-        while let Some(delta) = stream.next().await {
-            if !delta.is_empty() {
-                out.push_str(&delta);
+        while let Some(ev) = stream.next().await {
+            builder.push(ev);
+        }
+        if let Some((status, message)) = builder.error() {
+            let err = match StatusCode::from_u16(status) {
+                Ok(s) if s.is_client_error() => ApiError::new(ErrorCode::for_status(status), message).with_status(s),
+                Ok(StatusCode::GATEWAY_TIMEOUT) => ApiError::new(ErrorCode::DeadlineExceeded, message),
+                _ => ApiError::new(ErrorCode::UpstreamError, message),
+            };
+            return err.into_response();
+        }
+        if let Some(u) = builder.token_usage() {
+            usage.record(u);
+        }
+        if let Some(store) = store {
+            store_response(&store, &builder, conversation, owner, tenant).await;
+        }
+        (StatusCode::OK, Json(builder.response(builder.status()))).into_response()
+    }
+}
+
+fn anthropic_error(status: StatusCode, message: &str) -> Response {
+    (status, Json(messages::error_body(status.as_u16(), message))).into_response()
+}
+
+async fn send_named_events(
+    tx: &mpsc::Sender<Result<Event, Infallible>>,
+    events: Vec<(&'static str, serde_json::Value)>,
+) {
+    for (name, data) in events {
+        let _ = tx.send(Ok(Event::default().event(name).data(data.to_string()))).await;
+    }
+}
+
+/// Anthropic Messages API, served as a chat completion through the router.
+pub async fn create_message(
+    State(st): State<AppState>,
+    headers: HeaderMap,
+    auth: Option<Extension<AuthContext>>,
+    recorder: Option<Extension<UsageRecorder>>,
+    req: Result<Json<CreateMessageRequest>, JsonRejection>,
+) -> Response {
+    let req = match req {
+        Ok(Json(req)) => req,
+        Err(e) => return anthropic_error(StatusCode::BAD_REQUEST, &e.body_text()),
+    };
+    let mut chat_body = match req.chat_request() {
+        Ok(body) => body,
+        Err(message) => return anthropic_error(StatusCode::BAD_REQUEST, &message),
+    };
+    if let Some(Extension(ctx)) = auth.as_ref() {
+        if !ctx.allows_model(&req.model) {
+            return anthropic_error(
+                StatusCode::FORBIDDEN,
+                &format!("This API key is not allowed to use model '{}'.", req.model),
+            );
+        }
+        if let Some(d) = st.auth.acquire_model(ctx, &req.model).await.filter(|d| !d.allowed) {
+            let mut resp = anthropic_error(
+                StatusCode::TOO_MANY_REQUESTS,
+                &format!("Rate limit reached: {} requests per minute.", d.limit),
+            );
+            d.apply_headers(resp.headers_mut());
+            return resp;
+        }
+    }
+    let recorder = recorder.map(|Extension(r)| r);
+    let guard = select_guardrails(&st, auth.as_deref(), &req.model);
+    if let Some(guard) = guard.as_ref() {
+        if let Err(blocked) = check_request(guard, &mut chat_body, recorder.as_ref()).await {
+            return anthropic_error(StatusCode::BAD_REQUEST, &blocked.message());
+        }
+    }
+    let usage = UsageSink::new(
+        &st,
+        auth.as_ref().map(|Extension(c)| c),
+        recorder.clone(),
+        &req.model,
+    );
+    let mut builder = MessageBuilder::new(&req);
+    let prompt_tokens = prompt_estimate(&chat_body);
+    let mut stream = st.engine.stream_chat(chat_body, to_reqwest_headers(&headers));
+    if let Some(filter) = guard.as_ref().and_then(|g| g.stream_filter()) {
+        stream = filter.filter_events(stream, recorder);
+    }
+
+    if req.stream.unwrap_or(false) {
+        let (tx, rx) = mpsc::channel::<Result<Event, Infallible>>(256);
+
+        let done = tx.clone();
+        let metrics = st.metrics.clone();
+        tokio::spawn(async move {
+            let mut generated = String::new();
+            let forward = async {
+                send_named_events(&tx, builder.start_events()).await;
+                while let Some(ev) = stream.next().await {
+                    push_generated(&mut generated, &ev);
+                    send_named_events(&tx, builder.push(ev)).await;
+                }
+                if builder.error().is_some() {
+                    send_named_events(&tx, builder.fail_events()).await;
+                    return;
+                }
+                send_named_events(&tx, builder.finish_events()).await;
+                if let Some(u) = builder.token_usage() {
+                    usage.record(u);
+                }
+            };
+            if until_closed(done, forward).await.is_none() {
+                metrics.record_client_cancelled();
+                usage.record_abandoned(builder.token_usage(), prompt_tokens, &generated);
             }
+        });
+
+        Sse::new(ReceiverStream::new(rx))
+            .keep_alive(axum::response::sse::KeepAlive::new().interval(Duration::from_secs(15)))
+            .into_response()
+    } else {
+        while let Some(ev) = stream.next().await {
+            builder.push(ev);
+        }
+        if let Some((status, message)) = builder.error() {
+            let http_status = match StatusCode::from_u16(status) {
+                Ok(s) if s.is_client_error() || s == StatusCode::GATEWAY_TIMEOUT => s,
+                _ => StatusCode::BAD_GATEWAY,
+            };
+            return anthropic_error(http_status, message);
         }
-        let built = build_response(&req, out);
-        let body = build_non_stream_json(&built);
-        (StatusCode::OK, Json(body)).into_response()
+        if let Some(u) = builder.token_usage() {
+            usage.record(u);
+        }
+        (StatusCode::OK, Json(builder.message())).into_response()
+    }
+}
+
+async fn store_response(
+    store: &ResponseStore,
+    builder: &ResponseBuilder,
+    mut conversation: Vec<serde_json::Value>,
+    owner: String,
+    tenant: Option<String>,
+) {
+    conversation.extend(builder.assistant_messages());
+    let stored = StoredResponse {
+        response: builder.response(builder.status()),
+        messages: conversation,
+        owner,
+        tenant,
+    };
+    if let Err(e) = store.put(&stored).await {
+        tracing::warn!(error=%e, response_id=%builder.response_id(), "failed to store response");
+    }
+}
+
+/// A stored response the caller may see; other principals' responses are
+/// reported as missing.
+async fn owned_response(
+    store: &ResponseStore,
+    id: &str,
+    auth: Option<&AuthContext>,
+) -> anyhow::Result<Option<StoredResponse>> {
+    Ok(store.get(id).await?.filter(|r| can_access(auth, &r.owner)))
+}
+
+pub async fn get_response(
+    State(st): State<AppState>,
+    auth: Option<Extension<AuthContext>>,
+    Path(id): Path<String>,
+) -> Response {
+    match owned_response(&st.responses, &id, auth.as_deref()).await {
+        Ok(Some(stored)) => (StatusCode::OK, Json(stored.response)).into_response(),
+        Ok(None) => response_not_found(&id),
+        Err(e) => {
+            tracing::error!(error=%e, response_id=%id, "failed to load response");
+            ApiError::new(ErrorCode::InternalError, "response store error").into_response()
+        }
+    }
+}
+
+pub async fn delete_response(
+    State(st): State<AppState>,
+    auth: Option<Extension<AuthContext>>,
+    Path(id): Path<String>,
+) -> Response {
+    let deleted = match owned_response(&st.responses, &id, auth.as_deref()).await {
+        Ok(Some(_)) => st.responses.delete(&id).await,
+        Ok(None) => Ok(false),
+        Err(e) => Err(e),
+    };
+    match deleted {
+        Ok(true) => (
+            StatusCode::OK,
+            Json(json!({"id": id, "object": "response", "deleted": true})),
+        )
+            .into_response(),
+        Ok(false) => response_not_found(&id),
+        Err(e) => {
+            tracing::error!(error=%e, response_id=%id, "failed to delete response");
+            ApiError::new(ErrorCode::InternalError, "response store error").into_response()
+        }
+    }
+}
+
+/// Principal label files, batches and stored responses are owned by.
+fn owner_label(auth: Option<&AuthContext>) -> String {
+    auth.map(|c| c.principal_label())
+        .unwrap_or_else(|| "anonymous".to_string())
+}
+
+/// Files, batches and stored responses are visible to their owner and to admins.
+fn can_access(auth: Option<&AuthContext>, owner: &str) -> bool {
+    auth.is_some_and(|c| c.role == Role::Admin) || owner_label(auth) == owner
+}
+
+fn file_not_found(id: &str) -> Response {
+    ApiError::new(ErrorCode::NotFound, format!("No such File object: {id}"))
+        .with_param("file_id")
+        .into_response()
+}
+
+fn batch_not_found(id: &str) -> Response {
+    ApiError::new(ErrorCode::NotFound, format!("No such Batch object: {id}"))
+        .with_param("batch_id")
+        .into_response()
+}
+
+fn batch_store_error(e: anyhow::Error) -> Response {
+    tracing::error!(error=%e, "batch store error");
+    ApiError::new(ErrorCode::InternalError, "batch store error").into_response()
+}
+
+#[derive(Debug, serde::Deserialize)]
+pub(crate) struct UploadFileQuery {
+    purpose: Option<String>,
+    filename: Option<String>,
+}
+
+/// `POST /v1/files`: a `multipart/form-data` upload with `file` and
+/// `purpose` fields, or the raw JSONL body with `?purpose=batch&filename=`.
+pub async fn upload_file(
+    State(st): State<AppState>,
+    auth: Option<Extension<AuthContext>>,
+    axum::extract::Query(query): axum::extract::Query<UploadFileQuery>,
+    headers: HeaderMap,
+    body: Body,
+) -> Response {
+    let body = match axum::body::to_bytes(body, st.batches.max_file_bytes).await {
+        Ok(b) => b,
+        Err(_) => {
+            st.metrics
+                .request_too_large_total
+                .fetch_add(1, std::sync::atomic::Ordering::Relaxed);
+            return ApiError::new(ErrorCode::RequestTooLarge, "file too large").into_response();
+        }
+    };
+    let content_type = headers
+        .get(axum::http::header::CONTENT_TYPE)
+        .and_then(|v| v.to_str().ok())
+        .unwrap_or_default();
+    let (purpose, filename, data) = if content_type.starts_with("multipart/form-data") {
+        let Some(fields) = parse_multipart(content_type, &body) else {
+            return ApiError::new(ErrorCode::InvalidRequest, "malformed multipart body").into_response();
+        };
+        let purpose = fields
+            .iter()
+            .find(|f| f.name == "purpose")
+            .map(|f| String::from_utf8_lossy(&f.data).trim().to_string());
+        let Some(file) = fields.into_iter().find(|f| f.name == "file") else {
+            return ApiError::new(ErrorCode::InvalidRequest, "missing 'file' field")
+                .with_param("file")
+                .into_response();
+        };
+        let filename = file.filename.unwrap_or_else(|| "upload.jsonl".to_string());
+        (purpose, filename, file.data)
+    } else {
+        let filename = query.filename.unwrap_or_else(|| "upload.jsonl".to_string());
+        (query.purpose, filename, body)
+    };
+    if purpose.as_deref() != Some("batch") {
+        return ApiError::new(ErrorCode::InvalidRequest, "purpose must be 'batch'")
+            .with_param("purpose")
+            .into_response();
+    }
+
+    let owner = owner_label(auth.as_deref());
+    match st.batches.create_file(&owner, &filename, "batch", &data).await {
+        Ok(file) => (StatusCode::OK, Json(file)).into_response(),
+        Err(e) => batch_store_error(e),
+    }
+}
+
+pub async fn list_files(State(st): State<AppState>, auth: Option<Extension<AuthContext>>) -> Response {
+    match st.batches.list_files().await {
+        Ok(files) => {
+            let data: Vec<FileObject> = files
+                .into_iter()
+                .filter(|f| can_access(auth.as_deref(), &f.owner))
+                .map(|f| f.file)
+                .collect();
+            Json(json!({"object": "list", "data": data, "has_more": false})).into_response()
+        }
+        Err(e) => batch_store_error(e),
+    }
+}
+
+async fn load_file(st: &AppState, auth: Option<&AuthContext>, id: &str) -> Result<FileObject, Response> {
+    match st.batches.get_file(id).await {
+        Ok(Some(f)) if can_access(auth, &f.owner) => Ok(f.file),
+        Ok(_) => Err(file_not_found(id)),
+        Err(e) => Err(batch_store_error(e)),
+    }
+}
+
+pub async fn get_file(
+    State(st): State<AppState>,
+    auth: Option<Extension<AuthContext>>,
+    Path(id): Path<String>,
+) -> Response {
+    match load_file(&st, auth.as_deref(), &id).await {
+        Ok(file) => Json(file).into_response(),
+        Err(resp) => resp,
+    }
+}
+
+pub async fn get_file_content(
+    State(st): State<AppState>,
+    auth: Option<Extension<AuthContext>>,
+    Path(id): Path<String>,
+) -> Response {
+    if let Err(resp) = load_file(&st, auth.as_deref(), &id).await {
+        return resp;
+    }
+    match fs::read(st.batches.file_path(&id)).await {
+        Ok(data) => Response::builder()
+            .status(StatusCode::OK)
+            .header("content-type", "application/jsonl")
+            .body(Body::from(data))
+            .unwrap_or_else(|_| Response::new(Body::empty())),
+        Err(e) if e.kind() == std::io::ErrorKind::NotFound => file_not_found(&id),
+        Err(e) => batch_store_error(e.into()),
+    }
+}
+
+pub async fn delete_file(
+    State(st): State<AppState>,
+    auth: Option<Extension<AuthContext>>,
+    Path(id): Path<String>,
+) -> Response {
+    if let Err(resp) = load_file(&st, auth.as_deref(), &id).await {
+        return resp;
+    }
+    match st.batches.delete_file(&id).await {
+        Ok(true) => Json(json!({"id": id, "object": "file", "deleted": true})).into_response(),
+        Ok(false) => file_not_found(&id),
+        Err(e) => batch_store_error(e),
+    }
+}
+
+#[derive(Debug, serde::Deserialize)]
+pub(crate) struct CreateBatchRequest {
+    input_file_id: String,
+    endpoint: String,
+    completion_window: String,
+    #[serde(default)]
+    metadata: Option<serde_json::Value>,
+}
+
+pub async fn create_batch(
+    State(st): State<AppState>,
+    auth: Option<Extension<AuthContext>>,
+    payload: Result<Json<CreateBatchRequest>, JsonRejection>,
+) -> Response {
+    let req = match payload {
+        Ok(Json(req)) => req,
+        Err(e) => return ApiError::new(ErrorCode::InvalidRequest, e.body_text()).into_response(),
+    };
+    if !BATCH_ENDPOINTS.contains(&req.endpoint.as_str()) {
+        return ApiError::new(
+            ErrorCode::InvalidRequest,
+            format!("endpoint must be one of {}", BATCH_ENDPOINTS.join(", ")),
+        )
+        .with_param("endpoint")
+        .into_response();
+    }
+    if req.completion_window != COMPLETION_WINDOW {
+        return ApiError::new(ErrorCode::InvalidRequest, format!("completion_window must be '{COMPLETION_WINDOW}'"))
+            .with_param("completion_window")
+            .into_response();
+    }
+    match load_file(&st, auth.as_deref(), &req.input_file_id).await {
+        Ok(file) if file.purpose == "batch" => {}
+        Ok(_) => {
+            return ApiError::new(ErrorCode::InvalidRequest, "input file must have purpose 'batch'")
+                .with_param("input_file_id")
+                .into_response()
+        }
+        Err(resp) => return resp,
+    }
+    if let Some(ctx) = auth.as_deref().filter(|c| c.api_key.is_some()) {
+        let data = match fs::read(st.batches.file_path(&req.input_file_id)).await {
+            Ok(d) => d,
+            Err(e) => return batch_store_error(e.into()),
+        };
+        if let Err(e) = check_batch_models(ctx, &data) {
+            return e.into_response();
+        }
+    }
+
+    let stored = StoredBatch {
+        batch: Batch::new(req.input_file_id, req.endpoint, req.metadata),
+        owner: owner_label(auth.as_deref()),
+        tenant: auth.as_deref().and_then(|c| c.tenant.clone()),
+        api_key_id: auth.as_deref().and_then(|c| c.api_key.as_ref()).map(|k| k.id.clone()),
+        worker: None,
+        lease_until_ms: 0,
+    };
+    match st.batches.create_batch(&stored).await {
+        Ok(()) => Json(stored.batch).into_response(),
+        Err(e) => batch_store_error(e),
+    }
+}
+
+/// Rejects a batch input file naming a model the caller may not use.
+fn check_batch_models(ctx: &AuthContext, data: &[u8]) -> Result<(), ApiError> {
+    match input_models(data).into_iter().find(|(_, model)| !ctx.allows_model(model)) {
+        Some((line, model)) => Err(ApiError::new(
+            ErrorCode::ModelNotAllowed,
+            format!("Line {line}: this API key is not allowed to use model '{model}'."),
+        )
+        .with_param("input_file_id")),
+        None => Ok(()),
+    }
+}
+
+#[derive(Debug, serde::Deserialize)]
+pub(crate) struct ListBatchesQuery {
+    after: Option<String>,
+    limit: Option<usize>,
+}
+
+pub async fn list_batches(
+    State(st): State<AppState>,
+    auth: Option<Extension<AuthContext>>,
+    axum::extract::Query(query): axum::extract::Query<ListBatchesQuery>,
+) -> Response {
+    let batches = match st.batches.list_batches().await {
+        Ok(b) => b,
+        Err(e) => return batch_store_error(e),
+    };
+    let visible = batches
+        .into_iter()
+        .filter(|b| can_access(auth.as_deref(), &b.owner))
+        .map(|b| b.batch);
+    let limit = query.limit.unwrap_or(20).clamp(1, 100);
+    let (data, has_more) = match batch_page(visible, query.after.as_deref(), limit) {
+        Ok(page) => page,
+        Err(e) => return e.into_response(),
+    };
+    Json(json!({
+        "object": "list",
+        "data": data,
+        "first_id": data.first().map(|b| b.id.clone()),
+        "last_id": data.last().map(|b| b.id.clone()),
+        "has_more": has_more,
+    }))
+    .into_response()
+}
+
+/// Up to `limit` batches following the `after` cursor, and whether more
+/// remain. A cursor that isn't among `batches` is an error rather than an
+/// empty page, so a stale or foreign id isn't mistaken for the end of the list.
+fn batch_page(
+    batches: impl Iterator<Item = Batch>,
+    after: Option<&str>,
+    limit: usize,
+) -> Result<(Vec<Batch>, bool), ApiError> {
+    let mut batches = batches.peekable();
+    if let Some(after) = after {
+        while batches.next_if(|b| b.id != after).is_some() {}
+        if batches.next().is_none() {
+            return Err(ApiError::new(ErrorCode::InvalidRequest, format!("No such Batch object: {after}"))
+                .with_param("after")
+                .with_status(StatusCode::NOT_FOUND));
+        }
+    }
+    let data: Vec<Batch> = batches.by_ref().take(limit).collect();
+    Ok((data, batches.next().is_some()))
+}
+
+async fn load_batch(st: &AppState, auth: Option<&AuthContext>, id: &str) -> Result<StoredBatch, Response> {
+    match st.batches.get_batch(id).await {
+        Ok(Some(b)) if can_access(auth, &b.owner) => Ok(b),
+        Ok(_) => Err(batch_not_found(id)),
+        Err(e) => Err(batch_store_error(e)),
+    }
+}
+
+pub async fn get_batch(
+    State(st): State<AppState>,
+    auth: Option<Extension<AuthContext>>,
+    Path(id): Path<String>,
+) -> Response {
+    match load_batch(&st, auth.as_deref(), &id).await {
+        Ok(stored) => Json(stored.batch).into_response(),
+        Err(resp) => resp,
+    }
+}
+
+/// `POST /v1/batches/{id}/cancel`: the executor stops starting new requests
+/// and finishes the batch as `cancelled` with the results so far.
+pub async fn cancel_batch(
+    State(st): State<AppState>,
+    auth: Option<Extension<AuthContext>>,
+    Path(id): Path<String>,
+) -> Response {
+    if let Err(resp) = load_batch(&st, auth.as_deref(), &id).await {
+        return resp;
+    }
+    let cancelled = st
+        .batches
+        .update_batch(&id, |b| {
+            if !matches!(b.batch.status, BatchStatus::Validating | BatchStatus::InProgress) {
+                return false;
+            }
+            b.batch.status = BatchStatus::Cancelling;
+            b.batch.cancelling_at = Some(now_secs());
+            true
+        })
+        .await;
+    match cancelled {
+        Ok(Some(stored)) => Json(stored.batch).into_response(),
+        Ok(None) => match load_batch(&st, auth.as_deref(), &id).await {
+            Ok(stored) if stored.batch.status == BatchStatus::Cancelling => Json(stored.batch).into_response(),
+            Ok(stored) => ApiError::new(
+                ErrorCode::InvalidRequest,
+                format!("Cannot cancel a batch with status '{}'.", stored.batch.status.as_str()),
+            )
+            .into_response(),
+            Err(resp) => resp,
+        },
+        Err(e) => batch_store_error(e),
     }
 }
 
@@ -95,14 +732,25 @@ pub fn build_execution_context(headers: &HeaderMap) -> ExecutionContext {
         .and_then(|v| v.to_str().ok())
         .map(|s| s.to_string());
 
-    ExecutionContext {
-        request_id: format!("req_{}", Uuid::new_v4()),
+    let request_id = headers
+        .get(crate::audit::REQUEST_ID)
+        .and_then(|v| v.to_str().ok())
+        .filter(|id| !id.is_empty())
+        .map(|id| id.to_string())
+        .unwrap_or_else(|| format!("req_{}", Uuid::new_v4()));
+
+    let mut ctx = ExecutionContext {
+        request_id,
         session_id,
         tenant_id: None,
         priority: None,
         deadline_ms: None,
         budget_tokens: None,
-    }
+        required_context_tokens: None,
+        lora_adapter: None,
+    };
+    ctx.read_limit_headers(headers);
+    ctx
 }
 
 pub async fn healthz() -> impl IntoResponse {
@@ -111,14 +759,7 @@ pub async fn healthz() -> impl IntoResponse {
 
 pub async fn not_implemented(State(_st): State<AppState>, headers: HeaderMap) -> impl IntoResponse {
     let ctx = build_execution_context(&headers);
-    let body = json!({
-        "error": {
-            "message": "not implemented",
-            "type": "nebula_gateway_not_implemented",
-            "request_id": ctx.request_id
-        }
-    });
-    (StatusCode::NOT_IMPLEMENTED, Json(body))
+    ApiError::new(ErrorCode::NotImplemented, "not implemented").with_request_id(ctx.request_id)
 }
 
 fn to_reqwest_headers(headers: &HeaderMap) -> reqwest::header::HeaderMap {
@@ -145,6 +786,20 @@ fn append_headers(src: &reqwest::header::HeaderMap, dst: &mut Response) {
     }
 }
 
+/// Response for a buffered upstream body. The upstream `Content-Length` is
+/// dropped when the body was `rewritten`, since it no longer matches.
+fn buffered_response(status: StatusCode, headers: &reqwest::header::HeaderMap, bytes: Bytes, rewritten: bool) -> Response {
+    let mut out = Response::builder()
+        .status(status)
+        .body(Body::from(bytes))
+        .unwrap_or_else(|_| Response::new(Body::empty()));
+    append_headers(headers, &mut out);
+    if rewritten {
+        out.headers_mut().remove(axum::http::header::CONTENT_LENGTH);
+    }
+    out
+}
+
 fn classify_reqwest_error(error: &reqwest::Error) -> &'static str {
     if error.is_timeout() {
         return "timeout";
@@ -155,12 +810,113 @@ fn classify_reqwest_error(error: &reqwest::Error) -> &'static str {
     "other"
 }
 
+/// Where the token usage of one inference request is recorded: the per-model,
+/// per-principal counters, the quota ledger and, when auditing, the request's
+/// audit entry.
+#[derive(Clone)]
+struct UsageSink {
+    metrics: Arc<Metrics>,
+    quotas: Arc<Quotas>,
+    scopes: Vec<QuotaScope>,
+    recorder: Option<UsageRecorder>,
+    model: String,
+    principal: String,
+}
+
+impl UsageSink {
+    fn new(st: &AppState, auth: Option<&AuthContext>, recorder: Option<UsageRecorder>, model: &str) -> Self {
+        Self {
+            metrics: st.metrics.clone(),
+            quotas: st.quotas.clone(),
+            scopes: auth.map(QuotaScope::for_request).unwrap_or_default(),
+            recorder,
+            model: model.to_string(),
+            principal: auth
+                .map(|c| c.principal_label())
+                .unwrap_or_else(|| "anonymous".to_string()),
+        }
+    }
+
+    fn record(&self, usage: TokenUsage) {
+        self.metrics.record_tokens(&self.model, &self.principal, usage);
+        self.quotas.record(&self.scopes, usage);
+        if let Some(r) = self.recorder.as_ref() {
+            r.record(&self.model, usage);
+        }
+    }
+
+    /// Charge a stream the client abandoned: the usage the engine reported
+    /// before it went away, or else the heuristic count of the text generated
+    /// so far on top of the prompt estimate.
+    fn record_abandoned(&self, reported: Option<TokenUsage>, prompt_tokens: u32, generated: &str) {
+        self.record(reported.unwrap_or(TokenUsage {
+            prompt_tokens: prompt_tokens as u64,
+            completion_tokens: heuristic_count(generated) as u64,
+        }));
+    }
+}
+
+/// Prompt tokens of a chat or completion request body by the heuristic count.
+fn prompt_estimate(body: &serde_json::Value) -> u32 {
+    estimate_request(body, &heuristic_count).map_or(0, |e| e.prompt_tokens)
+}
+
+/// Append the text an engine event generated: content and tool call arguments.
+fn push_generated(generated: &mut String, ev: &EngineEvent) {
+    if let EngineEvent::Text(text) | EngineEvent::ToolCall { arguments: text, .. } = ev {
+        generated.push_str(text);
+    }
+}
+
+/// The `model` field of a JSON or `multipart/form-data` request body.
+fn request_model(headers: &HeaderMap, body: &[u8]) -> String {
+    let multipart = headers
+        .get(axum::http::header::CONTENT_TYPE)
+        .and_then(|v| v.to_str().ok())
+        .and_then(MultipartModelScanner::from_content_type);
+    let model = match multipart {
+        Some(mut scanner) => match scanner.feed(body) {
+            ModelScan::Found { model, .. } => Some(model),
+            _ => None,
+        },
+        None => serde_json::from_slice::<serde_json::Value>(body)
+            .ok()
+            .and_then(|v| v.get("model").and_then(|m| m.as_str()).map(str::to_string)),
+    };
+    model.unwrap_or_else(|| "unknown".to_string())
+}
+
+/// The guardrail policies that apply to a request for `model`.
+fn select_guardrails(st: &AppState, auth: Option<&AuthContext>, model: &str) -> Option<Selection> {
+    let tenant = auth.and_then(|c| c.tenant.as_deref());
+    st.guardrails.as_ref().and_then(|g| g.select(model, tenant))
+}
+
+/// Runs the request stages on a body, redacting it in place, and records the
+/// actions taken in the audit entry. Returns whether the body changed.
+async fn check_request(
+    guard: &Selection,
+    body: &mut serde_json::Value,
+    recorder: Option<&UsageRecorder>,
+) -> Result<bool, Blocked> {
+    let mut events = Vec::new();
+    let checked = guard.check_request(body, &mut events).await;
+    if let Some(recorder) = recorder {
+        let blocked = checked.as_ref().err().map(|b| b.0.clone());
+        recorder.record_guardrails(&events);
+        recorder.record_guardrails(blocked.as_slice());
+    }
+    checked
+}
+
 pub async fn proxy_post(
     State(st): State<AppState>,
     headers: HeaderMap,
     req: Request<Body>,
 ) -> Response {
     let base = st.router_base_url.trim_end_matches('/');
+    let (parts, body) = req.into_parts();
+    let req = Request::from_parts(parts, ());
     let uri_path = req.uri().path().to_string();
     let uri_query = req
         .uri()
@@ -169,30 +925,85 @@ pub async fn proxy_post(
         .unwrap_or_default();
     let url = format!("{base}{uri_path}{uri_query}");
 
-    let body_bytes = match axum::body::to_bytes(req.into_body(), st.max_request_body_bytes).await {
+    let mut body_bytes = match axum::body::to_bytes(body, st.max_request_body_bytes).await {
         Ok(b) => b,
         Err(_) => {
             st.metrics
                 .request_too_large_total
                 .fetch_add(1, std::sync::atomic::Ordering::Relaxed);
-            return (StatusCode::PAYLOAD_TOO_LARGE, "request body too large").into_response();
+            return ApiError::new(ErrorCode::RequestTooLarge, "request body too large").into_response();
         }
     };
+    let model = request_model(&headers, &body_bytes);
+    let recorder = req.extensions().get::<UsageRecorder>().cloned();
+    if let Some(recorder) = recorder.as_ref() {
+        recorder.record_model(&model);
+    }
+    if let Some(ctx) = req.extensions().get::<AuthContext>() {
+        if !ctx.allows_model(&model) {
+            return model_not_allowed(&model);
+        }
+        if let Some(d) = st.auth.acquire_model(ctx, &model).await.filter(|d| !d.allowed) {
+            return rate_limited(&d);
+        }
+    }
+    // Guardrails run before the cache so that a blocked prompt is never
+    // answered and redacted prompts share entries.
+    let guard = select_guardrails(&st, req.extensions().get::<AuthContext>(), &model);
+    if let Some(guard) = guard.as_ref() {
+        if let Ok(mut json) = serde_json::from_slice::<serde_json::Value>(&body_bytes) {
+            match check_request(guard, &mut json, recorder.as_ref()).await {
+                Ok(true) => body_bytes = Bytes::from(serde_json::to_vec(&json).unwrap_or_default()),
+                Ok(false) => {}
+                Err(blocked) => return blocked.into_response(),
+            }
+        }
+    }
+    // Cache hits are served without going upstream, so they use no tokens.
+    let cache_key = st.cache.as_ref().and_then(|c| {
+        c.key(&uri_path, &headers, req.extensions().get::<AuthContext>(), &body_bytes)
+    });
+    let mut embedding = None;
+    if let (Some(cache), Some(key)) = (st.cache.as_ref(), cache_key.as_ref()) {
+        match cache.lookup(key, &headers).await {
+            Lookup::Hit(resp) => return resp,
+            Lookup::Miss(e) => embedding = e,
+        }
+    }
+    let usage = UsageSink::new(
+        &st,
+        req.extensions().get::<AuthContext>(),
+        recorder.clone(),
+        &model,
+    );
+    // Streams always report usage for accounting; clients that didn't ask for
+    // it don't get the usage-only chunk.
+    let mut strip_usage = false;
+    if uri_path.ends_with("completions") {
+        if let Ok(mut json) = serde_json::from_slice::<serde_json::Value>(&body_bytes) {
+            if request_stream_usage(&mut json) {
+                strip_usage = true;
+                body_bytes = Bytes::from(serde_json::to_vec(&json).unwrap_or_default());
+            }
+        }
+    }
 
-    let resp = match st
-        .http
-        .post(url)
-        .headers(to_reqwest_headers(&headers))
-        .body(body_bytes)
-        .send()
-        .await
-    {
+    let mut request = st.http.post(url);
+    if let Some(remaining) = deadline_remaining(&headers) {
+        request = request.timeout(remaining);
+    }
+    // Kept to estimate prompt tokens should the client abandon a stream.
+    let request_body = body_bytes.clone();
+    let resp = match request.headers(to_reqwest_headers(&headers)).body(body_bytes).send().await {
         Ok(r) => r,
         Err(e) => {
             let kind = classify_reqwest_error(&e);
             st.metrics.record_upstream_error(kind);
             tracing::error!(error=%e, "upstream request failed");
-            return (StatusCode::BAD_GATEWAY, "upstream request failed").into_response();
+            if e.is_timeout() {
+                return ApiError::new(ErrorCode::DeadlineExceeded, "request deadline exceeded").into_response();
+            }
+            return ApiError::new(ErrorCode::UpstreamError, "upstream request failed").into_response();
         }
     };
 
@@ -208,13 +1019,72 @@ pub async fn proxy_post(
     if is_sse {
         let mut upstream = resp.bytes_stream();
         let (tx, rx) = mpsc::channel::<Result<Bytes, Infallible>>(64);
+        let ok = status.is_success();
+        let done = tx.clone();
+        let metrics = st.metrics.clone();
+        let mut filter = guard.as_ref().filter(|_| ok).and_then(|g| g.stream_filter());
+        let mut strip = strip_usage.then(UsageChunkFilter::default);
         tokio::spawn(async move {
-            while let Some(item) = upstream.next().await {
-                match item {
-                    Ok(b) => {
-                        let _ = tx.send(Ok(b)).await;
+            let mut scanner = SseUsageScanner::default();
+            // Whether the stream ended without the client going away.
+            let forward = async {
+                while let Some(item) = upstream.next().await {
+                    match item {
+                        Ok(b) => {
+                            scanner.push(&b);
+                            let b = match strip.as_mut() {
+                                Some(s) => Bytes::from(s.push(&b)),
+                                None => b,
+                            };
+                            let (b, blocked) = match filter.as_mut() {
+                                Some(f) => (f.push(&b), f.is_blocked()),
+                                None => (b, false),
+                            };
+                            if !b.is_empty() && tx.send(Ok(b)).await.is_err() {
+                                return false;
+                            }
+                            if blocked {
+                                break;
+                            }
+                        }
+                        Err(e) if e.is_timeout() => {
+                            let err = ApiError::new(ErrorCode::DeadlineExceeded, "request deadline exceeded");
+                            let _ = tx.send(Ok(Bytes::from(err.sse_event()))).await;
+                            return true;
+                        }
+                        Err(_) => break,
+                    }
+                }
+                let tail = strip.map(UsageChunkFilter::finish).unwrap_or_default();
+                if !tail.is_empty() {
+                    let tail = match filter.as_mut() {
+                        Some(f) => f.push(&tail),
+                        None => Bytes::from(tail),
+                    };
+                    if !tail.is_empty() && tx.send(Ok(tail)).await.is_err() {
+                        return false;
+                    }
+                }
+                if let Some(filter) = filter {
+                    let (rest, events) = filter.finish().await;
+                    if let Some(recorder) = recorder.as_ref() {
+                        recorder.record_guardrails(&events);
+                    }
+                    if !rest.is_empty() && tx.send(Ok(rest)).await.is_err() {
+                        return false;
                     }
-                    Err(_) => break,
+                }
+                if let Some(u) = scanner.usage().filter(|_| ok) {
+                    usage.record(u);
+                }
+                true
+            };
+            if until_closed(done, forward).await != Some(true) {
+                metrics.record_client_cancelled();
+                if ok {
+                    let prompt_tokens = serde_json::from_slice::<serde_json::Value>(&request_body)
+                        .map_or(0, |b| prompt_estimate(&b));
+                    usage.record_abandoned(scanner.usage(), prompt_tokens, scanner.text());
                 }
             }
         });
@@ -229,18 +1099,57 @@ pub async fn proxy_post(
         return out;
     }
 
-    let bytes = match resp.bytes().await {
+    // Binary bodies (e.g. generated speech) are passed through as they arrive.
+    let is_json = resp_headers
+        .get(reqwest::header::CONTENT_TYPE)
+        .and_then(|v| v.to_str().ok())
+        .is_none_or(|s| s.contains("json"));
+    if !is_json {
+        let mut out = Response::builder()
+            .status(status)
+            .body(Body::from_stream(resp.bytes_stream()))
+            .unwrap_or_else(|_| Response::new(Body::empty()));
+        append_headers(&resp_headers, &mut out);
+        return out;
+    }
+
+    let mut bytes = match resp.bytes().await {
         Ok(bytes) => bytes,
         Err(e) => {
             tracing::warn!(error=%e, "failed to read upstream response body");
             Bytes::new()
         }
     };
-    let mut out = Response::builder()
-        .status(status)
-        .body(Body::from(bytes))
-        .unwrap_or_else(|_| Response::new(Body::empty()));
-    append_headers(&resp_headers, &mut out);
+    let mut rewritten = false;
+    if status.is_success() {
+        if let Ok(mut json) = serde_json::from_slice::<serde_json::Value>(&bytes) {
+            if let Some(u) = TokenUsage::from_json(&json) {
+                usage.record(u);
+            }
+            if let Some(guard) = guard.as_ref() {
+                let mut events = Vec::new();
+                if guard.check_response(&mut json, &mut events).await {
+                    bytes = Bytes::from(serde_json::to_vec(&json).unwrap_or_default());
+                    rewritten = true;
+                }
+                if let Some(recorder) = recorder.as_ref() {
+                    recorder.record_guardrails(&events);
+                }
+            }
+        }
+    }
+    let cached = match (st.cache.as_ref(), cache_key) {
+        (Some(cache), Some(key)) if status == StatusCode::OK && !bytes.is_empty() => {
+            cache.store(key, bytes.clone(), embedding);
+            true
+        }
+        _ => false,
+    };
+    let mut out = buffered_response(status, &resp_headers, bytes, rewritten);
+    if cached {
+        out.headers_mut()
+            .insert(CACHE_HEADER, HeaderValue::from_static("miss"));
+    }
     out
 }
 
@@ -268,7 +1177,7 @@ pub async fn proxy_v2(
             st.metrics
                 .request_too_large_total
                 .fetch_add(1, std::sync::atomic::Ordering::Relaxed);
-            return (StatusCode::PAYLOAD_TOO_LARGE, "request body too large").into_response();
+            return ApiError::new(ErrorCode::RequestTooLarge, "request body too large").into_response();
         }
     };
 
@@ -288,7 +1197,7 @@ pub async fn proxy_v2(
             let kind = classify_reqwest_error(&e);
             st.metrics.record_upstream_error(kind);
             tracing::error!(error=%e, url=%url, "bff proxy request failed");
-            return (StatusCode::BAD_GATEWAY, "bff proxy request failed").into_response();
+            return ApiError::new(ErrorCode::UpstreamError, "bff proxy request failed").into_response();
         }
     };
 
@@ -319,11 +1228,7 @@ pub async fn admin_cluster_status(
     let nodes_raw = match st.store.list_prefix("/nodes/").await {
         Ok(n) => n,
         Err(e) => {
-            return (
-                StatusCode::INTERNAL_SERVER_ERROR,
-                format!("etcd error: {}", e),
-            )
-                .into_response();
+            return ApiError::new(ErrorCode::MetaStoreError, format!("etcd error: {}", e)).into_response();
         }
     };
     let mut nodes = Vec::new();
@@ -336,11 +1241,7 @@ pub async fn admin_cluster_status(
     let endpoints_raw = match st.store.list_prefix("/endpoints/").await {
         Ok(e) => e,
         Err(e) => {
-            return (
-                StatusCode::INTERNAL_SERVER_ERROR,
-                format!("etcd error: {}", e),
-            )
-                .into_response();
+            return ApiError::new(ErrorCode::MetaStoreError, format!("etcd error: {}", e)).into_response();
         }
     };
     let mut endpoints = Vec::new();
@@ -353,11 +1254,7 @@ pub async fn admin_cluster_status(
     let placements_raw = match st.store.list_prefix("/placements/").await {
         Ok(p) => p,
         Err(e) => {
-            return (
-                StatusCode::INTERNAL_SERVER_ERROR,
-                format!("etcd error: {}", e),
-            )
-                .into_response();
+            return ApiError::new(ErrorCode::MetaStoreError, format!("etcd error: {}", e)).into_response();
         }
     };
     let mut placements = Vec::new();
@@ -370,11 +1267,7 @@ pub async fn admin_cluster_status(
     let requests_raw = match st.store.list_prefix("/model_requests/").await {
         Ok(r) => r,
         Err(e) => {
-            return (
-                StatusCode::INTERNAL_SERVER_ERROR,
-                format!("etcd error: {}", e),
-            )
-                .into_response();
+            return ApiError::new(ErrorCode::MetaStoreError, format!("etcd error: {}", e)).into_response();
         }
     };
     let mut model_requests = Vec::new();
@@ -404,11 +1297,7 @@ pub async fn admin_list_requests(
     let requests_raw = match st.store.list_prefix("/model_requests/").await {
         Ok(r) => r,
         Err(e) => {
-            return (
-                StatusCode::INTERNAL_SERVER_ERROR,
-                format!("etcd error: {}", e),
-            )
-                .into_response();
+            return ApiError::new(ErrorCode::MetaStoreError, format!("etcd error: {}", e)).into_response();
         }
     };
     let mut model_requests = Vec::new();
@@ -518,41 +1407,225 @@ pub async fn admin_logs_stream(
         .into_response()
 }
 
-pub async fn list_models(State(st): State<AppState>) -> impl IntoResponse {
-    let placements_raw = match st.store.list_prefix("/placements/").await {
-        Ok(p) => p,
-        Err(e) => {
-            return (
-                StatusCode::INTERNAL_SERVER_ERROR,
-                format!("etcd error: {}", e),
-            )
-                .into_response();
+pub async fn list_models(
+    State(st): State<AppState>,
+    auth: Option<Extension<AuthContext>>,
+) -> impl IntoResponse {
+    let mut data = match model_catalog(st.store.as_ref()).await {
+        Ok(data) => data,
+        Err(e) => return e.into_response(),
+    };
+    if let Some(Extension(ctx)) = auth.as_ref() {
+        data.retain(|m| model_visible(ctx, m));
+    }
+
+    (
+        StatusCode::OK,
+        Json(json!({"object": "list", "data": data})),
+    )
+        .into_response()
+}
+
+/// A single `/v1/models` entry, looked up by id or alias (e.g. the model_uid).
+pub async fn get_model(
+    State(st): State<AppState>,
+    auth: Option<Extension<AuthContext>>,
+    Path(id): Path<String>,
+) -> impl IntoResponse {
+    let id = id.trim_start_matches('/');
+    let data = match model_catalog(st.store.as_ref()).await {
+        Ok(data) => data,
+        Err(e) => return e.into_response(),
+    };
+    match find_model(data, id) {
+        Some(model) if auth.as_ref().is_none_or(|Extension(ctx)| model_visible(ctx, &model)) => {
+            (StatusCode::OK, Json(model)).into_response()
         }
+        _ => ApiError::new(ErrorCode::ModelNotFound, format!("The model '{id}' does not exist")).into_response(),
+    }
+}
+
+/// The catalog entry listed as `id` or with `id` among its aliases.
+fn find_model(data: Vec<serde_json::Value>, id: &str) -> Option<serde_json::Value> {
+    data.into_iter().find(|m| {
+        m["id"] == id || m["aliases"].as_array().is_some_and(|a| a.iter().any(|alias| alias == id))
+    })
+}
+
+/// Whether an API key may use a model under its id or any of its aliases.
+fn model_visible(ctx: &AuthContext, model: &serde_json::Value) -> bool {
+    let id = model["id"].as_str().into_iter();
+    let aliases = model["aliases"].as_array().into_iter().flatten().filter_map(|a| a.as_str());
+    id.chain(aliases).any(|name| ctx.allows_model(name))
+}
+
+/// OpenAI model objects for every registered model (running or not) and every
+/// LoRA adapter, extended with Nebula metadata. Models are listed under their
+/// `model_name`, which the router resolves; the model_uid is an alias.
+async fn model_catalog(store: &dyn MetaStore) -> Result<Vec<serde_json::Value>, ApiError> {
+    let list = |prefix: &'static str| async move {
+        store
+            .list_prefix(prefix)
+            .await
+            .map_err(|e| ApiError::new(ErrorCode::MetaStoreError, format!("etcd error: {}", e)))
     };
+    let specs: Vec<ModelSpec> = list("/models/")
+        .await?
+        .into_iter()
+        .filter(|(k, _, _)| k.ends_with("/spec"))
+        .filter_map(|(_, v, _)| serde_json::from_slice(&v).ok())
+        .collect();
+    let deployments: HashMap<String, ModelDeployment> = list("/deployments/")
+        .await?
+        .into_iter()
+        .filter_map(|(_, v, _)| serde_json::from_slice::<ModelDeployment>(&v).ok())
+        .map(|d| (d.model_uid.clone(), d))
+        .collect();
+    let mut endpoints: HashMap<String, Vec<EndpointInfo>> = HashMap::new();
+    for (_, val, _) in list("/endpoints/").await? {
+        if let Ok(ep) = serde_json::from_slice::<EndpointInfo>(&val) {
+            endpoints.entry(ep.model_uid.clone()).or_default().push(ep);
+        }
+    }
 
-    let mut models = Vec::new();
-    for (key, val, _) in placements_raw {
+    // LoRA adapter → base model_uid, from plan args and from what replicas have loaded.
+    let mut adapters = std::collections::BTreeMap::new();
+    // Models started through the legacy load API have a placement but no spec.
+    let mut placed = std::collections::BTreeSet::new();
+    for (key, val, _) in list("/placements/").await? {
         if let Ok(plan) = serde_json::from_slice::<PlacementPlan>(&val) {
-            models.push(plan.model_uid);
-            continue;
+            for a in &plan.assignments {
+                for adapter in a.lora_adapter_names() {
+                    adapters.insert(adapter, plan.model_uid.clone());
+                }
+            }
+            placed.insert(plan.model_uid);
+        } else if let Some(uid) = key.strip_prefix("/placements/") {
+            placed.insert(uid.to_string());
         }
-        if let Some(uid) = key.strip_prefix("/placements/") {
-            models.push(uid.to_string());
+    }
+    for ep in endpoints.values().flatten() {
+        for adapter in &ep.lora_adapters {
+            adapters.entry(adapter.clone()).or_insert_with(|| ep.model_uid.clone());
         }
     }
-    models.sort();
-    models.dedup();
 
-    let data: Vec<serde_json::Value> = models
-        .into_iter()
-        .map(|id| json!({"id": id, "object": "model", "owned_by": "nebula"}))
-        .collect();
+    let no_endpoints = Vec::new();
+    let mut names: HashMap<String, String> = HashMap::new();
+    let mut data = Vec::new();
+    for spec in &specs {
+        let deployment = deployments.get(&spec.model_uid);
+        let eps = endpoints.get(&spec.model_uid).unwrap_or(&no_endpoints);
+        names.insert(spec.model_uid.clone(), spec.model_name.clone());
+
+        let config_len = deployment
+            .and_then(|d| d.config_overrides.as_ref())
+            .and_then(|c| c.max_model_len)
+            .or_else(|| spec.config.as_ref().and_then(|c| c.max_model_len));
+        let mut aliases = vec![spec.model_uid.clone()];
+        aliases.retain(|a| *a != spec.model_name);
+        let mut model = model_entry(&spec.model_name, &spec.model_uid, eps, deployment, config_len);
+        model["created"] = json!(spec.created_at_ms / 1000);
+        model["aliases"] = json!(aliases);
+        model["engine"] = json!(spec.engine_type);
+        model["capabilities"] = json!(capabilities(spec));
+        if let Some(policy) = RequestPolicy::effective(
+            spec.request_policy.as_ref(),
+            deployment.and_then(|d| d.request_policy.as_ref()),
+        ) {
+            model["request_policy"] = json!(policy);
+        }
+        data.push(model);
+    }
+    for uid in placed {
+        if names.contains_key(&uid) {
+            continue;
+        }
+        let eps = endpoints.get(&uid).unwrap_or(&no_endpoints);
+        let mut model = model_entry(&uid, &uid, eps, deployments.get(&uid), None);
+        model["capabilities"] = json!(["chat", "completions"]);
+        data.push(model);
+        names.insert(uid.clone(), uid);
+    }
 
-    (
-        StatusCode::OK,
-        Json(json!({"object": "list", "data": data})),
-    )
-        .into_response()
+    let model_adapters = |uid: &str| -> Vec<&String> {
+        adapters.iter().filter(|(_, parent)| *parent == uid).map(|(a, _)| a).collect()
+    };
+    for model in &mut data {
+        let uid = model["model_uid"].as_str().unwrap_or_default().to_string();
+        model["adapters"] = json!(model_adapters(&uid));
+    }
+    data.extend(adapters.iter().map(|(id, parent_uid)| {
+        let parent = names.get(parent_uid).unwrap_or(parent_uid);
+        json!({
+            "id": id,
+            "object": "model",
+            "owned_by": "nebula",
+            "parent": parent,
+            "root": parent,
+            "model_uid": parent_uid,
+        })
+    }));
+    Ok(data)
+}
+
+/// The parts of a model entry that come from its deployment and endpoints.
+fn model_entry(
+    id: &str,
+    model_uid: &str,
+    endpoints: &[EndpointInfo],
+    deployment: Option<&ModelDeployment>,
+    config_len: Option<u32>,
+) -> serde_json::Value {
+    let ready = endpoints.iter().filter(|e| e.status == EndpointStatus::Ready).count() as u32;
+    let desired = match deployment {
+        Some(d) if d.desired_state == DesiredState::Stopped => 0,
+        Some(d) => d.disaggregation.as_ref().map_or(d.replicas, |s| s.total_replicas()),
+        None => endpoints.len() as u32,
+    };
+    let status = if desired == 0 {
+        if endpoints.is_empty() { "stopped" } else { "stopping" }
+    } else if ready == 0 {
+        "starting"
+    } else if ready < desired {
+        "degraded"
+    } else {
+        "running"
+    };
+    // What the engines report wins over the configured limit.
+    let context_window = endpoints.iter().filter_map(|e| e.max_model_len).max().or(config_len);
+
+    json!({
+        "id": id,
+        "object": "model",
+        "created": 0,
+        "owned_by": "nebula",
+        "model_uid": model_uid,
+        "status": status,
+        "context_window": context_window,
+        "replicas": {"desired": desired, "ready": ready},
+    })
+}
+
+/// What a model serves: the comma-separated `capabilities` label if set,
+/// otherwise guessed from the model name.
+fn capabilities(spec: &ModelSpec) -> Vec<String> {
+    if let Some(label) = spec.labels.get("capabilities") {
+        return label
+            .split(',')
+            .map(|c| c.trim().to_string())
+            .filter(|c| !c.is_empty())
+            .collect();
+    }
+    let name = spec.model_name.to_ascii_lowercase();
+    let caps: &[&str] = if name.contains("rerank") {
+        &["rerank"]
+    } else if name.contains("embed") || name.contains("bge-") || name.contains("e5-") {
+        &["embeddings"]
+    } else {
+        &["chat", "completions"]
+    };
+    caps.iter().map(|c| c.to_string()).collect()
 }
 
 pub async fn admin_delete_request(
@@ -567,24 +1640,16 @@ pub async fn admin_delete_request(
 
     let (data, _) = match st.store.get(&key).await {
         Ok(Some(kv)) => kv,
-        Ok(None) => return (StatusCode::NOT_FOUND, "request not found").into_response(),
+        Ok(None) => return ApiError::new(ErrorCode::NotFound, "request not found").into_response(),
         Err(e) => {
-            return (
-                StatusCode::INTERNAL_SERVER_ERROR,
-                format!("etcd error: {}", e),
-            )
-                .into_response();
+            return ApiError::new(ErrorCode::MetaStoreError, format!("etcd error: {}", e)).into_response();
         }
     };
 
     let mut req: ModelRequest = match serde_json::from_slice(&data) {
         Ok(r) => r,
         Err(e) => {
-            return (
-                StatusCode::INTERNAL_SERVER_ERROR,
-                format!("deserialization error: {}", e),
-            )
-                .into_response();
+            return ApiError::new(ErrorCode::SerializationError, format!("deserialization error: {}", e)).into_response();
         }
     };
 
@@ -592,19 +1657,11 @@ pub async fn admin_delete_request(
     let val = match serde_json::to_vec(&req) {
         Ok(val) => val,
         Err(e) => {
-            return (
-                StatusCode::INTERNAL_SERVER_ERROR,
-                format!("serialization error: {}", e),
-            )
-                .into_response();
+            return ApiError::new(ErrorCode::SerializationError, format!("serialization error: {}", e)).into_response();
         }
     };
     if let Err(e) = st.store.put(&key, val, None).await {
-        return (
-            StatusCode::INTERNAL_SERVER_ERROR,
-            format!("etcd error: {}", e),
-        )
-            .into_response();
+        return ApiError::new(ErrorCode::MetaStoreError, format!("etcd error: {}", e)).into_response();
     }
 
     (
@@ -637,20 +1694,12 @@ pub async fn admin_load_model(
     let val = match serde_json::to_vec(&model_req) {
         Ok(val) => val,
         Err(e) => {
-            return (
-                StatusCode::INTERNAL_SERVER_ERROR,
-                format!("serialization error: {}", e),
-            )
-                .into_response();
+            return ApiError::new(ErrorCode::SerializationError, format!("serialization error: {}", e)).into_response();
         }
     };
 
     if let Err(e) = st.store.put(&key, val, None).await {
-        return (
-            StatusCode::INTERNAL_SERVER_ERROR,
-            format!("etcd error: {}", e),
-        )
-            .into_response();
+        return ApiError::new(ErrorCode::MetaStoreError, format!("etcd error: {}", e)).into_response();
     }
 
     let body = json!({
@@ -678,24 +1727,16 @@ pub async fn admin_scale_request(
 
     let (data, _) = match st.store.get(&key).await {
         Ok(Some(kv)) => kv,
-        Ok(None) => return (StatusCode::NOT_FOUND, "request not found").into_response(),
+        Ok(None) => return ApiError::new(ErrorCode::NotFound, "request not found").into_response(),
         Err(e) => {
-            return (
-                StatusCode::INTERNAL_SERVER_ERROR,
-                format!("etcd error: {}", e),
-            )
-                .into_response();
+            return ApiError::new(ErrorCode::MetaStoreError, format!("etcd error: {}", e)).into_response();
         }
     };
 
     let mut req: ModelRequest = match serde_json::from_slice(&data) {
         Ok(r) => r,
         Err(e) => {
-            return (
-                StatusCode::INTERNAL_SERVER_ERROR,
-                format!("deserialization error: {}", e),
-            )
-                .into_response();
+            return ApiError::new(ErrorCode::SerializationError, format!("deserialization error: {}", e)).into_response();
         }
     };
 
@@ -704,19 +1745,11 @@ pub async fn admin_scale_request(
     let val = match serde_json::to_vec(&req) {
         Ok(val) => val,
         Err(e) => {
-            return (
-                StatusCode::INTERNAL_SERVER_ERROR,
-                format!("serialization error: {}", e),
-            )
-                .into_response();
+            return ApiError::new(ErrorCode::SerializationError, format!("serialization error: {}", e)).into_response();
         }
     };
     if let Err(e) = st.store.put(&key, val, None).await {
-        return (
-            StatusCode::INTERNAL_SERVER_ERROR,
-            format!("etcd error: {}", e),
-        )
-            .into_response();
+        return ApiError::new(ErrorCode::MetaStoreError, format!("etcd error: {}", e)).into_response();
     }
 
     (
@@ -748,24 +1781,16 @@ pub async fn admin_drain_endpoint(
 
     let (data, _) = match st.store.get(&key).await {
         Ok(Some(kv)) => kv,
-        Ok(None) => return (StatusCode::NOT_FOUND, "endpoint not found").into_response(),
+        Ok(None) => return ApiError::new(ErrorCode::NotFound, "endpoint not found").into_response(),
         Err(e) => {
-            return (
-                StatusCode::INTERNAL_SERVER_ERROR,
-                format!("etcd error: {}", e),
-            )
-                .into_response();
+            return ApiError::new(ErrorCode::MetaStoreError, format!("etcd error: {}", e)).into_response();
         }
     };
 
     let mut ep: EndpointInfo = match serde_json::from_slice(&data) {
         Ok(ep) => ep,
         Err(e) => {
-            return (
-                StatusCode::INTERNAL_SERVER_ERROR,
-                format!("deserialization error: {}", e),
-            )
-                .into_response();
+            return ApiError::new(ErrorCode::SerializationError, format!("deserialization error: {}", e)).into_response();
         }
     };
 
@@ -782,19 +1807,11 @@ pub async fn admin_drain_endpoint(
     let val = match serde_json::to_vec(&ep) {
         Ok(val) => val,
         Err(e) => {
-            return (
-                StatusCode::INTERNAL_SERVER_ERROR,
-                format!("serialization error: {}", e),
-            )
-                .into_response();
+            return ApiError::new(ErrorCode::SerializationError, format!("serialization error: {}", e)).into_response();
         }
     };
     if let Err(e) = st.store.put(&key, val, None).await {
-        return (
-            StatusCode::INTERNAL_SERVER_ERROR,
-            format!("etcd error: {}", e),
-        )
-            .into_response();
+        return ApiError::new(ErrorCode::MetaStoreError, format!("etcd error: {}", e)).into_response();
     }
 
     (
@@ -822,11 +1839,7 @@ pub async fn admin_list_images(
     let kvs = match st.store.list_prefix("/images/").await {
         Ok(v) => v,
         Err(e) => {
-            return (
-                StatusCode::INTERNAL_SERVER_ERROR,
-                format!("etcd error: {}", e),
-            )
-                .into_response();
+            return ApiError::new(ErrorCode::MetaStoreError, format!("etcd error: {}", e)).into_response();
         }
     };
     let images: Vec<nebula_common::EngineImage> = kvs
@@ -848,18 +1861,10 @@ pub async fn admin_get_image(
     match st.store.get(&key).await {
         Ok(Some((data, _))) => match serde_json::from_slice::<nebula_common::EngineImage>(&data) {
             Ok(img) => (StatusCode::OK, Json(json!(img))).into_response(),
-            Err(e) => (
-                StatusCode::INTERNAL_SERVER_ERROR,
-                format!("deserialization error: {}", e),
-            )
-                .into_response(),
+            Err(e) => ApiError::new(ErrorCode::SerializationError, format!("deserialization error: {}", e)).into_response(),
         },
-        Ok(None) => (StatusCode::NOT_FOUND, "image not found").into_response(),
-        Err(e) => (
-            StatusCode::INTERNAL_SERVER_ERROR,
-            format!("etcd error: {}", e),
-        )
-            .into_response(),
+        Ok(None) => ApiError::new(ErrorCode::NotFound, "image not found").into_response(),
+        Err(e) => ApiError::new(ErrorCode::MetaStoreError, format!("etcd error: {}", e)).into_response(),
     }
 }
 
@@ -885,20 +1890,12 @@ pub async fn admin_put_image(
     let val = match serde_json::to_vec(&img) {
         Ok(v) => v,
         Err(e) => {
-            return (
-                StatusCode::INTERNAL_SERVER_ERROR,
-                format!("serialization error: {}", e),
-            )
-                .into_response();
+            return ApiError::new(ErrorCode::SerializationError, format!("serialization error: {}", e)).into_response();
         }
     };
     let key = format!("/images/{}", id);
     if let Err(e) = st.store.put(&key, val, None).await {
-        return (
-            StatusCode::INTERNAL_SERVER_ERROR,
-            format!("etcd error: {}", e),
-        )
-            .into_response();
+        return ApiError::new(ErrorCode::MetaStoreError, format!("etcd error: {}", e)).into_response();
     }
     (StatusCode::OK, Json(json!(img))).into_response()
 }
@@ -913,11 +1910,7 @@ pub async fn admin_delete_image(
     }
     let key = format!("/images/{}", id);
     if let Err(e) = st.store.delete(&key).await {
-        return (
-            StatusCode::INTERNAL_SERVER_ERROR,
-            format!("etcd error: {}", e),
-        )
-            .into_response();
+        return ApiError::new(ErrorCode::MetaStoreError, format!("etcd error: {}", e)).into_response();
     }
     // Also clean up image_status entries for this image
     let status_prefix = format!("/image_status/");
@@ -945,11 +1938,7 @@ pub async fn admin_list_image_status(
     let kvs = match st.store.list_prefix("/image_status/").await {
         Ok(v) => v,
         Err(e) => {
-            return (
-                StatusCode::INTERNAL_SERVER_ERROR,
-                format!("etcd error: {}", e),
-            )
-                .into_response();
+            return ApiError::new(ErrorCode::MetaStoreError, format!("etcd error: {}", e)).into_response();
         }
     };
     let statuses: Vec<nebula_common::NodeImageStatus> = kvs
@@ -968,14 +1957,25 @@ pub async fn admin_audit_logs(
         return resp;
     }
 
+    if let Some(file) = st.audit.as_ref().and_then(|a| a.file()) {
+        let (from, to) = match query.time_range() {
+            Ok(range) => range,
+            Err(e) => return ApiError::new(ErrorCode::InvalidRequest, e).into_response(),
+        };
+        return match file.query(&query, from, to).await {
+            Ok(body) => (StatusCode::OK, Json(body)).into_response(),
+            Err(e) => ApiError::new(ErrorCode::InternalError, format!("audit log read failed: {e}")).into_response(),
+        };
+    }
+
     let (xtrace_url, xtrace_token) = match (&st.xtrace_url, &st.xtrace_token) {
         (Some(u), Some(t)) => (u.clone(), t.clone()),
         _ => {
-            return (
-                StatusCode::SERVICE_UNAVAILABLE,
-                Json(json!({"error": "audit logging not configured (xtrace not set)"})),
+            return ApiError::new(
+                ErrorCode::ServiceUnavailable,
+                "audit logging not configured (no file or xtrace sink)",
             )
-                .into_response();
+            .into_response();
         }
     };
 
@@ -1005,11 +2005,7 @@ pub async fn admin_audit_logs(
     let resp = match st.http.get(&url).bearer_auth(&xtrace_token).send().await {
         Ok(r) => r,
         Err(e) => {
-            return (
-                StatusCode::BAD_GATEWAY,
-                Json(json!({"error": format!("xtrace request failed: {}", e)})),
-            )
-                .into_response();
+            return ApiError::new(ErrorCode::UpstreamError, format!("xtrace request failed: {}", e)).into_response();
         }
     };
 
@@ -1017,13 +2013,531 @@ pub async fn admin_audit_logs(
     let body: serde_json::Value = match resp.json().await {
         Ok(v) => v,
         Err(e) => {
-            return (
-                StatusCode::BAD_GATEWAY,
-                Json(json!({"error": format!("xtrace parse error: {}", e)})),
-            )
-                .into_response();
+            return ApiError::new(ErrorCode::UpstreamError, format!("xtrace parse error: {}", e)).into_response();
         }
     };
 
     (status, Json(body)).into_response()
 }
+
+fn unknown_quota_scope(kind: &str, id: &str) -> Response {
+    ApiError::new(
+        ErrorCode::NotFound,
+        format!("unknown quota scope '{kind}/{id}', expected keys/{{id}} or tenants/{{id}}"),
+    )
+    .into_response()
+}
+
+fn quota_scope_json(scope: &QuotaScope) -> serde_json::Value {
+    match scope {
+        QuotaScope::Key(id) => json!({"kind": "keys", "id": id}),
+        QuotaScope::Tenant(id) => json!({"kind": "tenants", "id": id}),
+    }
+}
+
+pub async fn admin_list_quotas(
+    State(st): State<AppState>,
+    Extension(ctx): Extension<AuthContext>,
+) -> impl IntoResponse {
+    if let Some(resp) = require_role(&st.metrics, &ctx, Role::Viewer) {
+        return resp;
+    }
+    let quotas = match st.quotas.list().await {
+        Ok(q) => q,
+        Err(e) => {
+            return ApiError::new(ErrorCode::MetaStoreError, format!("etcd error: {}", e)).into_response();
+        }
+    };
+    let mut items = Vec::with_capacity(quotas.len());
+    for (scope, limits) in quotas {
+        let usage = match st.quotas.usage(&scope).await {
+            Ok(u) => u,
+            Err(e) => {
+                return ApiError::new(ErrorCode::MetaStoreError, format!("etcd error: {}", e)).into_response();
+            }
+        };
+        let mut item = quota_scope_json(&scope);
+        item["limits"] = json!(limits);
+        item["usage"] = json!(usage);
+        items.push(item);
+    }
+    (StatusCode::OK, Json(json!({"quotas": items}))).into_response()
+}
+
+/// Limits (if any) and current usage of one API key or tenant. Keys are
+/// addressed by their principal label (`token-<hash>`).
+pub async fn admin_get_quota(
+    State(st): State<AppState>,
+    Extension(ctx): Extension<AuthContext>,
+    Path((kind, id)): Path<(String, String)>,
+) -> impl IntoResponse {
+    if let Some(resp) = require_role(&st.metrics, &ctx, Role::Viewer) {
+        return resp;
+    }
+    let Some(scope) = QuotaScope::parse(&kind, &id) else {
+        return unknown_quota_scope(&kind, &id);
+    };
+    let (limits, usage) = match (st.quotas.limits(&scope).await, st.quotas.usage(&scope).await) {
+        (Ok(l), Ok(u)) => (l, u),
+        (Err(e), _) | (_, Err(e)) => {
+            return ApiError::new(ErrorCode::MetaStoreError, format!("etcd error: {}", e)).into_response();
+        }
+    };
+    let mut body = quota_scope_json(&scope);
+    body["limits"] = json!(limits);
+    body["usage"] = json!(usage);
+    (StatusCode::OK, Json(body)).into_response()
+}
+
+pub async fn admin_put_quota(
+    State(st): State<AppState>,
+    Extension(ctx): Extension<AuthContext>,
+    Path((kind, id)): Path<(String, String)>,
+    Json(limits): Json<QuotaLimits>,
+) -> impl IntoResponse {
+    if let Some(resp) = require_role(&st.metrics, &ctx, Role::Admin) {
+        return resp;
+    }
+    let Some(scope) = QuotaScope::parse(&kind, &id) else {
+        return unknown_quota_scope(&kind, &id);
+    };
+    if let Err(e) = st.quotas.set_limits(&scope, limits).await {
+        return ApiError::new(ErrorCode::MetaStoreError, format!("etcd error: {}", e)).into_response();
+    }
+    let mut body = quota_scope_json(&scope);
+    body["limits"] = json!(limits);
+    (StatusCode::OK, Json(body)).into_response()
+}
+
+pub async fn admin_delete_quota(
+    State(st): State<AppState>,
+    Extension(ctx): Extension<AuthContext>,
+    Path((kind, id)): Path<(String, String)>,
+) -> impl IntoResponse {
+    if let Some(resp) = require_role(&st.metrics, &ctx, Role::Admin) {
+        return resp;
+    }
+    let Some(scope) = QuotaScope::parse(&kind, &id) else {
+        return unknown_quota_scope(&kind, &id);
+    };
+    match st.quotas.delete_limits(&scope).await {
+        Ok(true) => {
+            let mut body = quota_scope_json(&scope);
+            body["status"] = json!("deleted");
+            (StatusCode::OK, Json(body)).into_response()
+        }
+        Ok(false) => ApiError::new(ErrorCode::NotFound, "no quota configured").into_response(),
+        Err(e) => ApiError::new(ErrorCode::MetaStoreError, format!("etcd error: {}", e)).into_response(),
+    }
+}
+
+#[derive(Debug, Default, serde::Deserialize)]
+pub(crate) struct ResetQuotaRequest {
+    /// Windows to reset (`minute`, `day`, `month`); all when omitted.
+    #[serde(default)]
+    windows: Vec<String>,
+}
+
+pub async fn admin_reset_quota(
+    State(st): State<AppState>,
+    Extension(ctx): Extension<AuthContext>,
+    Path((kind, id)): Path<(String, String)>,
+    body: Option<Json<ResetQuotaRequest>>,
+) -> impl IntoResponse {
+    if let Some(resp) = require_role(&st.metrics, &ctx, Role::Admin) {
+        return resp;
+    }
+    let Some(scope) = QuotaScope::parse(&kind, &id) else {
+        return unknown_quota_scope(&kind, &id);
+    };
+    let req = body.map(|Json(b)| b).unwrap_or_default();
+    let windows = if req.windows.is_empty() {
+        Window::ALL.to_vec()
+    } else {
+        let mut windows = Vec::with_capacity(req.windows.len());
+        for w in &req.windows {
+            match Window::parse(w) {
+                Some(w) => windows.push(w),
+                None => {
+                    return ApiError::new(
+                        ErrorCode::InvalidRequest,
+                        format!("unknown window '{w}', expected minute, day or month"),
+                    )
+                    .into_response();
+                }
+            }
+        }
+        windows
+    };
+    if let Err(e) = st.quotas.reset(&scope, &windows).await {
+        return ApiError::new(ErrorCode::MetaStoreError, format!("etcd error: {}", e)).into_response();
+    }
+    let mut body = quota_scope_json(&scope);
+    body["reset"] = json!(windows.iter().map(|w| w.as_str()).collect::<Vec<_>>());
+    (StatusCode::OK, Json(body)).into_response()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use axum::routing::post;
+    use nebula_meta::MemoryMetaStore;
+
+    fn caller(principal: &str, role: Role) -> AuthContext {
+        AuthContext {
+            principal: principal.to_string(),
+            role,
+            tenant: Some("acme".to_string()),
+            api_key: None,
+        }
+    }
+
+    #[tokio::test]
+    async fn test_stored_responses_are_scoped_to_their_owner() {
+        let store = ResponseStore::new(Arc::new(MemoryMetaStore::new()), None);
+        let alice = caller("alice-token", Role::Operator);
+        let bob = caller("bob-token", Role::Operator);
+        store
+            .put(&StoredResponse {
+                response: json!({"id": "resp_1", "object": "response"}),
+                messages: vec![json!({"role": "user", "content": "hi"})],
+                owner: owner_label(Some(&alice)),
+                tenant: alice.tenant.clone(),
+            })
+            .await
+            .unwrap();
+
+        let own = owned_response(&store, "resp_1", Some(&alice)).await.unwrap();
+        assert_eq!(own.map(|r| r.messages.len()), Some(1));
+        assert!(owned_response(&store, "resp_1", Some(&bob)).await.unwrap().is_none());
+        assert!(owned_response(&store, "resp_1", None).await.unwrap().is_none());
+        let admin = caller("root-token", Role::Admin);
+        assert!(owned_response(&store, "resp_1", Some(&admin)).await.unwrap().is_some());
+        assert!(owned_response(&store, "resp_2", Some(&alice)).await.unwrap().is_none());
+    }
+
+    /// A router that records each request's path, content type and body. Speech
+    /// comes back as audio; everything else as JSON naming the path.
+    async fn fake_router() -> (String, Arc<std::sync::Mutex<Vec<(String, String, Bytes)>>>) {
+        type Seen = Arc<std::sync::Mutex<Vec<(String, String, Bytes)>>>;
+        async fn answer(State(seen): State<Seen>, uri: axum::http::Uri, headers: HeaderMap, body: Bytes) -> Response {
+            let content_type = headers
+                .get(axum::http::header::CONTENT_TYPE)
+                .and_then(|v| v.to_str().ok())
+                .unwrap_or_default();
+            seen.lock().unwrap().push((uri.path().to_string(), content_type.to_string(), body));
+            if uri.path() == "/v1/audio/speech" {
+                return ([(axum::http::header::CONTENT_TYPE, "audio/mpeg")], "ID3-audio").into_response();
+            }
+            Json(json!({"path": uri.path()})).into_response()
+        }
+        let seen = Seen::default();
+        let app = axum::Router::new().fallback(answer).with_state(seen.clone());
+        let listener = tokio::net::TcpListener::bind("127.0.0.1:0").await.unwrap();
+        let url = format!("http://{}", listener.local_addr().unwrap());
+        tokio::spawn(async move { axum::serve(listener, app).await });
+        (url, seen)
+    }
+
+    #[tokio::test]
+    async fn test_completion_audio_and_image_routes_reach_the_router() {
+        let (router, seen) = fake_router().await;
+        let st = AppState::for_tests(&router);
+        let app = |ctx: Option<AuthContext>| {
+            let app = axum::Router::new()
+                .route("/v1/completions", post(proxy_post))
+                .route("/v1/audio/*path", post(proxy_post))
+                .route("/v1/images/*path", post(proxy_post))
+                .with_state(st.clone());
+            match ctx {
+                Some(ctx) => app.layer(Extension(ctx)),
+                None => app,
+            }
+        };
+        let serve = |app: axum::Router| async move {
+            let listener = tokio::net::TcpListener::bind("127.0.0.1:0").await.unwrap();
+            let url = format!("http://{}", listener.local_addr().unwrap());
+            tokio::spawn(async move { axum::serve(listener, app).await });
+            url
+        };
+        let gateway = serve(app(None)).await;
+        let client = reqwest::Client::new();
+        let boundary = "nebula-test-boundary";
+        let multipart = format!(
+            "--{boundary}\r\nContent-Disposition: form-data; name=\"model\"\r\n\r\nwhisper-1\r\n\
+             --{boundary}\r\nContent-Disposition: form-data; name=\"file\"; filename=\"a.wav\"\r\n\
+             Content-Type: audio/wav\r\n\r\nRIFF\r\n--{boundary}--\r\n"
+        );
+        let multipart_type = format!("multipart/form-data; boundary={boundary}");
+
+        let resp = client
+            .post(format!("{gateway}/v1/completions"))
+            .json(&json!({"model": "llama-8b", "prompt": "hi"}))
+            .send()
+            .await
+            .unwrap();
+        assert_eq!(resp.status(), reqwest::StatusCode::OK);
+        assert_eq!(resp.json::<serde_json::Value>().await.unwrap()["path"], "/v1/completions");
+
+        let resp = client
+            .post(format!("{gateway}/v1/images/generations"))
+            .json(&json!({"model": "flux", "prompt": "a cat"}))
+            .send()
+            .await
+            .unwrap();
+        assert_eq!(resp.json::<serde_json::Value>().await.unwrap()["path"], "/v1/images/generations");
+
+        let resp = client
+            .post(format!("{gateway}/v1/audio/transcriptions"))
+            .header("content-type", &multipart_type)
+            .body(multipart.clone())
+            .send()
+            .await
+            .unwrap();
+        assert_eq!(resp.json::<serde_json::Value>().await.unwrap()["path"], "/v1/audio/transcriptions");
+        {
+            let seen = seen.lock().unwrap();
+            let (_, content_type, body) = &seen[2];
+            assert_eq!(content_type, &multipart_type);
+            assert_eq!(body, multipart.as_bytes());
+        }
+
+        let resp = client
+            .post(format!("{gateway}/v1/audio/speech"))
+            .json(&json!({"model": "tts-1", "input": "hello"}))
+            .send()
+            .await
+            .unwrap();
+        assert_eq!(resp.headers()["content-type"], "audio/mpeg");
+        assert_eq!(resp.bytes().await.unwrap(), "ID3-audio");
+        assert_eq!(seen.lock().unwrap().len(), 4);
+
+        // The model is read from multipart bodies for the key's allow-list.
+        let (_, key) = nebula_common::api_key::ApiKey::generate(
+            "alice".to_string(),
+            Role::Operator,
+            None,
+            vec!["llama-*".to_string()],
+            None,
+            None,
+            0,
+        );
+        let ctx = AuthContext { api_key: Some(Arc::new(key)), ..caller("alice", Role::Operator) };
+        let gateway = serve(app(Some(ctx))).await;
+        let resp = client
+            .post(format!("{gateway}/v1/audio/transcriptions"))
+            .header("content-type", &multipart_type)
+            .body(multipart)
+            .send()
+            .await
+            .unwrap();
+        assert_eq!(resp.status(), reqwest::StatusCode::FORBIDDEN);
+        let err = resp.json::<serde_json::Value>().await.unwrap();
+        assert!(err["error"]["message"].as_str().unwrap().contains("'whisper-1'"));
+        assert_eq!(seen.lock().unwrap().len(), 4);
+    }
+
+    #[tokio::test]
+    async fn test_model_catalog_lists_aliases_and_filters_by_key() {
+        let store = MemoryMetaStore::new();
+        let spec = json!({
+            "model_uid": "llama-8b",
+            "model_name": "meta-llama/Llama-3.1-8B-Instruct",
+            "model_source": "hugging_face",
+            "engine_type": "vllm",
+            "config": {"max_model_len": 8192},
+        });
+        store.put("/models/llama-8b/spec", spec.to_string().into_bytes(), None).await.unwrap();
+        let endpoint = EndpointInfo {
+            model_uid: "llama-8b".to_string(),
+            replica_id: 0,
+            plan_version: 1,
+            node_id: "n0".to_string(),
+            endpoint_kind: nebula_common::EndpointKind::NativeHttp,
+            api_flavor: "openai".to_string(),
+            status: EndpointStatus::Ready,
+            last_heartbeat_ms: 0,
+            grpc_target: None,
+            base_url: Some("http://127.0.0.1:8000".to_string()),
+            max_model_len: None,
+            lora_adapters: vec!["sql-lora".to_string()],
+            role: nebula_common::ServingRole::Both,
+        };
+        store
+            .put("/endpoints/llama-8b/0", serde_json::to_vec(&endpoint).unwrap(), None)
+            .await
+            .unwrap();
+        // Started through the legacy load API: a placement but no spec.
+        store.put("/placements/qwen-7b", b"{}".to_vec(), None).await.unwrap();
+
+        let data = model_catalog(&store).await.unwrap();
+        let ids: Vec<&str> = data.iter().filter_map(|m| m["id"].as_str()).collect();
+        assert_eq!(ids, ["meta-llama/Llama-3.1-8B-Instruct", "qwen-7b", "sql-lora"]);
+        let llama = find_model(data.clone(), "llama-8b").unwrap();
+        assert_eq!(llama["id"], "meta-llama/Llama-3.1-8B-Instruct");
+        assert_eq!(llama["aliases"], json!(["llama-8b"]));
+        assert_eq!(llama["adapters"], json!(["sql-lora"]));
+        assert_eq!(llama["status"], "running");
+        assert_eq!(llama["engine"], "vllm");
+        assert_eq!(find_model(data.clone(), "sql-lora").unwrap()["parent"], llama["id"]);
+        assert!(find_model(data.clone(), "mistral").is_none());
+
+        // A key allowed the model_uid sees the model under its name, but not the others.
+        let (_, key) = nebula_common::api_key::ApiKey::generate(
+            "alice".to_string(),
+            Role::Operator,
+            None,
+            vec!["llama-*".to_string()],
+            None,
+            None,
+            0,
+        );
+        let restricted = AuthContext { api_key: Some(Arc::new(key)), ..caller("alice", Role::Operator) };
+        let visible: Vec<&serde_json::Value> = data.iter().filter(|m| model_visible(&restricted, m)).collect();
+        assert_eq!(visible, [&llama]);
+        let open = caller("bob", Role::Operator);
+        assert!(data.iter().all(|m| model_visible(&open, m)));
+    }
+
+    #[test]
+    fn test_batch_pages_follow_the_after_cursor() {
+        let batches: Vec<Batch> = (0..5)
+            .map(|i| Batch {
+                id: format!("batch_{i}"),
+                ..Batch::new("file_1".to_string(), "/v1/chat/completions".to_string(), None)
+            })
+            .collect();
+        let ids = |page: &[Batch]| page.iter().map(|b| b.id.clone()).collect::<Vec<_>>();
+
+        let (page, more) = batch_page(batches.clone().into_iter(), None, 2).unwrap();
+        assert_eq!((ids(&page), more), (vec!["batch_0".to_string(), "batch_1".to_string()], true));
+        let (page, more) = batch_page(batches.clone().into_iter(), Some("batch_2"), 2).unwrap();
+        assert_eq!((ids(&page), more), (vec!["batch_3".to_string(), "batch_4".to_string()], false));
+        let (page, more) = batch_page(batches.clone().into_iter(), Some("batch_4"), 2).unwrap();
+        assert!(page.is_empty() && !more);
+
+        let err = batch_page(batches.into_iter(), Some("batch_9"), 2).unwrap_err();
+        assert_eq!(err.into_response().status(), StatusCode::NOT_FOUND);
+        let err = batch_page(std::iter::empty(), Some("batch_0"), 2).unwrap_err();
+        assert_eq!(err.code, ErrorCode::InvalidRequest);
+        assert_eq!(err.to_json()["error"]["param"], "after");
+    }
+
+    #[test]
+    fn test_batch_input_is_checked_against_the_key_allow_list() {
+        let (_, key) = nebula_common::api_key::ApiKey::generate(
+            "alice".to_string(),
+            Role::Operator,
+            None,
+            vec!["llama-*".to_string()],
+            None,
+            None,
+            0,
+        );
+        let restricted = AuthContext { api_key: Some(Arc::new(key)), ..caller("alice", Role::Operator) };
+        let line = |model: &str| json!({"custom_id": "r", "method": "POST", "url": "/v1/chat/completions", "body": {"model": model}}).to_string();
+        let input = [line("llama-8b"), line("qwen-7b")].join("\n");
+
+        let err = check_batch_models(&restricted, input.as_bytes()).unwrap_err();
+        assert_eq!(err.code, ErrorCode::ModelNotAllowed);
+        let body = err.to_json();
+        assert_eq!(body["error"]["param"], "input_file_id");
+        assert!(body["error"]["message"].as_str().unwrap().starts_with("Line 2:"));
+
+        assert!(check_batch_models(&restricted, line("llama-70b").as_bytes()).is_ok());
+        assert!(check_batch_models(&caller("root", Role::Admin), input.as_bytes()).is_ok());
+    }
+
+    fn usage_sink(metrics: &Arc<Metrics>) -> UsageSink {
+        let caller = caller("alice-token", Role::Operator);
+        UsageSink {
+            metrics: metrics.clone(),
+            quotas: Arc::new(Quotas::new(Arc::new(MemoryMetaStore::new()), "gw-1".to_string())),
+            scopes: QuotaScope::for_request(&caller),
+            recorder: None,
+            model: "m".to_string(),
+            principal: caller.principal_label(),
+        }
+    }
+
+    fn charged(metrics: &Metrics) -> TokenUsage {
+        metrics.tokens.lock().unwrap().values().copied().next().unwrap_or_default()
+    }
+
+    #[tokio::test]
+    async fn test_abandoned_stream_is_charged_for_what_was_generated() {
+        let metrics = Arc::new(Metrics::default());
+        let usage = usage_sink(&metrics);
+        let body = json!({"messages": [{"role": "user", "content": "abcdefgh"}]});
+        let prompt_tokens = prompt_estimate(&body);
+        assert_eq!(prompt_tokens, 3 + 4 + 2);
+
+        let (tx, mut rx) = mpsc::channel::<EngineEvent>(1);
+        let mut events = vec![
+            EngineEvent::Text("12345678".to_string()),
+            EngineEvent::ToolCall { index: 0, id: None, name: None, arguments: "abcd".to_string() },
+        ];
+        // The client reads the first delta and goes away mid-stream.
+        let client = tokio::spawn(async move {
+            rx.recv().await;
+        });
+        let mut generated = String::new();
+        let forward = async {
+            for ev in events.drain(..) {
+                push_generated(&mut generated, &ev);
+                let _ = tx.send(ev).await;
+            }
+            std::future::pending::<()>().await;
+            usage.record(TokenUsage { prompt_tokens: 100, completion_tokens: 100 });
+        };
+        assert!(until_closed(tx.clone(), forward).await.is_none());
+        client.await.unwrap();
+        usage.record_abandoned(None, prompt_tokens, &generated);
+        assert_eq!(generated, "12345678abcd");
+        assert_eq!(charged(&metrics), TokenUsage { prompt_tokens: 9, completion_tokens: 3 });
+
+        // Usage the engine already reported wins over the estimate.
+        let metrics = Arc::new(Metrics::default());
+        let reported = TokenUsage { prompt_tokens: 12, completion_tokens: 5 };
+        usage_sink(&metrics).record_abandoned(Some(reported), prompt_tokens, &generated);
+        assert_eq!(charged(&metrics), reported);
+    }
+
+    #[tokio::test]
+    async fn test_redacted_response_drops_the_upstream_content_length() {
+        let config = json!({"policies": [{
+            "name": "default",
+            "stages": [{"type": "keywords", "terms": ["secret"], "action": "redact", "applies_to": "output"}],
+        }]});
+        let path = std::env::temp_dir().join(format!("nebula-guardrails-{}.json", Uuid::new_v4()));
+        std::fs::write(&path, config.to_string()).unwrap();
+        let loaded = crate::guardrails::Guardrails::load(
+            path.to_str().unwrap(),
+            reqwest::Client::new(),
+            Arc::new(Metrics::default()),
+        );
+        std::fs::remove_file(&path).unwrap();
+        let guard = Arc::new(loaded.unwrap()).select("m", None).unwrap();
+
+        let upstream = json!({"choices": [
+            {"index": 0, "message": {"role": "assistant", "content": "the secret is out"}, "finish_reason": "stop"},
+        ]});
+        let bytes = Bytes::from(upstream.to_string());
+        let mut headers = reqwest::header::HeaderMap::new();
+        headers.insert(reqwest::header::CONTENT_LENGTH, bytes.len().into());
+        headers.insert(reqwest::header::CONTENT_TYPE, "application/json".parse().unwrap());
+
+        let unchanged = buffered_response(StatusCode::OK, &headers, bytes.clone(), false);
+        assert_eq!(unchanged.headers()["content-length"], bytes.len().to_string().as_str());
+
+        let mut json = upstream.clone();
+        assert!(guard.check_response(&mut json, &mut Vec::new()).await);
+        let redacted = Bytes::from(serde_json::to_vec(&json).unwrap());
+        assert_ne!(redacted.len(), bytes.len());
+        let out = buffered_response(StatusCode::OK, &headers, redacted.clone(), true);
+        assert!(out.headers().get("content-length").is_none());
+        assert_eq!(out.headers()["content-type"], "application/json");
+        let body = axum::body::to_bytes(out.into_body(), usize::MAX).await.unwrap();
+        assert_eq!(body, redacted);
+    }
+}
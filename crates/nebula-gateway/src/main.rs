@@ -1,13 +1,23 @@
 mod args;
 mod audit;
+mod audit_sink;
 mod auth;
+mod batch;
+mod cache;
+mod deadline;
 mod engine;
+mod guardrails;
 mod handlers;
+mod messages;
 mod metrics;
+mod quota;
+mod ratelimit;
+mod response_store;
 mod responses;
 mod state;
 mod util;
 
+use std::net::SocketAddr;
 use std::sync::Arc;
 use std::time::Duration;
 
@@ -19,17 +29,25 @@ use axum::{
 use clap::Parser;
 
 use crate::args::Args;
-use crate::audit::AuditWriter;
+use crate::audit::{AuditConfig, AuditWriter};
+use crate::batch::{BatchExecutor, BatchStore};
+use crate::cache::{CacheConfig, ResponseCache};
+use crate::guardrails::Guardrails;
 use nebula_common::auth::parse_auth_from_env;
 use crate::engine::{EngineClient, OpenAIEngineClient};
 use crate::handlers::{
     admin_audit_logs, admin_cluster_status, admin_delete_image, admin_delete_request,
-    admin_drain_endpoint, admin_get_image, admin_list_image_status, admin_list_images,
+    admin_delete_quota, admin_drain_endpoint, admin_get_image, admin_get_quota,
+    admin_list_quotas, admin_put_quota, admin_reset_quota, admin_list_image_status, admin_list_images,
     admin_list_requests, admin_load_model, admin_logs, admin_logs_stream, admin_put_image,
-    admin_scale_request, admin_whoami, create_responses, healthz, list_models, not_implemented,
-    proxy_post, proxy_v2,
+    admin_scale_request, admin_whoami, cancel_batch, create_batch, create_message, create_responses, delete_file,
+    delete_response, get_batch, get_file, get_file_content, get_model, get_response, healthz, list_batches, list_files,
+    list_models, not_implemented, proxy_post, proxy_v2, upload_file,
 };
 use crate::metrics::{metrics_handler, track_requests};
+use crate::quota::Quotas;
+use crate::ratelimit::MetaRateLimiter;
+use crate::response_store::ResponseStore;
 use crate::state::AppState;
 use crate::util::read_engine_env_file;
 
@@ -55,14 +73,16 @@ async fn main() {
 
     tracing::info!(router_base_url=%router_base_url, engine_model=%engine_model, "gateway starting");
 
+    let max_request_timeout = Duration::from_secs(args.max_request_timeout_secs.max(1));
     let engine: Arc<dyn EngineClient> = Arc::new(OpenAIEngineClient::new(
         router_base_url.clone(),
         engine_model,
+        max_request_timeout,
     ));
 
     let http = reqwest::Client::builder()
         .connect_timeout(Duration::from_secs(3))
-        .timeout(Duration::from_secs(300))
+        .timeout(max_request_timeout)
         .build()
         .unwrap_or_else(|e| {
             tracing::error!(error=%e, "failed to build reqwest client");
@@ -77,7 +97,41 @@ async fn main() {
         }
     };
 
-    let auth = parse_auth_from_env();
+    let store = Arc::new(store);
+
+    let response_meta: Arc<dyn nebula_meta::MetaStore> = match args.response_store.as_str() {
+        "memory" => Arc::new(nebula_meta::MemoryMetaStore::new()),
+        "etcd" => store.clone(),
+        other => {
+            tracing::error!(backend=%other, "unknown response store backend, expected etcd or memory");
+            return;
+        }
+    };
+    let responses = Arc::new(ResponseStore::new(
+        response_meta,
+        (args.response_ttl_secs > 0).then(|| args.response_ttl_secs * 1000),
+    ));
+
+    let replica_id = uuid::Uuid::new_v4().simple().to_string();
+    let quotas = Arc::new(Quotas::new(store.clone(), replica_id.clone()));
+    quotas.clone().spawn_sync(Duration::from_millis(args.quota_sync_ms.max(100)));
+
+    let mut auth = parse_auth_from_env();
+    match args.rate_limit_backend.as_str() {
+        "local" => {}
+        "meta" => {
+            let limiter = Arc::new(MetaRateLimiter::new(store.clone()));
+            limiter.clone().spawn_sweep();
+            auth.rate_limiter = limiter;
+        }
+        other => {
+            tracing::error!(backend=%other, "unknown rate limit backend, expected meta or local");
+            return;
+        }
+    }
+    if auth.api_keys_enabled {
+        tokio::spawn(auth::api_keys_sync_loop(store.clone(), auth.api_keys.clone()));
+    }
 
     let metrics = Arc::new(metrics::Metrics::default());
     let max_request_body_bytes = std::env::var("NEBULA_GATEWAY_MAX_REQUEST_BODY_BYTES")
@@ -85,19 +139,85 @@ async fn main() {
         .and_then(|v| v.parse::<usize>().ok())
         .unwrap_or(4 * 1024 * 1024);
 
-    let audit = AuditWriter::spawn(args.xtrace_url.as_deref(), args.xtrace_token.as_deref());
+    let audit_config = AuditConfig {
+        sink: args.audit_sink.clone(),
+        dir: args.audit_dir.clone().into(),
+        max_file_bytes: args.audit_max_file_mb.max(1) * 1024 * 1024,
+        max_files: args.audit_max_files,
+        xtrace_url: args.xtrace_url.clone(),
+        xtrace_token: args.xtrace_token.clone(),
+    };
+    let audit = match AuditWriter::spawn(&audit_config, metrics.clone()) {
+        Ok(audit) => audit,
+        Err(e) => {
+            tracing::error!(error=%e, "invalid audit configuration");
+            return;
+        }
+    };
+
+    let batches = Arc::new(BatchStore::new(
+        store.clone(),
+        args.files_dir.clone().into(),
+        args.max_file_mb.max(1) * 1024 * 1024,
+    ));
+
+    let cache = CacheConfig::parse_models(&args.cache_models).map(|models| {
+        let config = CacheConfig {
+            models,
+            ttl: Duration::from_secs(args.cache_ttl_secs.max(1)),
+            max_entries: args.cache_max_entries,
+            semantic_model: args.cache_semantic_model.clone().filter(|m| !m.is_empty()),
+            similarity_threshold: args.cache_similarity_threshold,
+        };
+        tracing::info!(models=?config.models, semantic_model=?config.semantic_model, "response cache enabled");
+        Arc::new(ResponseCache::new(config, http.clone(), router_base_url.clone(), metrics.clone()))
+    });
+
+    let guardrails = match args.guardrails_file.as_deref().filter(|f| !f.is_empty()) {
+        Some(path) => match Guardrails::load(path, http.clone(), metrics.clone()) {
+            Ok(guardrails) => {
+                tracing::info!(path=%path, policies=guardrails.policy_count(), "guardrails enabled");
+                Some(Arc::new(guardrails))
+            }
+            Err(e) => {
+                tracing::error!(error=%format!("{e:#}"), "invalid guardrails configuration");
+                return;
+            }
+        },
+        None => None,
+    };
+
+    Arc::new(BatchExecutor::new(
+        batches.clone(),
+        http.clone(),
+        router_base_url.clone(),
+        args.router_token.clone(),
+        replica_id,
+        args.batch_concurrency,
+        quotas.clone(),
+        metrics.clone(),
+        guardrails.clone(),
+        audit.clone(),
+    ))
+    .spawn();
 
     let st = AppState {
         _noop: Arc::new(()),
         engine,
         router_base_url,
         http,
-        store: Arc::new(store),
+        store,
+        responses,
+        batches,
+        quotas,
         auth,
         metrics,
         max_request_body_bytes,
+        max_request_timeout,
         log_path: args.log_path,
         audit,
+        cache,
+        guardrails,
         xtrace_url: args.xtrace_url.clone(),
         xtrace_token: args.xtrace_token.clone(),
         bff_url: args.bff_url,
@@ -118,6 +238,15 @@ async fn main() {
         .route("/models/requests/:id/scale", put(admin_scale_request))
         .route("/endpoints/drain", post(admin_drain_endpoint))
         .route("/audit-logs", get(admin_audit_logs))
+        // Token quotas and usage ledger
+        .route("/quotas", get(admin_list_quotas))
+        .route(
+            "/quotas/:kind/:id",
+            get(admin_get_quota)
+                .put(admin_put_quota)
+                .delete(admin_delete_quota),
+        )
+        .route("/quotas/:kind/:id/reset", post(admin_reset_quota))
         // Image registry
         .route("/images", get(admin_list_images))
         .route(
@@ -134,6 +263,8 @@ async fn main() {
         .route("/v2/models/:model_uid/stop", any(proxy_v2))
         .route("/v2/models/:model_uid/scale", any(proxy_v2))
         .route("/v2/models/:model_uid/save-as-template", any(proxy_v2))
+        .route("/v2/api-keys", any(proxy_v2))
+        .route("/v2/api-keys/:id", any(proxy_v2))
         .route("/v2/templates", any(proxy_v2))
         .route("/v2/templates/:id", any(proxy_v2))
         .route("/v2/templates/:id/deploy", any(proxy_v2))
@@ -149,12 +280,26 @@ async fn main() {
         .route("/health", get(healthz))
         .route("/metrics", get(metrics_handler))
         .route("/v1/responses", get(not_implemented).post(create_responses))
+        .route("/v1/responses/:id", get(get_response).delete(delete_response))
         .route("/v1/chat/completions", post(proxy_post))
+        .route("/v1/completions", post(proxy_post))
+        .route("/v1/messages", post(create_message))
         .route("/v1/embeddings", post(proxy_post))
         .route("/v1/rerank", post(proxy_post))
+        .route("/v1/audio/*path", post(proxy_post))
+        .route("/v1/images/*path", post(proxy_post))
         .route("/v1/models", get(list_models))
+        .route("/v1/models/*id", get(get_model))
+        .route("/v1/files", get(list_files).post(upload_file))
+        .route("/v1/files/:id", get(get_file).delete(delete_file))
+        .route("/v1/files/:id/content", get(get_file_content))
+        .route("/v1/batches", get(list_batches).post(create_batch))
+        .route("/v1/batches/:id", get(get_batch))
+        .route("/v1/batches/:id/cancel", post(cancel_batch))
         .nest("/v1/admin", admin_routes)
         // Global middleware
+        .layer(middleware::from_fn_with_state(st.clone(), deadline::deadline_middleware))
+        .layer(middleware::from_fn_with_state(st.clone(), quota::quota_middleware))
         .layer(middleware::from_fn_with_state(st.clone(), audit::audit_middleware))
         .layer(middleware::from_fn_with_state(st.clone(), nebula_common::auth::auth_middleware::<AppState>))
         .layer(middleware::from_fn_with_state(st.clone(), track_requests))
@@ -170,7 +315,7 @@ async fn main() {
         }
     };
 
-    if let Err(e) = axum::serve(listener, app).await {
+    if let Err(e) = axum::serve(listener, app.into_make_service_with_connect_info::<SocketAddr>()).await {
         tracing::error!(error=%e, "gateway server exited");
     }
 }
@@ -0,0 +1,636 @@
+use std::collections::BTreeMap;
+
+use serde::Deserialize;
+use serde_json::{json, Value};
+use uuid::Uuid;
+
+use nebula_common::TokenUsage;
+
+use crate::engine::EngineEvent;
+
+/// Anthropic Messages API request (`POST /v1/messages`).
+#[derive(Debug, Deserialize)]
+pub struct CreateMessageRequest {
+    pub model: String,
+    pub messages: Vec<Value>,
+    pub max_tokens: u32,
+    /// A string or a list of text blocks.
+    pub system: Option<Value>,
+    #[serde(default)]
+    pub stop_sequences: Vec<String>,
+    pub stream: Option<bool>,
+    pub temperature: Option<f64>,
+    pub top_p: Option<f64>,
+    pub top_k: Option<u32>,
+    #[serde(default)]
+    pub tools: Vec<Value>,
+    pub tool_choice: Option<Value>,
+    pub metadata: Option<Value>,
+}
+
+/// Text of a string or a list of text blocks.
+fn block_text(content: &Value, field: &str) -> Result<String, String> {
+    match content {
+        Value::String(s) => Ok(s.clone()),
+        Value::Null => Ok(String::new()),
+        Value::Array(blocks) => {
+            let mut text = Vec::new();
+            for b in blocks {
+                match b.get("type").and_then(|t| t.as_str()) {
+                    Some("text") => text.push(b.get("text").and_then(|t| t.as_str()).unwrap_or("")),
+                    other => {
+                        return Err(format!(
+                            "{field}: unsupported content block type '{}'",
+                            other.unwrap_or("")
+                        ));
+                    }
+                }
+            }
+            Ok(text.join("\n"))
+        }
+        _ => Err(format!("{field}: must be a string or an array of content blocks")),
+    }
+}
+
+/// Chat `image_url` part for an Anthropic image block.
+fn image_part(block: &Value, field: &str) -> Result<Value, String> {
+    let source = block.get("source");
+    let url = match source.and_then(|s| s.get("type")).and_then(|t| t.as_str()) {
+        Some("base64") => {
+            let media_type = source
+                .and_then(|s| s.get("media_type"))
+                .and_then(|m| m.as_str())
+                .unwrap_or("image/png");
+            let data = source.and_then(|s| s.get("data")).and_then(|d| d.as_str()).unwrap_or("");
+            format!("data:{media_type};base64,{data}")
+        }
+        Some("url") => source
+            .and_then(|s| s.get("url"))
+            .and_then(|u| u.as_str())
+            .ok_or_else(|| format!("{field}: image url source requires url"))?
+            .to_string(),
+        other => {
+            return Err(format!(
+                "{field}: unsupported image source type '{}'",
+                other.unwrap_or("")
+            ));
+        }
+    };
+    Ok(json!({"type": "image_url", "image_url": {"url": url}}))
+}
+
+/// Convert one Anthropic message to chat messages. A user turn's
+/// `tool_result` blocks become `tool` messages ahead of its remaining content;
+/// an assistant turn's `tool_use` blocks become `tool_calls`.
+fn chat_messages(i: usize, msg: &Value, out: &mut Vec<Value>) -> Result<(), String> {
+    let field = format!("messages.{i}");
+    let role = match msg.get("role").and_then(|r| r.as_str()) {
+        Some(r @ ("user" | "assistant")) => r,
+        other => return Err(format!("{field}.role: unsupported role '{}'", other.unwrap_or(""))),
+    };
+    let blocks = match msg.get("content") {
+        Some(Value::String(s)) => {
+            out.push(json!({"role": role, "content": s}));
+            return Ok(());
+        }
+        Some(Value::Array(blocks)) => blocks,
+        _ => return Err(format!("{field}.content: must be a string or an array of content blocks")),
+    };
+
+    let mut parts = Vec::new();
+    let mut tool_calls = Vec::new();
+    for (j, b) in blocks.iter().enumerate() {
+        let field = format!("{field}.content.{j}");
+        match (role, b.get("type").and_then(|t| t.as_str())) {
+            (_, Some("text")) => {
+                let text = b.get("text").and_then(|t| t.as_str()).unwrap_or("");
+                parts.push(json!({"type": "text", "text": text}));
+            }
+            ("user", Some("image")) => parts.push(image_part(b, &field)?),
+            ("user", Some("tool_result")) => {
+                let Some(id) = b.get("tool_use_id").and_then(|x| x.as_str()) else {
+                    return Err(format!("{field}.tool_use_id: field required"));
+                };
+                let content = block_text(b.get("content").unwrap_or(&Value::Null), &field)?;
+                out.push(json!({"role": "tool", "tool_call_id": id, "content": content}));
+            }
+            ("assistant", Some("tool_use")) => {
+                let (Some(id), Some(name)) = (
+                    b.get("id").and_then(|x| x.as_str()),
+                    b.get("name").and_then(|x| x.as_str()),
+                ) else {
+                    return Err(format!("{field}: tool_use requires id and name"));
+                };
+                let input = b.get("input").cloned().unwrap_or_else(|| json!({}));
+                tool_calls.push(json!({
+                    "id": id,
+                    "type": "function",
+                    "function": {"name": name, "arguments": input.to_string()}
+                }));
+            }
+            // Thinking carries nothing the engine can consume.
+            ("assistant", Some("thinking" | "redacted_thinking")) => {}
+            (_, other) => {
+                return Err(format!(
+                    "{field}: unsupported content block type '{}' for role '{role}'",
+                    other.unwrap_or("")
+                ));
+            }
+        }
+    }
+
+    if parts.is_empty() && tool_calls.is_empty() {
+        return Ok(());
+    }
+    // Text-only content becomes a plain string, which every engine accepts.
+    let content = if parts.iter().all(|p| p["type"] == "text") {
+        let text: Vec<&str> = parts.iter().filter_map(|p| p["text"].as_str()).collect();
+        if text.is_empty() { Value::Null } else { Value::String(text.join("\n")) }
+    } else {
+        Value::Array(parts)
+    };
+    let mut message = json!({"role": role, "content": content});
+    if !tool_calls.is_empty() {
+        message["tool_calls"] = Value::Array(tool_calls);
+    }
+    out.push(message);
+    Ok(())
+}
+
+impl CreateMessageRequest {
+    fn chat_tools(&self) -> Result<Vec<Value>, String> {
+        self.tools
+            .iter()
+            .enumerate()
+            .map(|(i, t)| {
+                match t.get("type").and_then(|k| k.as_str()) {
+                    None | Some("custom") => {}
+                    Some(kind) => {
+                        return Err(format!(
+                            "tools.{i}: unsupported tool type '{kind}', only custom tools are supported"
+                        ));
+                    }
+                }
+                let Some(name) = t.get("name").and_then(|n| n.as_str()) else {
+                    return Err(format!("tools.{i}.name: field required"));
+                };
+                let mut function = json!({
+                    "name": name,
+                    "parameters": t.get("input_schema").cloned().unwrap_or_else(|| json!({"type": "object"})),
+                });
+                if let Some(d) = t.get("description").filter(|d| d.is_string()) {
+                    function["description"] = d.clone();
+                }
+                Ok(json!({"type": "function", "function": function}))
+            })
+            .collect()
+    }
+
+    /// The chat/completions body for this request.
+    pub fn chat_request(&self) -> Result<Value, String> {
+        let mut messages = Vec::with_capacity(self.messages.len() + 1);
+        if let Some(system) = self.system.as_ref() {
+            let system = block_text(system, "system")?;
+            if !system.is_empty() {
+                messages.push(json!({"role": "system", "content": system}));
+            }
+        }
+        for (i, m) in self.messages.iter().enumerate() {
+            chat_messages(i, m, &mut messages)?;
+        }
+        if messages.is_empty() {
+            return Err("messages: at least one message is required".to_string());
+        }
+
+        let mut body = json!({
+            "model": self.model,
+            "messages": messages,
+            "max_tokens": self.max_tokens,
+        });
+        let tools = self.chat_tools()?;
+        if !tools.is_empty() {
+            body["tools"] = Value::Array(tools);
+            if let Some(choice) = self.tool_choice.as_ref() {
+                body["tool_choice"] = match choice.get("type").and_then(|t| t.as_str()) {
+                    Some("auto") => json!("auto"),
+                    Some("any") => json!("required"),
+                    Some("none") => json!("none"),
+                    Some("tool") => json!({
+                        "type": "function",
+                        "function": {"name": choice.get("name").cloned().unwrap_or(Value::Null)}
+                    }),
+                    other => return Err(format!("tool_choice.type: unsupported value '{}'", other.unwrap_or(""))),
+                };
+                if choice.get("disable_parallel_tool_use").and_then(|d| d.as_bool()) == Some(true) {
+                    body["parallel_tool_calls"] = Value::Bool(false);
+                }
+            }
+        }
+        if !self.stop_sequences.is_empty() {
+            body["stop"] = json!(self.stop_sequences);
+        }
+        if let Some(t) = self.temperature {
+            body["temperature"] = json!(t);
+        }
+        if let Some(p) = self.top_p {
+            body["top_p"] = json!(p);
+        }
+        if let Some(k) = self.top_k {
+            body["top_k"] = json!(k);
+        }
+        if let Some(user) = self
+            .metadata
+            .as_ref()
+            .and_then(|m| m.get("user_id"))
+            .and_then(|u| u.as_str())
+        {
+            body["user"] = json!(user);
+        }
+        Ok(body)
+    }
+}
+
+/// Anthropic error type for an HTTP status.
+pub fn error_type(status: u16) -> &'static str {
+    match status {
+        401 => "authentication_error",
+        403 => "permission_error",
+        404 => "not_found_error",
+        413 => "request_too_large",
+        429 => "rate_limit_error",
+        503 | 529 => "overloaded_error",
+        400..=499 => "invalid_request_error",
+        _ => "api_error",
+    }
+}
+
+/// Anthropic error body.
+pub fn error_body(status: u16, message: &str) -> Value {
+    json!({"type": "error", "error": {"type": error_type(status), "message": message}})
+}
+
+enum Block {
+    Text(String),
+    ToolUse { id: String, name: String, input: String },
+}
+
+/// Assembles an Anthropic message from engine events, emitting the matching
+/// streaming events along the way. Content blocks are streamed one at a time,
+/// so a block is closed as soon as the engine moves on to another.
+pub struct MessageBuilder {
+    message_id: String,
+    model: String,
+    blocks: Vec<Block>,
+    /// Block index of each engine tool call.
+    tool_blocks: BTreeMap<u32, usize>,
+    /// Block currently streaming, if any.
+    open: Option<usize>,
+    finish: Option<(String, Option<String>)>,
+    usage: Option<TokenUsage>,
+    /// Upstream failure: HTTP status and message.
+    error: Option<(u16, String)>,
+}
+
+impl MessageBuilder {
+    pub fn new(req: &CreateMessageRequest) -> Self {
+        Self {
+            message_id: format!("msg_{}", Uuid::new_v4().simple()),
+            model: req.model.clone(),
+            blocks: Vec::new(),
+            tool_blocks: BTreeMap::new(),
+            open: None,
+            finish: None,
+            usage: None,
+            error: None,
+        }
+    }
+
+    /// `message_start` and an initial `ping`.
+    pub fn start_events(&mut self) -> Vec<(&'static str, Value)> {
+        let mut message = self.message();
+        message["stop_reason"] = Value::Null;
+        vec![
+            ("message_start", json!({"type": "message_start", "message": message})),
+            ("ping", json!({"type": "ping"})),
+        ]
+    }
+
+    fn switch_to(&mut self, index: usize, out: &mut Vec<(&'static str, Value)>) {
+        if self.open == Some(index) {
+            return;
+        }
+        self.close(out);
+        let content_block = match &self.blocks[index] {
+            Block::Text(_) => json!({"type": "text", "text": ""}),
+            Block::ToolUse { id, name, .. } => json!({"type": "tool_use", "id": id, "name": name, "input": {}}),
+        };
+        out.push((
+            "content_block_start",
+            json!({"type": "content_block_start", "index": index, "content_block": content_block}),
+        ));
+        self.open = Some(index);
+    }
+
+    fn close(&mut self, out: &mut Vec<(&'static str, Value)>) {
+        if let Some(index) = self.open.take() {
+            out.push(("content_block_stop", json!({"type": "content_block_stop", "index": index})));
+        }
+    }
+
+    /// Apply one engine event; returns the streaming events it produces.
+    pub fn push(&mut self, ev: EngineEvent) -> Vec<(&'static str, Value)> {
+        let mut out = Vec::new();
+        match ev {
+            EngineEvent::Text(delta) => {
+                let index = match self.open.filter(|&i| matches!(self.blocks[i], Block::Text(_))) {
+                    Some(i) => i,
+                    None => {
+                        self.blocks.push(Block::Text(String::new()));
+                        self.blocks.len() - 1
+                    }
+                };
+                self.switch_to(index, &mut out);
+                if let Block::Text(text) = &mut self.blocks[index] {
+                    text.push_str(&delta);
+                }
+                out.push((
+                    "content_block_delta",
+                    json!({"type": "content_block_delta", "index": index, "delta": {"type": "text_delta", "text": delta}}),
+                ));
+            }
+            EngineEvent::ToolCall {
+                index,
+                id,
+                name,
+                arguments,
+            } => {
+                let block = match self.tool_blocks.get(&index) {
+                    // A call the engine returns to after moving on can't be
+                    // reopened; its arguments still reach the final message.
+                    Some(&b) if self.open != Some(b) => {
+                        if let Block::ToolUse { input, .. } = &mut self.blocks[b] {
+                            input.push_str(&arguments);
+                        }
+                        return out;
+                    }
+                    Some(&b) => b,
+                    None => {
+                        self.blocks.push(Block::ToolUse {
+                            id: id.unwrap_or_else(|| format!("toolu_{}", Uuid::new_v4().simple())),
+                            name: name.unwrap_or_default(),
+                            input: String::new(),
+                        });
+                        let b = self.blocks.len() - 1;
+                        self.tool_blocks.insert(index, b);
+                        b
+                    }
+                };
+                self.switch_to(block, &mut out);
+                if arguments.is_empty() {
+                    return out;
+                }
+                if let Block::ToolUse { input, .. } = &mut self.blocks[block] {
+                    input.push_str(&arguments);
+                }
+                out.push((
+                    "content_block_delta",
+                    json!({
+                        "type": "content_block_delta",
+                        "index": block,
+                        "delta": {"type": "input_json_delta", "partial_json": arguments}
+                    }),
+                ));
+            }
+            EngineEvent::Finish { reason, stop_sequence } => self.finish = Some((reason, stop_sequence)),
+            EngineEvent::Usage {
+                input_tokens,
+                output_tokens,
+            } => {
+                self.usage = Some(TokenUsage {
+                    prompt_tokens: input_tokens as u64,
+                    completion_tokens: output_tokens as u64,
+                })
+            }
+            EngineEvent::Error { status, message } => self.error = Some((status, message)),
+        }
+        out
+    }
+
+    /// Upstream failure reported by the engine, if any.
+    pub fn error(&self) -> Option<(u16, &str)> {
+        self.error.as_ref().map(|(s, m)| (*s, m.as_str()))
+    }
+
+    /// The `error` event, once the engine has reported a failure.
+    pub fn fail_events(&self) -> Vec<(&'static str, Value)> {
+        match self.error.as_ref() {
+            Some((status, message)) => vec![("error", error_body(*status, message))],
+            None => Vec::new(),
+        }
+    }
+
+    /// Close the open block, then `message_delta` and `message_stop`.
+    pub fn finish_events(&mut self) -> Vec<(&'static str, Value)> {
+        let mut out = Vec::new();
+        self.close(&mut out);
+        let (stop_reason, stop_sequence) = self.stop_reason();
+        let usage = self.usage.unwrap_or_default();
+        out.push((
+            "message_delta",
+            json!({
+                "type": "message_delta",
+                "delta": {"stop_reason": stop_reason, "stop_sequence": stop_sequence},
+                "usage": {"input_tokens": usage.prompt_tokens, "output_tokens": usage.completion_tokens}
+            }),
+        ));
+        out.push(("message_stop", json!({"type": "message_stop"})));
+        out
+    }
+
+    fn stop_reason(&self) -> (&'static str, Option<String>) {
+        match self.finish.as_ref() {
+            Some((reason, _)) if reason == "length" => ("max_tokens", None),
+            Some((reason, _)) if reason == "tool_calls" || reason == "function_call" => ("tool_use", None),
+            Some((reason, _)) if reason == "content_filter" => ("refusal", None),
+            Some((_, Some(seq))) => ("stop_sequence", Some(seq.clone())),
+            _ if !self.tool_blocks.is_empty() => ("tool_use", None),
+            _ => ("end_turn", None),
+        }
+    }
+
+    /// Token usage reported for this message, if any.
+    pub fn token_usage(&self) -> Option<TokenUsage> {
+        self.usage
+    }
+
+    /// The complete message object.
+    pub fn message(&self) -> Value {
+        let content: Vec<Value> = self
+            .blocks
+            .iter()
+            .map(|b| match b {
+                Block::Text(text) => json!({"type": "text", "text": text}),
+                Block::ToolUse { id, name, input } => json!({
+                    "type": "tool_use",
+                    "id": id,
+                    "name": name,
+                    "input": serde_json::from_str::<Value>(input).unwrap_or_else(|_| json!({})),
+                }),
+            })
+            .collect();
+        let (stop_reason, stop_sequence) = self.stop_reason();
+        let usage = self.usage.unwrap_or_default();
+        json!({
+            "id": self.message_id,
+            "type": "message",
+            "role": "assistant",
+            "model": self.model,
+            "content": content,
+            "stop_reason": stop_reason,
+            "stop_sequence": stop_sequence,
+            "usage": {"input_tokens": usage.prompt_tokens, "output_tokens": usage.completion_tokens}
+        })
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn request(v: Value) -> CreateMessageRequest {
+        serde_json::from_value(v).unwrap()
+    }
+
+    fn names(events: &[(&'static str, Value)]) -> Vec<&'static str> {
+        events.iter().map(|(name, _)| *name).collect()
+    }
+
+    #[test]
+    fn test_chat_request() {
+        let req = request(json!({
+            "model": "m",
+            "max_tokens": 128,
+            "system": [{"type": "text", "text": "be brief"}],
+            "messages": [
+                {"role": "user", "content": [
+                    {"type": "text", "text": "what is this?"},
+                    {"type": "image", "source": {"type": "base64", "media_type": "image/jpeg", "data": "AAAA"}}
+                ]},
+                {"role": "assistant", "content": [
+                    {"type": "thinking", "thinking": "hmm"},
+                    {"type": "tool_use", "id": "toolu_1", "name": "look", "input": {"q": 1}}
+                ]},
+                {"role": "user", "content": [
+                    {"type": "tool_result", "tool_use_id": "toolu_1", "content": "a cat"},
+                    {"type": "text", "text": "thanks"}
+                ]}
+            ],
+            "tools": [{"name": "look", "input_schema": {"type": "object"}}],
+            "tool_choice": {"type": "any", "disable_parallel_tool_use": true},
+            "stop_sequences": ["END"],
+            "metadata": {"user_id": "u1"}
+        }));
+        let body = req.chat_request().unwrap();
+        let messages = body["messages"].as_array().unwrap();
+        assert_eq!(messages[0], json!({"role": "system", "content": "be brief"}));
+        assert_eq!(messages[1]["content"][1]["image_url"]["url"], "data:image/jpeg;base64,AAAA");
+        assert_eq!(messages[2]["tool_calls"][0]["function"]["arguments"], "{\"q\":1}");
+        // Tool results come before the rest of the user turn.
+        assert_eq!(messages[3], json!({"role": "tool", "tool_call_id": "toolu_1", "content": "a cat"}));
+        assert_eq!(messages[4], json!({"role": "user", "content": "thanks"}));
+        assert_eq!(body["max_tokens"], 128);
+        assert_eq!(body["tools"][0]["function"]["parameters"], json!({"type": "object"}));
+        assert_eq!(body["tool_choice"], "required");
+        assert_eq!(body["parallel_tool_calls"], false);
+        assert_eq!(body["stop"], json!(["END"]));
+        assert_eq!(body["user"], "u1");
+
+        let bad = request(json!({"model": "m", "max_tokens": 1, "messages": [{"role": "system", "content": "x"}]}));
+        assert!(bad.chat_request().unwrap_err().starts_with("messages.0.role"));
+        let bad = request(json!({"model": "m", "max_tokens": 1, "messages": [
+            {"role": "user", "content": [{"type": "document"}]}
+        ]}));
+        assert!(bad.chat_request().unwrap_err().starts_with("messages.0.content.0"));
+    }
+
+    #[test]
+    fn test_stream_events() {
+        let req = request(json!({"model": "m", "max_tokens": 16, "messages": [{"role": "user", "content": "hi"}]}));
+        let mut b = MessageBuilder::new(&req);
+        let mut events = b.start_events();
+        events.extend(b.push(EngineEvent::Text("Hel".to_string())));
+        events.extend(b.push(EngineEvent::Text("lo".to_string())));
+        events.extend(b.push(EngineEvent::ToolCall {
+            index: 0,
+            id: Some("call_1".to_string()),
+            name: Some("f".to_string()),
+            arguments: "{\"a\":".to_string(),
+        }));
+        events.extend(b.push(EngineEvent::ToolCall {
+            index: 0,
+            id: None,
+            name: None,
+            arguments: "1}".to_string(),
+        }));
+        b.push(EngineEvent::Finish {
+            reason: "tool_calls".to_string(),
+            stop_sequence: None,
+        });
+        b.push(EngineEvent::Usage {
+            input_tokens: 3,
+            output_tokens: 4,
+        });
+        events.extend(b.finish_events());
+        assert_eq!(
+            names(&events),
+            [
+                "message_start",
+                "ping",
+                "content_block_start",
+                "content_block_delta",
+                "content_block_delta",
+                "content_block_stop",
+                "content_block_start",
+                "content_block_delta",
+                "content_block_delta",
+                "content_block_stop",
+                "message_delta",
+                "message_stop",
+            ]
+        );
+        assert_eq!(events[0].1["message"]["stop_reason"], Value::Null);
+        assert_eq!(events[6].1["index"], 1);
+        assert_eq!(events[6].1["content_block"]["type"], "tool_use");
+        assert_eq!(events[10].1["delta"]["stop_reason"], "tool_use");
+        assert_eq!(events[10].1["usage"], json!({"input_tokens": 3, "output_tokens": 4}));
+
+        let message = b.message();
+        assert_eq!(message["content"][0], json!({"type": "text", "text": "Hello"}));
+        assert_eq!(message["content"][1]["input"], json!({"a": 1}));
+    }
+
+    #[test]
+    fn test_stop_reason() {
+        let req = request(json!({"model": "m", "max_tokens": 1, "messages": [{"role": "user", "content": "hi"}]}));
+        let stop = |reason: &str, seq: Option<&str>| {
+            let mut b = MessageBuilder::new(&req);
+            b.push(EngineEvent::Finish {
+                reason: reason.to_string(),
+                stop_sequence: seq.map(str::to_string),
+            });
+            let m = b.message();
+            (m["stop_reason"].clone(), m["stop_sequence"].clone())
+        };
+        assert_eq!(stop("length", None), (json!("max_tokens"), Value::Null));
+        assert_eq!(stop("stop", None), (json!("end_turn"), Value::Null));
+        assert_eq!(stop("stop", Some("END")), (json!("stop_sequence"), json!("END")));
+        assert_eq!(stop("content_filter", None), (json!("refusal"), Value::Null));
+
+        let mut b = MessageBuilder::new(&req);
+        b.push(EngineEvent::Error {
+            status: 529,
+            message: "busy".to_string(),
+        });
+        assert_eq!(b.fail_events()[0].1["error"]["type"], "overloaded_error");
+    }
+}
@@ -1,4 +1,6 @@
+use std::collections::HashMap;
 use std::sync::atomic::{AtomicU64, Ordering};
+use std::sync::Mutex;
 
 use axum::{
     body::Body,
@@ -9,7 +11,12 @@ use axum::{
     Extension,
 };
 
+use nebula_common::TokenUsage;
+
 use crate::auth::{require_role, AuthContext, Role};
+use crate::cache::CacheResult;
+use crate::guardrails::Action;
+use crate::quota::Window;
 use crate::state::AppState;
 
 #[derive(Debug, Default)]
@@ -27,9 +34,79 @@ pub struct Metrics {
     pub upstream_error_connect_total: AtomicU64,
     pub upstream_error_timeout_total: AtomicU64,
     pub upstream_error_other_total: AtomicU64,
+    pub quota_rejected_minute: AtomicU64,
+    pub quota_rejected_day: AtomicU64,
+    pub quota_rejected_month: AtomicU64,
+    /// Audit entries written to a sink's disk spool.
+    pub audit_spooled: AtomicU64,
+    /// Audit entries lost because a spool was full or unwritable.
+    pub audit_dropped: AtomicU64,
+    /// Streams aborted because the client disconnected.
+    pub client_cancelled: AtomicU64,
+    /// Response cache lookups, by result.
+    pub cache_hit: AtomicU64,
+    pub cache_semantic_hit: AtomicU64,
+    pub cache_miss: AtomicU64,
+    pub cache_bypass: AtomicU64,
+    /// Batch requests written to the output and error files.
+    pub batch_requests_completed: AtomicU64,
+    pub batch_requests_failed: AtomicU64,
+    /// Guardrail actions taken, by action.
+    pub guardrail_blocked: AtomicU64,
+    pub guardrail_redacted: AtomicU64,
+    pub guardrail_flagged: AtomicU64,
+    /// Token usage by (model, principal).
+    pub tokens: Mutex<HashMap<(String, String), TokenUsage>>,
 }
 
 impl Metrics {
+    pub fn record_tokens(&self, model: &str, principal: &str, usage: TokenUsage) {
+        if let Ok(mut tokens) = self.tokens.lock() {
+            let total = tokens
+                .entry((model.to_string(), principal.to_string()))
+                .or_default();
+            total.prompt_tokens += usage.prompt_tokens;
+            total.completion_tokens += usage.completion_tokens;
+        }
+    }
+
+    pub fn record_quota_rejected(&self, window: Window) {
+        let counter = match window {
+            Window::Minute => &self.quota_rejected_minute,
+            Window::Day => &self.quota_rejected_day,
+            Window::Month => &self.quota_rejected_month,
+        };
+        counter.fetch_add(1, Ordering::Relaxed);
+    }
+
+    pub fn record_client_cancelled(&self) {
+        self.client_cancelled.fetch_add(1, Ordering::Relaxed);
+    }
+
+    pub fn record_cache(&self, result: CacheResult) {
+        let counter = match result {
+            CacheResult::Hit => &self.cache_hit,
+            CacheResult::SemanticHit => &self.cache_semantic_hit,
+            CacheResult::Miss => &self.cache_miss,
+            CacheResult::Bypass => &self.cache_bypass,
+        };
+        counter.fetch_add(1, Ordering::Relaxed);
+    }
+
+    pub fn record_batch_request(&self, ok: bool) {
+        let counter = if ok { &self.batch_requests_completed } else { &self.batch_requests_failed };
+        counter.fetch_add(1, Ordering::Relaxed);
+    }
+
+    pub fn record_guardrail(&self, action: Action) {
+        let counter = match action {
+            Action::Block => &self.guardrail_blocked,
+            Action::Redact => &self.guardrail_redacted,
+            Action::Flag => &self.guardrail_flagged,
+        };
+        counter.fetch_add(1, Ordering::Relaxed);
+    }
+
     pub fn record_upstream_error(&self, kind: &str) {
         match kind {
             "connect" => {
@@ -124,6 +201,88 @@ pub fn render_metrics(metrics: &Metrics) -> String {
         "nebula_gateway_upstream_error_total{{kind=\"other\"}} {}\n",
         metrics.upstream_error_other_total.load(Ordering::Relaxed),
     ));
+    body.push_str("# HELP nebula_gateway_quota_rejected_total Requests rejected by token quotas, by window.\n# TYPE nebula_gateway_quota_rejected_total counter\n");
+    for (window, counter) in [
+        ("minute", &metrics.quota_rejected_minute),
+        ("day", &metrics.quota_rejected_day),
+        ("month", &metrics.quota_rejected_month),
+    ] {
+        body.push_str(&format!(
+            "nebula_gateway_quota_rejected_total{{window=\"{window}\"}} {}\n",
+            counter.load(Ordering::Relaxed),
+        ));
+    }
+    body.push_str(&format!(
+        "# HELP nebula_gateway_audit_spooled_total Audit entries spooled to disk because a sink was full or failing.\n\
+         # TYPE nebula_gateway_audit_spooled_total counter\n\
+         nebula_gateway_audit_spooled_total {}\n",
+        metrics.audit_spooled.load(Ordering::Relaxed),
+    ));
+    body.push_str(&format!(
+        "# HELP nebula_gateway_audit_dropped_total Audit entries dropped because the spool was full.\n\
+         # TYPE nebula_gateway_audit_dropped_total counter\n\
+         nebula_gateway_audit_dropped_total {}\n",
+        metrics.audit_dropped.load(Ordering::Relaxed),
+    ));
+    body.push_str(&format!(
+        "# HELP nebula_gateway_client_cancelled_total Streams whose client disconnected; the upstream request was aborted.\n\
+         # TYPE nebula_gateway_client_cancelled_total counter\n\
+         nebula_gateway_client_cancelled_total {}\n",
+        metrics.client_cancelled.load(Ordering::Relaxed),
+    ));
+    body.push_str("# HELP nebula_gateway_cache_requests_total Cacheable requests by response cache result.\n# TYPE nebula_gateway_cache_requests_total counter\n");
+    for (result, counter) in [
+        ("hit", &metrics.cache_hit),
+        ("semantic_hit", &metrics.cache_semantic_hit),
+        ("miss", &metrics.cache_miss),
+        ("bypass", &metrics.cache_bypass),
+    ] {
+        body.push_str(&format!(
+            "nebula_gateway_cache_requests_total{{result=\"{result}\"}} {}\n",
+            counter.load(Ordering::Relaxed),
+        ));
+    }
+    body.push_str("# HELP nebula_gateway_batch_requests_total Batch requests run, by result.\n# TYPE nebula_gateway_batch_requests_total counter\n");
+    for (result, counter) in [
+        ("completed", &metrics.batch_requests_completed),
+        ("failed", &metrics.batch_requests_failed),
+    ] {
+        body.push_str(&format!(
+            "nebula_gateway_batch_requests_total{{result=\"{result}\"}} {}\n",
+            counter.load(Ordering::Relaxed),
+        ));
+    }
+    body.push_str("# HELP nebula_gateway_guardrail_actions_total Guardrail actions taken, by action.\n# TYPE nebula_gateway_guardrail_actions_total counter\n");
+    for (action, counter) in [
+        ("block", &metrics.guardrail_blocked),
+        ("redact", &metrics.guardrail_redacted),
+        ("flag", &metrics.guardrail_flagged),
+    ] {
+        body.push_str(&format!(
+            "nebula_gateway_guardrail_actions_total{{action=\"{action}\"}} {}\n",
+            counter.load(Ordering::Relaxed),
+        ));
+    }
+
+    let tokens: Vec<((String, String), TokenUsage)> = metrics
+        .tokens
+        .lock()
+        .map(|t| t.iter().map(|(k, v)| (k.clone(), *v)).collect())
+        .unwrap_or_default();
+    body.push_str("# HELP nebula_gateway_prompt_tokens_total Prompt tokens by model and principal.\n# TYPE nebula_gateway_prompt_tokens_total counter\n");
+    for ((model, principal), usage) in &tokens {
+        body.push_str(&format!(
+            "nebula_gateway_prompt_tokens_total{{model=\"{model}\",principal=\"{principal}\"}} {}\n",
+            usage.prompt_tokens,
+        ));
+    }
+    body.push_str("# HELP nebula_gateway_completion_tokens_total Completion tokens by model and principal.\n# TYPE nebula_gateway_completion_tokens_total counter\n");
+    for ((model, principal), usage) in &tokens {
+        body.push_str(&format!(
+            "nebula_gateway_completion_tokens_total{{model=\"{model}\",principal=\"{principal}\"}} {}\n",
+            usage.completion_tokens,
+        ));
+    }
 
     body
 }
@@ -164,6 +323,10 @@ pub async fn track_requests(
     st.metrics.requests_total.fetch_add(1, Ordering::Relaxed);
 
     let status = resp.status().as_u16();
+    // Rate limit rejections carry `x-ratelimit-*`; quota rejections don't.
+    if status == 429 && resp.headers().contains_key("x-ratelimit-limit-requests") {
+        st.metrics.auth_rate_limited.fetch_add(1, Ordering::Relaxed);
+    }
     if status >= 500 {
         st.metrics.status_5xx.fetch_add(1, Ordering::Relaxed);
     } else if status >= 400 {
@@ -0,0 +1,608 @@
+//! Token quotas per API key and per tenant.
+//!
+//! Usage is kept in a ledger in the meta store so limits survive restarts and
+//! are shared by all gateway replicas. Each replica buffers usage locally and
+//! flushes it on every sync tick: daily and monthly totals are updated with
+//! compare-and-swap, while per-minute usage is written per replica under a
+//! short TTL and summed on read. Admission checks use the totals of the last
+//! sync plus the replica's unflushed usage, so a key can overshoot its limit
+//! by at most one sync interval of traffic.
+
+use std::collections::HashMap;
+use std::sync::{Arc, Mutex};
+use std::time::Duration;
+
+use anyhow::Result;
+use axum::{
+    body::Body,
+    extract::State,
+    http::{Method, Request},
+    middleware::Next,
+    response::{IntoResponse, Response},
+};
+use chrono::{DateTime, Datelike, Duration as ChronoDuration, NaiveDate, Utc};
+use serde::{Deserialize, Serialize};
+
+use nebula_common::{ApiError, ErrorCode, TokenUsage};
+use nebula_meta::MetaStore;
+
+use crate::auth::AuthContext;
+use crate::state::AppState;
+
+const QUOTAS_PREFIX: &str = "/quotas/";
+const USAGE_PREFIX: &str = "/usage/";
+/// Per-replica minute usage outlives its minute long enough to be summed.
+const MINUTE_TTL_MS: u64 = 120_000;
+const CAS_RETRIES: usize = 16;
+
+/// Token limits of one API key or tenant; unset limits don't apply.
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq, Serialize, Deserialize)]
+pub struct QuotaLimits {
+    pub tokens_per_minute: Option<u64>,
+    pub tokens_per_day: Option<u64>,
+    pub tokens_per_month: Option<u64>,
+}
+
+/// What a quota and its usage are tracked for. API keys are identified by
+/// [`AuthContext::principal_label`], never by the key itself.
+#[derive(Debug, Clone, PartialEq, Eq, Hash)]
+pub enum QuotaScope {
+    Key(String),
+    Tenant(String),
+}
+
+impl QuotaScope {
+    /// Scope from the admin path segments `keys/{id}` or `tenants/{id}`.
+    pub fn parse(kind: &str, id: &str) -> Option<Self> {
+        if id.is_empty() || id.contains('/') {
+            return None;
+        }
+        match kind {
+            "keys" => Some(Self::Key(id.to_string())),
+            "tenants" => Some(Self::Tenant(id.to_string())),
+            _ => None,
+        }
+    }
+
+    fn path(&self) -> String {
+        match self {
+            Self::Key(id) => format!("keys/{id}"),
+            Self::Tenant(id) => format!("tenants/{id}"),
+        }
+    }
+
+    fn from_path(path: &str) -> Option<Self> {
+        let (kind, id) = path.split_once('/')?;
+        Self::parse(kind, id)
+    }
+
+    /// Scopes a request is accounted to: its key and, if any, its tenant.
+    pub fn for_request(ctx: &AuthContext) -> Vec<Self> {
+        let mut scopes = vec![Self::Key(ctx.principal_label())];
+        if let Some(tenant) = ctx.tenant.as_ref() {
+            scopes.push(Self::Tenant(tenant.clone()));
+        }
+        scopes
+    }
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Window {
+    Minute,
+    Day,
+    Month,
+}
+
+impl Window {
+    pub const ALL: [Window; 3] = [Window::Minute, Window::Day, Window::Month];
+
+    pub fn as_str(self) -> &'static str {
+        match self {
+            Window::Minute => "minute",
+            Window::Day => "day",
+            Window::Month => "month",
+        }
+    }
+
+    pub fn parse(s: &str) -> Option<Self> {
+        Self::ALL.into_iter().find(|w| w.as_str() == s)
+    }
+
+    fn limit(self, limits: &QuotaLimits) -> Option<u64> {
+        match self {
+            Window::Minute => limits.tokens_per_minute,
+            Window::Day => limits.tokens_per_day,
+            Window::Month => limits.tokens_per_month,
+        }
+    }
+
+    /// Ledger period containing `now` (UTC).
+    fn period(self, now: DateTime<Utc>) -> String {
+        match self {
+            Window::Minute => format!("minute-{}", now.timestamp() / 60),
+            Window::Day => format!("day-{}", now.format("%Y-%m-%d")),
+            Window::Month => format!("month-{}", now.format("%Y-%m")),
+        }
+    }
+
+    /// Seconds until the period containing `now` ends.
+    fn retry_after_secs(self, now: DateTime<Utc>) -> u64 {
+        let end = match self {
+            Window::Minute => return (60 - now.timestamp().rem_euclid(60)) as u64,
+            Window::Day => now.date_naive() + ChronoDuration::days(1),
+            Window::Month => {
+                let (y, m) = if now.month() == 12 { (now.year() + 1, 1) } else { (now.year(), now.month() + 1) };
+                NaiveDate::from_ymd_opt(y, m, 1).unwrap_or(now.date_naive())
+            }
+        };
+        let end = end.and_hms_opt(0, 0, 0).map(|t| t.and_utc()).unwrap_or(now);
+        (end - now).num_seconds().max(1) as u64
+    }
+}
+
+fn ledger_key(scope: &QuotaScope, window: Window, now: DateTime<Utc>) -> String {
+    format!("{USAGE_PREFIX}{}/{}", scope.path(), window.period(now))
+}
+
+fn add_usage(a: &mut TokenUsage, b: TokenUsage) {
+    a.prompt_tokens += b.prompt_tokens;
+    a.completion_tokens += b.completion_tokens;
+}
+
+/// A request rejected because a quota is used up.
+#[derive(Debug)]
+pub struct QuotaExceeded {
+    pub scope: QuotaScope,
+    pub window: Window,
+    pub limit: u64,
+    pub retry_after_secs: u64,
+}
+
+impl QuotaExceeded {
+    pub fn error(&self) -> ApiError {
+        let code = match self.window {
+            Window::Minute => ErrorCode::RateLimitExceeded,
+            Window::Day | Window::Month => ErrorCode::InsufficientQuota,
+        };
+        let subject = match &self.scope {
+            QuotaScope::Key(_) => "API key".to_string(),
+            QuotaScope::Tenant(t) => format!("tenant '{t}'"),
+        };
+        ApiError::new(
+            code,
+            format!("Token quota exceeded for {subject}: {} tokens per {}.", self.limit, self.window.as_str()),
+        )
+        .with_retry_after(self.retry_after_secs)
+    }
+}
+
+/// Usage of one scope in the current periods.
+#[derive(Debug, Clone, Copy, Default, Serialize)]
+pub struct ScopeUsage {
+    pub minute: TokenUsage,
+    pub day: TokenUsage,
+    pub month: TokenUsage,
+}
+
+#[derive(Default)]
+struct Cache {
+    limits: HashMap<QuotaScope, QuotaLimits>,
+    /// Ledger totals (in tokens) as of the last sync, by ledger key.
+    synced: HashMap<String, u64>,
+    /// Usage recorded since the last flush.
+    pending: HashMap<QuotaScope, TokenUsage>,
+    /// This replica's usage in the current minute, by scope.
+    minute: HashMap<QuotaScope, (String, TokenUsage)>,
+}
+
+pub struct Quotas {
+    meta: Arc<dyn MetaStore>,
+    replica_id: String,
+    cache: Mutex<Cache>,
+}
+
+impl Quotas {
+    pub fn new(meta: Arc<dyn MetaStore>, replica_id: String) -> Self {
+        Self {
+            meta,
+            replica_id,
+            cache: Mutex::new(Cache::default()),
+        }
+    }
+
+    /// Sync with the ledger every `interval` in the background.
+    pub fn spawn_sync(self: Arc<Self>, interval: Duration) {
+        tokio::spawn(async move {
+            let mut tick = tokio::time::interval(interval);
+            loop {
+                tick.tick().await;
+                if let Err(e) = self.sync().await {
+                    tracing::warn!(error=%e, "quota ledger sync failed");
+                }
+            }
+        });
+    }
+
+    /// Reject a request whose key or tenant has used up a quota.
+    pub fn check(&self, scopes: &[QuotaScope]) -> Result<(), QuotaExceeded> {
+        self.check_at(scopes, Utc::now())
+    }
+
+    fn check_at(&self, scopes: &[QuotaScope], now: DateTime<Utc>) -> Result<(), QuotaExceeded> {
+        let Ok(cache) = self.cache.lock() else {
+            return Ok(());
+        };
+        for scope in scopes {
+            let Some(limits) = cache.limits.get(scope) else {
+                continue;
+            };
+            let pending = cache.pending.get(scope).map(|u| u.total_tokens()).unwrap_or(0);
+            for window in Window::ALL {
+                let Some(limit) = window.limit(limits) else {
+                    continue;
+                };
+                let synced = cache.synced.get(&ledger_key(scope, window, now)).copied().unwrap_or(0);
+                if synced + pending >= limit {
+                    return Err(QuotaExceeded {
+                        scope: scope.clone(),
+                        window,
+                        limit,
+                        retry_after_secs: window.retry_after_secs(now),
+                    });
+                }
+            }
+        }
+        Ok(())
+    }
+
+    /// Buffer usage for the next flush.
+    pub fn record(&self, scopes: &[QuotaScope], usage: TokenUsage) {
+        if let Ok(mut cache) = self.cache.lock() {
+            for scope in scopes {
+                add_usage(cache.pending.entry(scope.clone()).or_default(), usage);
+            }
+        }
+    }
+
+    /// Reload limits, flush buffered usage to the ledger and refresh the
+    /// totals of every scope that has limits.
+    pub async fn sync(&self) -> Result<()> {
+        self.sync_at(Utc::now()).await
+    }
+
+    async fn sync_at(&self, now: DateTime<Utc>) -> Result<()> {
+        let mut limits = HashMap::new();
+        for (key, raw, _) in self.meta.list_prefix(QUOTAS_PREFIX).await? {
+            let Some(scope) = key.strip_prefix(QUOTAS_PREFIX).and_then(QuotaScope::from_path) else {
+                continue;
+            };
+            match serde_json::from_slice::<QuotaLimits>(&raw) {
+                Ok(l) => {
+                    limits.insert(scope, l);
+                }
+                Err(e) => tracing::warn!(error=%e, key=%key, "invalid quota document"),
+            }
+        }
+
+        let flushing: Vec<(QuotaScope, TokenUsage)> = match self.cache.lock() {
+            Ok(cache) => cache.pending.iter().map(|(s, u)| (s.clone(), *u)).collect(),
+            Err(_) => Vec::new(),
+        };
+        let mut flushed = Vec::new();
+        for (scope, usage) in flushing {
+            match self.flush(&scope, usage, now).await {
+                Ok(()) => flushed.push((scope, usage)),
+                Err(e) => tracing::warn!(error=%e, scope=%scope.path(), "failed to flush token usage"),
+            }
+        }
+
+        let mut synced = HashMap::new();
+        for scope in limits.keys() {
+            let usage = self.read_usage(scope, now).await?;
+            synced.insert(ledger_key(scope, Window::Minute, now), usage.minute.total_tokens());
+            synced.insert(ledger_key(scope, Window::Day, now), usage.day.total_tokens());
+            synced.insert(ledger_key(scope, Window::Month, now), usage.month.total_tokens());
+        }
+
+        if let Ok(mut cache) = self.cache.lock() {
+            cache.limits = limits;
+            cache.synced = synced;
+            for (scope, usage) in flushed {
+                if let Some(p) = cache.pending.get_mut(&scope) {
+                    p.prompt_tokens = p.prompt_tokens.saturating_sub(usage.prompt_tokens);
+                    p.completion_tokens = p.completion_tokens.saturating_sub(usage.completion_tokens);
+                    if p.total_tokens() == 0 {
+                        cache.pending.remove(&scope);
+                    }
+                }
+            }
+        }
+        Ok(())
+    }
+
+    async fn flush(&self, scope: &QuotaScope, usage: TokenUsage, now: DateTime<Utc>) -> Result<()> {
+        self.add(&ledger_key(scope, Window::Day, now), usage).await?;
+        self.add(&ledger_key(scope, Window::Month, now), usage).await?;
+
+        let period = Window::Minute.period(now);
+        let minute = match self.cache.lock() {
+            Ok(mut cache) => {
+                let entry = cache
+                    .minute
+                    .entry(scope.clone())
+                    .or_insert_with(|| (period.clone(), TokenUsage::default()));
+                if entry.0 != period {
+                    *entry = (period, TokenUsage::default());
+                }
+                add_usage(&mut entry.1, usage);
+                entry.1
+            }
+            Err(_) => usage,
+        };
+        let key = format!("{}/{}", ledger_key(scope, Window::Minute, now), self.replica_id);
+        self.meta
+            .put(&key, serde_json::to_vec(&minute)?, Some(MINUTE_TTL_MS))
+            .await?;
+        Ok(())
+    }
+
+    /// Add `usage` to a ledger entry.
+    async fn add(&self, key: &str, usage: TokenUsage) -> Result<()> {
+        for _ in 0..CAS_RETRIES {
+            let (mut total, rev) = match self.meta.get(key).await? {
+                Some((raw, rev)) => (serde_json::from_slice::<TokenUsage>(&raw)?, rev),
+                None => (TokenUsage::default(), 0),
+            };
+            add_usage(&mut total, usage);
+            if self.meta.compare_and_swap(key, rev, serde_json::to_vec(&total)?).await?.0 {
+                return Ok(());
+            }
+        }
+        anyhow::bail!("too much contention updating {key}")
+    }
+
+    async fn read_usage(&self, scope: &QuotaScope, now: DateTime<Utc>) -> Result<ScopeUsage> {
+        let mut usage = ScopeUsage::default();
+        let minute_prefix = format!("{}/", ledger_key(scope, Window::Minute, now));
+        for (_, raw, _) in self.meta.list_prefix(&minute_prefix).await? {
+            add_usage(&mut usage.minute, serde_json::from_slice(&raw)?);
+        }
+        for (window, total) in [(Window::Day, &mut usage.day), (Window::Month, &mut usage.month)] {
+            if let Some((raw, _)) = self.meta.get(&ledger_key(scope, window, now)).await? {
+                *total = serde_json::from_slice(&raw)?;
+            }
+        }
+        Ok(usage)
+    }
+
+    /// Current usage of a scope, read from the ledger.
+    pub async fn usage(&self, scope: &QuotaScope) -> Result<ScopeUsage> {
+        self.read_usage(scope, Utc::now()).await
+    }
+
+    pub async fn limits(&self, scope: &QuotaScope) -> Result<Option<QuotaLimits>> {
+        match self.meta.get(&format!("{QUOTAS_PREFIX}{}", scope.path())).await? {
+            Some((raw, _)) => Ok(Some(serde_json::from_slice(&raw)?)),
+            None => Ok(None),
+        }
+    }
+
+    /// Every scope with configured limits.
+    pub async fn list(&self) -> Result<Vec<(QuotaScope, QuotaLimits)>> {
+        let mut out = Vec::new();
+        for (key, raw, _) in self.meta.list_prefix(QUOTAS_PREFIX).await? {
+            if let Some(scope) = key.strip_prefix(QUOTAS_PREFIX).and_then(QuotaScope::from_path) {
+                out.push((scope, serde_json::from_slice(&raw)?));
+            }
+        }
+        Ok(out)
+    }
+
+    pub async fn set_limits(&self, scope: &QuotaScope, limits: QuotaLimits) -> Result<()> {
+        self.meta
+            .put(&format!("{QUOTAS_PREFIX}{}", scope.path()), serde_json::to_vec(&limits)?, None)
+            .await?;
+        if let Ok(mut cache) = self.cache.lock() {
+            cache.limits.insert(scope.clone(), limits);
+        }
+        Ok(())
+    }
+
+    /// Remove a scope's limits; `false` when it had none.
+    pub async fn delete_limits(&self, scope: &QuotaScope) -> Result<bool> {
+        let key = format!("{QUOTAS_PREFIX}{}", scope.path());
+        if self.meta.get(&key).await?.is_none() {
+            return Ok(false);
+        }
+        self.meta.delete(&key).await?;
+        if let Ok(mut cache) = self.cache.lock() {
+            cache.limits.remove(scope);
+        }
+        Ok(true)
+    }
+
+    /// Zero a scope's usage in the current period of each window. Other
+    /// replicas pick the reset up on their next sync.
+    pub async fn reset(&self, scope: &QuotaScope, windows: &[Window]) -> Result<()> {
+        self.reset_at(scope, windows, Utc::now()).await
+    }
+
+    async fn reset_at(&self, scope: &QuotaScope, windows: &[Window], now: DateTime<Utc>) -> Result<()> {
+        for &window in windows {
+            let key = ledger_key(scope, window, now);
+            if window == Window::Minute {
+                for (k, _, _) in self.meta.list_prefix(&format!("{key}/")).await? {
+                    self.meta.delete(&k).await?;
+                }
+            } else {
+                self.meta.delete(&key).await?;
+            }
+            if let Ok(mut cache) = self.cache.lock() {
+                cache.synced.remove(&key);
+                if window == Window::Minute {
+                    cache.minute.remove(scope);
+                }
+            }
+        }
+        Ok(())
+    }
+}
+
+fn is_metered(method: &Method, path: &str) -> bool {
+    method == Method::POST
+        && (matches!(
+            path,
+            "/v1/chat/completions"
+                | "/v1/completions"
+                | "/v1/messages"
+                | "/v1/responses"
+                | "/v1/embeddings"
+                | "/v1/rerank"
+        ) || path.starts_with("/v1/audio/")
+            || path.starts_with("/v1/images/"))
+}
+
+/// Rejects inference requests whose API key or tenant is over quota.
+pub async fn quota_middleware(State(st): State<AppState>, req: Request<Body>, next: Next) -> Response {
+    if !is_metered(req.method(), req.uri().path()) {
+        return next.run(req).await;
+    }
+    let Some(ctx) = req.extensions().get::<AuthContext>() else {
+        return next.run(req).await;
+    };
+    let Err(exceeded) = st.quotas.check(&QuotaScope::for_request(ctx)) else {
+        return next.run(req).await;
+    };
+
+    st.metrics.record_quota_rejected(exceeded.window);
+    exceeded.error().into_response()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use chrono::TimeZone;
+    use nebula_meta::MemoryMetaStore;
+
+    fn tokens(prompt_tokens: u64, completion_tokens: u64) -> TokenUsage {
+        TokenUsage {
+            prompt_tokens,
+            completion_tokens,
+        }
+    }
+
+    fn year_end() -> DateTime<Utc> {
+        Utc.with_ymd_and_hms(2026, 12, 31, 23, 59, 30).unwrap()
+    }
+
+    #[tokio::test]
+    async fn test_ledger_sums_replicas() {
+        let meta: Arc<dyn MetaStore> = Arc::new(MemoryMetaStore::new());
+        let a = Quotas::new(meta.clone(), "a".to_string());
+        let b = Quotas::new(meta.clone(), "b".to_string());
+        let key = QuotaScope::Key("k1".to_string());
+        let now = year_end();
+
+        a.flush(&key, tokens(10, 5), now).await.unwrap();
+        a.flush(&key, tokens(1, 1), now).await.unwrap();
+        b.flush(&key, tokens(20, 0), now).await.unwrap();
+        let usage = a.read_usage(&key, now).await.unwrap();
+        assert_eq!(usage.minute, tokens(31, 6));
+        assert_eq!(usage.day.total_tokens(), 37);
+        assert_eq!(usage.month.total_tokens(), 37);
+        // One minute entry per replica, not per flush.
+        let minute_prefix = format!("{}/", ledger_key(&key, Window::Minute, now));
+        assert_eq!(meta.list_prefix(&minute_prefix).await.unwrap().len(), 2);
+
+        // The next minute is also a new day and month.
+        let later = now + ChronoDuration::seconds(60);
+        b.flush(&key, tokens(2, 0), later).await.unwrap();
+        let usage = a.read_usage(&key, later).await.unwrap();
+        assert_eq!(usage.minute.total_tokens(), 2);
+        assert_eq!(usage.day.total_tokens(), 2);
+        assert_eq!(usage.month.total_tokens(), 2);
+        assert_eq!(a.read_usage(&key, now).await.unwrap().day.total_tokens(), 37);
+
+        for window in Window::ALL {
+            assert_eq!(window.retry_after_secs(now), 30);
+        }
+    }
+
+    #[tokio::test]
+    async fn test_concurrent_ledger_updates() {
+        let meta: Arc<dyn MetaStore> = Arc::new(MemoryMetaStore::new());
+        let replicas: Vec<Arc<Quotas>> = (0..8)
+            .map(|i| Arc::new(Quotas::new(meta.clone(), format!("r{i}"))))
+            .collect();
+        let tasks: Vec<_> = replicas
+            .iter()
+            .cloned()
+            .map(|q| tokio::spawn(async move { q.add("/usage/keys/k1/day-x", tokens(1, 2)).await }))
+            .collect();
+        for t in tasks {
+            t.await.unwrap().unwrap();
+        }
+        let (raw, _) = meta.get("/usage/keys/k1/day-x").await.unwrap().unwrap();
+        assert_eq!(serde_json::from_slice::<TokenUsage>(&raw).unwrap(), tokens(8, 16));
+    }
+
+    #[tokio::test]
+    async fn test_check_and_sync() {
+        let meta: Arc<dyn MetaStore> = Arc::new(MemoryMetaStore::new());
+        let a = Quotas::new(meta.clone(), "a".to_string());
+        let b = Quotas::new(meta.clone(), "b".to_string());
+        let key = QuotaScope::Key("k1".to_string());
+        let tenant = QuotaScope::Tenant("acme".to_string());
+        let scopes = [key.clone(), tenant.clone()];
+        let day = QuotaLimits {
+            tokens_per_day: Some(100),
+            ..QuotaLimits::default()
+        };
+        a.set_limits(&key, day).await.unwrap();
+        let now = year_end();
+
+        // Unflushed usage counts against the replica that recorded it.
+        a.record(&scopes, tokens(60, 0));
+        assert!(a.check_at(&scopes, now).is_ok());
+        a.record(&scopes, tokens(0, 40));
+        let exceeded = a.check_at(&scopes, now).unwrap_err();
+        assert_eq!((exceeded.scope, exceeded.window, exceeded.limit), (key.clone(), Window::Day, 100));
+
+        // Flushed usage reaches other replicas on their next sync.
+        a.sync_at(now).await.unwrap();
+        assert!(a.check_at(&scopes, now).is_err());
+        assert!(b.check_at(&scopes, now).is_ok());
+        b.sync_at(now).await.unwrap();
+        assert!(b.check_at(&scopes, now).is_err());
+        assert_eq!(a.read_usage(&tenant, now).await.unwrap().day.total_tokens(), 100);
+        // Limits apply per period.
+        assert!(b.check_at(&scopes, now + ChronoDuration::days(1)).is_ok());
+
+        let minute = QuotaLimits {
+            tokens_per_minute: Some(10),
+            ..QuotaLimits::default()
+        };
+        a.set_limits(&tenant, minute).await.unwrap();
+        a.delete_limits(&key).await.unwrap();
+        b.sync_at(now).await.unwrap();
+        let exceeded = b.check_at(&scopes, now).unwrap_err();
+        assert_eq!((exceeded.scope, exceeded.window), (tenant.clone(), Window::Minute));
+
+        b.reset_at(&tenant, &[Window::Minute], now).await.unwrap();
+        assert!(b.check_at(&scopes, now).is_ok());
+        a.sync_at(now).await.unwrap();
+        assert_eq!(a.read_usage(&tenant, now).await.unwrap().minute.total_tokens(), 0);
+    }
+
+    #[test]
+    fn test_scope_paths() {
+        let key = QuotaScope::Key("k1".to_string());
+        assert_eq!(QuotaScope::from_path(&key.path()), Some(key));
+        assert_eq!(QuotaScope::parse("tenants", "acme"), Some(QuotaScope::Tenant("acme".to_string())));
+        assert_eq!(QuotaScope::parse("keys", "a/b"), None);
+        assert_eq!(QuotaScope::parse("users", "a"), None);
+        assert!(is_metered(&Method::POST, "/v1/audio/speech"));
+        assert!(!is_metered(&Method::GET, "/v1/chat/completions"));
+        assert!(!is_metered(&Method::POST, "/v1/files"));
+    }
+}
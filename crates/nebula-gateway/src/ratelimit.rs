@@ -0,0 +1,240 @@
+//! Rate limit buckets shared by all gateway replicas.
+//!
+//! Each bucket is a [`TokenBucket`] document in the meta store. Rather than
+//! updating it on every request, a replica leases a small batch of tokens with
+//! compare-and-swap and serves requests from the lease until it runs out or
+//! goes stale. Leased tokens that are never used are lost, so the limit is
+//! never exceeded across replicas; a replica may be rejected slightly early
+//! while another holds unused tokens. When the meta store is unreachable the
+//! limiter falls back to per-replica buckets.
+
+use std::collections::HashMap;
+use std::sync::{Arc, Mutex};
+use std::time::{Duration, Instant};
+
+use anyhow::Result;
+use async_trait::async_trait;
+
+use nebula_common::ratelimit::{now_ms, LocalRateLimiter, RateLimitDecision, RateLimiter, TokenBucket};
+use nebula_meta::MetaStore;
+
+const RATE_LIMITS_PREFIX: &str = "/ratelimits/";
+/// Fraction of the limit leased at once.
+const LEASE_DIVISOR: u64 = 20;
+const MAX_LEASE: u64 = 50;
+/// Unused leased tokens are dropped after this long.
+const LEASE_TTL: Duration = Duration::from_secs(5);
+/// Buckets untouched for this long are full and can be deleted.
+const IDLE_BUCKET_MS: u64 = 120_000;
+const CAS_RETRIES: usize = 8;
+
+#[derive(Debug)]
+struct Lease {
+    tokens: u64,
+    /// Tokens left in the shared bucket when the lease was taken.
+    shared_remaining: u64,
+    reset_ms: u64,
+    taken_at: Instant,
+}
+
+pub struct MetaRateLimiter {
+    meta: Arc<dyn MetaStore>,
+    leases: Mutex<HashMap<String, Lease>>,
+    fallback: LocalRateLimiter,
+}
+
+impl std::fmt::Debug for MetaRateLimiter {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        f.debug_struct("MetaRateLimiter").finish_non_exhaustive()
+    }
+}
+
+impl MetaRateLimiter {
+    pub fn new(meta: Arc<dyn MetaStore>) -> Self {
+        Self {
+            meta,
+            leases: Mutex::new(HashMap::new()),
+            fallback: LocalRateLimiter::default(),
+        }
+    }
+
+    /// Periodically delete idle buckets from the meta store.
+    pub fn spawn_sweep(self: Arc<Self>) {
+        tokio::spawn(async move {
+            let mut tick = tokio::time::interval(Duration::from_millis(IDLE_BUCKET_MS));
+            loop {
+                tick.tick().await;
+                if let Err(e) = self.sweep().await {
+                    tracing::warn!(error=%e, "rate limit bucket sweep failed");
+                }
+            }
+        });
+    }
+
+    async fn sweep(&self) -> Result<()> {
+        let now = now_ms();
+        for (key, raw, _) in self.meta.list_prefix(RATE_LIMITS_PREFIX).await? {
+            let idle = serde_json::from_slice::<TokenBucket>(&raw)
+                .map(|b| now.saturating_sub(b.updated_ms) > IDLE_BUCKET_MS)
+                .unwrap_or(true);
+            if idle {
+                self.meta.delete(&key).await?;
+            }
+        }
+        if let Ok(mut leases) = self.leases.lock() {
+            leases.retain(|_, l| l.taken_at.elapsed() < LEASE_TTL);
+        }
+        Ok(())
+    }
+
+    /// Serve one request from a live lease.
+    fn take_leased(&self, key: &str, limit: u64) -> Option<RateLimitDecision> {
+        let mut leases = self.leases.lock().ok()?;
+        let lease = leases.get_mut(key)?;
+        if lease.tokens == 0 || lease.taken_at.elapsed() >= LEASE_TTL {
+            leases.remove(key);
+            return None;
+        }
+        lease.tokens -= 1;
+        Some(RateLimitDecision {
+            allowed: true,
+            limit,
+            remaining: lease.shared_remaining + lease.tokens,
+            reset_ms: lease.reset_ms,
+            retry_after_ms: 0,
+        })
+    }
+
+    /// Take up to `want` tokens from the shared bucket. Returns how many were
+    /// granted and the bucket afterwards.
+    async fn lease(&self, key: &str, limit: u64, want: u64) -> Result<(u64, TokenBucket)> {
+        let storage_key = format!("{RATE_LIMITS_PREFIX}{key}");
+        for _ in 0..CAS_RETRIES {
+            let now = now_ms();
+            let (mut bucket, rev) = match self.meta.get(&storage_key).await? {
+                Some((raw, rev)) => (
+                    serde_json::from_slice::<TokenBucket>(&raw).unwrap_or_else(|_| TokenBucket::full(limit, now)),
+                    rev,
+                ),
+                None => (TokenBucket::full(limit, now), 0),
+            };
+            bucket.refill(limit, now);
+            let granted = bucket.take(want);
+            if granted == 0 {
+                return Ok((0, bucket));
+            }
+            if self
+                .meta
+                .compare_and_swap(&storage_key, rev, serde_json::to_vec(&bucket)?)
+                .await?
+                .0
+            {
+                return Ok((granted, bucket));
+            }
+        }
+        anyhow::bail!("too much contention updating {storage_key}")
+    }
+}
+
+#[async_trait]
+impl RateLimiter for MetaRateLimiter {
+    async fn acquire(&self, key: &str, limit_per_minute: u64) -> RateLimitDecision {
+        if let Some(decision) = self.take_leased(key, limit_per_minute) {
+            return decision;
+        }
+
+        let want = (limit_per_minute / LEASE_DIVISOR).clamp(1, MAX_LEASE);
+        let (granted, bucket) = match self.lease(key, limit_per_minute, want).await {
+            Ok(v) => v,
+            Err(e) => {
+                tracing::warn!(error=%e, key=%key, "shared rate limit unavailable, using local bucket");
+                return self.fallback.acquire(key, limit_per_minute).await;
+            }
+        };
+        let mut decision = RateLimitDecision::from_bucket(granted > 0, limit_per_minute, &bucket);
+        if granted > 0 {
+            decision.remaining += granted - 1;
+            if let Ok(mut leases) = self.leases.lock() {
+                leases.insert(
+                    key.to_string(),
+                    Lease {
+                        tokens: granted - 1,
+                        shared_remaining: bucket.tokens.floor().max(0.0) as u64,
+                        reset_ms: decision.reset_ms,
+                        taken_at: Instant::now(),
+                    },
+                );
+            }
+        }
+        decision
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use nebula_meta::MemoryMetaStore;
+
+    #[tokio::test]
+    async fn test_replicas_share_the_limit() {
+        let meta: Arc<dyn MetaStore> = Arc::new(MemoryMetaStore::new());
+        let a = MetaRateLimiter::new(meta.clone());
+        let b = MetaRateLimiter::new(meta.clone());
+
+        let mut allowed = 0;
+        let mut last = None;
+        for _ in 0..50 {
+            for limiter in [&a, &b] {
+                let d = limiter.acquire("key/k1", 40).await;
+                assert_eq!(d.limit, 40);
+                if d.allowed {
+                    allowed += 1;
+                } else {
+                    last = Some(d);
+                }
+            }
+        }
+        // Both replicas together never exceed one limit's worth of requests.
+        assert_eq!(allowed, 40);
+        let rejected = last.unwrap();
+        assert_eq!(rejected.remaining, 0);
+        assert!(rejected.retry_after_ms > 0 && rejected.retry_after_ms <= 1_500);
+
+        // Other keys have their own buckets.
+        assert!(a.acquire("key/k2", 40).await.allowed);
+    }
+
+    #[tokio::test]
+    async fn test_leases_report_shared_remaining() {
+        let meta: Arc<dyn MetaStore> = Arc::new(MemoryMetaStore::new());
+        let a = MetaRateLimiter::new(meta.clone());
+        // 100/min leases 5 at a time.
+        let first = a.acquire("key/k1", 100).await;
+        assert_eq!(first.remaining, 99);
+        let second = a.acquire("key/k1", 100).await;
+        assert_eq!(second.remaining, 98);
+        let (raw, _) = meta.get("/ratelimits/key/k1").await.unwrap().unwrap();
+        let bucket: TokenBucket = serde_json::from_slice(&raw).unwrap();
+        assert_eq!(bucket.tokens.floor() as u64, 95);
+    }
+
+    #[tokio::test]
+    async fn test_sweep_drops_idle_buckets() {
+        let meta: Arc<dyn MetaStore> = Arc::new(MemoryMetaStore::new());
+        let limiter = MetaRateLimiter::new(meta.clone());
+        assert!(limiter.acquire("key/fresh", 10).await.allowed);
+        let idle = TokenBucket::full(10, now_ms() - IDLE_BUCKET_MS - 1_000);
+        meta.put("/ratelimits/key/idle", serde_json::to_vec(&idle).unwrap(), None)
+            .await
+            .unwrap();
+        limiter.sweep().await.unwrap();
+        let left: Vec<String> = meta
+            .list_prefix(RATE_LIMITS_PREFIX)
+            .await
+            .unwrap()
+            .into_iter()
+            .map(|(k, _, _)| k)
+            .collect();
+        assert_eq!(left, ["/ratelimits/key/fresh"]);
+    }
+}
@@ -0,0 +1,69 @@
+use std::sync::Arc;
+
+use anyhow::Result;
+use serde::{Deserialize, Serialize};
+use serde_json::Value;
+
+use nebula_meta::MetaStore;
+
+const RESPONSES_PREFIX: &str = "/responses/";
+
+/// A stored Responses object plus the chat conversation that produced it, so a
+/// follow-up with `previous_response_id` can continue it.
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct StoredResponse {
+    pub response: Value,
+    /// Conversation up to and including this response's output, without
+    /// system instructions.
+    pub messages: Vec<Value>,
+    /// Principal label of the creator; only it (and admins) can read,
+    /// delete or continue the response.
+    #[serde(default)]
+    pub owner: String,
+    #[serde(default)]
+    pub tenant: Option<String>,
+}
+
+/// Responses kept for `store: true`, on any [`MetaStore`] backend.
+pub struct ResponseStore {
+    meta: Arc<dyn MetaStore>,
+    ttl_ms: Option<u64>,
+}
+
+impl ResponseStore {
+    pub fn new(meta: Arc<dyn MetaStore>, ttl_ms: Option<u64>) -> Self {
+        Self { meta, ttl_ms }
+    }
+
+    fn key(id: &str) -> String {
+        format!("{RESPONSES_PREFIX}{id}")
+    }
+
+    pub async fn get(&self, id: &str) -> Result<Option<StoredResponse>> {
+        match self.meta.get(&Self::key(id)).await? {
+            Some((raw, _)) => Ok(Some(serde_json::from_slice(&raw)?)),
+            None => Ok(None),
+        }
+    }
+
+    pub async fn put(&self, stored: &StoredResponse) -> Result<()> {
+        let id = stored
+            .response
+            .get("id")
+            .and_then(|v| v.as_str())
+            .ok_or_else(|| anyhow::anyhow!("response has no id"))?;
+        self.meta
+            .put(&Self::key(id), serde_json::to_vec(stored)?, self.ttl_ms)
+            .await?;
+        Ok(())
+    }
+
+    /// Delete a stored response; `false` when it did not exist.
+    pub async fn delete(&self, id: &str) -> Result<bool> {
+        if self.meta.get(&Self::key(id)).await?.is_none() {
+            return Ok(false);
+        }
+        self.meta.delete(&Self::key(id)).await?;
+        Ok(true)
+    }
+}
@@ -1,48 +1,259 @@
+use std::collections::BTreeMap;
 use std::time::{SystemTime, UNIX_EPOCH};
 
 use serde::Deserialize;
 use serde_json::{json, Value};
 use uuid::Uuid;
 
+use nebula_common::TokenUsage;
+
+use crate::engine::EngineEvent;
+
 #[derive(Debug, Deserialize)]
 pub struct CreateResponseRequest {
     pub model: Option<String>,
     pub input: Option<Value>,
-    #[allow(dead_code)]
-    pub instructions: Option<Value>,
+    pub instructions: Option<String>,
     pub stream: Option<bool>,
+    #[serde(default)]
+    pub tools: Vec<Value>,
+    pub tool_choice: Option<Value>,
+    pub parallel_tool_calls: Option<bool>,
+    pub temperature: Option<f64>,
+    pub top_p: Option<f64>,
+    pub max_output_tokens: Option<u32>,
+    /// Defaults to `true`, as in the OpenAI API.
+    pub store: Option<bool>,
+    pub previous_response_id: Option<String>,
+    pub metadata: Option<Value>,
+    pub user: Option<String>,
 }
 
-impl CreateResponseRequest {
-    pub fn extract_input_text(&self) -> String {
-        if let Some(Value::String(s)) = self.input.as_ref() {
-            return s.clone();
+/// A request field that can't be mapped to a chat completion.
+#[derive(Debug)]
+pub struct InvalidRequest {
+    pub param: &'static str,
+    pub message: String,
+}
+
+impl InvalidRequest {
+    fn new(param: &'static str, message: impl Into<String>) -> Self {
+        Self {
+            param,
+            message: message.into(),
         }
+    }
+}
 
-        if let Some(Value::Array(items)) = self.input.as_ref() {
-            let mut parts = Vec::new();
-            for item in items {
-                if let Some(content_str) = item.get("content").and_then(|v| v.as_str()) {
-                    parts.push(content_str.to_string());
-                    continue;
+/// Convert Responses content (a string or a list of parts) to chat message content.
+/// Text-only content becomes a plain string, which every engine accepts.
+fn chat_content(content: &Value) -> Result<Value, InvalidRequest> {
+    let parts = match content {
+        Value::String(_) => return Ok(content.clone()),
+        Value::Array(parts) => parts,
+        Value::Null => return Ok(Value::String(String::new())),
+        _ => return Err(InvalidRequest::new("input", "message content must be a string or an array")),
+    };
+
+    let mut out = Vec::new();
+    for p in parts {
+        match p.get("type").and_then(|t| t.as_str()) {
+            Some("input_text" | "output_text" | "text") => {
+                let text = p.get("text").and_then(|t| t.as_str()).unwrap_or("");
+                out.push(json!({"type": "text", "text": text}));
+            }
+            Some("refusal") => {
+                let text = p.get("refusal").and_then(|t| t.as_str()).unwrap_or("");
+                out.push(json!({"type": "text", "text": text}));
+            }
+            Some("input_image") => {
+                let url = p
+                    .get("image_url")
+                    .and_then(|u| u.as_str().or_else(|| u.get("url").and_then(|x| x.as_str())))
+                    .ok_or_else(|| InvalidRequest::new("input", "input_image requires image_url"))?;
+                let mut image_url = json!({"url": url});
+                if let Some(detail) = p.get("detail").filter(|d| d.is_string()) {
+                    image_url["detail"] = detail.clone();
                 }
+                out.push(json!({"type": "image_url", "image_url": image_url}));
+            }
+            other => {
+                return Err(InvalidRequest::new(
+                    "input",
+                    format!("unsupported content part type '{}'", other.unwrap_or("")),
+                ));
+            }
+        }
+    }
+
+    if out.iter().all(|p| p["type"] == "text") {
+        let text: Vec<&str> = out.iter().filter_map(|p| p["text"].as_str()).collect();
+        return Ok(Value::String(text.join("\n")));
+    }
+    Ok(Value::Array(out))
+}
+
+/// Append a function call to the trailing assistant message, so parallel calls
+/// from one turn stay in one message.
+fn push_tool_call(messages: &mut Vec<Value>, call: Value) {
+    if let Some(calls) = messages
+        .last_mut()
+        .filter(|m| m["role"] == "assistant")
+        .and_then(|m| m.get_mut("tool_calls"))
+        .and_then(|c| c.as_array_mut())
+    {
+        calls.push(call);
+        return;
+    }
+    messages.push(json!({"role": "assistant", "content": null, "tool_calls": [call]}));
+}
+
+impl CreateResponseRequest {
+    /// This turn's input as chat messages, without `instructions`.
+    pub fn input_messages(&self) -> Result<Vec<Value>, InvalidRequest> {
+        let items = match self.input.as_ref() {
+            None | Some(Value::Null) => return Ok(Vec::new()),
+            Some(Value::String(s)) => return Ok(vec![json!({"role": "user", "content": s})]),
+            Some(Value::Array(items)) => items,
+            Some(_) => return Err(InvalidRequest::new("input", "input must be a string or an array")),
+        };
 
-                if let Some(content_parts) = item.get("content").and_then(|v| v.as_array()) {
-                    for p in content_parts {
-                        if p.get("type").and_then(|v| v.as_str()) == Some("input_text") {
-                            if let Some(text) = p.get("text").and_then(|v| v.as_str()) {
-                                parts.push(text.to_string());
-                            }
+        let mut messages = Vec::new();
+        for item in items {
+            let kind = item
+                .get("type")
+                .and_then(|t| t.as_str())
+                .unwrap_or("message");
+            match kind {
+                "message" => {
+                    let role = match item.get("role").and_then(|r| r.as_str()) {
+                        Some("developer") | Some("system") => "system",
+                        Some("user") => "user",
+                        Some("assistant") => "assistant",
+                        other => {
+                            return Err(InvalidRequest::new(
+                                "input",
+                                format!("unsupported message role '{}'", other.unwrap_or("")),
+                            ));
                         }
+                    };
+                    let content = chat_content(item.get("content").unwrap_or(&Value::Null))?;
+                    messages.push(json!({"role": role, "content": content}));
+                }
+                "function_call" => {
+                    let call_id = item.get("call_id").and_then(|c| c.as_str());
+                    let name = item.get("name").and_then(|n| n.as_str());
+                    let (Some(call_id), Some(name)) = (call_id, name) else {
+                        return Err(InvalidRequest::new("input", "function_call requires call_id and name"));
+                    };
+                    let arguments = item.get("arguments").and_then(|a| a.as_str()).unwrap_or("{}");
+                    push_tool_call(
+                        &mut messages,
+                        json!({
+                            "id": call_id,
+                            "type": "function",
+                            "function": {"name": name, "arguments": arguments}
+                        }),
+                    );
+                }
+                "function_call_output" => {
+                    let Some(call_id) = item.get("call_id").and_then(|c| c.as_str()) else {
+                        return Err(InvalidRequest::new("input", "function_call_output requires call_id"));
+                    };
+                    let output = match item.get("output") {
+                        Some(Value::String(s)) => s.clone(),
+                        Some(v) => v.to_string(),
+                        None => String::new(),
+                    };
+                    messages.push(json!({"role": "tool", "tool_call_id": call_id, "content": output}));
+                }
+                // Reasoning items carry nothing the engine can consume.
+                "reasoning" => {}
+                other => {
+                    return Err(InvalidRequest::new(
+                        "input",
+                        format!("unsupported input item type '{other}'"),
+                    ));
+                }
+            }
+        }
+        Ok(messages)
+    }
+
+    /// Function tools in chat/completions form. Built-in tools are rejected.
+    pub fn chat_tools(&self) -> Result<Vec<Value>, InvalidRequest> {
+        self.tools
+            .iter()
+            .map(|t| {
+                let kind = t.get("type").and_then(|k| k.as_str()).unwrap_or("");
+                if kind != "function" {
+                    return Err(InvalidRequest::new(
+                        "tools",
+                        format!("unsupported tool type '{kind}', only function tools are supported"),
+                    ));
+                }
+                let Some(name) = t.get("name").and_then(|n| n.as_str()) else {
+                    return Err(InvalidRequest::new("tools", "function tools require a name"));
+                };
+                let mut function = json!({"name": name});
+                for key in ["description", "parameters", "strict"] {
+                    if let Some(v) = t.get(key).filter(|v| !v.is_null()) {
+                        function[key] = v.clone();
                     }
                 }
+                Ok(json!({"type": "function", "function": function}))
+            })
+            .collect()
+    }
+
+    fn chat_tool_choice(&self) -> Option<Value> {
+        match self.tool_choice.as_ref()? {
+            Value::String(s) => Some(Value::String(s.clone())),
+            v if v.get("type").and_then(|t| t.as_str()) == Some("function") => Some(json!({
+                "type": "function",
+                "function": {"name": v.get("name").cloned().unwrap_or(Value::Null)}
+            })),
+            _ => None,
+        }
+    }
+
+    /// The chat/completions body for this request. `history` holds the stored
+    /// conversation of `previous_response_id`; instructions are not inherited
+    /// from it.
+    pub fn chat_request(&self, history: &[Value], input: &[Value], tools: Vec<Value>) -> Value {
+        let mut messages = Vec::with_capacity(history.len() + input.len() + 1);
+        if let Some(instructions) = self.instructions.as_deref().filter(|s| !s.is_empty()) {
+            messages.push(json!({"role": "system", "content": instructions}));
+        }
+        messages.extend_from_slice(history);
+        messages.extend_from_slice(input);
+
+        let mut body = json!({"messages": messages});
+        if let Some(model) = self.model.as_ref().filter(|m| !m.is_empty()) {
+            body["model"] = json!(model);
+        }
+        if !tools.is_empty() {
+            body["tools"] = Value::Array(tools);
+            if let Some(choice) = self.chat_tool_choice() {
+                body["tool_choice"] = choice;
             }
-            if !parts.is_empty() {
-                return parts.join("\n");
+            if let Some(parallel) = self.parallel_tool_calls {
+                body["parallel_tool_calls"] = Value::Bool(parallel);
             }
         }
-
-        "".to_string()
+        if let Some(t) = self.temperature {
+            body["temperature"] = json!(t);
+        }
+        if let Some(p) = self.top_p {
+            body["top_p"] = json!(p);
+        }
+        if let Some(n) = self.max_output_tokens {
+            body["max_tokens"] = json!(n);
+        }
+        if let Some(user) = self.user.as_ref() {
+            body["user"] = json!(user);
+        }
+        body
     }
 }
 
@@ -53,160 +264,518 @@ fn now_unix_seconds() -> u64 {
         .as_secs()
 }
 
-fn estimate_tokens(s: &str) -> u32 {
-    if s.is_empty() {
-        return 0;
-    }
-    ((s.len() as f64) / 4.0).ceil() as u32
+struct TextItem {
+    id: String,
+    output_index: u32,
+    text: String,
 }
 
-pub struct BuiltResponse {
-    pub response_id: String,
-    pub message_id: String,
-    pub created: u64,
-    pub model: String,
-    pub text: String,
-    pub usage: Value,
+struct ToolCallItem {
+    id: String,
+    call_id: String,
+    name: String,
+    arguments: String,
+    output_index: u32,
 }
 
-pub struct ResponseStreamBuilder {
+/// Assembles a Responses object from engine events, emitting the matching
+/// streaming events along the way.
+pub struct ResponseBuilder {
     response_id: String,
-    message_id: String,
     created: u64,
     model: String,
+    /// Request fields echoed back on the response object.
+    echo: Value,
     seq: u64,
-    deltas: Vec<String>,
-    input_tokens: u32,
+    next_output_index: u32,
+    text: Option<TextItem>,
+    tool_calls: BTreeMap<u32, ToolCallItem>,
+    /// Usage reported by the engine; the router fills it in when the engine
+    /// itself reports none.
+    usage: Option<TokenUsage>,
+    /// Chat `finish_reason` of the generation, once reported.
+    finish_reason: Option<String>,
+    /// Upstream failure: HTTP status and message.
+    error: Option<(u16, String)>,
 }
 
-impl ResponseStreamBuilder {
-    pub fn new(req: &CreateResponseRequest) -> Self {
-        let model = req.model.clone().unwrap_or_else(|| "unknown".to_string());
-        let created = now_unix_seconds();
-        let input_text = req.extract_input_text();
-        let input_tokens = estimate_tokens(&input_text);
+/// Responses error code for an upstream HTTP status.
+fn error_code(status: u16) -> &'static str {
+    match status {
+        429 => "rate_limit_exceeded",
+        400..=499 => "invalid_request_error",
+        _ => "server_error",
+    }
+}
 
+impl ResponseBuilder {
+    pub fn new(req: &CreateResponseRequest) -> Self {
+        let echo = json!({
+            "instructions": req.instructions,
+            "max_output_tokens": req.max_output_tokens,
+            "metadata": req.metadata.clone().unwrap_or_else(|| json!({})),
+            "parallel_tool_calls": req.parallel_tool_calls.unwrap_or(true),
+            "previous_response_id": req.previous_response_id,
+            "store": req.store.unwrap_or(true),
+            "temperature": req.temperature,
+            "tool_choice": req.tool_choice.clone().unwrap_or_else(|| json!("auto")),
+            "tools": req.tools,
+            "top_p": req.top_p,
+            "user": req.user,
+        });
         Self {
-            response_id: format!("resp_{}", Uuid::new_v4()),
-            message_id: format!("msg_{}", Uuid::new_v4()),
-            created,
-            model,
+            response_id: format!("resp_{}", Uuid::new_v4().simple()),
+            created: now_unix_seconds(),
+            model: req.model.clone().unwrap_or_else(|| "unknown".to_string()),
+            echo,
             seq: 0,
-            deltas: Vec::new(),
-            input_tokens,
+            next_output_index: 0,
+            text: None,
+            tool_calls: BTreeMap::new(),
+            usage: None,
+            finish_reason: None,
+            error: None,
         }
     }
 
-    pub fn created_event(&mut self) -> Value {
-        let created_response = json!({
-            "id": self.response_id,
-            "object": "response",
-            "created": self.created,
-            "model": self.model,
-            "status": "in_progress"
-        });
+    pub fn response_id(&self) -> &str {
+        &self.response_id
+    }
 
-        let ev = json!({
-            "type": "response.created",
+    fn event(&mut self, kind: &str, fields: Value) -> Value {
+        let mut ev = json!({
+            "type": kind,
             "sequence_number": self.seq,
             "response_id": self.response_id,
-            "response": created_response
         });
-
+        if let (Some(ev), Value::Object(fields)) = (ev.as_object_mut(), fields) {
+            ev.extend(fields);
+        }
         self.seq += 1;
         ev
     }
 
-    pub fn push_delta(&mut self, delta: String) -> Value {
-        self.deltas.push(delta.clone());
-        let ev = json!({
-            "type": "response.output_text.delta",
-            "sequence_number": self.seq,
-            "response_id": self.response_id,
-            "delta": delta,
-            "item_id": self.message_id,
-            "output_index": 0,
-            "content_index": 0
-        });
-        self.seq += 1;
-        ev
+    /// `response.created` and `response.in_progress`.
+    pub fn start_events(&mut self) -> Vec<Value> {
+        let response = self.response("in_progress");
+        vec![
+            self.event("response.created", json!({"response": response.clone()})),
+            self.event("response.in_progress", json!({"response": response})),
+        ]
     }
 
-    pub fn completed_event(&mut self) -> Value {
-        let full_text = self.deltas.join("");
-        let output_tokens = estimate_tokens(&full_text);
-        let usage = json!({
-            "input_tokens": self.input_tokens,
-            "output_tokens": output_tokens,
-            "total_tokens": self.input_tokens + output_tokens
-        });
+    /// Apply one engine event; returns the streaming events it produces.
+    pub fn push(&mut self, ev: EngineEvent) -> Vec<Value> {
+        let mut out = Vec::new();
+        match ev {
+            EngineEvent::Text(delta) => {
+                if self.text.is_none() {
+                    let item = TextItem {
+                        id: format!("msg_{}", Uuid::new_v4().simple()),
+                        output_index: self.next_output_index,
+                        text: String::new(),
+                    };
+                    self.next_output_index += 1;
+                    let (item_id, output_index) = (item.id.clone(), item.output_index);
+                    self.text = Some(item);
+                    out.push(self.event(
+                        "response.output_item.added",
+                        json!({
+                            "output_index": output_index,
+                            "item": {"id": item_id, "type": "message", "role": "assistant", "status": "in_progress", "content": []}
+                        }),
+                    ));
+                    out.push(self.event(
+                        "response.content_part.added",
+                        json!({
+                            "item_id": item_id,
+                            "output_index": output_index,
+                            "content_index": 0,
+                            "part": {"type": "output_text", "text": "", "annotations": []}
+                        }),
+                    ));
+                }
+                let Some(item) = self.text.as_mut() else {
+                    return out;
+                };
+                item.text.push_str(&delta);
+                let (item_id, output_index) = (item.id.clone(), item.output_index);
+                out.push(self.event(
+                    "response.output_text.delta",
+                    json!({"item_id": item_id, "output_index": output_index, "content_index": 0, "delta": delta}),
+                ));
+            }
+            EngineEvent::ToolCall {
+                index,
+                id,
+                name,
+                arguments,
+            } => {
+                if !self.tool_calls.contains_key(&index) {
+                    let item = ToolCallItem {
+                        id: format!("fc_{}", Uuid::new_v4().simple()),
+                        call_id: id.unwrap_or_else(|| format!("call_{}", Uuid::new_v4().simple())),
+                        name: name.unwrap_or_default(),
+                        arguments: String::new(),
+                        output_index: self.next_output_index,
+                    };
+                    self.next_output_index += 1;
+                    let added = json!({
+                        "output_index": item.output_index,
+                        "item": {
+                            "id": item.id,
+                            "type": "function_call",
+                            "status": "in_progress",
+                            "call_id": item.call_id,
+                            "name": item.name,
+                            "arguments": ""
+                        }
+                    });
+                    self.tool_calls.insert(index, item);
+                    out.push(self.event("response.output_item.added", added));
+                } else if let (Some(item), Some(name)) = (self.tool_calls.get_mut(&index), name) {
+                    item.name.push_str(&name);
+                }
+                if arguments.is_empty() {
+                    return out;
+                }
+                let Some(item) = self.tool_calls.get_mut(&index) else {
+                    return out;
+                };
+                item.arguments.push_str(&arguments);
+                let (item_id, output_index) = (item.id.clone(), item.output_index);
+                out.push(self.event(
+                    "response.function_call_arguments.delta",
+                    json!({"item_id": item_id, "output_index": output_index, "delta": arguments}),
+                ));
+            }
+            EngineEvent::Usage {
+                input_tokens,
+                output_tokens,
+            } => {
+                self.usage = Some(TokenUsage {
+                    prompt_tokens: input_tokens as u64,
+                    completion_tokens: output_tokens as u64,
+                })
+            }
+            EngineEvent::Error { status, message } => self.error = Some((status, message)),
+            EngineEvent::Finish { reason, .. } => self.finish_reason = Some(reason),
+        }
+        out
+    }
+
+    /// Upstream failure reported by the engine, if any.
+    pub fn error(&self) -> Option<(u16, &str)> {
+        self.error.as_ref().map(|(s, m)| (*s, m.as_str()))
+    }
 
-        let b = BuiltResponse {
-            response_id: self.response_id.clone(),
-            message_id: self.message_id.clone(),
-            created: self.created,
-            model: self.model.clone(),
-            text: full_text,
-            usage,
+    /// `error` and `response.failed`, once the engine has reported a failure.
+    pub fn fail_events(&mut self) -> Vec<Value> {
+        let Some((status, message)) = self.error.clone() else {
+            return Vec::new();
         };
-        let completed_response = build_non_stream_json(&b);
+        let error = self.event(
+            "error",
+            json!({"code": error_code(status), "message": message, "param": null}),
+        );
+        let response = self.response("failed");
+        vec![error, self.event("response.failed", json!({"response": response}))]
+    }
 
-        let ev = json!({
-            "type": "response.completed",
-            "sequence_number": self.seq,
-            "response_id": self.response_id,
-            "response": completed_response
+    /// Why the response stopped short, from the chat `finish_reason`.
+    fn incomplete_reason(&self) -> Option<&'static str> {
+        match self.finish_reason.as_deref() {
+            Some("length") => Some("max_output_tokens"),
+            Some("content_filter") => Some("content_filter"),
+            _ => None,
+        }
+    }
+
+    /// Final status of a response the engine finished: `completed`, or
+    /// `incomplete` when generation was cut off.
+    pub fn status(&self) -> &'static str {
+        if self.incomplete_reason().is_some() {
+            "incomplete"
+        } else {
+            "completed"
+        }
+    }
+
+    /// Done events for every output item, then `response.completed` (or
+    /// `response.incomplete`).
+    pub fn finish_events(&mut self) -> Vec<Value> {
+        let mut out = Vec::new();
+        if let Some((item_id, output_index, text)) = self
+            .text
+            .as_ref()
+            .map(|t| (t.id.clone(), t.output_index, t.text.clone()))
+        {
+            out.push(self.event(
+                "response.output_text.done",
+                json!({"item_id": item_id, "output_index": output_index, "content_index": 0, "text": text}),
+            ));
+            out.push(self.event(
+                "response.content_part.done",
+                json!({
+                    "item_id": item_id,
+                    "output_index": output_index,
+                    "content_index": 0,
+                    "part": {"type": "output_text", "text": text, "annotations": []}
+                }),
+            ));
+        }
+        let calls: Vec<(String, u32, String)> = self
+            .tool_calls
+            .values()
+            .map(|c| (c.id.clone(), c.output_index, c.arguments.clone()))
+            .collect();
+        for (item_id, output_index, arguments) in calls {
+            out.push(self.event(
+                "response.function_call_arguments.done",
+                json!({"item_id": item_id, "output_index": output_index, "arguments": arguments}),
+            ));
+        }
+        for (output_index, item) in self.indexed_output_items() {
+            out.push(self.event(
+                "response.output_item.done",
+                json!({"output_index": output_index, "item": item}),
+            ));
+        }
+        let status = self.status();
+        let response = self.response(status);
+        out.push(self.event(&format!("response.{status}"), json!({"response": response})));
+        out
+    }
+
+    fn output_items(&self) -> Vec<Value> {
+        self.indexed_output_items()
+            .into_iter()
+            .map(|(_, item)| item)
+            .collect()
+    }
+
+    fn indexed_output_items(&self) -> Vec<(u32, Value)> {
+        let mut items: Vec<(u32, Value)> = Vec::new();
+        if let Some(t) = self.text.as_ref() {
+            items.push((
+                t.output_index,
+                json!({
+                    "id": t.id,
+                    "type": "message",
+                    "role": "assistant",
+                    "status": self.status(),
+                    "content": [{"type": "output_text", "text": t.text, "annotations": []}]
+                }),
+            ));
+        }
+        for c in self.tool_calls.values() {
+            items.push((
+                c.output_index,
+                json!({
+                    "id": c.id,
+                    "type": "function_call",
+                    "status": "completed",
+                    "call_id": c.call_id,
+                    "name": c.name,
+                    "arguments": c.arguments
+                }),
+            ));
+        }
+        items.sort_by_key(|(i, _)| *i);
+        items
+    }
+
+    /// Token usage reported for this response, if any.
+    pub fn token_usage(&self) -> Option<TokenUsage> {
+        self.usage
+    }
+
+    fn usage(&self) -> Value {
+        let usage = self.usage.unwrap_or_default();
+        json!({
+            "input_tokens": usage.prompt_tokens,
+            "output_tokens": usage.completion_tokens,
+            "total_tokens": usage.total_tokens()
+        })
+    }
+
+    /// The Responses object with the given status.
+    pub fn response(&self, status: &str) -> Value {
+        let done = status != "in_progress";
+        let mut response = json!({
+            "id": self.response_id,
+            "object": "response",
+            "created_at": self.created,
+            "model": self.model,
+            "status": status,
+            "error": self.error.as_ref().map(|(status, message)| json!({"code": error_code(*status), "message": message})),
+            "incomplete_details": match self.incomplete_reason() {
+                Some(reason) if status == "incomplete" => json!({"reason": reason}),
+                _ => Value::Null,
+            },
+            "output": if done { Value::Array(self.output_items()) } else { json!([]) },
+            "usage": if done { self.usage() } else { Value::Null },
         });
+        if let (Some(r), Some(echo)) = (response.as_object_mut(), self.echo.as_object()) {
+            r.extend(echo.clone());
+        }
+        response
+    }
 
-        self.seq += 1;
-        ev
+    /// This turn's output as chat messages, for `previous_response_id` follow-ups.
+    pub fn assistant_messages(&self) -> Vec<Value> {
+        let mut msg = json!({
+            "role": "assistant",
+            "content": self.text.as_ref().map(|t| t.text.clone()),
+        });
+        if !self.tool_calls.is_empty() {
+            let calls: Vec<Value> = self
+                .tool_calls
+                .values()
+                .map(|c| {
+                    json!({
+                        "id": c.call_id,
+                        "type": "function",
+                        "function": {"name": c.name, "arguments": c.arguments}
+                    })
+                })
+                .collect();
+            msg["tool_calls"] = Value::Array(calls);
+        } else if self.text.is_none() {
+            msg["content"] = Value::String(String::new());
+        }
+        vec![msg]
     }
 }
 
-pub fn build_response(req: &CreateResponseRequest, text: String) -> BuiltResponse {
-    let model = req.model.clone().unwrap_or_else(|| "unknown".to_string());
+#[cfg(test)]
+mod tests {
+    use super::*;
 
-    let created = now_unix_seconds();
+    fn request(v: Value) -> CreateResponseRequest {
+        serde_json::from_value(v).unwrap()
+    }
 
-    let input_tokens = estimate_tokens(&req.extract_input_text());
-    let output_tokens = estimate_tokens(&text);
+    fn types(events: &[Value]) -> Vec<&str> {
+        events.iter().map(|e| e["type"].as_str().unwrap()).collect()
+    }
+
+    #[test]
+    fn test_chat_request() {
+        let req = request(json!({
+            "model": "m",
+            "instructions": "be brief",
+            "input": [
+                {"role": "user", "content": [{"type": "input_text", "text": "weather?"}]},
+                {"type": "function_call", "call_id": "c1", "name": "weather", "arguments": "{\"city\":\"a\"}"},
+                {"type": "function_call", "call_id": "c2", "name": "weather", "arguments": "{\"city\":\"b\"}"},
+                {"type": "function_call_output", "call_id": "c1", "output": "sunny"},
+                {"type": "reasoning", "summary": []}
+            ],
+            "tools": [{"type": "function", "name": "weather", "parameters": {"type": "object"}}],
+            "tool_choice": {"type": "function", "name": "weather"},
+            "max_output_tokens": 64
+        }));
+        let input = req.input_messages().unwrap();
+        assert_eq!(input.len(), 3);
+        assert_eq!(input[0], json!({"role": "user", "content": "weather?"}));
+        // Parallel calls stay in one assistant message.
+        assert_eq!(input[1]["tool_calls"].as_array().map(Vec::len), Some(2));
+        assert_eq!(input[2], json!({"role": "tool", "tool_call_id": "c1", "content": "sunny"}));
 
-    BuiltResponse {
-        response_id: format!("resp_{}", Uuid::new_v4()),
-        message_id: format!("msg_{}", Uuid::new_v4()),
-        created,
-        model,
-        text,
-        usage: json!({
-            "input_tokens": input_tokens,
-            "output_tokens": output_tokens,
-            "total_tokens": input_tokens + output_tokens
-        }),
+        let history = vec![json!({"role": "user", "content": "earlier"})];
+        let body = req.chat_request(&history, &input, req.chat_tools().unwrap());
+        assert_eq!(body["messages"][0], json!({"role": "system", "content": "be brief"}));
+        assert_eq!(body["messages"][1], history[0]);
+        assert_eq!(body["messages"].as_array().map(Vec::len), Some(5));
+        assert_eq!(body["tools"][0]["function"]["name"], "weather");
+        assert_eq!(body["tool_choice"], json!({"type": "function", "function": {"name": "weather"}}));
+        assert_eq!(body["max_tokens"], 64);
+
+        let bad = request(json!({"input": [{"type": "web_search_call"}]}));
+        assert_eq!(bad.input_messages().unwrap_err().param, "input");
+        let bad = request(json!({"tools": [{"type": "web_search"}]}));
+        assert_eq!(bad.chat_tools().unwrap_err().param, "tools");
     }
-}
 
-pub fn build_non_stream_json(b: &BuiltResponse) -> Value {
-    json!({
-        "id": b.response_id,
-        "object": "response",
-        "created": b.created,
-        "model": b.model,
-        "status": "completed",
-        "output": [
-            {
-                "id": b.message_id,
-                "type": "message",
-                "role": "assistant",
-                "status": "completed",
-                "content": [
-                    {
-                        "type": "output_text",
-                        "text": b.text,
-                        "annotations": []
-                    }
-                ]
-            }
-        ],
-        "usage": b.usage
-    })
+    #[test]
+    fn test_stream_events() {
+        let req = request(json!({"model": "m", "input": "hi"}));
+        let mut b = ResponseBuilder::new(&req);
+        let mut events = b.start_events();
+        events.extend(b.push(EngineEvent::Text("Hel".to_string())));
+        events.extend(b.push(EngineEvent::Text("lo".to_string())));
+        events.extend(b.push(EngineEvent::ToolCall {
+            index: 0,
+            id: Some("call_1".to_string()),
+            name: Some("f".to_string()),
+            arguments: "{}".to_string(),
+        }));
+        b.push(EngineEvent::Finish {
+            reason: "tool_calls".to_string(),
+            stop_sequence: None,
+        });
+        b.push(EngineEvent::Usage {
+            input_tokens: 3,
+            output_tokens: 4,
+        });
+        events.extend(b.finish_events());
+        assert_eq!(
+            types(&events),
+            [
+                "response.created",
+                "response.in_progress",
+                "response.output_item.added",
+                "response.content_part.added",
+                "response.output_text.delta",
+                "response.output_text.delta",
+                "response.output_item.added",
+                "response.function_call_arguments.delta",
+                "response.output_text.done",
+                "response.content_part.done",
+                "response.function_call_arguments.done",
+                "response.output_item.done",
+                "response.output_item.done",
+                "response.completed",
+            ]
+        );
+        let seqs: Vec<u64> = events.iter().map(|e| e["sequence_number"].as_u64().unwrap()).collect();
+        assert_eq!(seqs, (0..events.len() as u64).collect::<Vec<_>>());
+
+        let done = &events.last().unwrap()["response"];
+        assert_eq!(done["status"], "completed");
+        assert_eq!(done["incomplete_details"], Value::Null);
+        assert_eq!(done["output"][0]["content"][0]["text"], "Hello");
+        assert_eq!(done["output"][1]["call_id"], "call_1");
+        assert_eq!(done["usage"]["total_tokens"], 7);
+        assert_eq!(b.assistant_messages()[0]["tool_calls"][0]["id"], "call_1");
+    }
+
+    #[test]
+    fn test_incomplete_response() {
+        let req = request(json!({"model": "m", "input": "hi", "max_output_tokens": 1}));
+        let mut b = ResponseBuilder::new(&req);
+        b.start_events();
+        b.push(EngineEvent::Text("Hel".to_string()));
+        b.push(EngineEvent::Finish {
+            reason: "length".to_string(),
+            stop_sequence: None,
+        });
+        assert_eq!(b.status(), "incomplete");
+        let events = b.finish_events();
+        let last = events.last().unwrap();
+        assert_eq!(last["type"], "response.incomplete");
+        assert_eq!(last["response"]["status"], "incomplete");
+        assert_eq!(last["response"]["incomplete_details"], json!({"reason": "max_output_tokens"}));
+        assert_eq!(last["response"]["output"][0]["status"], "incomplete");
+
+        let mut b = ResponseBuilder::new(&req);
+        b.push(EngineEvent::Error {
+            status: 429,
+            message: "slow down".to_string(),
+        });
+        let failed = b.fail_events();
+        assert_eq!(types(&failed), ["error", "response.failed"]);
+        assert_eq!(failed[0]["code"], "rate_limit_exceeded");
+    }
 }
@@ -1,11 +1,17 @@
 use std::sync::Arc;
+use std::time::Duration;
 
-use nebula_meta::EtcdMetaStore;
+use nebula_meta::MetaStore;
 
 use crate::audit::AuditWriter;
 use crate::auth::AuthConfig;
+use crate::batch::BatchStore;
+use crate::cache::ResponseCache;
 use crate::engine::EngineClient;
+use crate::guardrails::Guardrails;
 use crate::metrics::Metrics;
+use crate::quota::Quotas;
+use crate::response_store::ResponseStore;
 
 #[derive(Clone)]
 pub struct AppState {
@@ -13,12 +19,22 @@ pub struct AppState {
     pub engine: Arc<dyn EngineClient>,
     pub router_base_url: String,
     pub http: reqwest::Client,
-    pub store: Arc<EtcdMetaStore>,
+    pub store: Arc<dyn MetaStore>,
+    pub responses: Arc<ResponseStore>,
+    /// Uploaded files and batch jobs.
+    pub batches: Arc<BatchStore>,
+    pub quotas: Arc<Quotas>,
     pub auth: AuthConfig,
     pub metrics: Arc<Metrics>,
     pub max_request_body_bytes: usize,
+    /// Upper bound on a request's deadline.
+    pub max_request_timeout: Duration,
     pub log_path: String,
     pub audit: Option<Arc<AuditWriter>>,
+    /// Response cache; `None` when no model opts in.
+    pub cache: Option<Arc<ResponseCache>>,
+    /// Guardrail policies; `None` when no guardrails file is configured.
+    pub guardrails: Option<Arc<Guardrails>>,
     pub xtrace_url: Option<String>,
     pub xtrace_token: Option<String>,
     pub bff_url: String,
@@ -29,3 +45,39 @@ impl AsRef<AuthConfig> for AppState {
         &self.auth
     }
 }
+
+#[cfg(test)]
+impl AppState {
+    /// State that proxies to `router_base_url`, with an in-memory meta store,
+    /// auth off and no audit, cache or guardrails.
+    pub(crate) fn for_tests(router_base_url: &str) -> Self {
+        let store: Arc<dyn MetaStore> = Arc::new(nebula_meta::MemoryMetaStore::new());
+        let dir = std::env::temp_dir().join(format!("nebula-gateway-{}", uuid::Uuid::new_v4()));
+        let timeout = Duration::from_secs(30);
+        Self {
+            _noop: Arc::new(()),
+            engine: Arc::new(crate::engine::OpenAIEngineClient::new(
+                router_base_url.to_string(),
+                "default".to_string(),
+                timeout,
+            )),
+            router_base_url: router_base_url.to_string(),
+            http: reqwest::Client::new(),
+            store: store.clone(),
+            responses: Arc::new(ResponseStore::new(store.clone(), None)),
+            batches: Arc::new(BatchStore::new(store.clone(), dir, 1 << 20)),
+            quotas: Arc::new(Quotas::new(store, "test".to_string())),
+            auth: nebula_common::auth::parse_auth_from_env(),
+            metrics: Arc::new(Metrics::default()),
+            max_request_body_bytes: 1 << 20,
+            max_request_timeout: timeout,
+            log_path: String::new(),
+            audit: None,
+            cache: None,
+            guardrails: None,
+            xtrace_url: None,
+            xtrace_token: None,
+            bff_url: String::new(),
+        }
+    }
+}
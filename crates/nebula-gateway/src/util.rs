@@ -18,3 +18,57 @@ pub async fn read_engine_env_file(path: &str) -> Option<(String, String)> {
     }
     Some((base_url?, model?))
 }
+
+/// One field of a `multipart/form-data` body.
+pub struct FormField {
+    pub name: String,
+    pub filename: Option<String>,
+    pub data: bytes::Bytes,
+}
+
+/// Splits a buffered `multipart/form-data` body into its fields. Returns
+/// `None` when the content type has no boundary or the body is malformed.
+pub fn parse_multipart(content_type: &str, body: &bytes::Bytes) -> Option<Vec<FormField>> {
+    let boundary = content_type
+        .split(';')
+        .map(str::trim)
+        .find_map(|p| p.strip_prefix("boundary="))?
+        .trim_matches('"');
+    let delimiter = format!("--{boundary}");
+    let separator = format!("\r\n{delimiter}");
+
+    let mut rest = &body[find(body, delimiter.as_bytes())? + delimiter.len()..];
+    let mut fields = Vec::new();
+    while !rest.starts_with(b"--") {
+        rest = rest.strip_prefix(b"\r\n")?;
+        let header_end = find(rest, b"\r\n\r\n")?;
+        let headers = std::str::from_utf8(&rest[..header_end]).ok()?;
+        let content = &rest[header_end + 4..];
+        let content_end = find(content, separator.as_bytes())?;
+
+        let disposition = headers
+            .lines()
+            .find_map(|l| {
+                let (k, v) = l.split_once(':')?;
+                k.trim().eq_ignore_ascii_case("content-disposition").then_some(v)
+            })
+            .unwrap_or_default();
+        let param = |key: &str| {
+            disposition.split(';').map(str::trim).find_map(|p| {
+                let (k, v) = p.split_once('=')?;
+                (k == key).then(|| v.trim_matches('"').to_string())
+            })
+        };
+        fields.push(FormField {
+            name: param("name")?,
+            filename: param("filename"),
+            data: body.slice_ref(&content[..content_end]),
+        });
+        rest = &content[content_end + separator.len()..];
+    }
+    Some(fields)
+}
+
+fn find(haystack: &[u8], needle: &[u8]) -> Option<usize> {
+    haystack.windows(needle.len()).position(|w| w == needle)
+}
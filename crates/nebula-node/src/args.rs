@@ -112,6 +112,14 @@ pub struct Args {
     #[arg(long, default_value_t = 9090)]
     pub api_port: u16,
 
+    /// Serve each engine through a gRPC shim and register its endpoint as `grpc_shim`.
+    #[arg(long, default_value_t = false)]
+    pub grpc_shim: bool,
+
+    /// gRPC shim port = engine assignment port + this offset (next free port if taken).
+    #[arg(long, default_value_t = 1000)]
+    pub grpc_shim_port_offset: u16,
+
     /// xtrace server URL for metrics reporting (e.g. "http://10.21.11.92:8742/").
     /// If not set, metrics reporting is disabled.
     #[arg(long, env = "OBSERVE_URL")]
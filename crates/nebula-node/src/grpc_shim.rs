@@ -0,0 +1,247 @@
+use std::net::SocketAddr;
+use std::time::Duration;
+
+use futures_util::StreamExt;
+use tokio_stream::wrappers::ReceiverStream;
+use tokio_util::sync::CancellationToken;
+use tonic::transport::server::TcpIncoming;
+use tonic::{Request, Response, Status};
+
+use nebula_proto::convert::{
+    code_from_http_status, embeddings_response_from_openai, generate_chunks_from_openai,
+    generate_response_from_openai, openai_json_from_embeddings_request,
+    openai_json_from_generate_request,
+};
+use nebula_proto::inference::{
+    EmbeddingsRequest, EmbeddingsResponse, GenerateChunk, GenerateRequest, GenerateResponse,
+    HealthRequest, HealthResponse,
+};
+use nebula_proto::{InferenceService, InferenceServiceServer};
+
+use crate::engine::find_available_port;
+
+/// Translates `InferenceService` calls into OpenAI HTTP requests against a
+/// locally running engine.
+struct EngineShim {
+    http: reqwest::Client,
+    base_url: String,
+    engine_model: String,
+}
+
+impl EngineShim {
+    async fn post(&self, path: &str, body: &serde_json::Value) -> Result<reqwest::Response, Status> {
+        let url = format!("{}{}", self.base_url.trim_end_matches('/'), path);
+        let resp = self
+            .http
+            .post(url)
+            .json(body)
+            .send()
+            .await
+            .map_err(|e| Status::unavailable(format!("engine request failed: {e}")))?;
+        if !resp.status().is_success() {
+            let code = code_from_http_status(resp.status().as_u16());
+            let text = resp.text().await.unwrap_or_default();
+            return Err(Status::new(code, text));
+        }
+        Ok(resp)
+    }
+}
+
+#[tonic::async_trait]
+impl InferenceService for EngineShim {
+    type GenerateStreamStream = ReceiverStream<Result<GenerateChunk, Status>>;
+
+    async fn generate(
+        &self,
+        request: Request<GenerateRequest>,
+    ) -> Result<Response<GenerateResponse>, Status> {
+        let req = request.into_inner();
+        let kind = req.kind();
+        let body = openai_json_from_generate_request(&req, false).map_err(Status::invalid_argument)?;
+        let resp = self.post(kind.path(), &body).await?;
+        let json: serde_json::Value = resp
+            .json()
+            .await
+            .map_err(|e| Status::internal(format!("invalid engine response: {e}")))?;
+        Ok(Response::new(generate_response_from_openai(kind, &json)))
+    }
+
+    async fn generate_stream(
+        &self,
+        request: Request<GenerateRequest>,
+    ) -> Result<Response<Self::GenerateStreamStream>, Status> {
+        let req = request.into_inner();
+        let kind = req.kind();
+        let body = openai_json_from_generate_request(&req, true).map_err(Status::invalid_argument)?;
+        let resp = self.post(kind.path(), &body).await?;
+
+        let (tx, rx) = tokio::sync::mpsc::channel::<Result<GenerateChunk, Status>>(64);
+        tokio::spawn(async move {
+            let mut upstream = resp.bytes_stream();
+            // Raw bytes: a multibyte character may be split across chunks.
+            let mut buf: Vec<u8> = Vec::new();
+            loop {
+                // Receiver gone means the router cancelled; dropping the
+                // upstream response aborts the engine request.
+                let item = tokio::select! {
+                    item = upstream.next() => item,
+                    _ = tx.closed() => return,
+                };
+                let ended = match item {
+                    Some(Ok(b)) => {
+                        buf.extend_from_slice(&b);
+                        false
+                    }
+                    None => true,
+                    Some(Err(e)) => {
+                        let _ = tx
+                            .send(Err(Status::unavailable(format!("engine stream failed: {e}"))))
+                            .await;
+                        return;
+                    }
+                };
+                let mut events = std::iter::from_fn(|| next_event(&mut buf)).collect::<Vec<_>>();
+                if ended && !buf.is_empty() {
+                    events.push(String::from_utf8_lossy(&std::mem::take(&mut buf)).into_owned());
+                }
+                for event in events {
+                    for line in event.lines() {
+                        let Some(data) = line.trim().strip_prefix("data:").map(str::trim) else {
+                            continue;
+                        };
+                        if data == "[DONE]" {
+                            return;
+                        }
+                        let Ok(json) = serde_json::from_str::<serde_json::Value>(data) else {
+                            continue;
+                        };
+                        for chunk in generate_chunks_from_openai(kind, &json) {
+                            if tx.send(Ok(chunk)).await.is_err() {
+                                return;
+                            }
+                        }
+                    }
+                }
+                if ended {
+                    return;
+                }
+            }
+        });
+
+        Ok(Response::new(ReceiverStream::new(rx)))
+    }
+
+    async fn embeddings(
+        &self,
+        request: Request<EmbeddingsRequest>,
+    ) -> Result<Response<EmbeddingsResponse>, Status> {
+        let body = openai_json_from_embeddings_request(&request.into_inner())
+            .map_err(Status::invalid_argument)?;
+        let resp = self.post("/v1/embeddings", &body).await?;
+        let json: serde_json::Value = resp
+            .json()
+            .await
+            .map_err(|e| Status::internal(format!("invalid engine response: {e}")))?;
+        embeddings_response_from_openai(&json)
+            .map(Response::new)
+            .map_err(Status::internal)
+    }
+
+    async fn health(
+        &self,
+        _request: Request<HealthRequest>,
+    ) -> Result<Response<HealthResponse>, Status> {
+        let url = format!("{}/health", self.base_url.trim_end_matches('/'));
+        let ready = matches!(
+            self.http.get(url).timeout(Duration::from_secs(3)).send().await,
+            Ok(r) if r.status().is_success()
+        );
+        Ok(Response::new(HealthResponse {
+            ready,
+            model: self.engine_model.clone(),
+        }))
+    }
+}
+
+/// Removes the next complete SSE event (up to a blank line) from `buf` and
+/// decodes it. Incomplete events stay buffered as bytes.
+fn next_event(buf: &mut Vec<u8>) -> Option<String> {
+    let end = buf.windows(3).enumerate().find_map(|(i, w)| match w {
+        [b'\n', b'\n', _] => Some(i + 2),
+        [b'\n', b'\r', b'\n'] => Some(i + 3),
+        _ => None,
+    });
+    // A trailing "\n\n" isn't covered by the 3-byte windows above.
+    let end = end.or_else(|| buf.ends_with(b"\n\n").then_some(buf.len()))?;
+    let event: Vec<u8> = buf.drain(..end).collect();
+    Some(String::from_utf8_lossy(&event).into_owned())
+}
+
+/// A running gRPC shim; the server shuts down when the handle is dropped.
+pub struct GrpcShimHandle {
+    pub port: u16,
+    cancel: CancellationToken,
+}
+
+impl Drop for GrpcShimHandle {
+    fn drop(&mut self) {
+        self.cancel.cancel();
+    }
+}
+
+/// Start a gRPC shim in front of the engine at `base_url`, binding the first
+/// free port at or above `start_port`.
+pub async fn start_grpc_shim(
+    base_url: &str,
+    engine_model: &str,
+    start_port: u16,
+) -> anyhow::Result<GrpcShimHandle> {
+    let port = find_available_port(start_port, 64).await?;
+    let addr = SocketAddr::from(([0, 0, 0, 0], port));
+    let incoming = TcpIncoming::new(addr, true, None)
+        .map_err(|e| anyhow::anyhow!("failed to bind grpc shim on {addr}: {e}"))?;
+
+    let shim = EngineShim {
+        http: reqwest::Client::new(),
+        base_url: base_url.to_string(),
+        engine_model: engine_model.to_string(),
+    };
+    let cancel = CancellationToken::new();
+    let shutdown = cancel.clone();
+    tokio::spawn(async move {
+        let res = tonic::transport::Server::builder()
+            .add_service(InferenceServiceServer::new(shim))
+            .serve_with_incoming_shutdown(incoming, shutdown.cancelled_owned())
+            .await;
+        if let Err(e) = res {
+            tracing::error!(error=%e, %port, "grpc shim server exited");
+        }
+    });
+
+    Ok(GrpcShimHandle { port, cancel })
+}
+
+/// `host:port` the router should dial for a shim serving the engine at `base_url`.
+pub fn grpc_target_for(base_url: &str, port: u16) -> Option<String> {
+    let url = reqwest::Url::parse(base_url).ok()?;
+    let host = url.host_str()?;
+    Some(format!("{host}:{port}"))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_next_event_keeps_split_characters() {
+        let stream = "data: {\"x\":\"你好\"}\n\ndata: [DONE]\r\n\r\n".as_bytes();
+        // Split inside the first CJK character.
+        let split = stream.iter().position(|&b| b >= 0x80).unwrap() + 1;
+        let mut buf = stream[..split].to_vec();
+        assert_eq!(next_event(&mut buf), None);
+        buf.extend_from_slice(&stream[split..]);
+        assert_eq!(next_event(&mut buf).as_deref(), Some("data: {\"x\":\"你好\"}\n\n"));
+        assert_eq!(next_event(&mut buf).as_deref(), Some("data: [DONE]\r\n\r\n"));
+        assert!(buf.is_empty());
+    }
+}
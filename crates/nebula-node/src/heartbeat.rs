@@ -10,7 +10,7 @@ use nebula_meta::{EtcdMetaStore, MetaStore};
 
 use crate::docker_api::{EngineMetricSnapshot, NodeMetricsSnapshot, SharedNodeMetrics};
 use crate::gpu::read_gpu_statuses;
-use crate::reconcile::RunningModel;
+use crate::reconcile::{probe_engine_models, RunningModel};
 use crate::util::now_ms;
 
 /// Number of consecutive health-check failures before marking endpoint as Unhealthy.
@@ -184,6 +184,23 @@ pub async fn heartbeat_loop(
                         }
                     }
 
+                    // Track LoRA adapters loaded or unloaded at runtime. Engines that don't list
+                    // adapters keep the plan-declared ones registered at startup.
+                    if let Some(models) =
+                        probe_engine_models(&http, &rm.handle.base_url, &rm.handle.engine_model)
+                            .await
+                            .filter(|m| !m.lora_adapters.is_empty())
+                    {
+                        let mut ep_guard = endpoint.lock().await;
+                        if let Some(info) = ep_guard.get_mut(&rm.model_uid) {
+                            if info.lora_adapters != models.lora_adapters {
+                                tracing::info!(model_uid=%rm.model_uid, adapters=?models.lora_adapters, "engine lora adapters changed");
+                                info.lora_adapters = models.lora_adapters;
+                                let _ = register_endpoint(&store, info, ttl_ms).await;
+                            }
+                        }
+                    }
+
                     // Scrape metrics only when healthy
                     if let Some(stats) =
                         rm.engine.scrape_stats(&http, &rm.handle, &rm.model_uid, rm.replica_id).await
@@ -2,6 +2,7 @@ mod args;
 mod docker_api;
 mod engine;
 mod gpu;
+mod grpc_shim;
 mod heartbeat;
 mod image_manager;
 mod model_cache_manager;
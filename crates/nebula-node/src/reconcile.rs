@@ -9,6 +9,7 @@ use nebula_meta::{EtcdMetaStore, MetaStore};
 
 use crate::args::Args;
 use crate::engine::{write_engine_env, Engine, EngineHandle, EngineStartContext};
+use crate::grpc_shim::{grpc_target_for, start_grpc_shim, GrpcShimHandle};
 use crate::heartbeat::{delete_endpoint, register_endpoint};
 use crate::util::now_ms;
 
@@ -18,6 +19,8 @@ pub struct RunningModel {
     pub assignment_signature: String,
     pub handle: EngineHandle,
     pub engine: Arc<dyn Engine>,
+    /// gRPC shim in front of the engine when `--grpc-shim` is set; stopped on drop.
+    pub grpc_shim: Option<GrpcShimHandle>,
 }
 
 fn assignment_signature(assignment: &nebula_common::PlacementAssignment) -> String {
@@ -35,6 +38,75 @@ fn assignment_signature(assignment: &nebula_common::PlacementAssignment) -> Stri
     })
 }
 
+/// Value of `--flag N` / `--flag=N` in engine CLI args.
+fn flag_value(args: &[String], flag: &str) -> Option<u32> {
+    args.iter().enumerate().find_map(|(i, a)| {
+        if a == flag {
+            args.get(i + 1).and_then(|v| v.parse().ok())
+        } else {
+            a.strip_prefix(flag)
+                .and_then(|rest| rest.strip_prefix('='))
+                .and_then(|v| v.parse().ok())
+        }
+    })
+}
+
+/// What a running engine reports on `/v1/models`.
+#[derive(Debug, Default)]
+pub(crate) struct EngineModels {
+    pub max_model_len: Option<u32>,
+    /// Served model ids other than the base model (vLLM lists LoRA adapters here).
+    pub lora_adapters: Vec<String>,
+}
+
+/// Query the engine's `/v1/models`; `None` if it is unreachable or not OpenAI-shaped.
+pub(crate) async fn probe_engine_models(
+    http: &reqwest::Client,
+    base_url: &str,
+    engine_model: &str,
+) -> Option<EngineModels> {
+    let url = format!("{}/v1/models", base_url.trim_end_matches('/'));
+    let resp = http
+        .get(url)
+        .timeout(Duration::from_secs(3))
+        .send()
+        .await
+        .ok()
+        .filter(|r| r.status().is_success())?;
+    let v = resp.json::<serde_json::Value>().await.ok()?;
+    let data = v.get("data")?.as_array()?;
+    Some(EngineModels {
+        // Base model and LoRA adapters all report the same window.
+        max_model_len: data
+            .iter()
+            .find_map(|m| m.get("max_model_len")?.as_u64())
+            .map(|len| len.min(u32::MAX as u64) as u32),
+        lora_adapters: data
+            .iter()
+            .filter_map(|m| m.get("id")?.as_str())
+            .filter(|id| *id != engine_model)
+            .map(str::to_string)
+            .collect(),
+    })
+}
+
+/// Effective context window of a started engine: the `max_model_len` it
+/// reports on `/v1/models` (vLLM), else the value it was configured with.
+fn detect_max_model_len(
+    reported: Option<&EngineModels>,
+    extra_args: Option<&[String]>,
+    configured: Option<u32>,
+) -> Option<u32> {
+    reported
+        .and_then(|m| m.max_model_len)
+        .or_else(|| {
+            extra_args.and_then(|a| {
+                flag_value(a, "--max-model-len").or_else(|| flag_value(a, "--context-length"))
+            })
+        })
+        .or(configured)
+}
+
 async fn mark_request_failed(store: &EtcdMetaStore, request_id: &str, reason: String) {
     let key = format!("/model_requests/{request_id}");
     let loaded = store.get(&key).await;
@@ -72,6 +144,7 @@ pub async fn reconcile_model(
         None => {
             if let Some(mut rm) = running.remove(model_uid) {
                 tracing::info!(%model_uid, "stopping engine");
+                rm.grpc_shim = None;
                 rm.engine.stop(&mut rm.handle).await?;
                 let _ = delete_endpoint(store, &rm.model_uid, rm.replica_id).await;
                 endpoint_state.lock().await.remove(model_uid);
@@ -85,6 +158,7 @@ pub async fn reconcile_model(
     let Some(assignment) = desired else {
         if let Some(mut rm) = running.remove(model_uid) {
             tracing::info!(%model_uid, "no longer assigned, stopping engine");
+            rm.grpc_shim = None;
             rm.engine.stop(&mut rm.handle).await?;
             let _ = delete_endpoint(store, &rm.model_uid, rm.replica_id).await;
             endpoint_state.lock().await.remove(model_uid);
@@ -104,6 +178,7 @@ pub async fn reconcile_model(
 
     if let Some(mut rm) = running.remove(model_uid) {
         tracing::info!(%model_uid, "restarting engine due to placement update");
+        rm.grpc_shim = None;
         rm.engine.stop(&mut rm.handle).await?;
         let _ = delete_endpoint(store, &rm.model_uid, rm.replica_id).await;
         endpoint_state.lock().await.remove(model_uid);
@@ -200,17 +275,58 @@ pub async fn reconcile_model(
 
     write_engine_env(&args.engine_env_path, &handle.base_url, &handle.engine_model).await?;
 
+    let configured_max_model_len = if engine.engine_type() == "vllm" {
+        args.vllm_max_model_len
+    } else {
+        None
+    };
+    let reported = probe_engine_models(&reqwest::Client::new(), &handle.base_url, &handle.engine_model).await;
+    let max_model_len = detect_max_model_len(
+        reported.as_ref(),
+        assignment.extra_args.as_deref(),
+        configured_max_model_len,
+    );
+    // Adapters the engine already serves, else the ones the plan asks it to load.
+    let lora_adapters = reported
+        .map(|m| m.lora_adapters)
+        .filter(|a| !a.is_empty())
+        .unwrap_or_else(|| assignment.lora_adapter_names());
+
+    let (grpc_shim, grpc_target) = if args.grpc_shim {
+        let start_port = assignment.port.saturating_add(args.grpc_shim_port_offset);
+        match start_grpc_shim(&handle.base_url, &handle.engine_model, start_port).await {
+            Ok(shim) => {
+                let target = grpc_target_for(&handle.base_url, shim.port);
+                tracing::info!(%model_uid, port=shim.port, "started grpc shim");
+                (Some(shim), target)
+            }
+            Err(e) => {
+                tracing::error!(%model_uid, error=%e, "failed to start grpc shim, registering native http endpoint");
+                (None, None)
+            }
+        }
+    } else {
+        (None, None)
+    };
+
     let info = EndpointInfo {
         model_uid: plan.model_uid.clone(),
         replica_id: assignment.replica_id,
         plan_version: plan.version,
         node_id: args.node_id.clone(),
-        endpoint_kind: EndpointKind::NativeHttp,
+        endpoint_kind: if grpc_target.is_some() {
+            EndpointKind::GrpcShim
+        } else {
+            EndpointKind::NativeHttp
+        },
         api_flavor: "openai".to_string(),
         status: EndpointStatus::Ready,
         last_heartbeat_ms: now_ms(),
-        grpc_target: None,
+        grpc_target,
         base_url: Some(handle.base_url.clone()),
+        max_model_len,
+        lora_adapters,
+        role: assignment.role.unwrap_or_default(),
     };
 
     register_endpoint(store, &info, args.heartbeat_ttl_ms).await?;
@@ -228,6 +344,7 @@ pub async fn reconcile_model(
             assignment_signature: desired_signature,
             handle,
             engine,
+            grpc_shim,
         },
     );
 
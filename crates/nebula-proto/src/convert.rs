@@ -0,0 +1,825 @@
+//! Translation between OpenAI-compatible JSON bodies and `InferenceService` messages.
+//!
+//! The router converts incoming OpenAI requests into proto requests and proto
+//! responses back into OpenAI JSON; the node shim does the reverse against the
+//! engine's native HTTP API. Fields without a typed proto counterpart travel in
+//! the `extra_json` objects so that nothing the client sent or the engine
+//! returned (e.g. `logprobs`, `reasoning_content`) is silently dropped.
+
+use serde_json::{json, Map, Value};
+
+use crate::inference::{
+    ChatMessage, Choice, Embedding, EmbeddingsRequest, EmbeddingsResponse, GenerateChunk,
+    GenerateKind, GenerateRequest, GenerateResponse, SamplingParams, Usage,
+};
+
+/// Request fields carried by typed proto fields (or implied by the RPC itself).
+const GENERATE_TYPED_FIELDS: &[&str] = &[
+    "model",
+    "messages",
+    "prompt",
+    "stream",
+    "temperature",
+    "top_p",
+    "max_tokens",
+    "stop",
+    "seed",
+    "presence_penalty",
+    "frequency_penalty",
+];
+const MESSAGE_TYPED_FIELDS: &[&str] = &["role", "content"];
+/// Response, choice and assistant message (or delta) fields with typed counterparts.
+const RESPONSE_TYPED_FIELDS: &[&str] = &["id", "object", "created", "model", "choices", "usage"];
+const CHOICE_TYPED_FIELDS: &[&str] = &["index", "text", "message", "delta", "finish_reason"];
+const ASSISTANT_TYPED_FIELDS: &[&str] = &["role", "content", "tool_calls"];
+const EMBEDDINGS_TYPED_FIELDS: &[&str] = &["model", "input"];
+
+impl GenerateKind {
+    /// Map an OpenAI API path to the generate kind served over gRPC, if any.
+    pub fn from_path(path: &str) -> Option<Self> {
+        match path {
+            "/v1/chat/completions" => Some(Self::Chat),
+            "/v1/completions" => Some(Self::Completion),
+            _ => None,
+        }
+    }
+
+    /// OpenAI API path the engine serves this kind on.
+    pub fn path(self) -> &'static str {
+        match self {
+            Self::Completion => "/v1/completions",
+            Self::Chat | Self::Unspecified => "/v1/chat/completions",
+        }
+    }
+}
+
+fn extra_fields(obj: &Map<String, Value>, typed: &[&str]) -> String {
+    let extra: Map<String, Value> = obj
+        .iter()
+        .filter(|(k, _)| !typed.contains(&k.as_str()))
+        .map(|(k, v)| (k.clone(), v.clone()))
+        .collect();
+    if extra.is_empty() {
+        String::new()
+    } else {
+        Value::Object(extra).to_string()
+    }
+}
+
+/// [`extra_fields`] of a nested object such as a choice's `message`.
+fn nested_extra_fields(v: Option<&Value>, typed: &[&str]) -> String {
+    v.and_then(Value::as_object)
+        .map(|obj| extra_fields(obj, typed))
+        .unwrap_or_default()
+}
+
+fn merge_extra(obj: &mut Map<String, Value>, extra_json: &str) -> Result<(), String> {
+    if extra_json.is_empty() {
+        return Ok(());
+    }
+    match serde_json::from_str::<Value>(extra_json) {
+        Ok(Value::Object(extra)) => {
+            for (k, v) in extra {
+                obj.entry(k).or_insert(v);
+            }
+            Ok(())
+        }
+        Ok(_) => Err("extra_json must be a JSON object".to_string()),
+        Err(e) => Err(format!("invalid extra_json: {e}")),
+    }
+}
+
+fn parse_json_array(raw: &str) -> Option<Value> {
+    if raw.is_empty() {
+        return None;
+    }
+    serde_json::from_str::<Value>(raw).ok()
+}
+
+fn string_list(v: Option<&Value>, field: &str) -> Result<Vec<String>, String> {
+    match v {
+        None | Some(Value::Null) => Ok(Vec::new()),
+        Some(Value::String(s)) => Ok(vec![s.clone()]),
+        Some(Value::Array(items)) => items
+            .iter()
+            .map(|i| {
+                i.as_str()
+                    .map(|s| s.to_string())
+                    .ok_or_else(|| format!("'{field}' must contain only strings"))
+            })
+            .collect(),
+        Some(_) => Err(format!("'{field}' must be a string or an array of strings")),
+    }
+}
+
+fn chat_message_from_openai(m: &Value) -> Result<ChatMessage, String> {
+    let obj = m
+        .as_object()
+        .ok_or_else(|| "each message must be a JSON object".to_string())?;
+    let role = obj
+        .get("role")
+        .and_then(Value::as_str)
+        .ok_or_else(|| "message 'role' must be a string".to_string())?
+        .to_string();
+    let (content, content_json) = match obj.get("content") {
+        Some(Value::String(s)) => (s.clone(), String::new()),
+        None => (String::new(), String::new()),
+        Some(other) => (String::new(), other.to_string()),
+    };
+    Ok(ChatMessage {
+        role,
+        content,
+        content_json,
+        extra_json: extra_fields(obj, MESSAGE_TYPED_FIELDS),
+    })
+}
+
+fn openai_message_from_chat(m: &ChatMessage) -> Result<Value, String> {
+    let mut obj = Map::new();
+    obj.insert("role".to_string(), json!(m.role));
+    let content = match parse_json_array(&m.content_json) {
+        Some(v) => v,
+        None => json!(m.content),
+    };
+    obj.insert("content".to_string(), content);
+    merge_extra(&mut obj, &m.extra_json)?;
+    Ok(Value::Object(obj))
+}
+
+fn usage_from_openai(v: Option<&Value>) -> Option<Usage> {
+    let u = v?.as_object()?;
+    let field = |k: &str| u.get(k).and_then(Value::as_u64).unwrap_or(0) as u32;
+    Some(Usage {
+        prompt_tokens: field("prompt_tokens"),
+        completion_tokens: field("completion_tokens"),
+        total_tokens: field("total_tokens"),
+    })
+}
+
+fn openai_usage(u: &Usage) -> Value {
+    json!({
+        "prompt_tokens": u.prompt_tokens,
+        "completion_tokens": u.completion_tokens,
+        "total_tokens": u.total_tokens,
+    })
+}
+
+/// Adds the fields of an `extra_json` object that `obj` doesn't already have.
+/// Engine output is passed through as is, so malformed extras are skipped.
+fn with_extra(obj: &mut Value, extra_json: &str) {
+    if let Some(obj) = obj.as_object_mut() {
+        let _ = merge_extra(obj, extra_json);
+    }
+}
+
+/// Text completion choices always carry `logprobs`, null when not requested.
+fn with_logprobs(choice: &mut Value) {
+    if let Some(choice) = choice.as_object_mut() {
+        choice.entry("logprobs").or_insert(Value::Null);
+    }
+}
+
+fn finish_reason_value(reason: &str) -> Value {
+    if reason.is_empty() {
+        Value::Null
+    } else {
+        json!(reason)
+    }
+}
+
+/// Build a `GenerateRequest` from an OpenAI chat/completions request body.
+pub fn generate_request_from_openai(
+    kind: GenerateKind,
+    request_id: &str,
+    body: &Value,
+) -> Result<GenerateRequest, String> {
+    let obj = body
+        .as_object()
+        .ok_or_else(|| "request body must be a JSON object".to_string())?;
+
+    let mut messages = Vec::new();
+    let mut prompt = String::new();
+    match kind {
+        GenerateKind::Chat => {
+            let raw = obj
+                .get("messages")
+                .and_then(Value::as_array)
+                .ok_or_else(|| "'messages' must be an array".to_string())?;
+            for m in raw {
+                messages.push(chat_message_from_openai(m)?);
+            }
+        }
+        GenerateKind::Completion => {
+            let mut prompts = string_list(obj.get("prompt"), "prompt")?;
+            if prompts.len() != 1 {
+                return Err("'prompt' must be a single string".to_string());
+            }
+            prompt = prompts.remove(0);
+        }
+        GenerateKind::Unspecified => return Err("unspecified generate kind".to_string()),
+    }
+
+    let sampling = SamplingParams {
+        temperature: obj.get("temperature").and_then(Value::as_f64),
+        top_p: obj.get("top_p").and_then(Value::as_f64),
+        max_tokens: obj
+            .get("max_tokens")
+            .and_then(Value::as_u64)
+            .map(|v| v.min(u32::MAX as u64) as u32),
+        stop: string_list(obj.get("stop"), "stop")?,
+        seed: obj.get("seed").and_then(Value::as_i64),
+        presence_penalty: obj.get("presence_penalty").and_then(Value::as_f64),
+        frequency_penalty: obj.get("frequency_penalty").and_then(Value::as_f64),
+    };
+
+    Ok(GenerateRequest {
+        request_id: request_id.to_string(),
+        model: obj
+            .get("model")
+            .and_then(Value::as_str)
+            .unwrap_or_default()
+            .to_string(),
+        kind: kind as i32,
+        messages,
+        prompt,
+        sampling: Some(sampling),
+        extra_json: extra_fields(obj, GENERATE_TYPED_FIELDS),
+    })
+}
+
+/// Rebuild the OpenAI request body for a `GenerateRequest` (node shim side).
+pub fn openai_json_from_generate_request(
+    req: &GenerateRequest,
+    stream: bool,
+) -> Result<Value, String> {
+    let mut obj = Map::new();
+    obj.insert("model".to_string(), json!(req.model));
+    match req.kind() {
+        GenerateKind::Chat => {
+            let messages = req
+                .messages
+                .iter()
+                .map(openai_message_from_chat)
+                .collect::<Result<Vec<_>, _>>()?;
+            obj.insert("messages".to_string(), Value::Array(messages));
+        }
+        GenerateKind::Completion => {
+            obj.insert("prompt".to_string(), json!(req.prompt));
+        }
+        GenerateKind::Unspecified => return Err("unspecified generate kind".to_string()),
+    }
+
+    if let Some(s) = req.sampling.as_ref() {
+        if let Some(v) = s.temperature {
+            obj.insert("temperature".to_string(), json!(v));
+        }
+        if let Some(v) = s.top_p {
+            obj.insert("top_p".to_string(), json!(v));
+        }
+        if let Some(v) = s.max_tokens {
+            obj.insert("max_tokens".to_string(), json!(v));
+        }
+        if !s.stop.is_empty() {
+            obj.insert("stop".to_string(), json!(s.stop));
+        }
+        if let Some(v) = s.seed {
+            obj.insert("seed".to_string(), json!(v));
+        }
+        if let Some(v) = s.presence_penalty {
+            obj.insert("presence_penalty".to_string(), json!(v));
+        }
+        if let Some(v) = s.frequency_penalty {
+            obj.insert("frequency_penalty".to_string(), json!(v));
+        }
+    }
+    obj.insert("stream".to_string(), json!(stream));
+    merge_extra(&mut obj, &req.extra_json)?;
+    Ok(Value::Object(obj))
+}
+
+/// Convert a non-streaming OpenAI chat/completions response into a `GenerateResponse`.
+pub fn generate_response_from_openai(kind: GenerateKind, body: &Value) -> GenerateResponse {
+    let choices = body
+        .get("choices")
+        .and_then(Value::as_array)
+        .map(|items| {
+            items
+                .iter()
+                .enumerate()
+                .map(|(i, c)| {
+                    let (text, tool_calls, message) = match kind {
+                        GenerateKind::Completion => (c.get("text"), None, None),
+                        _ => {
+                            let msg = c.get("message");
+                            (
+                                msg.and_then(|m| m.get("content")),
+                                msg.and_then(|m| m.get("tool_calls")),
+                                msg,
+                            )
+                        }
+                    };
+                    Choice {
+                        index: c
+                            .get("index")
+                            .and_then(Value::as_u64)
+                            .unwrap_or(i as u64) as u32,
+                        text: text.and_then(Value::as_str).unwrap_or_default().to_string(),
+                        finish_reason: c
+                            .get("finish_reason")
+                            .and_then(Value::as_str)
+                            .unwrap_or_default()
+                            .to_string(),
+                        tool_calls_json: tool_calls
+                            .filter(|v| !v.is_null())
+                            .map(|v| v.to_string())
+                            .unwrap_or_default(),
+                        extra_json: nested_extra_fields(Some(c), CHOICE_TYPED_FIELDS),
+                        message_extra_json: nested_extra_fields(message, ASSISTANT_TYPED_FIELDS),
+                    }
+                })
+                .collect()
+        })
+        .unwrap_or_default();
+
+    GenerateResponse {
+        id: body
+            .get("id")
+            .and_then(Value::as_str)
+            .unwrap_or_default()
+            .to_string(),
+        model: body
+            .get("model")
+            .and_then(Value::as_str)
+            .unwrap_or_default()
+            .to_string(),
+        created: body.get("created").and_then(Value::as_u64).unwrap_or(0),
+        choices,
+        usage: usage_from_openai(body.get("usage")),
+        extra_json: nested_extra_fields(Some(body), RESPONSE_TYPED_FIELDS),
+    }
+}
+
+/// Render a `GenerateResponse` as an OpenAI `chat.completion` / `text_completion` object.
+pub fn openai_json_from_generate_response(kind: GenerateKind, resp: &GenerateResponse) -> Value {
+    let choices: Vec<Value> = resp
+        .choices
+        .iter()
+        .map(|c| {
+            let mut choice = match kind {
+                GenerateKind::Completion => json!({
+                    "index": c.index,
+                    "text": c.text,
+                    "finish_reason": finish_reason_value(&c.finish_reason),
+                }),
+                _ => {
+                    let tool_calls = parse_json_array(&c.tool_calls_json);
+                    let mut message = json!({ "role": "assistant", "content": c.text });
+                    if let Some(tc) = tool_calls {
+                        if c.text.is_empty() {
+                            message["content"] = Value::Null;
+                        }
+                        message["tool_calls"] = tc;
+                    }
+                    with_extra(&mut message, &c.message_extra_json);
+                    json!({
+                        "index": c.index,
+                        "message": message,
+                        "finish_reason": finish_reason_value(&c.finish_reason),
+                    })
+                }
+            };
+            with_extra(&mut choice, &c.extra_json);
+            if kind == GenerateKind::Completion {
+                with_logprobs(&mut choice);
+            }
+            choice
+        })
+        .collect();
+
+    let object = match kind {
+        GenerateKind::Completion => "text_completion",
+        _ => "chat.completion",
+    };
+    let mut out = json!({
+        "id": resp.id,
+        "object": object,
+        "created": resp.created,
+        "model": resp.model,
+        "choices": choices,
+    });
+    if let Some(u) = resp.usage.as_ref() {
+        out["usage"] = openai_usage(u);
+    }
+    with_extra(&mut out, &resp.extra_json);
+    out
+}
+
+/// Split one OpenAI streaming chunk into per-choice `GenerateChunk`s.
+/// A usage-only chunk (empty `choices`) yields a single chunk carrying the usage.
+pub fn generate_chunks_from_openai(kind: GenerateKind, body: &Value) -> Vec<GenerateChunk> {
+    let id = body
+        .get("id")
+        .and_then(Value::as_str)
+        .unwrap_or_default()
+        .to_string();
+    let model = body
+        .get("model")
+        .and_then(Value::as_str)
+        .unwrap_or_default()
+        .to_string();
+    let created = body.get("created").and_then(Value::as_u64).unwrap_or(0);
+    let usage = usage_from_openai(body.get("usage"));
+    let extra_json = nested_extra_fields(Some(body), RESPONSE_TYPED_FIELDS);
+
+    let choices = body
+        .get("choices")
+        .and_then(Value::as_array)
+        .cloned()
+        .unwrap_or_default();
+
+    if choices.is_empty() {
+        if usage.is_none() {
+            return Vec::new();
+        }
+        return vec![GenerateChunk {
+            id,
+            model,
+            created,
+            usage,
+            extra_json,
+            ..Default::default()
+        }];
+    }
+
+    let last = choices.len() - 1;
+    choices
+        .iter()
+        .enumerate()
+        .map(|(i, c)| {
+            let (delta, tool_calls, delta_obj) = match kind {
+                GenerateKind::Completion => (c.get("text"), None, None),
+                _ => {
+                    let d = c.get("delta");
+                    (
+                        d.and_then(|d| d.get("content")),
+                        d.and_then(|d| d.get("tool_calls")),
+                        d,
+                    )
+                }
+            };
+            GenerateChunk {
+                id: id.clone(),
+                model: model.clone(),
+                created,
+                index: c.get("index").and_then(Value::as_u64).unwrap_or(i as u64) as u32,
+                delta: delta.and_then(Value::as_str).unwrap_or_default().to_string(),
+                finish_reason: c
+                    .get("finish_reason")
+                    .and_then(Value::as_str)
+                    .unwrap_or_default()
+                    .to_string(),
+                tool_calls_json: tool_calls
+                    .filter(|v| !v.is_null())
+                    .map(|v| v.to_string())
+                    .unwrap_or_default(),
+                usage: if i == last { usage } else { None },
+                extra_json: extra_json.clone(),
+                choice_extra_json: nested_extra_fields(Some(c), CHOICE_TYPED_FIELDS),
+                delta_extra_json: nested_extra_fields(delta_obj, ASSISTANT_TYPED_FIELDS),
+            }
+        })
+        .collect()
+}
+
+/// Render a `GenerateChunk` as an OpenAI streaming chunk object.
+/// `include_role` marks the first chat delta of a choice, which carries `role: assistant`.
+pub fn openai_chunk_from_generate_chunk(
+    kind: GenerateKind,
+    chunk: &GenerateChunk,
+    include_role: bool,
+) -> Value {
+    let usage_only = chunk.usage.is_some()
+        && chunk.delta.is_empty()
+        && chunk.finish_reason.is_empty()
+        && chunk.tool_calls_json.is_empty()
+        && chunk.choice_extra_json.is_empty()
+        && chunk.delta_extra_json.is_empty();
+
+    let choices = if usage_only {
+        Vec::new()
+    } else {
+        let mut choice = match kind {
+            GenerateKind::Completion => json!({
+                "index": chunk.index,
+                "text": chunk.delta,
+                "finish_reason": finish_reason_value(&chunk.finish_reason),
+            }),
+            _ => {
+                let mut delta = Map::new();
+                if include_role {
+                    delta.insert("role".to_string(), json!("assistant"));
+                }
+                // Reasoning and tool call deltas carry no content.
+                if !chunk.delta.is_empty()
+                    || (chunk.tool_calls_json.is_empty() && chunk.delta_extra_json.is_empty())
+                {
+                    delta.insert("content".to_string(), json!(chunk.delta));
+                }
+                if let Some(tc) = parse_json_array(&chunk.tool_calls_json) {
+                    delta.insert("tool_calls".to_string(), tc);
+                }
+                let mut delta = Value::Object(delta);
+                with_extra(&mut delta, &chunk.delta_extra_json);
+                json!({
+                    "index": chunk.index,
+                    "delta": delta,
+                    "finish_reason": finish_reason_value(&chunk.finish_reason),
+                })
+            }
+        };
+        with_extra(&mut choice, &chunk.choice_extra_json);
+        if kind == GenerateKind::Completion {
+            with_logprobs(&mut choice);
+        }
+        vec![choice]
+    };
+
+    let object = match kind {
+        GenerateKind::Completion => "text_completion",
+        _ => "chat.completion.chunk",
+    };
+    let mut out = json!({
+        "id": chunk.id,
+        "object": object,
+        "created": chunk.created,
+        "model": chunk.model,
+        "choices": choices,
+    });
+    if let Some(u) = chunk.usage.as_ref() {
+        out["usage"] = openai_usage(u);
+    }
+    with_extra(&mut out, &chunk.extra_json);
+    out
+}
+
+/// Build an `EmbeddingsRequest` from an OpenAI embeddings request body.
+/// Token-id inputs and base64 output are not representable and are rejected.
+pub fn embeddings_request_from_openai(
+    request_id: &str,
+    body: &Value,
+) -> Result<EmbeddingsRequest, String> {
+    let obj = body
+        .as_object()
+        .ok_or_else(|| "request body must be a JSON object".to_string())?;
+    if obj.get("encoding_format").and_then(Value::as_str) == Some("base64") {
+        return Err("base64 encoding_format is not supported over gRPC".to_string());
+    }
+    let input = string_list(obj.get("input"), "input")?;
+    if input.is_empty() {
+        return Err("'input' must not be empty".to_string());
+    }
+    Ok(EmbeddingsRequest {
+        request_id: request_id.to_string(),
+        model: obj
+            .get("model")
+            .and_then(Value::as_str)
+            .unwrap_or_default()
+            .to_string(),
+        input,
+        extra_json: extra_fields(obj, EMBEDDINGS_TYPED_FIELDS),
+    })
+}
+
+/// Rebuild the OpenAI embeddings request body for an `EmbeddingsRequest` (node shim side).
+pub fn openai_json_from_embeddings_request(req: &EmbeddingsRequest) -> Result<Value, String> {
+    let mut obj = Map::new();
+    obj.insert("model".to_string(), json!(req.model));
+    obj.insert("input".to_string(), json!(req.input));
+    merge_extra(&mut obj, &req.extra_json)?;
+    Ok(Value::Object(obj))
+}
+
+/// Convert an OpenAI embeddings response into an `EmbeddingsResponse`.
+pub fn embeddings_response_from_openai(body: &Value) -> Result<EmbeddingsResponse, String> {
+    let items = body
+        .get("data")
+        .and_then(Value::as_array)
+        .ok_or_else(|| "embeddings response missing 'data'".to_string())?;
+    let mut data = Vec::with_capacity(items.len());
+    for (i, item) in items.iter().enumerate() {
+        let values = item
+            .get("embedding")
+            .and_then(Value::as_array)
+            .ok_or_else(|| "embedding must be an array of floats".to_string())?
+            .iter()
+            .map(|v| v.as_f64().unwrap_or(0.0) as f32)
+            .collect();
+        data.push(Embedding {
+            index: item
+                .get("index")
+                .and_then(Value::as_u64)
+                .unwrap_or(i as u64) as u32,
+            values,
+        });
+    }
+    Ok(EmbeddingsResponse {
+        model: body
+            .get("model")
+            .and_then(Value::as_str)
+            .unwrap_or_default()
+            .to_string(),
+        data,
+        usage: usage_from_openai(body.get("usage")),
+    })
+}
+
+/// Render an `EmbeddingsResponse` as an OpenAI embeddings list object.
+pub fn openai_json_from_embeddings_response(resp: &EmbeddingsResponse) -> Value {
+    let data: Vec<Value> = resp
+        .data
+        .iter()
+        .map(|e| json!({ "object": "embedding", "index": e.index, "embedding": e.values }))
+        .collect();
+    let mut out = json!({
+        "object": "list",
+        "model": resp.model,
+        "data": data,
+    });
+    if let Some(u) = resp.usage.as_ref() {
+        out["usage"] = json!({
+            "prompt_tokens": u.prompt_tokens,
+            "total_tokens": u.total_tokens,
+        });
+    }
+    out
+}
+
+/// Map an engine HTTP status to the gRPC code the shim reports it as.
+pub fn code_from_http_status(status: u16) -> tonic::Code {
+    match status {
+        400 | 422 => tonic::Code::InvalidArgument,
+        401 => tonic::Code::Unauthenticated,
+        403 => tonic::Code::PermissionDenied,
+        404 => tonic::Code::NotFound,
+        408 | 504 => tonic::Code::DeadlineExceeded,
+        413 => tonic::Code::OutOfRange,
+        429 => tonic::Code::ResourceExhausted,
+        501 => tonic::Code::Unimplemented,
+        502 | 503 => tonic::Code::Unavailable,
+        _ => tonic::Code::Internal,
+    }
+}
+
+/// Map a gRPC code from the shim back to the HTTP status returned to clients.
+pub fn http_status_from_code(code: tonic::Code) -> u16 {
+    match code {
+        tonic::Code::Ok => 200,
+        tonic::Code::InvalidArgument | tonic::Code::FailedPrecondition => 400,
+        tonic::Code::Unauthenticated => 401,
+        tonic::Code::PermissionDenied => 403,
+        tonic::Code::NotFound => 404,
+        tonic::Code::OutOfRange => 413,
+        tonic::Code::ResourceExhausted => 429,
+        tonic::Code::Cancelled => 499,
+        tonic::Code::Unimplemented => 501,
+        tonic::Code::Unavailable => 503,
+        tonic::Code::DeadlineExceeded => 504,
+        _ => 500,
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_chat_request_round_trip() {
+        let body = json!({
+            "model": "qwen",
+            "messages": [
+                {"role": "system", "content": "be brief"},
+                {"role": "user", "content": [{"type": "text", "text": "hi"}]},
+                {"role": "assistant", "content": null, "tool_calls": [{"id": "c1"}]},
+            ],
+            "temperature": 0.2,
+            "max_tokens": 64,
+            "stop": "\n",
+            "stream": true,
+            "stream_options": {"include_usage": true},
+        });
+
+        let req = generate_request_from_openai(GenerateKind::Chat, "req_1", &body).unwrap();
+        assert_eq!(req.messages.len(), 3);
+        assert_eq!(req.sampling.as_ref().unwrap().stop, vec!["\n".to_string()]);
+        assert!(req.extra_json.contains("stream_options"));
+        assert!(!req.extra_json.contains("\"stream\":"));
+
+        let rebuilt = openai_json_from_generate_request(&req, true).unwrap();
+        assert_eq!(rebuilt["messages"], body["messages"]);
+        assert_eq!(rebuilt["temperature"], json!(0.2));
+        assert_eq!(rebuilt["max_tokens"], json!(64));
+        assert_eq!(rebuilt["stop"], json!(["\n"]));
+        assert_eq!(rebuilt["stream"], json!(true));
+        assert_eq!(rebuilt["stream_options"], json!({"include_usage": true}));
+    }
+
+    #[test]
+    fn test_response_round_trip() {
+        let body = json!({
+            "id": "chatcmpl-1",
+            "object": "chat.completion",
+            "created": 7,
+            "model": "qwen",
+            "system_fingerprint": "fp_1",
+            "choices": [{
+                "index": 0,
+                "message": {"role": "assistant", "content": "hi", "reasoning_content": "greet"},
+                "logprobs": {"content": [{"token": "hi", "logprob": -0.1}]},
+                "finish_reason": "stop",
+            }],
+            "usage": {"prompt_tokens": 3, "completion_tokens": 1, "total_tokens": 4},
+        });
+        let resp = generate_response_from_openai(GenerateKind::Chat, &body);
+        assert_eq!(openai_json_from_generate_response(GenerateKind::Chat, &resp), body);
+
+        let body = json!({
+            "id": "cmpl-1",
+            "object": "text_completion",
+            "created": 7,
+            "model": "qwen",
+            "choices": [{"index": 0, "text": " there", "logprobs": null, "finish_reason": "length"}],
+        });
+        let resp = generate_response_from_openai(GenerateKind::Completion, &body);
+        assert_eq!(openai_json_from_generate_response(GenerateKind::Completion, &resp), body);
+
+        let chunk = json!({
+            "id": "chatcmpl-1",
+            "object": "chat.completion.chunk",
+            "created": 7,
+            "model": "qwen",
+            "system_fingerprint": "fp_1",
+            "choices": [{
+                "index": 0,
+                "delta": {"reasoning_content": "hm"},
+                "logprobs": null,
+                "finish_reason": null,
+            }],
+        });
+        let chunks = generate_chunks_from_openai(GenerateKind::Chat, &chunk);
+        assert_eq!(openai_chunk_from_generate_chunk(GenerateKind::Chat, &chunks[0], false), chunk);
+    }
+
+    #[test]
+    fn test_completion_requires_single_prompt() {
+        let body = json!({"model": "m", "prompt": ["a", "b"]});
+        assert!(generate_request_from_openai(GenerateKind::Completion, "r", &body).is_err());
+
+        let body = json!({"model": "m", "prompt": "once upon"});
+        let req = generate_request_from_openai(GenerateKind::Completion, "r", &body).unwrap();
+        assert_eq!(req.prompt, "once upon");
+    }
+
+    #[test]
+    fn test_chunk_round_trip_with_role_and_usage() {
+        let upstream = json!({
+            "id": "chatcmpl-1",
+            "model": "qwen",
+            "created": 7,
+            "choices": [{"index": 0, "delta": {"content": "Hel"}, "finish_reason": null}],
+        });
+        let chunks = generate_chunks_from_openai(GenerateKind::Chat, &upstream);
+        assert_eq!(chunks.len(), 1);
+        let out = openai_chunk_from_generate_chunk(GenerateKind::Chat, &chunks[0], true);
+        assert_eq!(out["object"], json!("chat.completion.chunk"));
+        assert_eq!(out["choices"][0]["delta"]["role"], json!("assistant"));
+        assert_eq!(out["choices"][0]["delta"]["content"], json!("Hel"));
+        assert_eq!(out["choices"][0]["finish_reason"], Value::Null);
+
+        let usage_only = json!({
+            "id": "chatcmpl-1",
+            "model": "qwen",
+            "created": 7,
+            "choices": [],
+            "usage": {"prompt_tokens": 3, "completion_tokens": 2, "total_tokens": 5},
+        });
+        let chunks = generate_chunks_from_openai(GenerateKind::Chat, &usage_only);
+        let out = openai_chunk_from_generate_chunk(GenerateKind::Chat, &chunks[0], false);
+        assert_eq!(out["choices"], json!([]));
+        assert_eq!(out["usage"]["total_tokens"], json!(5));
+    }
+
+    #[test]
+    fn test_embeddings_rejects_unsupported_inputs() {
+        let tokens = json!({"model": "e", "input": [[1, 2, 3]]});
+        assert!(embeddings_request_from_openai("r", &tokens).is_err());
+
+        let b64 = json!({"model": "e", "input": "x", "encoding_format": "base64"});
+        assert!(embeddings_request_from_openai("r", &b64).is_err());
+
+        let ok = json!({"model": "e", "input": "x", "dimensions": 8});
+        let req = embeddings_request_from_openai("r", &ok).unwrap();
+        let rebuilt = openai_json_from_embeddings_request(&req).unwrap();
+        assert_eq!(rebuilt["input"], json!(["x"]));
+        assert_eq!(rebuilt["dimensions"], json!(8));
+    }
+}
@@ -0,0 +1,8 @@
+pub mod convert;
+
+pub mod inference {
+    tonic::include_proto!("nebula.inference.v1");
+}
+
+pub use inference::inference_service_client::InferenceServiceClient;
+pub use inference::inference_service_server::{InferenceService, InferenceServiceServer};
@@ -0,0 +1,274 @@
+use std::collections::HashSet;
+use std::convert::Infallible;
+use std::time::{Duration, Instant};
+
+use axum::{
+    body::Body,
+    http::StatusCode,
+    response::{IntoResponse, Response},
+};
+use bytes::Bytes;
+use dashmap::DashMap;
+use tokio_stream::wrappers::ReceiverStream;
+use tonic::transport::{Channel, Endpoint};
+
+use nebula_common::usage::is_usage_only_chunk;
+use nebula_common::{ApiError, EndpointInfo, EndpointKind, ErrorCode};
+use nebula_proto::convert::{
+    embeddings_request_from_openai, generate_request_from_openai, http_status_from_code,
+    openai_chunk_from_generate_chunk, openai_json_from_embeddings_response,
+    openai_json_from_generate_response,
+};
+use nebula_proto::inference::GenerateKind;
+use nebula_proto::InferenceServiceClient;
+
+use crate::state::AppState;
+
+/// Lazily connected channels to node gRPC shims, keyed by `grpc_target`.
+#[derive(Default)]
+pub struct GrpcClients {
+    channels: DashMap<String, Channel>,
+}
+
+impl GrpcClients {
+    pub fn client(
+        &self,
+        target: &str,
+    ) -> Result<InferenceServiceClient<Channel>, tonic::transport::Error> {
+        if let Some(ch) = self.channels.get(target) {
+            return Ok(InferenceServiceClient::new(ch.clone()));
+        }
+        let uri = if target.contains("://") {
+            target.to_string()
+        } else {
+            format!("http://{target}")
+        };
+        let ch = Endpoint::from_shared(uri)?
+            .connect_timeout(Duration::from_secs(3))
+            .timeout(Duration::from_secs(300))
+            .tcp_nodelay(true)
+            .connect_lazy();
+        self.channels.insert(target.to_string(), ch.clone());
+        Ok(InferenceServiceClient::new(ch))
+    }
+}
+
+#[derive(Debug, Clone, Copy)]
+pub enum GrpcCall {
+    Generate(GenerateKind),
+    Embeddings,
+}
+
+pub enum GrpcOutcome {
+    /// Response ready for the client (success or a non-retryable upstream error).
+    Done(Response),
+    /// Shim unreachable or engine-side server error; the caller may retry elsewhere.
+    Retryable(tonic::Status),
+    /// The request can't be expressed over gRPC; the caller should use the HTTP path.
+    Unsupported(String),
+}
+
+/// Returns the shim target and RPC for `path` when `ep` should be reached over gRPC.
+pub fn grpc_call_for<'a>(ep: &'a EndpointInfo, path: &str) -> Option<(&'a str, GrpcCall)> {
+    if ep.endpoint_kind != EndpointKind::GrpcShim {
+        return None;
+    }
+    let target = ep.grpc_target.as_deref()?;
+    let call = match path {
+        "/v1/embeddings" => GrpcCall::Embeddings,
+        p => GrpcCall::Generate(GenerateKind::from_path(p)?),
+    };
+    Some((target, call))
+}
+
+pub fn classify_status(status: &tonic::Status) -> &'static str {
+    match status.code() {
+        tonic::Code::Unavailable => "connect",
+        tonic::Code::DeadlineExceeded => "timeout",
+        _ => "upstream_5xx",
+    }
+}
+
+fn is_retryable(status: &tonic::Status) -> bool {
+    http_status_from_code(status.code()) >= 500
+}
+
+/// Render a shim error as an HTTP response. The shim forwards the engine's error
+/// body as the status message, so JSON messages are passed through verbatim.
+pub fn status_response(status: &tonic::Status) -> Response {
+    let code = StatusCode::from_u16(http_status_from_code(status.code()))
+        .unwrap_or(StatusCode::BAD_GATEWAY);
+    match serde_json::from_str::<serde_json::Value>(status.message()) {
+        Ok(v) if v.is_object() => (code, axum::Json(v)).into_response(),
+        _ => ApiError::from_upstream(code.as_u16(), status.message().as_bytes()).into_response(),
+    }
+}
+
+fn json_response(body: serde_json::Value) -> Response {
+    (StatusCode::OK, axum::Json(body)).into_response()
+}
+
+/// One OpenAI request to be sent to a gRPC shim.
+pub struct ForwardRequest<'a> {
+    pub target: &'a str,
+    pub call: GrpcCall,
+    pub request_id: &'a str,
+    pub body: &'a [u8],
+    pub model_uid: &'a str,
+    /// Per-attempt deadline, propagated to the shim as `grpc-timeout`.
+    pub timeout: Option<Duration>,
+    /// Whether the client asked for the usage-only chunk of a stream.
+    pub client_usage: bool,
+}
+
+fn with_timeout<T>(msg: T, timeout: Option<Duration>) -> tonic::Request<T> {
+    let mut req = tonic::Request::new(msg);
+    if let Some(timeout) = timeout {
+        req.set_timeout(timeout);
+    }
+    req
+}
+
+/// Forward one OpenAI request to a gRPC shim and translate the result back.
+pub async fn forward(st: &AppState, fwd: ForwardRequest<'_>, request_start: Instant) -> GrpcOutcome {
+    let ForwardRequest {
+        target,
+        call,
+        request_id,
+        body,
+        model_uid,
+        timeout,
+        client_usage,
+    } = fwd;
+    let json = match serde_json::from_slice::<serde_json::Value>(body) {
+        Ok(v) => v,
+        Err(e) => return GrpcOutcome::Unsupported(format!("invalid json body: {e}")),
+    };
+
+    let mut client = match st.grpc.client(target) {
+        Ok(c) => c,
+        Err(e) => return GrpcOutcome::Retryable(tonic::Status::unavailable(e.to_string())),
+    };
+
+    let result = match call {
+        GrpcCall::Embeddings => {
+            let req = match embeddings_request_from_openai(request_id, &json) {
+                Ok(r) => r,
+                Err(e) => return GrpcOutcome::Unsupported(e),
+            };
+            client
+                .embeddings(with_timeout(req, timeout))
+                .await
+                .map(|resp| json_response(openai_json_from_embeddings_response(&resp.into_inner())))
+        }
+        GrpcCall::Generate(kind) => {
+            let req = match generate_request_from_openai(kind, request_id, &json) {
+                Ok(r) => r,
+                Err(e) => return GrpcOutcome::Unsupported(e),
+            };
+            let stream = json.get("stream").and_then(|v| v.as_bool()).unwrap_or(false);
+            if stream {
+                match client.generate_stream(with_timeout(req, timeout)).await {
+                    Ok(resp) => {
+                        return GrpcOutcome::Done(sse_response(
+                            st,
+                            kind,
+                            resp.into_inner(),
+                            model_uid,
+                            request_start,
+                            client_usage,
+                        ))
+                    }
+                    Err(status) => Err(status),
+                }
+            } else {
+                client.generate(with_timeout(req, timeout)).await.map(|resp| {
+                    json_response(openai_json_from_generate_response(kind, &resp.into_inner()))
+                })
+            }
+        }
+    };
+
+    let out = match result {
+        Ok(out) => out,
+        Err(status) if is_retryable(&status) => return GrpcOutcome::Retryable(status),
+        Err(status) => status_response(&status),
+    };
+    st.metrics
+        .observe_e2e_latency(model_uid, request_start.elapsed().as_secs_f64());
+    st.metrics.record_model_status(model_uid, out.status().as_u16());
+    GrpcOutcome::Done(out)
+}
+
+/// Re-encode a stream of `GenerateChunk`s as OpenAI SSE.
+fn sse_response(
+    st: &AppState,
+    kind: GenerateKind,
+    mut upstream: tonic::Streaming<nebula_proto::inference::GenerateChunk>,
+    model_uid: &str,
+    request_start: Instant,
+    client_usage: bool,
+) -> Response {
+    let (tx, rx) = tokio::sync::mpsc::channel::<Result<Bytes, Infallible>>(64);
+    let metrics = st.metrics.clone();
+    let model_uid = model_uid.to_string();
+    tokio::spawn(async move {
+        let mut first_chunk = true;
+        let mut started: HashSet<u32> = HashSet::new();
+        let mut cancelled = false;
+        loop {
+            // Dropping `upstream` on disconnect cancels the gRPC stream.
+            let message = tokio::select! {
+                message = upstream.message() => message,
+                _ = tx.closed() => {
+                    cancelled = true;
+                    break;
+                }
+            };
+            match message {
+                Ok(Some(chunk)) => {
+                    if first_chunk {
+                        first_chunk = false;
+                        metrics.observe_ttft(&model_uid, request_start.elapsed().as_secs_f64());
+                    }
+                    let include_role = started.insert(chunk.index);
+                    let data = openai_chunk_from_generate_chunk(kind, &chunk, include_role);
+                    if !client_usage && is_usage_only_chunk(&data) {
+                        continue;
+                    }
+                    if tx
+                        .send(Ok(Bytes::from(format!("data: {data}\n\n"))))
+                        .await
+                        .is_err()
+                    {
+                        cancelled = true;
+                        break;
+                    }
+                }
+                Ok(None) => {
+                    let _ = tx.send(Ok(Bytes::from_static(b"data: [DONE]\n\n"))).await;
+                    break;
+                }
+                Err(status) => {
+                    tracing::warn!(code=?status.code(), message=%status.message(), "grpc shim stream failed");
+                    let err = ApiError::new(ErrorCode::UpstreamError, status.message());
+                    let _ = tx.send(Ok(Bytes::from(err.sse_event()))).await;
+                    break;
+                }
+            }
+        }
+        drop(upstream);
+        if cancelled {
+            metrics.record_cancelled(0);
+        }
+        metrics.observe_e2e_latency(&model_uid, request_start.elapsed().as_secs_f64());
+        metrics.record_model_status(&model_uid, 200);
+    });
+
+    Response::builder()
+        .status(StatusCode::OK)
+        .header("content-type", "text/event-stream")
+        .header("cache-control", "no-cache")
+        .body(Body::from_stream(ReceiverStream::new(rx)))
+        .unwrap_or_else(|_| Response::new(Body::empty()))
+}
@@ -11,11 +11,20 @@ use futures_util::StreamExt;
 use reqwest::header::HeaderMap as ReqwestHeaderMap;
 use tokio_stream::wrappers::ReceiverStream;
 
-use nebula_common::ExecutionContext;
+use nebula_common::auth::AuthContext;
+use nebula_common::body::{rewrite_model, JsonModelScanner, ModelScan, MultipartModelScanner};
+use nebula_common::usage::{request_stream_usage, SseUsageScanner, UsageChunkFilter};
+use nebula_common::{ApiError, EndpointInfo, ErrorCode, ExecutionContext};
 
+use crate::grpc::{self, GrpcOutcome};
+use crate::hedge;
+use crate::pd;
+use crate::shadow;
 use crate::state::AppState;
+use crate::tokens::clamp_max_tokens;
+use crate::usage::UsageAccounting;
 
-fn classify_reqwest_error(error: &reqwest::Error) -> &'static str {
+pub(crate) fn classify_reqwest_error(error: &reqwest::Error) -> &'static str {
     if error.is_timeout() {
         return "timeout";
     }
@@ -29,23 +38,71 @@ pub async fn healthz() -> impl IntoResponse {
     (StatusCode::OK, "ok")
 }
 
+/// `GET /v1/models`: base models known from placements plus the LoRA adapters
+/// declared for or loaded on their replicas.
+pub async fn list_models(State(st): State<AppState>) -> impl IntoResponse {
+    let mut data: Vec<serde_json::Value> = st
+        .router
+        .list_models()
+        .into_iter()
+        .map(|(uid, name)| {
+            let mut model = serde_json::json!({ "id": name, "object": "model", "owned_by": "nebula" });
+            if let Some(policy) = st.router.request_policy(&uid) {
+                model["request_policy"] = serde_json::json!(policy.as_ref());
+            }
+            model
+        })
+        .collect();
+    for (adapter, model_uid) in st.router.list_adapters() {
+        let parent = st.router.get_model_name(&model_uid).unwrap_or(model_uid);
+        data.push(serde_json::json!({
+            "id": adapter,
+            "object": "model",
+            "owned_by": "nebula",
+            "parent": parent,
+            "root": parent,
+        }));
+    }
+    axum::Json(serde_json::json!({ "object": "list", "data": data }))
+}
+
 pub fn build_execution_context(headers: &HeaderMap) -> ExecutionContext {
     let session_id = headers
         .get("x-session-id")
         .and_then(|v| v.to_str().ok())
         .map(|s| s.to_string());
 
-    ExecutionContext {
+    let mut ctx = ExecutionContext {
         request_id: format!("req_{}", uuid::Uuid::new_v4()),
         session_id,
         tenant_id: None,
         priority: None,
         deadline_ms: None,
         budget_tokens: None,
-    }
+        required_context_tokens: None,
+        lora_adapter: None,
+    };
+    ctx.read_limit_headers(headers);
+    ctx
 }
 
-fn to_reqwest_headers(headers: &HeaderMap) -> ReqwestHeaderMap {
+/// The error for a request whose deadline has passed.
+pub(crate) fn deadline_exceeded(st: &AppState, model_uid: &str) -> Response {
+    st.metrics.record_model_status(model_uid, 504);
+    st.metrics
+        .deadline_exceeded_total
+        .fetch_add(1, std::sync::atomic::Ordering::Relaxed);
+    ApiError::new(ErrorCode::DeadlineExceeded, "request deadline exceeded").into_response()
+}
+
+/// Whether another attempt can start after `backoff_ms` and still leave time
+/// before the deadline.
+pub(crate) fn retry_fits(ctx: &ExecutionContext, backoff_ms: u64) -> bool {
+    ctx.remaining()
+        .is_none_or(|r| r > std::time::Duration::from_millis(backoff_ms))
+}
+
+pub(crate) fn to_reqwest_headers(headers: &HeaderMap) -> ReqwestHeaderMap {
     let mut out = ReqwestHeaderMap::new();
     for (k, v) in headers.iter() {
         if k.as_str().eq_ignore_ascii_case("host")
@@ -81,13 +138,96 @@ fn copy_response_headers(src: &ReqwestHeaderMap, dst: &mut Response) {
     }
 }
 
+/// Pick an endpoint for `model_uid`, honoring the placement plan version for the
+/// router's primary model. Routing failures are rendered as the client response.
+pub(crate) fn select_endpoint(
+    st: &AppState,
+    ctx: &ExecutionContext,
+    model_uid: &str,
+    plan_version: u64,
+    excluded_endpoint: Option<&(String, u32)>,
+) -> Result<EndpointInfo, Response> {
+    let ep = if model_uid == st.model_uid && plan_version > 0 {
+        if let Some((exclude_model_uid, exclude_replica_id)) = excluded_endpoint {
+            st.router.route_with_plan_version_excluding(
+                ctx,
+                model_uid,
+                plan_version,
+                (exclude_model_uid.as_str(), *exclude_replica_id),
+            )
+        } else {
+            st.router.route_with_plan_version(ctx, model_uid, plan_version)
+        }
+    } else if let Some((exclude_model_uid, exclude_replica_id)) = excluded_endpoint {
+        st.router
+            .route_excluding(ctx, model_uid, (exclude_model_uid.as_str(), *exclude_replica_id))
+    } else {
+        st.router.route(ctx, model_uid)
+    };
+
+    ep.map_err(|e| route_error_response(st, ctx, model_uid, e))
+}
+
+/// Client response for a routing failure.
+pub(crate) fn route_error_response(
+    st: &AppState,
+    ctx: &ExecutionContext,
+    model_uid: &str,
+    err: nebula_router::RouteError,
+) -> Response {
+    match err {
+        nebula_router::RouteError::Overloaded => {
+            st.metrics.record_model_status(model_uid, 429);
+            ApiError::new(
+                ErrorCode::ModelOverloaded,
+                format!("all endpoints overloaded for model '{}'", model_uid),
+            )
+            .with_retry_after(5)
+            .into_response()
+        }
+        nebula_router::RouteError::ContextLengthExceeded { max_model_len } => {
+            st.metrics
+                .context_length_rejected_total
+                .fetch_add(1, std::sync::atomic::Ordering::Relaxed);
+            st.metrics.record_model_status(model_uid, 400);
+            let requested = ctx.required_context_tokens.unwrap_or_default();
+            ApiError::new(
+                ErrorCode::ContextLengthExceeded,
+                format!(
+                    "This model's maximum context length is {max_model_len} tokens. \
+                     However, you requested about {requested} tokens (prompt plus max_tokens). \
+                     Please reduce the length of the messages or completion."
+                ),
+            )
+            .with_param("messages")
+            .into_response()
+        }
+        _ => {
+            st.metrics.record_model_status(model_uid, 503);
+            ApiError::new(
+                ErrorCode::NoReadyEndpoint,
+                format!("no ready endpoint for model '{}'", model_uid),
+            )
+            .into_response()
+        }
+    }
+}
+
 pub async fn proxy_chat_completions(
     State(st): State<AppState>,
     headers: HeaderMap,
     req: Request<Body>,
 ) -> Response {
-    let _ctx = build_execution_context(&headers);
+    let mut ctx = build_execution_context(&headers);
     let request_start = std::time::Instant::now();
+    if ctx.is_expired() {
+        return deadline_exceeded(&st, &st.model_uid);
+    }
+    let principal = req
+        .extensions()
+        .get::<AuthContext>()
+        .map(|c| c.principal_label())
+        .unwrap_or_else(|| "anonymous".to_string());
 
     let method = req.method().clone();
     let uri_path = req.uri().path().to_string();
@@ -97,106 +237,231 @@ pub async fn proxy_chat_completions(
         .map(|q| format!("?{q}"))
         .unwrap_or_default();
 
-    let (method_reqwest, body_bytes, model_uid) = match method {
-        axum::http::Method::GET => (reqwest::Method::GET, None, st.model_uid.clone()),
+    let mut client_usage = true;
+    // The JSON body is parsed once, rewritten, and reused by everything below.
+    let (method_reqwest, body_bytes, mut body_json, model_uid) = match method {
+        axum::http::Method::GET => (reqwest::Method::GET, None, None, st.model_uid.clone()),
         axum::http::Method::POST => {
-            let body_bytes = match axum::body::to_bytes(req.into_body(), st.max_request_body_bytes).await {
-                Ok(b) => b,
-                Err(_) => {
-                    st.metrics
-                        .request_too_large_total
-                        .fetch_add(1, std::sync::atomic::Ordering::Relaxed);
-                    st.metrics.record_model_status(&st.model_uid, 413);
-                    return (StatusCode::PAYLOAD_TOO_LARGE, "request body too large").into_response();
+            let body_bytes = if should_stream_body(&st, &ctx, &headers, &uri_path) {
+                match proxy_streaming_body(&st, &ctx, &headers, req, request_start).await {
+                    StreamedBody::Sent(resp) => return resp,
+                    StreamedBody::Buffered(bytes) => bytes,
+                }
+            } else {
+                match axum::body::to_bytes(req.into_body(), st.max_request_body_bytes).await {
+                    Ok(b) => b,
+                    Err(_) => return request_too_large(&st),
                 }
             };
 
-            let raw_model =
-                if let Ok(json) = serde_json::from_slice::<serde_json::Value>(&body_bytes) {
-                    json.get("model")
-                        .and_then(|m| m.as_str())
-                        .map(|s| s.to_string())
-                        .unwrap_or_else(|| st.model_uid.clone())
-                } else {
-                    st.model_uid.clone()
-                };
+            let mut json = serde_json::from_slice::<serde_json::Value>(&body_bytes).ok();
+            let raw_model = json
+                .as_ref()
+                .and_then(|j| j.get("model"))
+                .and_then(|m| m.as_str())
+                .map(|s| s.to_string())
+                .unwrap_or_else(|| st.model_uid.clone());
 
             // Resolve model_name → model_uid (or pass through if already a uid)
             let model_uid = st.router.resolve_model(&raw_model);
+            if st.router.resolve_adapter(&raw_model).is_some() {
+                ctx.lora_adapter = Some(raw_model.clone());
+            }
 
-            // Rewrite the body's "model" field using serde_json::Value
-            let body_bytes = {
-                let model_name = st.router.get_model_name(&model_uid)
-                    .unwrap_or_else(|| raw_model.clone());
-                if let Ok(mut json) = serde_json::from_slice::<serde_json::Value>(&body_bytes) {
-                    json["model"] = serde_json::Value::String(model_name);
-                    Bytes::from(serde_json::to_vec(&json).unwrap_or_else(|_| body_bytes.to_vec()))
-                } else {
-                    body_bytes
+            // Rewrite the body's "model" field.
+            // LoRA adapters are addressed by their own name, so they are left as-is.
+            let body_bytes = match json.as_mut() {
+                Some(json) => {
+                    json["model"] = serde_json::Value::String(upstream_model_name(&st, &ctx, &model_uid, &raw_model));
+                    if is_generation_path(&uri_path) {
+                        if let Some(policy) = st.router.request_policy(&model_uid) {
+                            if let Err(param) = policy.apply(json) {
+                                return forbidden_param(&st, &model_uid, &raw_model, param);
+                            }
+                        }
+                        client_usage = !request_stream_usage(json);
+                        if let Some(budget) = ctx.budget_tokens {
+                            clamp_max_tokens(json, budget);
+                        }
+                    }
+                    Bytes::from(serde_json::to_vec(json).unwrap_or_else(|_| body_bytes.to_vec()))
                 }
+                None => body_bytes,
             };
 
-            (reqwest::Method::POST, Some(body_bytes), model_uid)
+            (reqwest::Method::POST, Some(body_bytes), json, model_uid)
         }
         _ => {
-            return (StatusCode::METHOD_NOT_ALLOWED, "method not allowed").into_response();
+            return ApiError::new(ErrorCode::MethodNotAllowed, "method not allowed").into_response();
         }
     };
 
+    let model_name = st
+        .router
+        .get_model_name(&model_uid)
+        .unwrap_or_else(|| model_uid.clone());
+    let mut prompt_estimate = None;
+    let mut max_tokens = None;
+    if is_generation_path(&uri_path) {
+        if let Some(json) = body_json.as_ref() {
+            if let Some(estimate) = st.tokens.estimate(&model_name, json).await {
+                ctx.required_context_tokens = Some(estimate.required_context_tokens());
+                prompt_estimate = Some(estimate.prompt_tokens);
+                max_tokens = estimate.completion_tokens;
+            }
+        }
+    }
+    let accounting = body_bytes.is_some().then(|| UsageAccounting {
+        principal,
+        model_name,
+        prompt_estimate,
+        max_tokens,
+        fill_missing: is_generation_path(&uri_path),
+        client_usage,
+    });
+
     let plan_version = st.plan_version.load(std::sync::atomic::Ordering::Relaxed);
 
+    let policy = st.router.routing_policy(&model_uid);
+    let retry_max = policy.as_ref().and_then(|p| p.retry_max).unwrap_or(st.retry_max);
+    let retry_backoff_ms = policy
+        .as_ref()
+        .and_then(|p| p.retry_backoff_ms)
+        .unwrap_or(st.retry_backoff_ms);
+    let request_timeout = ctx.bound_timeout(
+        policy
+            .as_ref()
+            .and_then(|p| p.request_timeout_ms)
+            .map(std::time::Duration::from_millis),
+    );
+
+    let shadow_tap = body_bytes
+        .as_ref()
+        .and_then(|b| shadow::mirror(&st, &model_uid, &uri_path, &headers, b));
+
+    if is_generation_path(&uri_path)
+        && st.router.has_disaggregated_pools(&model_uid)
+    {
+        if let Some(json) = body_json.take_if(|v| v.is_object()) {
+            let req = pd::DisaggregatedRequest {
+                ctx: &ctx,
+                headers: &headers,
+                path: &uri_path,
+                model_uid: &model_uid,
+                plan_version: (model_uid == st.model_uid && plan_version > 0).then_some(plan_version),
+                timeout: request_timeout,
+                accounting: accounting.as_ref(),
+            };
+            return pd::proxy_disaggregated(&st, req, json, request_start).await;
+        }
+    }
+
     let mut attempt: u32 = 0;
-    let max_attempts = st.retry_max.saturating_add(1).max(1);
+    let max_attempts = retry_max.saturating_add(1).max(1);
     let mut excluded_endpoint: Option<(String, u32)> = None;
 
-    let (_selected_ep, resp) = loop {
-        let ep = if model_uid == st.model_uid && plan_version > 0 {
-            if let Some((exclude_model_uid, exclude_replica_id)) = excluded_endpoint.as_ref() {
-                st.router.route_with_plan_version_excluding(
-                    &_ctx,
-                    &model_uid,
-                    plan_version,
-                    (exclude_model_uid.as_str(), *exclude_replica_id),
-                )
-            } else {
-                st.router
-                    .route_with_plan_version(&_ctx, &model_uid, plan_version)
+    // Hedge buffered non-streaming calls when the model's policy asks for it.
+    let mut shadow_tap = shadow_tap;
+    if let (Some(hedge_policy), Some(body)) =
+        (policy.as_ref().and_then(|p| p.hedge.as_ref()), body_bytes.as_ref())
+    {
+        let streaming = body_json
+            .as_ref()
+            .and_then(|v| v.get("stream").and_then(|s| s.as_bool()))
+            .unwrap_or(false);
+        if !streaming {
+            let req = hedge::HedgedRequest {
+                ctx: &ctx,
+                headers: &headers,
+                path: &uri_path,
+                query: &uri_query,
+                body,
+                model_uid: &model_uid,
+                plan_version,
+                timeout: request_timeout,
+                policy: hedge_policy,
+                accounting: accounting.as_ref(),
+                retry_max,
+                retry_backoff_ms,
+            };
+            match hedge::proxy_hedged(&st, req, request_start, shadow_tap).await {
+                Ok(resp) => return resp,
+                Err(fallback) => {
+                    shadow_tap = fallback.shadow_tap;
+                    attempt = fallback.attempts;
+                    excluded_endpoint = fallback.excluded;
+                }
             }
-        } else if let Some((exclude_model_uid, exclude_replica_id)) = excluded_endpoint.as_ref() {
-            st.router
-                .route_excluding(&_ctx, &model_uid, (exclude_model_uid.as_str(), *exclude_replica_id))
-        } else {
-            st.router.route(&_ctx, &model_uid)
-        };
+        }
+    }
 
-        let ep = match ep {
+    let (_selected_ep, resp) = loop {
+        let ep = match select_endpoint(
+            &st,
+            &ctx,
+            &model_uid,
+            plan_version,
+            excluded_endpoint.as_ref(),
+        ) {
             Ok(ep) => ep,
-            Err(nebula_router::RouteError::Overloaded) => {
-                st.metrics.record_model_status(&model_uid, 429);
-                return Response::builder()
-                    .status(StatusCode::TOO_MANY_REQUESTS)
-                    .header("Retry-After", "5")
-                    .body(Body::from(format!(
-                        "all endpoints overloaded for model '{}'",
-                        model_uid
-                    )))
-                    .unwrap_or_else(|_| Response::new(Body::empty()));
-            }
-            Err(_) => {
-                st.metrics.record_model_status(&model_uid, 503);
-                return (
-                    StatusCode::SERVICE_UNAVAILABLE,
-                    format!("no ready endpoint for model '{}'", model_uid),
-                )
-                    .into_response();
-            }
+            Err(resp) => return resp,
         };
 
+        if let (Some((target, call)), Some(body)) =
+            (grpc::grpc_call_for(&ep, &uri_path), body_bytes.as_ref())
+        {
+            let req = grpc::ForwardRequest {
+                target,
+                call,
+                request_id: &ctx.request_id,
+                body,
+                model_uid: &model_uid,
+                timeout: request_timeout,
+                client_usage,
+            };
+            match grpc::forward(&st, req, request_start).await {
+                GrpcOutcome::Done(out) => {
+                    if attempt > 0 {
+                        st.metrics
+                            .retry_success_total
+                            .fetch_add(1, std::sync::atomic::Ordering::Relaxed);
+                    }
+                    st.router
+                        .record_endpoint_success(&ep.model_uid, ep.replica_id);
+                    return out;
+                }
+                GrpcOutcome::Retryable(status) => {
+                    st.router
+                        .record_endpoint_failure(&ep.model_uid, ep.replica_id);
+                    let kind = grpc::classify_status(&status);
+                    st.metrics.record_upstream_error(kind);
+                    tracing::error!(code=?status.code(), message=%status.message(), retry_kind=%kind, attempt, "router grpc upstream request failed");
+                    if attempt + 1 < max_attempts && retry_fits(&ctx, retry_backoff_ms) {
+                        attempt += 1;
+                        excluded_endpoint = Some((ep.model_uid.clone(), ep.replica_id));
+                        st.metrics
+                            .retry_total
+                            .fetch_add(1, std::sync::atomic::Ordering::Relaxed);
+                        tokio::time::sleep(std::time::Duration::from_millis(retry_backoff_ms)).await;
+                        continue;
+                    }
+
+                    let out = grpc::status_response(&status);
+                    st.metrics.record_model_status(&model_uid, out.status().as_u16());
+                    st.metrics.observe_e2e_latency(&model_uid, request_start.elapsed().as_secs_f64());
+                    return out;
+                }
+                GrpcOutcome::Unsupported(reason) => {
+                    tracing::debug!(%reason, "request not expressible over grpc, falling back to http");
+                }
+            }
+        }
+
         let base = match ep.base_url.as_deref() {
             Some(s) => s.trim_end_matches('/'),
             None => {
                 st.metrics.record_model_status(&model_uid, 503);
-                return (StatusCode::SERVICE_UNAVAILABLE, "endpoint missing base_url")
+                return ApiError::new(ErrorCode::ServiceUnavailable, "endpoint missing base_url")
                     .into_response();
             }
         };
@@ -210,6 +475,9 @@ pub async fn proxy_chat_completions(
         if let Some(b) = body_bytes.clone() {
             builder = builder.body(b);
         }
+        if let Some(timeout) = request_timeout {
+            builder = builder.timeout(timeout);
+        }
 
         match builder.send().await {
             Ok(resp) => {
@@ -217,13 +485,13 @@ pub async fn proxy_chat_completions(
                     st.router
                         .record_endpoint_failure(&ep.model_uid, ep.replica_id);
                     st.metrics.record_upstream_error("upstream_5xx");
-                    if attempt + 1 < max_attempts {
+                    if attempt + 1 < max_attempts && retry_fits(&ctx, retry_backoff_ms) {
                         attempt += 1;
                         excluded_endpoint = Some((ep.model_uid.clone(), ep.replica_id));
                         st.metrics
                             .retry_total
                             .fetch_add(1, std::sync::atomic::Ordering::Relaxed);
-                        tokio::time::sleep(std::time::Duration::from_millis(st.retry_backoff_ms)).await;
+                        tokio::time::sleep(std::time::Duration::from_millis(retry_backoff_ms)).await;
                         continue;
                     }
                 } else if attempt > 0 {
@@ -243,23 +511,310 @@ pub async fn proxy_chat_completions(
                 let kind = classify_reqwest_error(&e);
                 st.metrics.record_upstream_error(kind);
                 tracing::error!(error=%e, retry_kind=%kind, attempt, "router upstream request failed");
-                if attempt + 1 < max_attempts {
+                if attempt + 1 < max_attempts && retry_fits(&ctx, retry_backoff_ms) {
                     attempt += 1;
                     excluded_endpoint = Some((ep.model_uid.clone(), ep.replica_id));
                     st.metrics
                         .retry_total
                         .fetch_add(1, std::sync::atomic::Ordering::Relaxed);
-                    tokio::time::sleep(std::time::Duration::from_millis(st.retry_backoff_ms)).await;
+                    tokio::time::sleep(std::time::Duration::from_millis(retry_backoff_ms)).await;
                     continue;
                 }
 
-                st.metrics.record_model_status(&model_uid, 502);
                 st.metrics.observe_e2e_latency(&model_uid, request_start.elapsed().as_secs_f64());
-                return (StatusCode::BAD_GATEWAY, "upstream request failed").into_response();
+                if e.is_timeout() && ctx.is_expired() {
+                    return deadline_exceeded(&st, &model_uid);
+                }
+                st.metrics.record_model_status(&model_uid, 502);
+                return ApiError::new(ErrorCode::UpstreamError, "upstream request failed")
+                    .into_response();
             }
         }
     };
 
+    upstream_response_tapped(
+        &st,
+        &model_uid,
+        request_start,
+        resp,
+        shadow_tap,
+        accounting.as_ref(),
+    )
+    .await
+}
+
+/// Paths whose responses carry generated tokens.
+fn is_generation_path(path: &str) -> bool {
+    matches!(path, "/v1/chat/completions" | "/v1/completions")
+}
+
+/// Whether a POST body should be streamed to the engine instead of buffered.
+/// Multipart uploads always stream. JSON bodies stream when larger than the
+/// configured threshold or of unknown length, except generation requests with
+/// a token budget, which is applied to the body. Generation requests whose
+/// model needs the whole body are still buffered once the model is known, see
+/// [`needs_whole_body`].
+///
+/// Streamed bodies can't be replayed, so they get a single upstream attempt and
+/// always use the HTTP path. Their usage is still accounted.
+fn should_stream_body(st: &AppState, ctx: &ExecutionContext, headers: &HeaderMap, path: &str) -> bool {
+    let is_multipart = headers
+        .get(axum::http::header::CONTENT_TYPE)
+        .and_then(|v| v.to_str().ok())
+        .is_some_and(|ct| ct.to_ascii_lowercase().starts_with("multipart/"));
+    if is_multipart {
+        return true;
+    }
+    if is_generation_path(path) && ctx.budget_tokens.is_some() {
+        return false;
+    }
+    match headers
+        .get(axum::http::header::CONTENT_LENGTH)
+        .and_then(|v| v.to_str().ok())
+        .and_then(|v| v.parse::<usize>().ok())
+    {
+        Some(len) => len > st.stream_body_threshold_bytes,
+        None => true,
+    }
+}
+
+/// Whether generation requests for `model_uid` must be read in full: to apply
+/// a request policy, to estimate the context they need, or for a routing
+/// policy or prefill/decode split that replays or rewrites the body.
+fn needs_whole_body(st: &AppState, model_uid: &str) -> bool {
+    st.router.request_policy(model_uid).is_some()
+        || st.router.routing_policy(model_uid).is_some()
+        || st.router.has_disaggregated_pools(model_uid)
+        || st.router.has_context_limits(model_uid)
+}
+
+fn unreadable_body() -> Response {
+    ApiError::new(ErrorCode::InvalidRequest, "failed to read request body").into_response()
+}
+
+fn request_too_large(st: &AppState) -> Response {
+    st.metrics
+        .request_too_large_total
+        .fetch_add(1, std::sync::atomic::Ordering::Relaxed);
+    st.metrics.record_model_status(&st.model_uid, 413);
+    ApiError::new(ErrorCode::RequestTooLarge, "request body too large").into_response()
+}
+
+/// Rejects a request that sets a parameter its model's request policy forbids.
+fn forbidden_param(st: &AppState, model_uid: &str, raw_model: &str, param: String) -> Response {
+    st.metrics.record_model_status(model_uid, 400);
+    ApiError::new(
+        ErrorCode::InvalidRequest,
+        format!("parameter '{param}' is not allowed for model '{raw_model}'"),
+    )
+    .with_param(param)
+    .into_response()
+}
+
+/// Model name to send upstream: the adapter name for LoRA requests, otherwise the
+/// engine-facing name of the resolved base model.
+fn upstream_model_name(st: &AppState, ctx: &ExecutionContext, model_uid: &str, raw_model: &str) -> String {
+    if let Some(adapter) = ctx.lora_adapter.as_deref() {
+        return adapter.to_string();
+    }
+    st.router
+        .get_model_name(model_uid)
+        .unwrap_or_else(|| raw_model.to_string())
+}
+
+/// Outcome of [`proxy_streaming_body`].
+enum StreamedBody {
+    Sent(Response),
+    /// The request turned out to need its whole body; here it is, as received.
+    Buffered(Bytes),
+}
+
+async fn proxy_streaming_body(
+    st: &AppState,
+    ctx: &ExecutionContext,
+    headers: &HeaderMap,
+    req: Request<Body>,
+    request_start: std::time::Instant,
+) -> StreamedBody {
+    let uri_path = req.uri().path().to_string();
+    let uri_query = req
+        .uri()
+        .query()
+        .map(|q| format!("?{q}"))
+        .unwrap_or_default();
+    let principal = req
+        .extensions()
+        .get::<AuthContext>()
+        .map(|c| c.principal_label())
+        .unwrap_or_else(|| "anonymous".to_string());
+    let mut multipart = headers
+        .get(axum::http::header::CONTENT_TYPE)
+        .and_then(|v| v.to_str().ok())
+        .and_then(MultipartModelScanner::from_content_type);
+    let mut json_scanner = JsonModelScanner::default();
+
+    // Read only as much of the body as needed to locate the model field.
+    let mut body = req.into_body().into_data_stream();
+    let mut prefix: Vec<u8> = Vec::new();
+    let scan = loop {
+        let scan = match multipart.as_mut() {
+            Some(scanner) => scanner.feed(&prefix),
+            None => json_scanner.feed(&prefix),
+        };
+        if scan != ModelScan::NeedMore {
+            break scan;
+        }
+        match body.next().await {
+            Some(Ok(chunk)) => {
+                prefix.extend_from_slice(&chunk);
+                if prefix.len() > st.max_request_body_bytes {
+                    return StreamedBody::Sent(request_too_large(st));
+                }
+            }
+            Some(Err(_)) => return StreamedBody::Sent(unreadable_body()),
+            None => break ModelScan::Unsupported,
+        }
+    };
+
+    let raw_model = match &scan {
+        ModelScan::Found { model, .. } => model.clone(),
+        _ => st.model_uid.clone(),
+    };
+    let model_uid = st.router.resolve_model(&raw_model);
+    if multipart.is_none() && is_generation_path(&uri_path) && needs_whole_body(st, &model_uid) {
+        while let Some(chunk) = body.next().await {
+            let Ok(chunk) = chunk else {
+                return StreamedBody::Sent(unreadable_body());
+            };
+            prefix.extend_from_slice(&chunk);
+            if prefix.len() > st.max_request_body_bytes {
+                return StreamedBody::Sent(request_too_large(st));
+            }
+        }
+        return StreamedBody::Buffered(Bytes::from(prefix));
+    }
+    let mut ctx = ctx.clone();
+    if st.router.resolve_adapter(&raw_model).is_some() {
+        ctx.lora_adapter = Some(raw_model.clone());
+    }
+    let ctx = &ctx;
+    let model_name = upstream_model_name(st, ctx, &model_uid, &raw_model);
+    let prefix = rewrite_model(&prefix, &scan, &model_name, multipart.is_none());
+    let accounting = UsageAccounting {
+        principal,
+        model_name: st
+            .router
+            .get_model_name(&model_uid)
+            .unwrap_or_else(|| model_uid.clone()),
+        prompt_estimate: None,
+        max_tokens: None,
+        fill_missing: false,
+        client_usage: true,
+    };
+
+    st.metrics
+        .request_body_streamed_total
+        .fetch_add(1, std::sync::atomic::Ordering::Relaxed);
+
+    let plan_version = st.plan_version.load(std::sync::atomic::Ordering::Relaxed);
+    let ep = match select_endpoint(st, ctx, &model_uid, plan_version, None) {
+        Ok(ep) => ep,
+        Err(resp) => return StreamedBody::Sent(resp),
+    };
+    let base = match ep.base_url.as_deref() {
+        Some(s) => s.trim_end_matches('/'),
+        None => {
+            st.metrics.record_model_status(&model_uid, 503);
+            return StreamedBody::Sent(
+                ApiError::new(ErrorCode::ServiceUnavailable, "endpoint missing base_url").into_response(),
+            );
+        }
+    };
+
+    // Forward the rewritten prefix followed by the rest of the client body,
+    // still enforcing the size cap on the bytes that flow through.
+    let limit = st.max_request_body_bytes;
+    let metrics = st.metrics.clone();
+    let mut forwarded = prefix.len();
+    let rest = body.map(move |item| match item {
+        Ok(chunk) => {
+            forwarded += chunk.len();
+            if forwarded > limit {
+                metrics
+                    .request_too_large_total
+                    .fetch_add(1, std::sync::atomic::Ordering::Relaxed);
+                Err(std::io::Error::other("request body too large"))
+            } else {
+                Ok(chunk)
+            }
+        }
+        Err(e) => Err(std::io::Error::other(e)),
+    });
+    let stream = futures_util::stream::once(async move { Ok(Bytes::from(prefix)) }).chain(rest);
+
+    let url = format!("{base}{uri_path}{uri_query}");
+    let mut builder = st
+        .http
+        .post(url)
+        .headers(to_reqwest_headers(headers))
+        .body(reqwest::Body::wrap_stream(stream));
+    if let Some(timeout) = ctx.bound_timeout(
+        st.router
+            .routing_policy(&model_uid)
+            .and_then(|p| p.request_timeout_ms)
+            .map(std::time::Duration::from_millis),
+    ) {
+        builder = builder.timeout(timeout);
+    }
+    let result = builder.send().await;
+
+    StreamedBody::Sent(match result {
+        Ok(resp) => {
+            if resp.status().is_server_error() {
+                st.router.record_endpoint_failure(&ep.model_uid, ep.replica_id);
+                st.metrics.record_upstream_error("upstream_5xx");
+            } else {
+                st.router.record_endpoint_success(&ep.model_uid, ep.replica_id);
+            }
+            upstream_response(st, &model_uid, request_start, resp, Some(&accounting)).await
+        }
+        Err(e) => {
+            st.router.record_endpoint_failure(&ep.model_uid, ep.replica_id);
+            let kind = classify_reqwest_error(&e);
+            st.metrics.record_upstream_error(kind);
+            tracing::error!(error=%e, retry_kind=%kind, "router streamed upstream request failed");
+            st.metrics.observe_e2e_latency(&model_uid, request_start.elapsed().as_secs_f64());
+            if e.is_timeout() && ctx.is_expired() {
+                return StreamedBody::Sent(deadline_exceeded(st, &model_uid));
+            }
+            st.metrics.record_model_status(&model_uid, 502);
+            ApiError::new(ErrorCode::UpstreamError, "upstream request failed").into_response()
+        }
+    })
+}
+
+/// Relay an upstream HTTP response to the client, recording latency and status
+/// metrics. SSE bodies are streamed through chunk by chunk.
+pub(crate) async fn upstream_response(
+    st: &AppState,
+    model_uid: &str,
+    request_start: std::time::Instant,
+    resp: reqwest::Response,
+    accounting: Option<&UsageAccounting>,
+) -> Response {
+    upstream_response_tapped(st, model_uid, request_start, resp, None, accounting).await
+}
+
+/// Like [`upstream_response`], also handing a non-streaming body to `tap`
+/// (used to compare shadow output with the primary).
+async fn upstream_response_tapped(
+    st: &AppState,
+    model_uid: &str,
+    request_start: std::time::Instant,
+    resp: reqwest::Response,
+    tap: Option<tokio::sync::oneshot::Sender<Bytes>>,
+    accounting: Option<&UsageAccounting>,
+) -> Response {
     let status = StatusCode::from_u16(resp.status().as_u16()).unwrap_or(StatusCode::BAD_GATEWAY);
     let resp_headers = resp.headers().clone();
     let is_sse = resp
@@ -273,26 +828,78 @@ pub async fn proxy_chat_completions(
         let mut upstream = resp.bytes_stream();
         let (tx, rx) = tokio::sync::mpsc::channel::<Result<Bytes, Infallible>>(64);
         let metrics = st.metrics.clone();
-        let model_uid_for_stream = model_uid.clone();
+        let model_uid_for_stream = model_uid.to_string();
         let status_code = status.as_u16();
+        let accounting = accounting.filter(|_| status.is_success()).cloned();
+        let st = st.clone();
         tokio::spawn(async move {
             let mut first_chunk = true;
-            while let Some(item) = upstream.next().await {
+            let mut scanner = SseUsageScanner::default();
+            let mut strip = accounting
+                .as_ref()
+                .filter(|acc| !acc.client_usage)
+                .map(|_| UsageChunkFilter::default());
+            let mut cancelled = false;
+            'forward: loop {
+                // Stop reading as soon as the client goes away; dropping
+                // `upstream` closes the connection and aborts generation.
+                let item = tokio::select! {
+                    item = upstream.next() => item,
+                    _ = tx.closed() => {
+                        cancelled = true;
+                        break;
+                    }
+                };
                 match item {
-                    Ok(b) => {
+                    Some(Ok(mut b)) => {
                         if first_chunk {
                             first_chunk = false;
                             let ttft = request_start.elapsed().as_secs_f64();
                             metrics.observe_ttft(&model_uid_for_stream, ttft);
                         }
-                        let _ = tx.send(Ok(b)).await;
+                        let mut chunks = Vec::with_capacity(3);
+                        if let Some(acc) = accounting.as_ref() {
+                            if let Some((at, usage_event)) = acc.scan_chunk(&st, &mut scanner, &b).await {
+                                chunks.push(b.split_to(at));
+                                chunks.push(usage_event);
+                            }
+                        }
+                        chunks.push(b);
+                        for chunk in chunks {
+                            let chunk = match strip.as_mut() {
+                                Some(f) => Bytes::from(f.push(&chunk)),
+                                None => chunk,
+                            };
+                            if !chunk.is_empty() && tx.send(Ok(chunk)).await.is_err() {
+                                cancelled = true;
+                                break 'forward;
+                            }
+                        }
+                    }
+                    Some(Err(e)) if e.is_timeout() => {
+                        let err = ApiError::new(ErrorCode::DeadlineExceeded, "request deadline exceeded");
+                        let _ = tx.send(Ok(Bytes::from(err.sse_event()))).await;
+                        break;
                     }
-                    Err(_) => break,
+                    Some(Err(_)) | None => break,
                 }
             }
+            drop(upstream);
+            if let Some(rest) = strip.take().map(UsageChunkFilter::finish).filter(|r| !cancelled && !r.is_empty()) {
+                let _ = tx.send(Ok(Bytes::from(rest))).await;
+            }
             let e2e = request_start.elapsed().as_secs_f64();
             metrics.observe_e2e_latency(&model_uid_for_stream, e2e);
             metrics.record_model_status(&model_uid_for_stream, status_code);
+            match (accounting.as_ref(), cancelled) {
+                (Some(acc), true) => acc.cancel_stream(&st, &model_uid_for_stream, &scanner).await,
+                (Some(acc), false) => acc.finish_stream(&st, &model_uid_for_stream, &scanner).await,
+                (None, true) => metrics.record_cancelled(0),
+                (None, false) => {}
+            }
+            if cancelled {
+                tracing::debug!(model_uid=%model_uid_for_stream, "client disconnected, upstream stream aborted");
+            }
         });
 
         let stream = ReceiverStream::new(rx);
@@ -309,15 +916,120 @@ pub async fn proxy_chat_completions(
         Ok(b) => b,
         Err(_) => Bytes::new(),
     };
+    if let Some(tap) = tap {
+        let _ = tap.send(bytes.clone());
+    }
 
     let e2e = request_start.elapsed().as_secs_f64();
-    st.metrics.observe_e2e_latency(&model_uid, e2e);
-    st.metrics.record_model_status(&model_uid, status.as_u16());
+    st.metrics.observe_e2e_latency(model_uid, e2e);
+    st.metrics.record_model_status(model_uid, status.as_u16());
+
+    buffered_response(st, model_uid, status, &resp_headers, bytes, accounting).await
+}
+
+/// Build the client response for a buffered upstream body, accounting its
+/// token usage (which may add a missing `usage` object to the body).
+pub(crate) async fn buffered_response(
+    st: &AppState,
+    model_uid: &str,
+    status: StatusCode,
+    headers: &ReqwestHeaderMap,
+    bytes: Bytes,
+    accounting: Option<&UsageAccounting>,
+) -> Response {
+    let filled = match accounting {
+        Some(acc) => acc.account_body(st, model_uid, status.as_u16(), &bytes).await,
+        None => None,
+    };
+    let rewritten = filled.is_some();
+    let bytes = filled.unwrap_or(bytes);
 
     let mut out = Response::builder()
         .status(status)
         .body(Body::from(bytes))
         .unwrap_or_else(|_| Response::new(Body::empty()));
-    copy_response_headers(&resp_headers, &mut out);
+    copy_response_headers(headers, &mut out);
+    if rewritten {
+        out.headers_mut().remove(axum::http::header::CONTENT_LENGTH);
+    }
     out
 }
+
+#[cfg(test)]
+mod tests {
+    use std::sync::{Arc, Mutex};
+
+    use axum::routing::post;
+    use serde_json::{json, Value};
+
+    use super::*;
+
+    /// An engine that records the request bodies it receives and answers with
+    /// a chat completion. Returns its base URL and the recorded bodies.
+    async fn fake_engine() -> (String, Arc<Mutex<Vec<Value>>>) {
+        async fn complete(State(seen): State<Arc<Mutex<Vec<Value>>>>, body: Bytes) -> axum::Json<Value> {
+            seen.lock().unwrap().push(serde_json::from_slice(&body).unwrap());
+            axum::Json(json!({
+                "object": "chat.completion",
+                "choices": [{"index": 0, "message": {"role": "assistant", "content": "ok"}, "finish_reason": "stop"}],
+                "usage": {"prompt_tokens": 1, "completion_tokens": 1, "total_tokens": 2},
+            }))
+        }
+        let seen = Arc::new(Mutex::new(Vec::new()));
+        let app = axum::Router::new()
+            .route("/v1/chat/completions", post(complete))
+            .with_state(seen.clone());
+        let listener = tokio::net::TcpListener::bind("127.0.0.1:0").await.unwrap();
+        let url = format!("http://{}", listener.local_addr().unwrap());
+        tokio::spawn(async move { axum::serve(listener, app).await });
+        (url, seen)
+    }
+
+    async fn chat(st: &AppState, body: &Value) -> Response {
+        let bytes = serde_json::to_vec(body).unwrap();
+        let req = Request::builder()
+            .method("POST")
+            .uri("/v1/chat/completions")
+            .header("content-type", "application/json")
+            .header("content-length", bytes.len())
+            .body(Body::from(bytes))
+            .unwrap();
+        let headers = req.headers().clone();
+        proxy_chat_completions(State(st.clone()), headers, req).await
+    }
+
+    fn streamed(st: &AppState) -> u64 {
+        st.metrics
+            .request_body_streamed_total
+            .load(std::sync::atomic::Ordering::Relaxed)
+    }
+
+    #[tokio::test]
+    async fn test_large_generation_bodies_stream_unless_the_model_needs_them() {
+        let (url, seen) = fake_engine().await;
+        let st = AppState::for_tests("m", &[url]);
+        let image = "A".repeat(4096);
+        let body = json!({"model": "m", "messages": [{"role": "user", "content": image}]});
+
+        // Small bodies are buffered.
+        let small = json!({"model": "m", "messages": [{"role": "user", "content": "hi"}]});
+        assert_eq!(chat(&st, &small).await.status(), StatusCode::OK);
+        assert_eq!(streamed(&st), 0);
+
+        assert_eq!(chat(&st, &body).await.status(), StatusCode::OK);
+        assert_eq!(streamed(&st), 1);
+        assert_eq!(seen.lock().unwrap()[1], body);
+
+        // A request policy needs the whole body, so it is read in full and applied.
+        let policy = nebula_common::RequestPolicy {
+            defaults: [("temperature".to_string(), json!(0.5))].into(),
+            ..Default::default()
+        };
+        st.router.set_request_policy("m", Some(policy));
+        assert_eq!(chat(&st, &body).await.status(), StatusCode::OK);
+        assert_eq!(streamed(&st), 1);
+        let sent = seen.lock().unwrap()[2].clone();
+        assert_eq!(sent["temperature"], 0.5);
+        assert_eq!(sent["messages"], body["messages"]);
+    }
+}
@@ -0,0 +1,501 @@
+//! Request hedging for non-streaming calls.
+//!
+//! With a [`HedgePolicy`] on the model, a buffered non-streaming request is
+//! sent to one replica; if it hasn't answered within the model's recent
+//! latency percentile, a duplicate goes to a different replica and whichever
+//! finishes first is returned. Dropping the losing request future closes its
+//! connection, which cancels the work on the engine.
+//!
+//! Hedges are capped per model to `max_extra_load` of its requests within a
+//! short window so a slow pool can't double its own load. Failures that
+//! hedging doesn't absorb are retried under the model's usual retry settings.
+
+use std::collections::VecDeque;
+use std::sync::atomic::Ordering;
+use std::sync::{Arc, Mutex};
+use std::time::{Duration, Instant};
+
+use axum::{
+    http::{HeaderMap, StatusCode},
+    response::{IntoResponse, Response},
+};
+use bytes::Bytes;
+use dashmap::DashMap;
+use reqwest::header::HeaderMap as ReqwestHeaderMap;
+use tokio::sync::oneshot;
+
+use nebula_common::{ApiError, EndpointInfo, ErrorCode, ExecutionContext, HedgePolicy};
+
+use crate::grpc;
+use crate::handlers::{
+    buffered_response, classify_reqwest_error, deadline_exceeded, retry_fits, select_endpoint, to_reqwest_headers,
+};
+use crate::state::AppState;
+use crate::usage::UsageAccounting;
+
+/// Successful latencies kept per model for the percentile.
+const WINDOW_SAMPLES: usize = 512;
+/// Below this many samples the delay is unknown and requests are not hedged.
+const MIN_SAMPLES: usize = 20;
+/// Window over which the hedge budget is counted.
+const BUDGET_WINDOW_MS: u64 = 10_000;
+
+#[derive(Debug, Default)]
+struct Budget {
+    window_start_ms: u64,
+    requests: u64,
+    hedges: u64,
+}
+
+/// Latency history and hedge budget for one model.
+#[derive(Debug, Default)]
+pub struct ModelHedge {
+    latencies: Mutex<VecDeque<f64>>,
+    budget: Mutex<Budget>,
+}
+
+fn now_ms() -> u64 {
+    std::time::SystemTime::now()
+        .duration_since(std::time::UNIX_EPOCH)
+        .unwrap_or_default()
+        .as_millis() as u64
+}
+
+/// Value at `p` (0.0–1.0) of `samples`, by nearest rank.
+pub fn percentile(samples: &[f64], p: f64) -> Option<f64> {
+    if samples.is_empty() {
+        return None;
+    }
+    let mut sorted = samples.to_vec();
+    sorted.sort_by(|a, b| a.total_cmp(b));
+    let idx = ((sorted.len() - 1) as f64 * p).round() as usize;
+    sorted.get(idx.min(sorted.len() - 1)).copied()
+}
+
+impl ModelHedge {
+    fn observe(&self, seconds: f64) {
+        if let Ok(mut l) = self.latencies.lock() {
+            if l.len() == WINDOW_SAMPLES {
+                l.pop_front();
+            }
+            l.push_back(seconds);
+        }
+    }
+
+    /// Hedge delay for `policy`, once enough latencies have been observed.
+    fn delay(&self, policy: &HedgePolicy) -> Option<Duration> {
+        let l = self.latencies.lock().ok()?;
+        if l.len() < MIN_SAMPLES {
+            return None;
+        }
+        let (a, b) = l.as_slices();
+        let samples = [a, b].concat();
+        let p = percentile(&samples, policy.percentile)?;
+        Some(Duration::from_secs_f64(p).max(Duration::from_millis(policy.min_delay_ms)))
+    }
+
+    fn with_budget<T>(&self, now_ms: u64, f: impl FnOnce(&mut Budget) -> T) -> Option<T> {
+        let mut b = self.budget.lock().ok()?;
+        if now_ms.saturating_sub(b.window_start_ms) >= BUDGET_WINDOW_MS {
+            *b = Budget {
+                window_start_ms: now_ms,
+                ..Budget::default()
+            };
+        }
+        Some(f(&mut b))
+    }
+
+    fn on_request(&self, now_ms: u64) {
+        self.with_budget(now_ms, |b| b.requests += 1);
+    }
+
+    /// Take a hedge from the budget: one, plus `max_extra_load` of the window's requests.
+    fn try_hedge(&self, now_ms: u64, max_extra_load: f64) -> bool {
+        self.with_budget(now_ms, |b| {
+            let allowed = 1.0 + b.requests as f64 * max_extra_load;
+            if ((b.hedges + 1) as f64) <= allowed {
+                b.hedges += 1;
+                true
+            } else {
+                false
+            }
+        })
+        .unwrap_or(false)
+    }
+}
+
+/// Per-model hedging state shared by all requests.
+#[derive(Debug, Default)]
+pub struct Hedging {
+    models: DashMap<String, Arc<ModelHedge>>,
+}
+
+impl Hedging {
+    fn model(&self, model_uid: &str) -> Arc<ModelHedge> {
+        self.models
+            .entry(model_uid.to_string())
+            .or_default()
+            .value()
+            .clone()
+    }
+}
+
+/// A buffered non-streaming request for a model with a hedge policy.
+pub struct HedgedRequest<'a> {
+    pub ctx: &'a ExecutionContext,
+    pub headers: &'a HeaderMap,
+    pub path: &'a str,
+    pub query: &'a str,
+    pub body: &'a Bytes,
+    pub model_uid: &'a str,
+    pub plan_version: u64,
+    pub timeout: Option<Duration>,
+    pub policy: &'a HedgePolicy,
+    pub accounting: Option<&'a UsageAccounting>,
+    pub retry_max: u32,
+    pub retry_backoff_ms: u64,
+}
+
+struct Attempt {
+    result: Result<(reqwest::StatusCode, ReqwestHeaderMap, Bytes), reqwest::Error>,
+}
+
+impl Attempt {
+    fn failed(&self) -> bool {
+        match &self.result {
+            Ok((status, _, _)) => status.is_server_error(),
+            Err(_) => true,
+        }
+    }
+}
+
+/// Send the request to `ep` and read the whole response body.
+async fn send(st: &AppState, hedge: &ModelHedge, ep: &EndpointInfo, req: &HedgedRequest<'_>) -> Attempt {
+    let Some(base) = ep.base_url.as_deref() else {
+        return Attempt {
+            result: Ok((reqwest::StatusCode::SERVICE_UNAVAILABLE, ReqwestHeaderMap::new(), Bytes::new())),
+        };
+    };
+    let start = Instant::now();
+    let mut builder = st
+        .http
+        .post(format!("{}{}{}", base.trim_end_matches('/'), req.path, req.query))
+        .headers(to_reqwest_headers(req.headers))
+        .body(req.body.clone());
+    if let Some(timeout) = req.timeout {
+        builder = builder.timeout(timeout);
+    }
+    let result = match builder.send().await {
+        Ok(resp) => {
+            let status = resp.status();
+            let headers = resp.headers().clone();
+            resp.bytes().await.map(|b| (status, headers, b))
+        }
+        Err(e) => Err(e),
+    };
+
+    let attempt = Attempt { result };
+    if attempt.failed() {
+        st.router.record_endpoint_failure(&ep.model_uid, ep.replica_id);
+        match &attempt.result {
+            Err(e) => st.metrics.record_upstream_error(classify_reqwest_error(e)),
+            Ok(_) => st.metrics.record_upstream_error("upstream_5xx"),
+        }
+    } else {
+        st.router.record_endpoint_success(&ep.model_uid, ep.replica_id);
+        hedge.observe(start.elapsed().as_secs_f64());
+    }
+    attempt
+}
+
+/// Pick a second replica, never the first one. Routing errors just skip the hedge.
+fn second_endpoint(st: &AppState, req: &HedgedRequest<'_>, first: &EndpointInfo) -> Option<EndpointInfo> {
+    // The hedge must not move the client's session affinity.
+    let mut ctx = req.ctx.clone();
+    ctx.session_id = None;
+    let exclude = (first.model_uid.as_str(), first.replica_id);
+    let routed = if req.model_uid == st.model_uid && req.plan_version > 0 {
+        st.router
+            .route_with_plan_version_excluding(&ctx, req.model_uid, req.plan_version, exclude)
+    } else {
+        st.router.route_excluding(&ctx, req.model_uid, exclude)
+    };
+    routed
+        .ok()
+        .filter(|ep| grpc::grpc_call_for(ep, req.path).is_none())
+}
+
+async fn finish(
+    st: &AppState,
+    req: &HedgedRequest<'_>,
+    attempt: Attempt,
+    request_start: Instant,
+    shadow_tap: Option<oneshot::Sender<Bytes>>,
+) -> Response {
+    let model_uid = req.model_uid;
+    st.metrics
+        .observe_e2e_latency(model_uid, request_start.elapsed().as_secs_f64());
+    match attempt.result {
+        Ok((status, headers, bytes)) => {
+            let status = StatusCode::from_u16(status.as_u16()).unwrap_or(StatusCode::BAD_GATEWAY);
+            st.metrics.record_model_status(model_uid, status.as_u16());
+            if let Some(tap) = shadow_tap {
+                let _ = tap.send(bytes.clone());
+            }
+            buffered_response(st, model_uid, status, &headers, bytes, req.accounting).await
+        }
+        Err(e) => {
+            tracing::error!(%model_uid, error=%e, "hedged upstream request failed");
+            if e.is_timeout() && req.ctx.is_expired() {
+                return deadline_exceeded(st, model_uid);
+            }
+            st.metrics.record_model_status(model_uid, 502);
+            ApiError::new(ErrorCode::UpstreamError, "upstream request failed").into_response()
+        }
+    }
+}
+
+/// Where the regular retry loop picks up when the hedged path gives up.
+pub struct Fallback {
+    pub shadow_tap: Option<oneshot::Sender<Bytes>>,
+    /// Upstream attempts already made.
+    pub attempts: u32,
+    /// Replica of the last failed attempt, to skip on the next one.
+    pub excluded: Option<(String, u32)>,
+}
+
+/// Finish with `done`, or hand a failure back to the retry loop when the
+/// model's retry settings allow another attempt.
+async fn finish_or_retry(
+    st: &AppState,
+    req: &HedgedRequest<'_>,
+    done: Attempt,
+    ep: &EndpointInfo,
+    attempts: u32,
+    request_start: Instant,
+    shadow_tap: Option<oneshot::Sender<Bytes>>,
+) -> Result<Response, Fallback> {
+    let max_attempts = req.retry_max.saturating_add(1);
+    if done.failed() && attempts < max_attempts && retry_fits(req.ctx, req.retry_backoff_ms) {
+        st.metrics.retry_total.fetch_add(1, Ordering::Relaxed);
+        tokio::time::sleep(Duration::from_millis(req.retry_backoff_ms)).await;
+        return Err(Fallback {
+            shadow_tap,
+            attempts,
+            excluded: Some((ep.model_uid.clone(), ep.replica_id)),
+        });
+    }
+    Ok(finish(st, req, done, request_start, shadow_tap).await)
+}
+
+/// Serve a non-streaming request, hedging to a second replica when the first is slow.
+/// Returns a [`Fallback`] when the chosen replica is served over gRPC, or when
+/// a failure is left for the regular retry loop to retry.
+pub async fn proxy_hedged(
+    st: &AppState,
+    req: HedgedRequest<'_>,
+    request_start: Instant,
+    shadow_tap: Option<oneshot::Sender<Bytes>>,
+) -> Result<Response, Fallback> {
+    let hedge = st.hedge.model(req.model_uid);
+    hedge.on_request(now_ms());
+
+    let first = match select_endpoint(st, req.ctx, req.model_uid, req.plan_version, None) {
+        Ok(ep) => ep,
+        Err(resp) => return Ok(resp),
+    };
+    if grpc::grpc_call_for(&first, req.path).is_some() {
+        return Err(Fallback {
+            shadow_tap,
+            attempts: 0,
+            excluded: None,
+        });
+    }
+    let delay = hedge.delay(req.policy);
+
+    let a = send(st, &hedge, &first, &req);
+    tokio::pin!(a);
+
+    let Some(delay) = delay else {
+        let done = a.await;
+        return finish_or_retry(st, &req, done, &first, 1, request_start, shadow_tap).await;
+    };
+
+    tokio::select! {
+        done = &mut a => {
+            // Settled before the hedge delay; failures go through the retry loop.
+            return finish_or_retry(st, &req, done, &first, 1, request_start, shadow_tap).await;
+        }
+        _ = tokio::time::sleep(delay) => {}
+    }
+
+    if !hedge.try_hedge(now_ms(), req.policy.max_extra_load) {
+        st.metrics
+            .hedge_budget_exhausted_total
+            .fetch_add(1, Ordering::Relaxed);
+        let done = a.await;
+        return finish_or_retry(st, &req, done, &first, 1, request_start, shadow_tap).await;
+    }
+
+    let Some(second) = second_endpoint(st, &req, &first) else {
+        let done = a.await;
+        return finish_or_retry(st, &req, done, &first, 1, request_start, shadow_tap).await;
+    };
+
+    let b = send(st, &hedge, &second, &req);
+    tokio::pin!(b);
+
+    st.metrics.hedge_fired_total.fetch_add(1, Ordering::Relaxed);
+    tracing::debug!(model_uid=%req.model_uid, first=first.replica_id, second=second.replica_id, delay_ms=delay.as_millis() as u64, "hedging request");
+
+    // Return whichever finishes first successfully; the dropped future is cancelled.
+    // When both fail, the later one is retried against the remaining replicas.
+    let (done, ep) = tokio::select! {
+        done = &mut a => {
+            if done.failed() {
+                let hedged = b.await;
+                if !hedged.failed() {
+                    st.metrics.hedge_won_total.fetch_add(1, Ordering::Relaxed);
+                }
+                (hedged, &second)
+            } else {
+                st.metrics.hedge_lost_total.fetch_add(1, Ordering::Relaxed);
+                (done, &first)
+            }
+        }
+        done = &mut b => {
+            if done.failed() {
+                let primary = a.await;
+                if !primary.failed() {
+                    st.metrics.hedge_lost_total.fetch_add(1, Ordering::Relaxed);
+                }
+                (primary, &first)
+            } else {
+                st.metrics.hedge_won_total.fetch_add(1, Ordering::Relaxed);
+                (done, &second)
+            }
+        }
+    };
+    finish_or_retry(st, &req, done, ep, 2, request_start, shadow_tap).await
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    use axum::extract::State;
+    use axum::routing::post;
+
+    use crate::handlers::build_execution_context;
+
+    type Script = Arc<Mutex<VecDeque<(u64, u16)>>>;
+
+    /// An engine shared by every replica that answers the n-th request it
+    /// receives after the n-th scripted `(delay_ms, status)`.
+    async fn scripted_engine(script: Script) -> String {
+        async fn answer(State(script): State<Script>) -> Response {
+            let (delay_ms, status) = script.lock().unwrap().pop_front().unwrap_or((0, 200));
+            tokio::time::sleep(Duration::from_millis(delay_ms)).await;
+            let status = StatusCode::from_u16(status).unwrap();
+            (status, axum::Json(serde_json::json!({"status": status.as_u16()}))).into_response()
+        }
+        let app = axum::Router::new()
+            .route("/v1/chat/completions", post(answer))
+            .with_state(script);
+        let listener = tokio::net::TcpListener::bind("127.0.0.1:0").await.unwrap();
+        let url = format!("http://{}", listener.local_addr().unwrap());
+        tokio::spawn(async move { axum::serve(listener, app).await });
+        url
+    }
+
+    #[tokio::test]
+    async fn test_hedge_wins_when_the_primary_is_slow_or_fails() {
+        let script = Script::default();
+        let url = scripted_engine(script.clone()).await;
+        let st = AppState::for_tests("m", &[url.clone(), url]);
+        for _ in 0..MIN_SAMPLES {
+            st.hedge.model("m").observe(0.001);
+        }
+        let policy = HedgePolicy {
+            percentile: 0.5,
+            min_delay_ms: 20,
+            max_extra_load: 1.0,
+        };
+        let ctx = build_execution_context(&HeaderMap::new());
+        let headers = HeaderMap::new();
+        let body = Bytes::from_static(b"{}");
+        let hedged = || async {
+            let req = HedgedRequest {
+                ctx: &ctx,
+                headers: &headers,
+                path: "/v1/chat/completions",
+                query: "",
+                body: &body,
+                model_uid: "m",
+                plan_version: 0,
+                timeout: None,
+                policy: &policy,
+                accounting: None,
+                retry_max: 0,
+                retry_backoff_ms: 0,
+            };
+            let Ok(resp) = proxy_hedged(&st, req, Instant::now(), None).await else {
+                panic!("hedged request fell back");
+            };
+            resp
+        };
+        let won = || st.metrics.hedge_won_total.load(Ordering::Relaxed);
+
+        // The primary is still running when the hedge answers.
+        script.lock().unwrap().extend([(2_000, 200), (0, 200)]);
+        assert_eq!(hedged().await.status(), StatusCode::OK);
+        assert_eq!(won(), 1);
+
+        // The primary fails while the hedge is in flight; the hedge's answer is used.
+        script.lock().unwrap().extend([(100, 500), (300, 200)]);
+        assert_eq!(hedged().await.status(), StatusCode::OK);
+        assert_eq!(won(), 2);
+        assert_eq!(st.metrics.hedge_fired_total.load(Ordering::Relaxed), 2);
+        assert_eq!(st.metrics.hedge_lost_total.load(Ordering::Relaxed), 0);
+    }
+
+    #[test]
+    fn test_percentile() {
+        assert_eq!(percentile(&[], 0.5), None);
+        let samples: Vec<f64> = (1..=100).map(|i| i as f64).collect();
+        assert_eq!(percentile(&samples, 0.5), Some(51.0));
+        assert_eq!(percentile(&samples, 0.95), Some(95.0));
+        assert_eq!(percentile(&[3.0, 1.0, 2.0], 0.99), Some(3.0));
+    }
+
+    #[test]
+    fn test_delay_and_budget() {
+        let h = ModelHedge::default();
+        let policy = HedgePolicy {
+            percentile: 0.9,
+            min_delay_ms: 50,
+            max_extra_load: 0.1,
+        };
+        for _ in 0..MIN_SAMPLES - 1 {
+            h.observe(0.01);
+        }
+        assert_eq!(h.delay(&policy), None);
+        h.observe(0.01);
+        assert_eq!(h.delay(&policy), Some(Duration::from_millis(50)));
+        for _ in 0..MIN_SAMPLES {
+            h.observe(2.0);
+        }
+        assert_eq!(h.delay(&policy), Some(Duration::from_secs(2)));
+
+        let t = 1_000_000;
+        for _ in 0..10 {
+            h.on_request(t);
+        }
+        assert!(h.try_hedge(t, 0.1));
+        assert!(h.try_hedge(t, 0.1));
+        assert!(!h.try_hedge(t, 0.1));
+        // A new window resets the budget.
+        h.on_request(t + BUDGET_WINDOW_MS);
+        assert!(h.try_hedge(t + BUDGET_WINDOW_MS, 0.1));
+    }
+}
@@ -1,18 +1,26 @@
+use std::collections::BTreeMap;
 use std::sync::atomic::{AtomicU64, Ordering};
 use std::sync::Arc;
 use std::time::{Duration, SystemTime, UNIX_EPOCH};
 
 use dashmap::DashMap;
-use nebula_common::{EndpointInfo, EndpointStats, EndpointStatus, ExecutionContext};
+use nebula_common::{
+    EndpointInfo, EndpointStats, EndpointStatus, ExecutionContext, RequestPolicy, RoutingPolicy,
+    ServingRole,
+};
 
 pub mod strategy;
 
-use strategy::{Candidate, LeastPending, RoutingStrategy};
+use strategy::{build_strategy, Candidate, LeastPending, RoutingStrategy};
 
 /// KV cache usage threshold (fraction 0.0–1.0). When ALL endpoints exceed this,
 /// admission control kicks in and returns Overloaded.
 const KV_CACHE_OVERLOAD_THRESHOLD: f64 = 0.95;
 
+/// Low-priority requests are admitted only below this fraction of the
+/// overload threshold, keeping headroom for interactive traffic.
+const LOW_PRIORITY_HEADROOM: f64 = 0.8;
+
 #[derive(Debug, Clone, Default)]
 struct EndpointCircuitState {
     consecutive_failures: u32,
@@ -26,12 +34,39 @@ fn now_ms() -> u64 {
         .as_millis() as u64
 }
 
+/// A model's routing policy with its strategy instantiated.
+struct ModelPolicy {
+    policy: RoutingPolicy,
+    strategy: Option<Box<dyn RoutingStrategy>>,
+}
+
+/// Whether an endpoint can serve a request needing `required` context tokens.
+/// Endpoints with an unknown context window are assumed to fit.
+/// Whether `ep` may serve `phase` of a request. Whole requests (`None`) never go
+/// to prefill-only replicas.
+fn serves_phase(ep: &EndpointInfo, phase: Option<ServingRole>) -> bool {
+    match phase {
+        None => ep.role != ServingRole::Prefill,
+        Some(role) => ep.role == role,
+    }
+}
+
+fn fits_context(ep: &EndpointInfo, required: Option<u32>) -> bool {
+    match (ep.max_model_len, required) {
+        (Some(max), Some(required)) => required <= max,
+        _ => true,
+    }
+}
+
 #[derive(Debug, Clone)]
 pub enum RouteError {
     /// No ready endpoint found for the requested model.
     NoEndpoint,
     /// All endpoints are overloaded (kv_cache_usage > threshold).
     Overloaded,
+    /// Ready endpoints exist, but none has a context window large enough
+    /// for `ExecutionContext::required_context_tokens`.
+    ContextLengthExceeded { max_model_len: u32 },
 }
 
 impl std::fmt::Display for RouteError {
@@ -39,6 +74,9 @@ impl std::fmt::Display for RouteError {
         match self {
             RouteError::NoEndpoint => write!(f, "no ready endpoint"),
             RouteError::Overloaded => write!(f, "all endpoints overloaded"),
+            RouteError::ContextLengthExceeded { max_model_len } => {
+                write!(f, "request exceeds the largest context window ({max_model_len} tokens)")
+            }
         }
     }
 }
@@ -63,6 +101,12 @@ pub struct Router {
     circuit_failure_threshold: u32,
     circuit_open_ms: u64,
     endpoint_circuit: DashMap<(String, u32), EndpointCircuitState>,
+    /// model_uid → per-model policy overriding the global settings above.
+    policies: DashMap<String, Arc<ModelPolicy>>,
+    /// model_uid → request policy merged from its spec and deployment.
+    request_policies: DashMap<String, Arc<RequestPolicy>>,
+    /// LoRA adapter name → base model_uid, as declared by placement plans.
+    plan_adapters: DashMap<String, String>,
 }
 
 impl std::fmt::Debug for Router {
@@ -115,6 +159,9 @@ impl Router {
             circuit_failure_threshold,
             circuit_open_ms,
             endpoint_circuit: DashMap::new(),
+            policies: DashMap::new(),
+            request_policies: DashMap::new(),
+            plan_adapters: DashMap::new(),
         })
     }
 
@@ -122,6 +169,92 @@ impl Router {
         self.strategy.name()
     }
 
+    /// Install or replace the routing policy for `policy.model_uid`.
+    /// Fails (leaving any previous policy in place) if the strategy is invalid.
+    pub fn set_routing_policy(&self, policy: RoutingPolicy) -> Result<(), String> {
+        let strategy = policy
+            .strategy
+            .as_deref()
+            .map(|name| build_strategy(name, &policy.strategy_params))
+            .transpose()?;
+        if let Some(shadow) = &policy.shadow {
+            shadow.validate(&policy.model_uid)?;
+        }
+        if let Some(hedge) = &policy.hedge {
+            hedge.validate()?;
+        }
+        tracing::info!(
+            model_uid=%policy.model_uid,
+            strategy=strategy.as_ref().map(|s| s.name()).unwrap_or(self.strategy.name()),
+            "routing policy applied"
+        );
+        self.policies.insert(
+            policy.model_uid.clone(),
+            Arc::new(ModelPolicy { policy, strategy }),
+        );
+        Ok(())
+    }
+
+    pub fn remove_routing_policy(&self, model_uid: &str) {
+        if self.policies.remove(model_uid).is_some() {
+            tracing::info!(%model_uid, "routing policy removed, using global defaults");
+        }
+    }
+
+    /// Replace all routing policies, e.g. after a full meta store resync.
+    pub fn replace_all_routing_policies(&self, policies: Vec<RoutingPolicy>) {
+        self.policies.clear();
+        for policy in policies {
+            let model_uid = policy.model_uid.clone();
+            if let Err(e) = self.set_routing_policy(policy) {
+                tracing::warn!(%model_uid, error=%e, "ignoring invalid routing policy");
+            }
+        }
+    }
+
+    /// The routing policy currently applied for `model_uid`, if any.
+    pub fn routing_policy(&self, model_uid: &str) -> Option<RoutingPolicy> {
+        self.policies.get(model_uid).map(|p| p.policy.clone())
+    }
+
+    /// Install (`Some`) or remove (`None`) the request policy for `model_uid`.
+    pub fn set_request_policy(&self, model_uid: &str, policy: Option<RequestPolicy>) {
+        match policy {
+            Some(policy) => {
+                let changed = self
+                    .request_policies
+                    .get(model_uid)
+                    .is_none_or(|p| **p != policy);
+                if changed {
+                    tracing::info!(%model_uid, "request policy applied");
+                    self.request_policies.insert(model_uid.to_string(), Arc::new(policy));
+                }
+            }
+            None => {
+                if self.request_policies.remove(model_uid).is_some() {
+                    tracing::info!(%model_uid, "request policy removed");
+                }
+            }
+        }
+    }
+
+    /// Replace all request policies, e.g. after a full meta store resync.
+    pub fn replace_all_request_policies(&self, policies: Vec<(String, RequestPolicy)>) {
+        self.request_policies.clear();
+        for (model_uid, policy) in policies {
+            self.request_policies.insert(model_uid, Arc::new(policy));
+        }
+    }
+
+    /// The request policy currently applied for `model_uid`, if any.
+    pub fn request_policy(&self, model_uid: &str) -> Option<Arc<RequestPolicy>> {
+        self.request_policies.get(model_uid).map(|p| p.value().clone())
+    }
+
+    fn model_policy(&self, model_uid: &str) -> Option<Arc<ModelPolicy>> {
+        self.policies.get(model_uid).map(|p| p.value().clone())
+    }
+
     pub fn replace_all_endpoints(&self, infos: Vec<EndpointInfo>) {
         self.endpoints.clear();
         for info in infos {
@@ -198,6 +331,15 @@ impl Router {
     }
 
     pub fn record_endpoint_failure(&self, model_uid: &str, replica_id: u32) {
+        let policy = self.model_policy(model_uid);
+        let failure_threshold = policy
+            .as_ref()
+            .and_then(|p| p.policy.circuit_failure_threshold)
+            .unwrap_or(self.circuit_failure_threshold);
+        let open_ms = policy
+            .as_ref()
+            .and_then(|p| p.policy.circuit_open_ms)
+            .unwrap_or(self.circuit_open_ms);
         let key = (model_uid.to_string(), replica_id);
         let now = now_ms();
         let mut entry = self
@@ -210,9 +352,9 @@ impl Router {
         }
 
         entry.consecutive_failures = entry.consecutive_failures.saturating_add(1);
-        if entry.consecutive_failures >= self.circuit_failure_threshold {
+        if entry.consecutive_failures >= failure_threshold {
             entry.consecutive_failures = 0;
-            entry.open_until_ms = now.saturating_add(self.circuit_open_ms);
+            entry.open_until_ms = now.saturating_add(open_ms);
             self.circuit_open_total.fetch_add(1, Ordering::Relaxed);
         }
     }
@@ -259,10 +401,64 @@ impl Router {
         if let Some(uid) = self.model_names.get(input) {
             return uid.value().clone();
         }
+        // LoRA adapter → base model_uid?
+        if let Some(uid) = self.resolve_adapter(input) {
+            return uid;
+        }
         // Fallback: return as-is
         input.to_string()
     }
 
+    /// Replace the LoRA adapters a placement plan declares for `model_uid`.
+    pub fn set_plan_adapters(&self, model_uid: &str, adapters: Vec<String>) {
+        self.plan_adapters.retain(|_, uid| uid != model_uid);
+        for adapter in adapters {
+            self.plan_adapters.insert(adapter, model_uid.to_string());
+        }
+    }
+
+    /// Base model_uid of a LoRA adapter, declared by a plan or loaded by any replica.
+    /// Known model uids and names are never treated as adapters.
+    pub fn resolve_adapter(&self, input: &str) -> Option<String> {
+        if self.model_uids_to_names.contains_key(input) || self.model_names.contains_key(input) {
+            return None;
+        }
+        if let Some(uid) = self.plan_adapters.get(input) {
+            return Some(uid.value().clone());
+        }
+        self.endpoints
+            .iter()
+            .find(|e| e.value().lora_adapters.iter().any(|a| a == input))
+            .map(|e| e.value().model_uid.clone())
+    }
+
+    /// All known LoRA adapters as `(adapter, base model_uid)`, sorted by adapter name.
+    pub fn list_adapters(&self) -> Vec<(String, String)> {
+        let mut out: BTreeMap<String, String> = self
+            .plan_adapters
+            .iter()
+            .map(|e| (e.key().clone(), e.value().clone()))
+            .collect();
+        for e in self.endpoints.iter() {
+            for adapter in &e.value().lora_adapters {
+                out.entry(adapter.clone())
+                    .or_insert_with(|| e.value().model_uid.clone());
+            }
+        }
+        out.into_iter().collect()
+    }
+
+    /// All registered models as `(model_uid, model_name)`, sorted by uid.
+    pub fn list_models(&self) -> Vec<(String, String)> {
+        let mut out: Vec<(String, String)> = self
+            .model_uids_to_names
+            .iter()
+            .map(|e| (e.key().clone(), e.value().clone()))
+            .collect();
+        out.sort();
+        out
+    }
+
     /// Get the user-facing model_name for a given model_uid.
     pub fn get_model_name(&self, model_uid: &str) -> Option<String> {
         self.model_uids_to_names.get(model_uid).map(|v| v.value().clone())
@@ -277,7 +473,12 @@ impl Router {
             .collect()
     }
 
-    fn get_fresh_stats(&self, model_uid: &str, replica_id: u32) -> Option<EndpointStats> {
+    fn get_fresh_stats(
+        &self,
+        model_uid: &str,
+        replica_id: u32,
+        max_age_ms: u64,
+    ) -> Option<EndpointStats> {
         let stats = self
             .stats
             .get(&(model_uid.to_string(), replica_id))
@@ -288,14 +489,14 @@ impl Router {
             .unwrap_or(Duration::ZERO)
             .as_millis() as u64;
         let age_ms = now_ms.saturating_sub(stats.last_updated_ms);
-        if age_ms > self.stats_max_age_ms {
+        if age_ms > max_age_ms {
             self.route_stale_stats_dropped_total
                 .fetch_add(1, Ordering::Relaxed);
             tracing::debug!(
                 model_uid=%model_uid,
                 replica_id,
                 age_ms,
-                max_age_ms=self.stats_max_age_ms,
+                max_age_ms,
                 "dropping stale routing stats"
             );
             return None;
@@ -310,9 +511,16 @@ impl Router {
         model_uid: &str,
         plan_version: Option<u64>,
         exclude: Option<(&str, u32)>,
+        phase: Option<ServingRole>,
     ) -> Result<EndpointInfo, RouteError> {
-        // Session affinity check
-        if let Some(session_id) = ctx.session_id.as_deref() {
+        let has_adapter = |ep: &EndpointInfo| {
+            ctx.lora_adapter
+                .as_deref()
+                .is_none_or(|adapter| ep.lora_adapters.iter().any(|a| a == adapter))
+        };
+
+        // Session affinity check (whole requests only; phases are routed independently)
+        if let Some(session_id) = ctx.session_id.as_deref().filter(|_| phase.is_none()) {
             if let Some(aff) = self.session_affinity.get(session_id) {
                 let (aff_model_uid, aff_replica_id) = aff.value();
                 if aff_model_uid == model_uid {
@@ -329,7 +537,12 @@ impl Router {
                         let plan_ok = plan_version
                             .map(|v| ep.plan_version == v)
                             .unwrap_or(true);
-                        if ep.status == EndpointStatus::Ready && plan_ok {
+                        if ep.status == EndpointStatus::Ready
+                            && plan_ok
+                            && fits_context(&ep, ctx.required_context_tokens)
+                            && has_adapter(&ep)
+                            && serves_phase(&ep, None)
+                        {
                             return Ok(ep);
                         }
                     }
@@ -337,6 +550,24 @@ impl Router {
             }
         }
 
+        let policy = self.model_policy(model_uid);
+        let stats_max_age_ms = policy
+            .as_ref()
+            .and_then(|p| p.policy.stats_max_age_ms)
+            .unwrap_or(self.stats_max_age_ms);
+        let overload_threshold = policy
+            .as_ref()
+            .and_then(|p| p.policy.kv_cache_overload_threshold)
+            .unwrap_or(KV_CACHE_OVERLOAD_THRESHOLD);
+        let overload_threshold = if ctx.is_low_priority() {
+            overload_threshold * LOW_PRIORITY_HEADROOM
+        } else {
+            overload_threshold
+        };
+
+        // Largest context window among endpoints rejected only for being too small.
+        let mut largest_too_small: Option<u32> = None;
+
         // Build candidate list: filter by model_uid, Ready, optional plan_version and optional exclude
         let filtered: Vec<EndpointInfo> = self
             .endpoints
@@ -347,6 +578,10 @@ impl Router {
                     tracing::debug!(model_uid=%ep.model_uid, "endpoint filtered: uid mismatch");
                     return false;
                 }
+                if !serves_phase(ep, phase) {
+                    tracing::debug!(model_uid=%ep.model_uid, replica_id=%ep.replica_id, role=?ep.role, phase=?phase, "endpoint filtered: role mismatch");
+                    return false;
+                }
                 if ep.status != EndpointStatus::Ready {
                     tracing::debug!(model_uid=%ep.model_uid, replica_id=%ep.replica_id, status=?ep.status, "endpoint filtered: not ready");
                     return false;
@@ -369,6 +604,11 @@ impl Router {
                         return false;
                     }
                 }
+                if !fits_context(ep, ctx.required_context_tokens) {
+                    tracing::debug!(model_uid=%ep.model_uid, replica_id=%ep.replica_id, max_model_len=?ep.max_model_len, required=?ctx.required_context_tokens, "endpoint filtered: context window too small");
+                    largest_too_small = largest_too_small.max(ep.max_model_len);
+                    return false;
+                }
                 tracing::debug!(model_uid=%ep.model_uid, replica_id=%ep.replica_id, "endpoint accepted as candidate");
                 true
             })
@@ -376,13 +616,24 @@ impl Router {
             .collect();
 
         if filtered.is_empty() {
+            if let Some(max_model_len) = largest_too_small {
+                return Err(RouteError::ContextLengthExceeded { max_model_len });
+            }
             tracing::warn!(model_uid=%model_uid, total_endpoints=self.endpoints.len(), "no endpoints passed filters");
             return Err(RouteError::NoEndpoint);
         }
 
+        // Prefer replicas that already have the requested LoRA adapter loaded; otherwise any
+        // replica of the base model may load it on demand.
+        let filtered = if filtered.iter().any(has_adapter) {
+            filtered.into_iter().filter(|ep| has_adapter(ep)).collect()
+        } else {
+            filtered
+        };
+
         let stats_snapshot: Vec<Option<EndpointStats>> = filtered
             .iter()
-            .map(|ep| self.get_fresh_stats(&ep.model_uid, ep.replica_id))
+            .map(|ep| self.get_fresh_stats(&ep.model_uid, ep.replica_id, stats_max_age_ms))
             .collect();
 
         let mut candidates_data: Vec<(EndpointInfo, Option<EndpointStats>)> = filtered
@@ -423,7 +674,7 @@ impl Router {
                                 return false;
                             }
                             let usage = used as f64 / total as f64;
-                            return usage < KV_CACHE_OVERLOAD_THRESHOLD;
+                            return usage < overload_threshold;
                         }
                     }
                     true
@@ -444,13 +695,16 @@ impl Router {
             })
             .collect();
 
-        let selected = self
-            .strategy
+        let strategy = policy
+            .as_ref()
+            .and_then(|p| p.strategy.as_deref())
+            .unwrap_or(self.strategy.as_ref());
+        let selected = strategy
             .select(&candidates)
             .map(|i| candidates_data[i].0.clone())
             .ok_or(RouteError::NoEndpoint)?;
 
-        if let Some(session_id) = ctx.session_id.clone() {
+        if let Some(session_id) = ctx.session_id.clone().filter(|_| phase.is_none()) {
             self.session_affinity
                 .insert(session_id, (selected.model_uid.clone(), selected.replica_id));
         }
@@ -464,7 +718,7 @@ impl Router {
         model_uid: &str,
         plan_version: u64,
     ) -> Result<EndpointInfo, RouteError> {
-        self.route_internal(ctx, model_uid, Some(plan_version), None)
+        self.route_internal(ctx, model_uid, Some(plan_version), None, None)
     }
 
     pub fn route_with_plan_version_excluding(
@@ -474,11 +728,11 @@ impl Router {
         plan_version: u64,
         exclude: (&str, u32),
     ) -> Result<EndpointInfo, RouteError> {
-        self.route_internal(ctx, model_uid, Some(plan_version), Some(exclude))
+        self.route_internal(ctx, model_uid, Some(plan_version), Some(exclude), None)
     }
 
     pub fn route(&self, ctx: &ExecutionContext, model_uid: &str) -> Result<EndpointInfo, RouteError> {
-        self.route_internal(ctx, model_uid, None, None)
+        self.route_internal(ctx, model_uid, None, None, None)
     }
 
     pub fn route_excluding(
@@ -487,6 +741,38 @@ impl Router {
         model_uid: &str,
         exclude: (&str, u32),
     ) -> Result<EndpointInfo, RouteError> {
-        self.route_internal(ctx, model_uid, None, Some(exclude))
+        self.route_internal(ctx, model_uid, None, Some(exclude), None)
+    }
+
+    /// Route one phase of a prefill/decode disaggregated request to a replica of that role.
+    pub fn route_phase(
+        &self,
+        ctx: &ExecutionContext,
+        model_uid: &str,
+        plan_version: Option<u64>,
+        phase: ServingRole,
+        exclude: Option<(&str, u32)>,
+    ) -> Result<EndpointInfo, RouteError> {
+        self.route_internal(ctx, model_uid, plan_version, exclude, Some(phase))
+    }
+
+    /// Whether `model_uid` has Ready prefill and decode replicas, so requests
+    /// should be split across the two pools.
+    /// Whether any endpoint of `model_uid` reports its context window, so that
+    /// requests need a context estimate to be routed.
+    pub fn has_context_limits(&self, model_uid: &str) -> bool {
+        self.endpoints
+            .iter()
+            .any(|e| e.value().model_uid == model_uid && e.value().max_model_len.is_some())
+    }
+
+    pub fn has_disaggregated_pools(&self, model_uid: &str) -> bool {
+        let ready_with = |role: ServingRole| {
+            self.endpoints.iter().any(|e| {
+                let ep = e.value();
+                ep.model_uid == model_uid && ep.role == role && ep.status == EndpointStatus::Ready
+            })
+        };
+        ready_with(ServingRole::Prefill) && ready_with(ServingRole::Decode)
     }
 }
@@ -1,8 +1,14 @@
 mod args;
+mod grpc;
 mod handlers;
+mod hedge;
 mod metrics;
+mod pd;
+mod shadow;
 mod state;
 mod sync;
+mod tokens;
+mod usage;
 
 use std::sync::atomic::AtomicU64;
 use std::sync::Arc;
@@ -16,10 +22,13 @@ use axum::{
 use clap::Parser;
 
 use crate::args::Args;
-use crate::handlers::{healthz, proxy_chat_completions};
+use crate::handlers::{healthz, list_models, proxy_chat_completions};
 use crate::metrics::{metrics_handler, track_requests};
 use crate::state::AppState;
-use crate::sync::{endpoints_sync_loop, placement_sync_loop, stats_sync_loop};
+use crate::sync::{
+    api_keys_sync_loop, endpoints_sync_loop, placement_sync_loop, request_policy_sync_loop, routing_policy_sync_loop,
+    stats_sync_loop,
+};
 
 #[tokio::main]
 async fn main() -> anyhow::Result<()> {
@@ -70,6 +79,22 @@ async fn main() -> anyhow::Result<()> {
         }
     });
 
+    let store_for_policies = store.clone();
+    let router_for_policies = router.clone();
+    tokio::spawn(async move {
+        if let Err(e) = routing_policy_sync_loop(store_for_policies, router_for_policies).await {
+            tracing::error!(error=%e, "routing policy sync loop exited");
+        }
+    });
+
+    let store_for_request_policies = store.clone();
+    let router_for_request_policies = router.clone();
+    tokio::spawn(async move {
+        if let Err(e) = request_policy_sync_loop(store_for_request_policies, router_for_request_policies).await {
+            tracing::error!(error=%e, "request policy sync loop exited");
+        }
+    });
+
     let router_for_stats = router.clone();
     if let Some(url) = args.xtrace_url.clone() {
         let token = args.xtrace_token.clone().unwrap_or_default();
@@ -97,6 +122,10 @@ async fn main() -> anyhow::Result<()> {
         .ok()
         .and_then(|v| v.parse::<usize>().ok())
         .unwrap_or(4 * 1024 * 1024);
+    let stream_body_threshold_bytes = std::env::var("NEBULA_ROUTER_STREAM_BODY_THRESHOLD_BYTES")
+        .ok()
+        .and_then(|v| v.parse::<usize>().ok())
+        .unwrap_or(1024 * 1024);
     let retry_max = std::env::var("NEBULA_ROUTER_RETRY_MAX")
         .ok()
         .and_then(|v| v.parse::<u32>().ok())
@@ -106,15 +135,43 @@ async fn main() -> anyhow::Result<()> {
         .and_then(|v| v.parse::<u64>().ok())
         .unwrap_or(75);
 
+    // Local model cache used to find tokenizer.json files for context estimation.
+    let model_dir = std::env::var("NEBULA_ROUTER_MODEL_DIR")
+        .ok()
+        .filter(|v| !v.is_empty())
+        .map(std::path::PathBuf::from);
+    let tokens = Arc::new(tokens::TokenEstimator::new(model_dir));
+
     let auth = nebula_common::auth::parse_auth_from_env();
+    if auth.api_keys_enabled {
+        let store_for_keys = store.clone();
+        let keys = auth.api_keys.clone();
+        tokio::spawn(async move {
+            if let Err(e) = api_keys_sync_loop(store_for_keys, keys).await {
+                tracing::error!(error=%e, "api keys sync loop exited");
+            }
+        });
+    }
+
+    // xtrace client for reporting shadow traffic results.
+    let shadow_xtrace = args.xtrace_url.as_deref().and_then(|url| {
+        xtrace_client::Client::new(url, args.xtrace_token.as_deref().unwrap_or(""))
+            .map_err(|e| tracing::warn!(error=%e, "failed to create xtrace client, shadow metrics stay local"))
+            .ok()
+    });
 
     let st = AppState {
         model_uid: args.model_uid,
         router,
         http,
+        grpc: Arc::new(grpc::GrpcClients::default()),
         plan_version,
         metrics,
+        tokens,
+        shadow: Arc::new(shadow::ShadowMirror::new(shadow_xtrace)),
+        hedge: Arc::new(hedge::Hedging::default()),
         max_request_body_bytes,
+        stream_body_threshold_bytes,
         retry_max,
         retry_backoff_ms,
         auth,
@@ -126,9 +183,11 @@ async fn main() -> anyhow::Result<()> {
         .route("/v1/completions", post(proxy_chat_completions))
         .route("/v1/embeddings", post(proxy_chat_completions))
         .route("/v1/rerank", post(proxy_chat_completions))
+        .route("/v1/audio/*path", post(proxy_chat_completions))
+        .route("/v1/images/*path", post(proxy_chat_completions))
         .route(
             "/v1/models",
-            post(proxy_chat_completions).get(proxy_chat_completions),
+            post(proxy_chat_completions).get(list_models),
         )
         .layer(middleware::from_fn_with_state(
             st.clone(),
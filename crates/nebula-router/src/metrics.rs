@@ -9,6 +9,7 @@ use axum::{
     response::{IntoResponse, Response},
 };
 use dashmap::DashMap;
+use nebula_common::TokenUsage;
 
 use crate::state::AppState;
 
@@ -71,6 +72,13 @@ impl Histogram {
     }
 }
 
+/// Prompt and completion tokens served.
+#[derive(Debug, Default)]
+pub struct TokenCounter {
+    pub prompt_tokens: AtomicU64,
+    pub completion_tokens: AtomicU64,
+}
+
 /// Per-model request counters.
 #[derive(Debug, Default)]
 pub struct ModelCounter {
@@ -90,17 +98,42 @@ pub struct Metrics {
     pub retry_total: AtomicU64,
     pub retry_success_total: AtomicU64,
     pub request_too_large_total: AtomicU64,
+    pub request_body_streamed_total: AtomicU64,
+    pub context_length_rejected_total: AtomicU64,
+    pub deadline_exceeded_total: AtomicU64,
     pub upstream_error_connect_total: AtomicU64,
     pub upstream_error_timeout_total: AtomicU64,
     pub upstream_error_5xx_total: AtomicU64,
     pub upstream_error_other_total: AtomicU64,
+    pub pd_prefill_total: AtomicU64,
+    pub pd_prefill_failed_total: AtomicU64,
+    pub pd_decode_total: AtomicU64,
+    pub pd_decode_failed_total: AtomicU64,
+    pub shadow_mirrored_total: AtomicU64,
+    pub shadow_dropped_total: AtomicU64,
+    pub shadow_failed_total: AtomicU64,
+    pub shadow_output_chars_total: AtomicU64,
+    pub shadow_output_match_total: AtomicU64,
+    pub shadow_output_mismatch_total: AtomicU64,
+    pub hedge_fired_total: AtomicU64,
+    pub hedge_won_total: AtomicU64,
+    pub hedge_lost_total: AtomicU64,
+    pub hedge_budget_exhausted_total: AtomicU64,
+    pub client_cancelled_total: AtomicU64,
+    pub cancelled_tokens_saved_total: AtomicU64,
 
     /// Per-model E2E latency histogram (seconds).
     pub e2e_latency: DashMap<String, Histogram>,
     /// Per-model TTFT histogram (seconds) — only for SSE streaming responses.
     pub ttft: DashMap<String, Histogram>,
+    /// Per-model prefill phase latency histogram (seconds) for disaggregated serving.
+    pub prefill_latency: DashMap<String, Histogram>,
+    /// Per-shadow-model latency histogram (seconds) for mirrored requests.
+    pub shadow_latency: DashMap<String, Histogram>,
     /// Per-model request counters.
     pub model_counters: DashMap<String, ModelCounter>,
+    /// Token counters by (model_uid, principal).
+    pub token_counters: DashMap<(String, String), TokenCounter>,
 }
 
 impl Metrics {
@@ -118,6 +151,20 @@ impl Metrics {
             .observe(seconds);
     }
 
+    pub fn observe_prefill_latency(&self, model_uid: &str, seconds: f64) {
+        self.prefill_latency
+            .entry(model_uid.to_string())
+            .or_insert_with(|| Histogram::new(HISTOGRAM_BUCKETS))
+            .observe(seconds);
+    }
+
+    pub fn observe_shadow_latency(&self, model_uid: &str, seconds: f64) {
+        self.shadow_latency
+            .entry(model_uid.to_string())
+            .or_insert_with(|| Histogram::new(HISTOGRAM_BUCKETS))
+            .observe(seconds);
+    }
+
     pub fn record_model_status(&self, model_uid: &str, status: u16) {
         let counter = self
             .model_counters
@@ -133,6 +180,27 @@ impl Metrics {
         }
     }
 
+    pub fn record_tokens(&self, model_uid: &str, principal: &str, usage: TokenUsage) {
+        let counter = self
+            .token_counters
+            .entry((model_uid.to_string(), principal.to_string()))
+            .or_default();
+        counter
+            .prompt_tokens
+            .fetch_add(usage.prompt_tokens, Ordering::Relaxed);
+        counter
+            .completion_tokens
+            .fetch_add(usage.completion_tokens, Ordering::Relaxed);
+    }
+
+    /// A stream abandoned by its client; `tokens_saved` is the completion
+    /// budget the engine no longer has to generate.
+    pub fn record_cancelled(&self, tokens_saved: u64) {
+        self.client_cancelled_total.fetch_add(1, Ordering::Relaxed);
+        self.cancelled_tokens_saved_total
+            .fetch_add(tokens_saved, Ordering::Relaxed);
+    }
+
     pub fn record_upstream_error(&self, kind: &str) {
         match kind {
             "connect" => {
@@ -195,6 +263,24 @@ pub async fn metrics_handler(State(st): State<AppState>) -> impl IntoResponse {
          nebula_router_request_too_large_total {}\n",
         st.metrics.request_too_large_total.load(Ordering::Relaxed),
     ));
+    body.push_str(&format!(
+        "# HELP nebula_router_request_body_streamed_total Requests whose body was streamed to the engine unbuffered.\n\
+         # TYPE nebula_router_request_body_streamed_total counter\n\
+         nebula_router_request_body_streamed_total {}\n",
+        st.metrics.request_body_streamed_total.load(Ordering::Relaxed),
+    ));
+    body.push_str(&format!(
+        "# HELP nebula_router_context_length_rejected_total Requests rejected because no endpoint's context window fits them.\n\
+         # TYPE nebula_router_context_length_rejected_total counter\n\
+         nebula_router_context_length_rejected_total {}\n",
+        st.metrics.context_length_rejected_total.load(Ordering::Relaxed),
+    ));
+    body.push_str(&format!(
+        "# HELP nebula_router_deadline_exceeded_total Requests failed because their deadline passed.\n\
+         # TYPE nebula_router_deadline_exceeded_total counter\n\
+         nebula_router_deadline_exceeded_total {}\n",
+        st.metrics.deadline_exceeded_total.load(Ordering::Relaxed),
+    ));
     body.push_str("# HELP nebula_router_upstream_error_total Upstream errors by kind.\n# TYPE nebula_router_upstream_error_total counter\n");
     body.push_str(&format!(
         "nebula_router_upstream_error_total{{kind=\"connect\"}} {}\n",
@@ -212,6 +298,57 @@ pub async fn metrics_handler(State(st): State<AppState>) -> impl IntoResponse {
         "nebula_router_upstream_error_total{{kind=\"other\"}} {}\n",
         st.metrics.upstream_error_other_total.load(Ordering::Relaxed),
     ));
+    body.push_str("# HELP nebula_router_pd_phase_total Prefill/decode disaggregated request phases dispatched.\n# TYPE nebula_router_pd_phase_total counter\n");
+    body.push_str(&format!(
+        "nebula_router_pd_phase_total{{phase=\"prefill\"}} {}\nnebula_router_pd_phase_total{{phase=\"decode\"}} {}\n",
+        st.metrics.pd_prefill_total.load(Ordering::Relaxed),
+        st.metrics.pd_decode_total.load(Ordering::Relaxed),
+    ));
+    body.push_str("# HELP nebula_router_pd_phase_failed_total Failed prefill/decode phases (a failed prefill falls back to decode-side prefill).\n# TYPE nebula_router_pd_phase_failed_total counter\n");
+    body.push_str(&format!(
+        "nebula_router_pd_phase_failed_total{{phase=\"prefill\"}} {}\nnebula_router_pd_phase_failed_total{{phase=\"decode\"}} {}\n",
+        st.metrics.pd_prefill_failed_total.load(Ordering::Relaxed),
+        st.metrics.pd_decode_failed_total.load(Ordering::Relaxed),
+    ));
+    body.push_str("# HELP nebula_router_shadow_requests_total Shadow (mirrored) requests by outcome.\n# TYPE nebula_router_shadow_requests_total counter\n");
+    body.push_str(&format!(
+        "nebula_router_shadow_requests_total{{result=\"mirrored\"}} {}\nnebula_router_shadow_requests_total{{result=\"dropped\"}} {}\nnebula_router_shadow_requests_total{{result=\"failed\"}} {}\n",
+        st.metrics.shadow_mirrored_total.load(Ordering::Relaxed),
+        st.metrics.shadow_dropped_total.load(Ordering::Relaxed),
+        st.metrics.shadow_failed_total.load(Ordering::Relaxed),
+    ));
+    body.push_str(&format!(
+        "# HELP nebula_router_shadow_output_chars_total Characters generated by shadow requests.\n\
+         # TYPE nebula_router_shadow_output_chars_total counter\n\
+         nebula_router_shadow_output_chars_total {}\n",
+        st.metrics.shadow_output_chars_total.load(Ordering::Relaxed),
+    ));
+    body.push_str("# HELP nebula_router_shadow_output_compared_total Shadow outputs compared with the primary response.\n# TYPE nebula_router_shadow_output_compared_total counter\n");
+    body.push_str(&format!(
+        "nebula_router_shadow_output_compared_total{{result=\"match\"}} {}\nnebula_router_shadow_output_compared_total{{result=\"mismatch\"}} {}\n",
+        st.metrics.shadow_output_match_total.load(Ordering::Relaxed),
+        st.metrics.shadow_output_mismatch_total.load(Ordering::Relaxed),
+    ));
+    body.push_str("# HELP nebula_router_hedge_total Hedged requests by outcome.\n# TYPE nebula_router_hedge_total counter\n");
+    body.push_str(&format!(
+        "nebula_router_hedge_total{{result=\"fired\"}} {}\nnebula_router_hedge_total{{result=\"won\"}} {}\nnebula_router_hedge_total{{result=\"lost\"}} {}\nnebula_router_hedge_total{{result=\"budget_exhausted\"}} {}\n",
+        st.metrics.hedge_fired_total.load(Ordering::Relaxed),
+        st.metrics.hedge_won_total.load(Ordering::Relaxed),
+        st.metrics.hedge_lost_total.load(Ordering::Relaxed),
+        st.metrics.hedge_budget_exhausted_total.load(Ordering::Relaxed),
+    ));
+    body.push_str(&format!(
+        "# HELP nebula_router_client_cancelled_total Streams whose client disconnected; the upstream request was aborted.\n\
+         # TYPE nebula_router_client_cancelled_total counter\n\
+         nebula_router_client_cancelled_total {}\n",
+        st.metrics.client_cancelled_total.load(Ordering::Relaxed),
+    ));
+    body.push_str(&format!(
+        "# HELP nebula_router_cancelled_tokens_saved_total Requested completion tokens not generated because the client disconnected.\n\
+         # TYPE nebula_router_cancelled_tokens_saved_total counter\n\
+         nebula_router_cancelled_tokens_saved_total {}\n",
+        st.metrics.cancelled_tokens_saved_total.load(Ordering::Relaxed),
+    ));
     body.push_str(&format!(
         "# HELP nebula_router_xtrace_query_errors_total xtrace query errors in stats sync loop.\n\
          # TYPE nebula_router_xtrace_query_errors_total counter\n\
@@ -274,6 +411,24 @@ pub async fn metrics_handler(State(st): State<AppState>) -> impl IntoResponse {
         ));
     }
 
+    // Token usage
+    body.push_str("# HELP nebula_route_prompt_tokens_total Prompt tokens by model and principal.\n# TYPE nebula_route_prompt_tokens_total counter\n");
+    for entry in st.metrics.token_counters.iter() {
+        let (model, principal) = entry.key();
+        body.push_str(&format!(
+            "nebula_route_prompt_tokens_total{{model_uid=\"{model}\",principal=\"{principal}\"}} {}\n",
+            entry.value().prompt_tokens.load(Ordering::Relaxed)
+        ));
+    }
+    body.push_str("# HELP nebula_route_completion_tokens_total Completion tokens by model and principal.\n# TYPE nebula_route_completion_tokens_total counter\n");
+    for entry in st.metrics.token_counters.iter() {
+        let (model, principal) = entry.key();
+        body.push_str(&format!(
+            "nebula_route_completion_tokens_total{{model_uid=\"{model}\",principal=\"{principal}\"}} {}\n",
+            entry.value().completion_tokens.load(Ordering::Relaxed)
+        ));
+    }
+
     // E2E latency histograms
     body.push_str("# HELP nebula_route_latency_seconds E2E request latency.\n# TYPE nebula_route_latency_seconds histogram\n");
     for entry in st.metrics.e2e_latency.iter() {
@@ -286,6 +441,18 @@ pub async fn metrics_handler(State(st): State<AppState>) -> impl IntoResponse {
         body.push_str(&entry.value().format_prometheus("nebula_route_ttft_seconds", entry.key()));
     }
 
+    // Prefill phase histograms
+    body.push_str("# HELP nebula_route_prefill_latency_seconds Prefill phase latency for disaggregated serving.\n# TYPE nebula_route_prefill_latency_seconds histogram\n");
+    for entry in st.metrics.prefill_latency.iter() {
+        body.push_str(&entry.value().format_prometheus("nebula_route_prefill_latency_seconds", entry.key()));
+    }
+
+    // Shadow latency histograms
+    body.push_str("# HELP nebula_route_shadow_latency_seconds Latency of mirrored requests by shadow model.\n# TYPE nebula_route_shadow_latency_seconds histogram\n");
+    for entry in st.metrics.shadow_latency.iter() {
+        body.push_str(&entry.value().format_prometheus("nebula_route_shadow_latency_seconds", entry.key()));
+    }
+
     (
         axum::http::StatusCode::OK,
         [(axum::http::header::CONTENT_TYPE, "text/plain; version=0.0.4; charset=utf-8")],
@@ -0,0 +1,255 @@
+//! Prefill/decode disaggregated request orchestration.
+//!
+//! When a model runs as separate prefill and decode pools, a completion is
+//! split in two phases. The prompt is first sent to a prefill replica with
+//! `max_tokens = 1` and `kv_transfer_params.do_remote_decode = true`; the engine
+//! answers with `kv_transfer_params` describing where its KV cache can be
+//! pulled from. Those params are attached to the original request, which is
+//! then sent to a decode replica that streams the completion back.
+//!
+//! Both phases use the engines' OpenAI HTTP API; gRPC shims are not used here.
+
+use std::sync::atomic::Ordering;
+use std::time::{Duration, Instant};
+
+use axum::{
+    http::HeaderMap,
+    response::{IntoResponse, Response},
+};
+use serde_json::{json, Value};
+
+use nebula_common::{ApiError, EndpointInfo, ErrorCode, ExecutionContext, ServingRole};
+
+use crate::handlers::{
+    classify_reqwest_error, deadline_exceeded, route_error_response, to_reqwest_headers, upstream_response,
+};
+use crate::state::AppState;
+use crate::usage::UsageAccounting;
+
+/// A buffered chat/completions request for a model with prefill and decode pools.
+pub struct DisaggregatedRequest<'a> {
+    pub ctx: &'a ExecutionContext,
+    pub headers: &'a HeaderMap,
+    pub path: &'a str,
+    pub model_uid: &'a str,
+    pub plan_version: Option<u64>,
+    /// Per-phase upstream timeout.
+    pub timeout: Option<Duration>,
+    pub accounting: Option<&'a UsageAccounting>,
+}
+
+/// Body for the prefill phase: compute the prompt only and keep the KV cache
+/// for a remote decode.
+pub fn prefill_body(body: &Value) -> Value {
+    let mut out = body.clone();
+    if let Some(obj) = out.as_object_mut() {
+        obj.insert("max_tokens".to_string(), json!(1));
+        if obj.contains_key("max_completion_tokens") {
+            obj.insert("max_completion_tokens".to_string(), json!(1));
+        }
+        obj.insert("stream".to_string(), json!(false));
+        obj.remove("stream_options");
+        obj.insert(
+            "kv_transfer_params".to_string(),
+            json!({
+                "do_remote_decode": true,
+                "do_remote_prefill": false,
+                "remote_engine_id": null,
+                "remote_block_ids": null,
+                "remote_host": null,
+                "remote_port": null
+            }),
+        );
+    }
+    out
+}
+
+/// Body for the decode phase: the original request plus the prefill's KV hand-off params.
+pub fn decode_body(body: &Value, kv_transfer_params: Option<Value>) -> Value {
+    let mut out = body.clone();
+    if let (Some(obj), Some(params)) = (out.as_object_mut(), kv_transfer_params) {
+        obj.insert("kv_transfer_params".to_string(), params);
+    }
+    out
+}
+
+enum PrefillOutcome {
+    /// KV cache is ready; the params (if the engine returned any) go to the decode replica.
+    Ready(Option<Value>),
+    /// The prefill replica failed; the decode replica will recompute the prompt.
+    Failed,
+    /// The request itself was rejected; relay the engine's answer to the client.
+    Rejected(reqwest::Response),
+}
+
+async fn run_prefill(
+    st: &AppState,
+    ep: &EndpointInfo,
+    req: &DisaggregatedRequest<'_>,
+    body: &Value,
+) -> PrefillOutcome {
+    let Some(base) = ep.base_url.as_deref() else {
+        return PrefillOutcome::Failed;
+    };
+    let url = format!("{}{}", base.trim_end_matches('/'), req.path);
+    let mut builder = st
+        .http
+        .post(url)
+        .headers(to_reqwest_headers(req.headers))
+        .header(reqwest::header::CONTENT_TYPE, "application/json")
+        .body(prefill_body(body).to_string());
+    if let Some(timeout) = req.timeout {
+        builder = builder.timeout(timeout);
+    }
+
+    match builder.send().await {
+        Ok(resp) if resp.status().is_success() => {
+            st.router.record_endpoint_success(&ep.model_uid, ep.replica_id);
+            let params = resp
+                .json::<Value>()
+                .await
+                .ok()
+                .and_then(|v| v.get("kv_transfer_params").cloned())
+                .filter(|p| !p.is_null());
+            if params.is_none() {
+                tracing::warn!(model_uid=%ep.model_uid, replica_id=ep.replica_id, "prefill response carried no kv_transfer_params");
+            }
+            PrefillOutcome::Ready(params)
+        }
+        Ok(resp) if resp.status().is_client_error() => PrefillOutcome::Rejected(resp),
+        Ok(resp) => {
+            st.router.record_endpoint_failure(&ep.model_uid, ep.replica_id);
+            st.metrics.record_upstream_error("upstream_5xx");
+            tracing::error!(model_uid=%ep.model_uid, replica_id=ep.replica_id, status=%resp.status(), "prefill request failed");
+            PrefillOutcome::Failed
+        }
+        Err(e) => {
+            st.router.record_endpoint_failure(&ep.model_uid, ep.replica_id);
+            st.metrics.record_upstream_error(classify_reqwest_error(&e));
+            tracing::error!(model_uid=%ep.model_uid, replica_id=ep.replica_id, error=%e, "prefill request failed");
+            PrefillOutcome::Failed
+        }
+    }
+}
+
+/// Serve a completion by running prefill and decode on separate replicas.
+pub async fn proxy_disaggregated(
+    st: &AppState,
+    req: DisaggregatedRequest<'_>,
+    body: Value,
+    request_start: Instant,
+) -> Response {
+    let model_uid = req.model_uid;
+
+    let prefill_start = Instant::now();
+    let kv_transfer_params =
+        match st
+            .router
+            .route_phase(req.ctx, model_uid, req.plan_version, ServingRole::Prefill, None)
+        {
+            Ok(ep) => {
+                st.metrics.pd_prefill_total.fetch_add(1, Ordering::Relaxed);
+                match run_prefill(st, &ep, &req, &body).await {
+                    PrefillOutcome::Ready(params) => {
+                        st.metrics
+                            .observe_prefill_latency(model_uid, prefill_start.elapsed().as_secs_f64());
+                        params
+                    }
+                    PrefillOutcome::Failed => {
+                        st.metrics.pd_prefill_failed_total.fetch_add(1, Ordering::Relaxed);
+                        None
+                    }
+                    PrefillOutcome::Rejected(resp) => {
+                        return upstream_response(st, model_uid, request_start, resp, req.accounting).await;
+                    }
+                }
+            }
+            Err(e) => {
+                tracing::warn!(%model_uid, error=%e, "no prefill replica available, decoding without kv hand-off");
+                st.metrics.pd_prefill_failed_total.fetch_add(1, Ordering::Relaxed);
+                None
+            }
+        };
+
+    let ep = match st
+        .router
+        .route_phase(req.ctx, model_uid, req.plan_version, ServingRole::Decode, None)
+    {
+        Ok(ep) => ep,
+        Err(e) => return route_error_response(st, req.ctx, model_uid, e),
+    };
+    let Some(base) = ep.base_url.as_deref() else {
+        st.metrics.record_model_status(model_uid, 503);
+        return ApiError::new(ErrorCode::ServiceUnavailable, "endpoint missing base_url")
+            .into_response();
+    };
+
+    st.metrics.pd_decode_total.fetch_add(1, Ordering::Relaxed);
+    let url = format!("{}{}", base.trim_end_matches('/'), req.path);
+    let mut builder = st
+        .http
+        .post(url)
+        .headers(to_reqwest_headers(req.headers))
+        .header(reqwest::header::CONTENT_TYPE, "application/json")
+        .body(decode_body(&body, kv_transfer_params).to_string());
+    if let Some(timeout) = req.timeout {
+        builder = builder.timeout(timeout);
+    }
+
+    match builder.send().await {
+        Ok(resp) => {
+            if resp.status().is_server_error() {
+                st.router.record_endpoint_failure(&ep.model_uid, ep.replica_id);
+                st.metrics.record_upstream_error("upstream_5xx");
+                st.metrics.pd_decode_failed_total.fetch_add(1, Ordering::Relaxed);
+            } else {
+                st.router.record_endpoint_success(&ep.model_uid, ep.replica_id);
+            }
+            upstream_response(st, model_uid, request_start, resp, req.accounting).await
+        }
+        Err(e) => {
+            st.router.record_endpoint_failure(&ep.model_uid, ep.replica_id);
+            st.metrics.record_upstream_error(classify_reqwest_error(&e));
+            st.metrics.pd_decode_failed_total.fetch_add(1, Ordering::Relaxed);
+            tracing::error!(%model_uid, replica_id=ep.replica_id, error=%e, "decode request failed");
+            st.metrics
+                .observe_e2e_latency(model_uid, request_start.elapsed().as_secs_f64());
+            if e.is_timeout() && req.ctx.is_expired() {
+                return deadline_exceeded(st, model_uid);
+            }
+            st.metrics.record_model_status(model_uid, 502);
+            ApiError::new(ErrorCode::UpstreamError, "upstream request failed").into_response()
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_prefill_and_decode_bodies() {
+        let body = json!({
+            "model": "m",
+            "messages": [{"role": "user", "content": "hi"}],
+            "max_tokens": 256,
+            "stream": true,
+            "stream_options": {"include_usage": true}
+        });
+
+        let prefill = prefill_body(&body);
+        assert_eq!(prefill["max_tokens"], 1);
+        assert_eq!(prefill["stream"], false);
+        assert!(prefill.get("stream_options").is_none());
+        assert_eq!(prefill["kv_transfer_params"]["do_remote_decode"], true);
+        assert_eq!(prefill["messages"], body["messages"]);
+
+        let params = json!({"remote_engine_id": "e1", "remote_block_ids": [1, 2]});
+        let decode = decode_body(&body, Some(params.clone()));
+        assert_eq!(decode["kv_transfer_params"], params);
+        assert_eq!(decode["max_tokens"], 256);
+        assert_eq!(decode["stream"], true);
+
+        assert_eq!(decode_body(&body, None), body);
+    }
+}
@@ -0,0 +1,324 @@
+//! Shadow (mirrored) traffic to a candidate model.
+//!
+//! A model's routing policy may carry a [`ShadowPolicy`]. For a sampled share
+//! of its buffered requests the router sends a copy to the shadow model in the
+//! background. The client only ever sees the primary response; the shadow's
+//! latency, status, output length and (optionally) output similarity to the
+//! primary are recorded to Prometheus metrics and xtrace.
+
+use std::collections::HashMap;
+use std::sync::atomic::Ordering;
+use std::sync::Arc;
+use std::time::{Duration, Instant};
+
+use axum::http::HeaderMap;
+use bytes::Bytes;
+use dashmap::DashMap;
+use tokio::sync::{oneshot, OwnedSemaphorePermit, Semaphore};
+
+use nebula_common::ShadowPolicy;
+
+use crate::handlers::{build_execution_context, to_reqwest_headers};
+use crate::state::AppState;
+
+/// How long a shadow request waits for the primary body it is compared against.
+const PRIMARY_WAIT: Duration = Duration::from_secs(300);
+
+/// Per-source-model concurrency limits for mirrored requests.
+pub struct ShadowMirror {
+    limits: DashMap<String, (u32, Arc<Semaphore>)>,
+    xtrace: Option<xtrace_client::Client>,
+}
+
+impl ShadowMirror {
+    pub fn new(xtrace: Option<xtrace_client::Client>) -> Self {
+        Self {
+            limits: DashMap::new(),
+            xtrace,
+        }
+    }
+
+    /// Take a mirror slot for `source_uid`, or `None` when `max` are already in flight.
+    fn permit(&self, source_uid: &str, max: u32) -> Option<OwnedSemaphorePermit> {
+        let sem = {
+            let mut entry = self
+                .limits
+                .entry(source_uid.to_string())
+                .or_insert_with(|| (max, Arc::new(Semaphore::new(max as usize))));
+            if entry.0 != max {
+                // Limit changed by a policy update; in-flight mirrors drain on the old semaphore.
+                *entry = (max, Arc::new(Semaphore::new(max as usize)));
+            }
+            entry.1.clone()
+        };
+        sem.try_acquire_owned().ok()
+    }
+}
+
+fn sampled(rate: f64) -> bool {
+    if rate >= 1.0 {
+        return true;
+    }
+    if rate <= 0.0 {
+        return false;
+    }
+    let r = (uuid::Uuid::new_v4().as_u128() >> 64) as u64;
+    (r as f64 / u64::MAX as f64) < rate
+}
+
+/// Generated text in an OpenAI chat/completions response, JSON or SSE.
+pub fn output_text(body: &[u8]) -> String {
+    fn choice_text(v: &serde_json::Value, out: &mut String) {
+        let Some(choices) = v.get("choices").and_then(|c| c.as_array()) else {
+            return;
+        };
+        for c in choices {
+            let text = c
+                .pointer("/message/content")
+                .or_else(|| c.pointer("/delta/content"))
+                .or_else(|| c.get("text"))
+                .and_then(|t| t.as_str());
+            if let Some(text) = text {
+                out.push_str(text);
+            }
+        }
+    }
+
+    let mut out = String::new();
+    if let Ok(v) = serde_json::from_slice::<serde_json::Value>(body) {
+        choice_text(&v, &mut out);
+        return out;
+    }
+    for line in String::from_utf8_lossy(body).lines() {
+        let Some(data) = line.strip_prefix("data:").map(str::trim) else {
+            continue;
+        };
+        if let Ok(v) = serde_json::from_str::<serde_json::Value>(data) {
+            choice_text(&v, &mut out);
+        }
+    }
+    out
+}
+
+/// Dice similarity of the two outputs' whitespace-separated words (1.0 = same words).
+pub fn output_similarity(a: &str, b: &str) -> f64 {
+    let mut counts: HashMap<&str, i64> = HashMap::new();
+    let mut total = 0usize;
+    for w in a.split_whitespace() {
+        *counts.entry(w).or_default() += 1;
+        total += 1;
+    }
+    let mut common = 0usize;
+    for w in b.split_whitespace() {
+        total += 1;
+        if let Some(n) = counts.get_mut(w).filter(|n| **n > 0) {
+            *n -= 1;
+            common += 1;
+        }
+    }
+    if total == 0 {
+        return 1.0;
+    }
+    (2 * common) as f64 / total as f64
+}
+
+/// Mirror a buffered request to the source model's shadow target, if its policy
+/// samples it. Returns a sender for the primary response body when the rule
+/// compares outputs; dropping it skips the comparison.
+pub fn mirror(
+    st: &AppState,
+    source_uid: &str,
+    path: &str,
+    headers: &HeaderMap,
+    body: &Bytes,
+) -> Option<oneshot::Sender<Bytes>> {
+    let policy: ShadowPolicy = st.router.routing_policy(source_uid)?.shadow?;
+    if !sampled(policy.sample_rate) {
+        return None;
+    }
+    let Some(permit) = st.shadow.permit(source_uid, policy.max_concurrency) else {
+        st.metrics.shadow_dropped_total.fetch_add(1, Ordering::Relaxed);
+        return None;
+    };
+
+    let target_uid = st.router.resolve_model(&policy.target_model);
+    let target_name = st
+        .router
+        .get_model_name(&target_uid)
+        .unwrap_or_else(|| policy.target_model.clone());
+    let mut json = serde_json::from_slice::<serde_json::Value>(body)
+        .ok()
+        .filter(|v| v.is_object())?;
+    json["model"] = serde_json::Value::String(target_name);
+
+    // Shadow requests must not move the client's session affinity.
+    let mut ctx = build_execution_context(headers);
+    ctx.session_id = None;
+    let mut req_headers = to_reqwest_headers(headers);
+    req_headers.insert("x-nebula-shadow", reqwest::header::HeaderValue::from_static("1"));
+
+    let (tap, primary) = if policy.compare_output {
+        let (tx, rx) = oneshot::channel::<Bytes>();
+        (Some(tx), Some(rx))
+    } else {
+        (None, None)
+    };
+
+    st.metrics.shadow_mirrored_total.fetch_add(1, Ordering::Relaxed);
+    let st = st.clone();
+    let source_uid = source_uid.to_string();
+    let path = path.to_string();
+    tokio::spawn(async move {
+        let _permit = permit;
+        let start = Instant::now();
+        let (status, output) = match st.router.route(&ctx, &target_uid) {
+            Ok(ep) => match ep.base_url.as_deref() {
+                Some(base) => {
+                    let url = format!("{}{}", base.trim_end_matches('/'), path);
+                    let sent = st
+                        .http
+                        .post(url)
+                        .headers(req_headers)
+                        .body(json.to_string())
+                        .send()
+                        .await;
+                    match sent {
+                        Ok(resp) => {
+                            let status = resp.status().as_u16();
+                            if resp.status().is_server_error() {
+                                st.router.record_endpoint_failure(&ep.model_uid, ep.replica_id);
+                            } else {
+                                st.router.record_endpoint_success(&ep.model_uid, ep.replica_id);
+                            }
+                            let body = resp.bytes().await.unwrap_or_default();
+                            (status, output_text(&body))
+                        }
+                        Err(e) => {
+                            st.router.record_endpoint_failure(&ep.model_uid, ep.replica_id);
+                            tracing::debug!(%source_uid, %target_uid, error=%e, "shadow request failed");
+                            (502, String::new())
+                        }
+                    }
+                }
+                None => (503, String::new()),
+            },
+            Err(e) => {
+                tracing::debug!(%source_uid, %target_uid, error=%e, "no endpoint for shadow model");
+                (503, String::new())
+            }
+        };
+        let latency = start.elapsed().as_secs_f64();
+
+        let similarity = match primary {
+            Some(rx) if status < 400 => match tokio::time::timeout(PRIMARY_WAIT, rx).await {
+                Ok(Ok(primary)) => Some(output_similarity(&output_text(&primary), &output)),
+                _ => None,
+            },
+            _ => None,
+        };
+
+        record(&st, &source_uid, &target_uid, status, latency, output.chars().count(), similarity);
+    });
+
+    tap
+}
+
+fn record(
+    st: &AppState,
+    source_uid: &str,
+    target_uid: &str,
+    status: u16,
+    latency: f64,
+    output_chars: usize,
+    similarity: Option<f64>,
+) {
+    let m = &st.metrics;
+    if status >= 400 {
+        m.shadow_failed_total.fetch_add(1, Ordering::Relaxed);
+    }
+    m.observe_shadow_latency(target_uid, latency);
+    m.shadow_output_chars_total
+        .fetch_add(output_chars as u64, Ordering::Relaxed);
+    match similarity {
+        Some(s) if s >= 1.0 => {
+            m.shadow_output_match_total.fetch_add(1, Ordering::Relaxed);
+        }
+        Some(_) => {
+            m.shadow_output_mismatch_total.fetch_add(1, Ordering::Relaxed);
+        }
+        None => {}
+    }
+    tracing::info!(%source_uid, %target_uid, status, latency_s=latency, output_chars, similarity=?similarity, "shadow request completed");
+
+    let Some(client) = st.shadow.xtrace.clone() else {
+        return;
+    };
+    let ts = chrono::Utc::now();
+    let labels = HashMap::from([
+        ("source_model_uid".to_string(), source_uid.to_string()),
+        ("shadow_model_uid".to_string(), target_uid.to_string()),
+        ("status".to_string(), status.to_string()),
+    ]);
+    let mut points = vec![
+        xtrace_client::MetricPoint {
+            name: "shadow_latency_seconds".to_string(),
+            labels: labels.clone(),
+            value: latency,
+            timestamp: ts,
+        },
+        xtrace_client::MetricPoint {
+            name: "shadow_output_chars".to_string(),
+            labels: labels.clone(),
+            value: output_chars as f64,
+            timestamp: ts,
+        },
+    ];
+    if let Some(s) = similarity {
+        points.push(xtrace_client::MetricPoint {
+            name: "shadow_output_similarity".to_string(),
+            labels,
+            value: s,
+            timestamp: ts,
+        });
+    }
+    tokio::spawn(async move {
+        if let Err(e) = client.push_metrics(&points).await {
+            tracing::debug!(error=%e, "failed to push shadow metrics to xtrace");
+        }
+    });
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_output_text_json_and_sse() {
+        let json = br#"{"choices":[{"index":0,"message":{"role":"assistant","content":"Hello world"}}]}"#;
+        assert_eq!(output_text(json), "Hello world");
+
+        let sse = b"data: {\"choices\":[{\"delta\":{\"role\":\"assistant\"}}]}\n\n\
+data: {\"choices\":[{\"delta\":{\"content\":\"Hel\"}}]}\n\n\
+data: {\"choices\":[{\"delta\":{\"content\":\"lo\"}}]}\n\n\
+data: [DONE]\n\n";
+        assert_eq!(output_text(sse), "Hello");
+
+        let completion = br#"{"choices":[{"text":"abc"}]}"#;
+        assert_eq!(output_text(completion), "abc");
+        assert_eq!(output_text(b"not json"), "");
+    }
+
+    #[test]
+    fn test_output_similarity() {
+        assert_eq!(output_similarity("", ""), 1.0);
+        assert_eq!(output_similarity("a b c", "a b c"), 1.0);
+        assert_eq!(output_similarity("a b", "c d"), 0.0);
+        assert!((output_similarity("a b c d", "a b x y") - 0.5).abs() < 1e-9);
+    }
+
+    #[test]
+    fn test_sampled_bounds() {
+        assert!(sampled(1.0));
+        assert!(!sampled(0.0));
+    }
+}
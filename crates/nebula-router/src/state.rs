@@ -3,16 +3,27 @@ use std::sync::Arc;
 
 use nebula_common::auth::AuthConfig;
 
+use crate::grpc::GrpcClients;
+use crate::hedge::Hedging;
 use crate::metrics::Metrics;
+use crate::shadow::ShadowMirror;
+use crate::tokens::TokenEstimator;
 
 #[derive(Clone)]
 pub struct AppState {
     pub model_uid: String,
     pub router: Arc<nebula_router::Router>,
     pub http: reqwest::Client,
+    pub grpc: Arc<GrpcClients>,
     pub plan_version: Arc<AtomicU64>,
     pub metrics: Arc<Metrics>,
+    pub tokens: Arc<TokenEstimator>,
+    pub shadow: Arc<ShadowMirror>,
+    pub hedge: Arc<Hedging>,
     pub max_request_body_bytes: usize,
+    /// JSON bodies above this size (or without Content-Length) are streamed,
+    /// not buffered, unless the request needs its whole body.
+    pub stream_body_threshold_bytes: usize,
     pub retry_max: u32,
     pub retry_backoff_ms: u64,
     pub auth: AuthConfig,
@@ -23,3 +34,47 @@ impl AsRef<AuthConfig> for AppState {
         &self.auth
     }
 }
+
+#[cfg(test)]
+impl AppState {
+    /// State for `model_uid` served by one ready endpoint per engine URL in
+    /// `base_urls`, with auth off and no retries.
+    pub(crate) fn for_tests(model_uid: &str, base_urls: &[String]) -> Self {
+        use nebula_common::{EndpointInfo, EndpointKind, EndpointStatus, ServingRole};
+
+        let router = nebula_router::Router::new();
+        for (replica_id, url) in base_urls.iter().enumerate() {
+            router.upsert_endpoint(EndpointInfo {
+                model_uid: model_uid.to_string(),
+                replica_id: replica_id as u32,
+                plan_version: 0,
+                node_id: format!("n{replica_id}"),
+                endpoint_kind: EndpointKind::NativeHttp,
+                api_flavor: "openai".to_string(),
+                status: EndpointStatus::Ready,
+                last_heartbeat_ms: 0,
+                grpc_target: None,
+                base_url: Some(url.clone()),
+                max_model_len: None,
+                lora_adapters: Vec::new(),
+                role: ServingRole::Both,
+            });
+        }
+        Self {
+            model_uid: model_uid.to_string(),
+            router,
+            http: reqwest::Client::new(),
+            grpc: Arc::new(GrpcClients::default()),
+            plan_version: Arc::new(AtomicU64::new(0)),
+            metrics: Arc::new(Metrics::default()),
+            tokens: Arc::new(TokenEstimator::new(None)),
+            shadow: Arc::new(ShadowMirror::new(None)),
+            hedge: Arc::new(Hedging::default()),
+            max_request_body_bytes: 1 << 20,
+            stream_body_threshold_bytes: 1024,
+            retry_max: 0,
+            retry_backoff_ms: 0,
+            auth: nebula_common::auth::parse_auth_from_env(),
+        }
+    }
+}
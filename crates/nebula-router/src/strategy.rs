@@ -1,3 +1,5 @@
+use std::collections::BTreeMap;
+
 use nebula_common::{EndpointInfo, EndpointStats};
 
 /// A candidate endpoint with its optional stats, presented to the routing strategy.
@@ -87,7 +89,18 @@ impl RoutingStrategy for LeastKvCache {
 
 const PREFIX_CACHE_THRESHOLD: f64 = 0.1;
 
-pub struct PrefixCacheAware;
+pub struct PrefixCacheAware {
+    /// Minimum best hit rate required to prefer it over least pending.
+    pub hit_rate_threshold: f64,
+}
+
+impl Default for PrefixCacheAware {
+    fn default() -> Self {
+        Self {
+            hit_rate_threshold: PREFIX_CACHE_THRESHOLD,
+        }
+    }
+}
 
 impl RoutingStrategy for PrefixCacheAware {
     fn select(&self, candidates: &[Candidate]) -> Option<usize> {
@@ -105,7 +118,7 @@ impl RoutingStrategy for PrefixCacheAware {
             }
         }
 
-        if has_cache_data && best_hit_rate >= PREFIX_CACHE_THRESHOLD {
+        if has_cache_data && best_hit_rate >= self.hit_rate_threshold {
             return best_idx;
         }
 
@@ -120,10 +133,34 @@ impl RoutingStrategy for PrefixCacheAware {
 
 /// Parse a strategy name string into a boxed strategy.
 pub fn parse_strategy(name: &str) -> Result<Box<dyn RoutingStrategy>, String> {
+    build_strategy(name, &BTreeMap::new())
+}
+
+/// Build a strategy from its name and numeric parameters (as found in a
+/// `RoutingPolicy`). Unknown parameters are rejected so typos don't go unnoticed.
+pub fn build_strategy(
+    name: &str,
+    params: &BTreeMap<String, f64>,
+) -> Result<Box<dyn RoutingStrategy>, String> {
+    let allowed: &[&str] = match name {
+        "prefix_cache_aware" => &["hit_rate_threshold"],
+        _ => &[],
+    };
+    if let Some(unknown) = params.keys().find(|k| !allowed.contains(&k.as_str())) {
+        return Err(format!(
+            "unknown parameter '{}' for routing strategy '{}'",
+            unknown, name
+        ));
+    }
     match name {
         "least_pending" => Ok(Box::new(LeastPending)),
         "least_kv_cache" => Ok(Box::new(LeastKvCache)),
-        "prefix_cache_aware" => Ok(Box::new(PrefixCacheAware)),
+        "prefix_cache_aware" => Ok(Box::new(PrefixCacheAware {
+            hit_rate_threshold: params
+                .get("hit_rate_threshold")
+                .copied()
+                .unwrap_or(PREFIX_CACHE_THRESHOLD),
+        })),
         other => Err(format!(
             "unknown routing strategy '{}', available: least_pending, least_kv_cache, prefix_cache_aware",
             other
@@ -134,7 +171,7 @@ pub fn parse_strategy(name: &str) -> Result<Box<dyn RoutingStrategy>, String> {
 #[cfg(test)]
 mod tests {
     use super::*;
-    use nebula_common::{EndpointInfo, EndpointKind, EndpointStats, EndpointStatus};
+    use nebula_common::{EndpointInfo, EndpointKind, EndpointStats, EndpointStatus, ServingRole};
 
     fn make_ep(model: &str, replica: u32) -> EndpointInfo {
         EndpointInfo {
@@ -148,6 +185,9 @@ mod tests {
             last_heartbeat_ms: 0,
             grpc_target: None,
             base_url: Some("http://127.0.0.1:8000".to_string()),
+            max_model_len: None,
+            lora_adapters: Vec::new(),
+            role: ServingRole::Both,
         }
     }
 
@@ -222,7 +262,7 @@ mod tests {
             Candidate { endpoint: &ep1, stats: Some(&s1) },
         ];
 
-        assert_eq!(PrefixCacheAware.select(&candidates), Some(0));
+        assert_eq!(PrefixCacheAware::default().select(&candidates), Some(0));
     }
 
     #[test]
@@ -238,6 +278,26 @@ mod tests {
         ];
 
         // All below threshold → falls back to least pending (index 1)
-        assert_eq!(PrefixCacheAware.select(&candidates), Some(1));
+        assert_eq!(PrefixCacheAware::default().select(&candidates), Some(1));
+    }
+
+    #[test]
+    fn test_build_strategy_params() {
+        let ep0 = make_ep("m", 0);
+        let ep1 = make_ep("m", 1);
+        let s0 = make_stats("m", 0, 10, None, Some(0.05));
+        let s1 = make_stats("m", 1, 3, None, Some(0.02));
+
+        let candidates = vec![
+            Candidate { endpoint: &ep0, stats: Some(&s0) },
+            Candidate { endpoint: &ep1, stats: Some(&s1) },
+        ];
+
+        let params = BTreeMap::from([("hit_rate_threshold".to_string(), 0.04)]);
+        let strategy = build_strategy("prefix_cache_aware", &params).unwrap();
+        assert_eq!(strategy.select(&candidates), Some(0));
+
+        assert!(build_strategy("least_pending", &params).is_err());
+        assert!(build_strategy("round_robin", &BTreeMap::new()).is_err());
     }
 }
@@ -4,8 +4,11 @@ use std::time::Duration;
 
 use futures_util::StreamExt;
 
-use nebula_common::{EndpointInfo, EndpointStats, PlacementPlan};
-use nebula_meta::{EtcdMetaStore, MetaStore};
+use nebula_common::api_key::{ApiKeyCache, API_KEYS_PREFIX};
+use nebula_common::{
+    ApiKey, EndpointInfo, EndpointStats, ModelDeployment, ModelSpec, PlacementPlan, RequestPolicy, RoutingPolicy,
+};
+use nebula_meta::{EtcdMetaStore, MetaStore, WatchEvent};
 
 pub async fn endpoints_sync_loop(
     store: EtcdMetaStore,
@@ -58,6 +61,18 @@ pub async fn endpoints_sync_loop(
     }
 }
 
+/// LoRA adapters declared by any assignment of `plan`.
+fn plan_adapters(plan: &PlacementPlan) -> Vec<String> {
+    let mut adapters: Vec<String> = plan
+        .assignments
+        .iter()
+        .flat_map(|a| a.lora_adapter_names())
+        .collect();
+    adapters.sort();
+    adapters.dedup();
+    adapters
+}
+
 pub async fn placement_sync_loop(
     store: EtcdMetaStore,
     model_uid: String,
@@ -72,6 +87,7 @@ pub async fn placement_sync_loop(
                 for (_k, v, _rev) in items {
                     if let Ok(plan) = serde_json::from_slice::<PlacementPlan>(&v) {
                         router.set_model_mapping(&plan.model_uid, &plan.model_name);
+                        router.set_plan_adapters(&plan.model_uid, plan_adapters(&plan));
                         if plan.model_uid == model_uid {
                             plan_version.store(plan.version, Ordering::Relaxed);
                             found_primary = true;
@@ -106,6 +122,7 @@ pub async fn placement_sync_loop(
             };
             // Always update model mappings for every placement
             router.set_model_mapping(&plan.model_uid, &plan.model_name);
+            router.set_plan_adapters(&plan.model_uid, plan_adapters(&plan));
             // Update plan_version only for the primary model
             if plan.model_uid == model_uid {
                 plan_version.store(plan.version, Ordering::Relaxed);
@@ -117,6 +134,198 @@ pub async fn placement_sync_loop(
     }
 }
 
+/// Keep per-model routing policies (`/routing_policies/{model_uid}`) in sync
+/// so that policy edits take effect without restarting the router.
+pub async fn routing_policy_sync_loop(
+    store: EtcdMetaStore,
+    router: Arc<nebula_router::Router>,
+) -> anyhow::Result<()> {
+    loop {
+        match store.list_prefix("/routing_policies/").await {
+            Ok(items) => {
+                let policies = items
+                    .into_iter()
+                    .filter_map(|(_k, v, _rev)| serde_json::from_slice::<RoutingPolicy>(&v).ok())
+                    .collect();
+                router.replace_all_routing_policies(policies);
+            }
+            Err(e) => {
+                tracing::warn!(error=%e, "failed to list routing policies, will retry");
+                tokio::time::sleep(Duration::from_secs(1)).await;
+                continue;
+            }
+        }
+
+        let mut stream = match store.watch_prefix("/routing_policies/", None).await {
+            Ok(s) => s,
+            Err(e) => {
+                tracing::warn!(error=%e, "failed to watch routing policies, will retry");
+                tokio::time::sleep(Duration::from_secs(1)).await;
+                continue;
+            }
+        };
+
+        while let Some(ev) = stream.next().await {
+            apply_routing_policy_event(&router, ev);
+        }
+
+        tracing::warn!("routing policies watch stream ended, reconnecting");
+        tokio::time::sleep(Duration::from_secs(1)).await;
+    }
+}
+
+/// Apply one change under `/routing_policies/`. The key names the model, and a
+/// policy that fails to parse or validate leaves the current one in place.
+fn apply_routing_policy_event(router: &nebula_router::Router, ev: WatchEvent) {
+    let Some(model_uid) = ev.key.strip_prefix("/routing_policies/") else {
+        return;
+    };
+    match ev.value {
+        Some(v) => match serde_json::from_slice::<RoutingPolicy>(&v) {
+            Ok(mut policy) => {
+                policy.model_uid = model_uid.to_string();
+                if let Err(e) = router.set_routing_policy(policy) {
+                    tracing::warn!(%model_uid, error=%e, "ignoring invalid routing policy");
+                }
+            }
+            Err(e) => {
+                tracing::warn!(%model_uid, error=%e, "failed to parse routing policy");
+            }
+        },
+        None => router.remove_routing_policy(model_uid),
+    }
+}
+
+/// The model a spec (`/models/{uid}/spec`) or deployment (`/deployments/{uid}`)
+/// key belongs to.
+fn request_policy_model(key: &str) -> Option<&str> {
+    key.strip_prefix("/models/")
+        .and_then(|k| k.strip_suffix("/spec"))
+        .or_else(|| key.strip_prefix("/deployments/"))
+}
+
+/// A model's request policy from its spec and deployment.
+async fn load_request_policy(store: &EtcdMetaStore, model_uid: &str) -> anyhow::Result<Option<RequestPolicy>> {
+    let spec = store
+        .get(&format!("/models/{model_uid}/spec"))
+        .await?
+        .and_then(|(v, _)| serde_json::from_slice::<ModelSpec>(&v).ok());
+    let deployment = store
+        .get(&format!("/deployments/{model_uid}"))
+        .await?
+        .and_then(|(v, _)| serde_json::from_slice::<ModelDeployment>(&v).ok());
+    Ok(RequestPolicy::effective(
+        spec.as_ref().and_then(|s| s.request_policy.as_ref()),
+        deployment.as_ref().and_then(|d| d.request_policy.as_ref()),
+    ))
+}
+
+/// Keep per-model request policies, set on model specs and deployments, in
+/// sync so that edits take effect without restarting the router.
+pub async fn request_policy_sync_loop(
+    store: EtcdMetaStore,
+    router: Arc<nebula_router::Router>,
+) -> anyhow::Result<()> {
+    loop {
+        let listed = async {
+            let mut model_uids: Vec<String> = store
+                .list_prefix("/models/")
+                .await?
+                .into_iter()
+                .chain(store.list_prefix("/deployments/").await?)
+                .filter_map(|(k, _, _)| request_policy_model(&k).map(str::to_string))
+                .collect();
+            model_uids.sort();
+            model_uids.dedup();
+            let mut policies = Vec::new();
+            for model_uid in model_uids {
+                if let Some(policy) = load_request_policy(&store, &model_uid).await? {
+                    policies.push((model_uid, policy));
+                }
+            }
+            anyhow::Ok(policies)
+        }
+        .await;
+        match listed {
+            Ok(policies) => router.replace_all_request_policies(policies),
+            Err(e) => {
+                tracing::warn!(error=%e, "failed to list request policies, will retry");
+                tokio::time::sleep(Duration::from_secs(1)).await;
+                continue;
+            }
+        }
+
+        let watches = async {
+            let specs = store.watch_prefix("/models/", None).await?;
+            let deployments = store.watch_prefix("/deployments/", None).await?;
+            anyhow::Ok(futures_util::stream::select(specs, deployments))
+        };
+        let mut stream = match watches.await {
+            Ok(s) => s,
+            Err(e) => {
+                tracing::warn!(error=%e, "failed to watch model specs and deployments, will retry");
+                tokio::time::sleep(Duration::from_secs(1)).await;
+                continue;
+            }
+        };
+
+        while let Some(ev) = stream.next().await {
+            let Some(model_uid) = request_policy_model(&ev.key) else {
+                continue;
+            };
+            match load_request_policy(&store, model_uid).await {
+                Ok(policy) => router.set_request_policy(model_uid, policy),
+                Err(e) => tracing::warn!(%model_uid, error=%e, "failed to load request policy"),
+            }
+        }
+
+        tracing::warn!("model specs watch stream ended, reconnecting");
+        tokio::time::sleep(Duration::from_secs(1)).await;
+    }
+}
+
+/// Keep the auth middleware's API keys in step with `/api_keys/`.
+pub async fn api_keys_sync_loop(store: EtcdMetaStore, keys: ApiKeyCache) -> anyhow::Result<()> {
+    loop {
+        match store.list_prefix(API_KEYS_PREFIX).await {
+            Ok(items) => {
+                let snapshot = items
+                    .into_iter()
+                    .filter_map(|(_k, v, _rev)| serde_json::from_slice::<ApiKey>(&v).ok())
+                    .collect();
+                keys.replace_all(snapshot);
+            }
+            Err(e) => {
+                tracing::warn!(error=%e, "failed to list api keys, will retry");
+                tokio::time::sleep(Duration::from_secs(1)).await;
+                continue;
+            }
+        }
+
+        let mut stream = match store.watch_prefix(API_KEYS_PREFIX, None).await {
+            Ok(s) => s,
+            Err(e) => {
+                tracing::warn!(error=%e, "failed to watch api keys, will retry");
+                tokio::time::sleep(Duration::from_secs(1)).await;
+                continue;
+            }
+        };
+
+        while let Some(ev) = stream.next().await {
+            match ev.value {
+                Some(v) => match serde_json::from_slice::<ApiKey>(&v) {
+                    Ok(key) => keys.upsert(key),
+                    Err(e) => tracing::warn!(key=%ev.key, error=%e, "failed to parse api key"),
+                },
+                None => keys.remove(&ev.key),
+            }
+        }
+
+        tracing::warn!("api keys watch stream ended, reconnecting");
+        tokio::time::sleep(Duration::from_secs(1)).await;
+    }
+}
+
 #[derive(Debug, serde::Deserialize)]
 struct XtraceMetricValue {
     timestamp: String,
@@ -367,3 +576,111 @@ pub async fn stats_sync_loop(
         tokio::time::sleep(POLL_INTERVAL).await;
     }
 }
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    use nebula_common::{EndpointKind, EndpointStatus, ExecutionContext, ServingRole};
+    use nebula_router::RouteError;
+
+    fn event(key: &str, policy: Option<serde_json::Value>) -> WatchEvent {
+        WatchEvent {
+            key: key.to_string(),
+            value: policy.map(|p| p.to_string().into_bytes()),
+            revision: 0,
+        }
+    }
+
+    fn ctx() -> ExecutionContext {
+        ExecutionContext {
+            request_id: "req-1".to_string(),
+            session_id: None,
+            tenant_id: None,
+            priority: None,
+            deadline_ms: None,
+            budget_tokens: None,
+            required_context_tokens: None,
+            lora_adapter: None,
+        }
+    }
+
+    /// A router with one ready endpoint per model, each at 90% KV cache usage.
+    fn router(models: &[&str]) -> Arc<nebula_router::Router> {
+        let router = nebula_router::Router::new();
+        for model_uid in models {
+            router.upsert_endpoint(EndpointInfo {
+                model_uid: model_uid.to_string(),
+                replica_id: 0,
+                plan_version: 0,
+                node_id: "n0".to_string(),
+                endpoint_kind: EndpointKind::NativeHttp,
+                api_flavor: "openai".to_string(),
+                status: EndpointStatus::Ready,
+                last_heartbeat_ms: 0,
+                grpc_target: None,
+                base_url: Some("http://127.0.0.1:8000".to_string()),
+                max_model_len: None,
+                lora_adapters: Vec::new(),
+                role: ServingRole::Both,
+            });
+            router.upsert_stats(EndpointStats {
+                model_uid: model_uid.to_string(),
+                replica_id: 0,
+                last_updated_ms: chrono::Utc::now().timestamp_millis() as u64,
+                pending_requests: 0,
+                prefix_cache_hit_rate: None,
+                prompt_cache_hit_rate: None,
+                kv_cache_used_bytes: Some(90),
+                kv_cache_free_bytes: Some(10),
+            });
+        }
+        router
+    }
+
+    #[test]
+    fn test_routing_policy_fields_override_global_settings() {
+        let router = router(&["a", "b"]);
+        let policy = serde_json::json!({"model_uid": "a", "kv_cache_overload_threshold": 0.5, "circuit_failure_threshold": 1});
+        apply_routing_policy_event(&router, event("/routing_policies/a", Some(policy)));
+
+        // The policy's threshold applies to its model only; others keep the global 0.95.
+        assert!(matches!(router.route(&ctx(), "a"), Err(RouteError::Overloaded)));
+        assert!(router.route(&ctx(), "b").is_ok());
+
+        // Unset fields fall back to the global settings (three failures open a circuit).
+        router.record_endpoint_failure("b", 0);
+        assert_eq!(router.circuit_open_total(), 0);
+        router.record_endpoint_failure("a", 0);
+        assert_eq!(router.circuit_open_total(), 1);
+    }
+
+    #[test]
+    fn test_routing_policy_watch_events_update_the_router() {
+        let router = router(&["a"]);
+        let key = "/routing_policies/a";
+        // The key names the model, whatever the document says.
+        let policy = serde_json::json!({"model_uid": "other", "retry_max": 2, "strategy": "least_kv_cache"});
+        apply_routing_policy_event(&router, event(key, Some(policy)));
+        let applied = router.routing_policy("a").unwrap();
+        assert_eq!((applied.model_uid.as_str(), applied.retry_max), ("a", Some(2)));
+        assert!(router.routing_policy("other").is_none());
+
+        // Updates replace the whole policy.
+        apply_routing_policy_event(&router, event(key, Some(serde_json::json!({"model_uid": "a", "retry_max": 0}))));
+        let applied = router.routing_policy("a").unwrap();
+        assert_eq!((applied.retry_max, applied.strategy), (Some(0), None));
+
+        // Invalid or unparseable documents keep the last good policy.
+        let invalid = serde_json::json!({"model_uid": "a", "strategy": "fastest"});
+        apply_routing_policy_event(&router, event(key, Some(invalid)));
+        let unparseable = WatchEvent { value: Some(b"{".to_vec()), ..event(key, None) };
+        apply_routing_policy_event(&router, unparseable);
+        assert_eq!(router.routing_policy("a").unwrap().retry_max, Some(0));
+
+        // Deleting the key restores the global settings.
+        apply_routing_policy_event(&router, event(key, None));
+        assert!(router.routing_policy("a").is_none());
+        assert!(router.route(&ctx(), "a").is_ok());
+    }
+}
@@ -0,0 +1,327 @@
+//! Prompt token estimation for context-length-aware routing.
+//!
+//! When a model's `tokenizer.json` is found under the router's model directory,
+//! its vocabulary is used for a greedy longest-match tokenization, which tracks
+//! the real BPE/Unigram token count closely. Otherwise a deliberately low
+//! character-based estimate is used so that heuristic counts never cause a
+//! request to be rejected that the engine would have accepted.
+//!
+//! Vocabulary counting is O(n·max_piece), so it runs on the blocking pool
+//! rather than on the request task.
+
+use std::collections::HashSet;
+use std::path::{Path, PathBuf};
+use std::sync::Arc;
+use std::time::{Duration, Instant};
+
+use dashmap::DashMap;
+use serde_json::Value;
+
+pub use nebula_common::tokens::{estimate_request, heuristic_count, TokenEstimate};
+
+/// How long a model without a tokenizer keeps using the heuristic before the
+/// model directory is searched again (the tokenizer may be downloaded later).
+const MISSING_TOKENIZER_TTL: Duration = Duration::from_secs(60);
+
+/// How a text is split into the symbols the vocabulary is expressed in.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+enum Encoding {
+    /// GPT-2 style byte-level BPE: bytes mapped to printable code points (`Ġ` for space).
+    ByteLevel,
+    /// SentencePiece style: spaces become `▁`, with a leading `▁`.
+    Metaspace,
+    /// Plain text pieces.
+    Plain,
+}
+
+/// A vocabulary loaded from a HuggingFace `tokenizer.json`.
+#[derive(Debug)]
+pub struct VocabTokenizer {
+    vocab: HashSet<String>,
+    max_piece_chars: usize,
+    encoding: Encoding,
+}
+
+impl VocabTokenizer {
+    pub fn from_json(json: &Value) -> Option<Self> {
+        let model = json.get("model")?;
+        let vocab: HashSet<String> = match model.get("vocab")? {
+            Value::Object(map) => map.keys().cloned().collect(),
+            // Unigram: [[piece, score], ...]
+            Value::Array(items) => items
+                .iter()
+                .filter_map(|i| i.get(0).and_then(|p| p.as_str()).map(str::to_string))
+                .collect(),
+            _ => return None,
+        };
+        if vocab.is_empty() {
+            return None;
+        }
+        let max_piece_chars = vocab.iter().map(|p| p.chars().count()).max().unwrap_or(1);
+
+        let pipeline = format!(
+            "{}{}",
+            json.get("pre_tokenizer").map(|v| v.to_string()).unwrap_or_default(),
+            json.get("decoder").map(|v| v.to_string()).unwrap_or_default()
+        );
+        let encoding = if pipeline.contains("ByteLevel") {
+            Encoding::ByteLevel
+        } else if pipeline.contains("Metaspace") || vocab.iter().any(|p| p.starts_with('▁')) {
+            Encoding::Metaspace
+        } else {
+            Encoding::Plain
+        };
+
+        Some(Self {
+            vocab,
+            max_piece_chars,
+            encoding,
+        })
+    }
+
+    fn symbols(&self, text: &str) -> Vec<char> {
+        match self.encoding {
+            Encoding::ByteLevel => text.bytes().map(byte_to_char).collect(),
+            Encoding::Metaspace => std::iter::once('▁')
+                .chain(text.chars().map(|c| if c == ' ' { '▁' } else { c }))
+                .collect(),
+            Encoding::Plain => text.chars().collect(),
+        }
+    }
+
+    /// Greedy longest-match token count for `text`.
+    pub fn count(&self, text: &str) -> u32 {
+        let symbols = self.symbols(text);
+        let mut count: u32 = 0;
+        let mut i = 0;
+        let mut piece = String::new();
+        while i < symbols.len() {
+            let max = self.max_piece_chars.min(symbols.len() - i);
+            let mut matched = 1;
+            for len in (1..=max).rev() {
+                piece.clear();
+                piece.extend(&symbols[i..i + len]);
+                if self.vocab.contains(&piece) {
+                    matched = len;
+                    break;
+                }
+            }
+            // Unknown symbols still cost at least one token (byte fallback may cost more).
+            i += matched;
+            count = count.saturating_add(1);
+        }
+        count
+    }
+}
+
+/// GPT-2 `bytes_to_unicode`: printable bytes map to themselves, the rest to U+0100 onwards.
+fn byte_to_char(b: u8) -> char {
+    let printable = (b'!'..=b'~').contains(&b) || (0xA1..=0xAC).contains(&b) || (0xAE..=0xFF).contains(&b);
+    if printable {
+        return b as char;
+    }
+    let mut n = 0u32;
+    for x in 0u8..b {
+        let p = (b'!'..=b'~').contains(&x) || (0xA1..=0xAC).contains(&x) || (0xAE..=0xFF).contains(&x);
+        if !p {
+            n += 1;
+        }
+    }
+    char::from_u32(256 + n).unwrap_or('?')
+}
+
+/// Lower the request's `max_completion_tokens` / `max_tokens` to `budget`,
+/// setting `max_tokens` when neither is present.
+pub fn clamp_max_tokens(body: &mut Value, budget: u32) {
+    let Some(obj) = body.as_object_mut() else {
+        return;
+    };
+    let mut clamped = false;
+    for field in ["max_completion_tokens", "max_tokens"] {
+        if let Some(v) = obj.get_mut(field) {
+            if v.as_u64().is_none_or(|n| n > budget as u64) {
+                *v = Value::from(budget);
+            }
+            clamped = true;
+        }
+    }
+    if !clamped {
+        obj.insert("max_tokens".to_string(), Value::from(budget));
+    }
+}
+
+enum CachedTokenizer {
+    Loaded(Arc<VocabTokenizer>),
+    /// No tokenizer was found at this time.
+    Missing(Instant),
+}
+
+/// Per-model tokenizers, loaded lazily from the local model directory.
+pub struct TokenEstimator {
+    model_dir: Option<PathBuf>,
+    tokenizers: DashMap<String, CachedTokenizer>,
+    missing_ttl: Duration,
+}
+
+impl TokenEstimator {
+    pub fn new(model_dir: Option<PathBuf>) -> Self {
+        Self {
+            model_dir,
+            tokenizers: DashMap::new(),
+            missing_ttl: MISSING_TOKENIZER_TTL,
+        }
+    }
+
+    async fn tokenizer(&self, model_name: &str) -> Option<Arc<VocabTokenizer>> {
+        let cached = self.tokenizers.get(model_name).and_then(|c| match c.value() {
+            CachedTokenizer::Loaded(t) => Some(Some(t.clone())),
+            CachedTokenizer::Missing(at) if at.elapsed() < self.missing_ttl => Some(None),
+            CachedTokenizer::Missing(_) => None,
+        });
+        if let Some(cached) = cached {
+            return cached;
+        }
+        let dir = self.model_dir.clone()?;
+        let name = model_name.to_string();
+        let loaded = tokio::task::spawn_blocking(move || load_tokenizer(&dir, &name))
+            .await
+            .ok()
+            .flatten()
+            .map(Arc::new);
+        match &loaded {
+            Some(t) => {
+                tracing::info!(model=%model_name, vocab=t.vocab.len(), "loaded tokenizer for context estimation");
+                self.tokenizers
+                    .insert(model_name.to_string(), CachedTokenizer::Loaded(t.clone()));
+            }
+            None => {
+                tracing::debug!(model=%model_name, "no tokenizer.json found, using heuristic token estimate");
+                self.tokenizers
+                    .insert(model_name.to_string(), CachedTokenizer::Missing(Instant::now()));
+            }
+        }
+        loaded
+    }
+
+    /// Token count of `text` for `model_name`.
+    pub async fn count_text(&self, model_name: &str, text: &str) -> u32 {
+        let Some(t) = self.tokenizer(model_name).await else {
+            return heuristic_count(text);
+        };
+        let owned = text.to_string();
+        tokio::task::spawn_blocking(move || t.count(&owned))
+            .await
+            .unwrap_or_else(|_| heuristic_count(text))
+    }
+
+    /// Estimate the token requirements of `body` for `model_name`.
+    pub async fn estimate(&self, model_name: &str, body: &Value) -> Option<TokenEstimate> {
+        let Some(t) = self.tokenizer(model_name).await else {
+            return estimate_request(body, &heuristic_count);
+        };
+        let owned = body.clone();
+        tokio::task::spawn_blocking(move || estimate_request(&owned, &|s| t.count(s)))
+            .await
+            .unwrap_or_else(|_| estimate_request(body, &heuristic_count))
+    }
+}
+
+/// Candidate `tokenizer.json` locations for a model under the node model cache layouts.
+fn tokenizer_candidates(model_dir: &Path, model_name: &str) -> Vec<PathBuf> {
+    let mut out = vec![model_dir.join(model_name).join("tokenizer.json")];
+    if let Some(short) = model_name.rsplit('/').next() {
+        out.push(model_dir.join(short).join("tokenizer.json"));
+    }
+    out.push(
+        model_dir
+            .join(".cache/modelscope/hub")
+            .join(model_name)
+            .join("tokenizer.json"),
+    );
+    let hf = model_dir
+        .join(".cache/huggingface/hub")
+        .join(format!("models--{}", model_name.replace('/', "--")))
+        .join("snapshots");
+    if let Ok(entries) = std::fs::read_dir(hf) {
+        for e in entries.flatten() {
+            out.push(e.path().join("tokenizer.json"));
+        }
+    }
+    out
+}
+
+fn load_tokenizer(model_dir: &Path, model_name: &str) -> Option<VocabTokenizer> {
+    let path = tokenizer_candidates(model_dir, model_name)
+        .into_iter()
+        .find(|p| p.is_file())?;
+    let bytes = std::fs::read(&path).ok()?;
+    let json: Value = serde_json::from_slice(&bytes).ok()?;
+    VocabTokenizer::from_json(&json)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use serde_json::json;
+
+    #[test]
+    fn test_byte_level_greedy_count() {
+        let tok = VocabTokenizer::from_json(&json!({
+            "pre_tokenizer": {"type": "ByteLevel"},
+            "model": {"type": "BPE", "vocab": {"Hello": 0, "Ġworld": 1, "Ġ": 2, "!": 3, "w": 4}}
+        }))
+        .unwrap();
+        assert_eq!(tok.encoding, Encoding::ByteLevel);
+        assert_eq!(tok.count("Hello world!"), 3);
+        // Unknown bytes count one token each.
+        assert_eq!(tok.count("Hello  w"), 4);
+        assert_eq!(byte_to_char(b' '), 'Ġ');
+        assert_eq!(byte_to_char(b'\n'), 'Ċ');
+    }
+
+    #[test]
+    fn test_metaspace_unigram_count() {
+        let tok = VocabTokenizer::from_json(&json!({
+            "model": {"type": "Unigram", "vocab": [["▁hello", 0.0], ["▁there", 0.0], ["▁", 0.0]]}
+        }))
+        .unwrap();
+        assert_eq!(tok.encoding, Encoding::Metaspace);
+        assert_eq!(tok.count("hello there"), 2);
+    }
+
+    #[tokio::test]
+    async fn test_missing_tokenizer_is_retried() {
+        let dir = std::env::temp_dir().join(format!("nebula-tokens-{}", uuid::Uuid::new_v4()));
+        let mut estimator = TokenEstimator::new(Some(dir.clone()));
+        assert_eq!(estimator.count_text("m", "Hello world!").await, heuristic_count("Hello world!"));
+
+        std::fs::create_dir_all(dir.join("m")).unwrap();
+        let tokenizer = json!({
+            "pre_tokenizer": {"type": "ByteLevel"},
+            "model": {"type": "BPE", "vocab": {"HelloĠworld!": 0}}
+        });
+        std::fs::write(dir.join("m/tokenizer.json"), tokenizer.to_string()).unwrap();
+        // Still within the negative cache TTL.
+        assert_eq!(estimator.count_text("m", "Hello world!").await, heuristic_count("Hello world!"));
+
+        estimator.missing_ttl = Duration::ZERO;
+        assert_eq!(estimator.count_text("m", "Hello world!").await, 1);
+        std::fs::remove_dir_all(dir).unwrap();
+    }
+
+    #[test]
+    fn test_clamp_max_tokens() {
+        let mut body = json!({"max_tokens": 500});
+        clamp_max_tokens(&mut body, 100);
+        assert_eq!(body["max_tokens"], 100);
+
+        let mut body = json!({"max_completion_tokens": 50});
+        clamp_max_tokens(&mut body, 100);
+        assert_eq!(body["max_completion_tokens"], 50);
+        assert!(body.get("max_tokens").is_none());
+
+        let mut body = json!({"messages": []});
+        clamp_max_tokens(&mut body, 100);
+        assert_eq!(body["max_tokens"], 100);
+    }
+}
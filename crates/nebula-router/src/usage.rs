@@ -0,0 +1,117 @@
+//! Token usage accounting for proxied requests.
+//!
+//! Usage is taken from the engine's response: the `usage` object of buffered
+//! bodies, or the usage chunk that streamed chat/completions requests are asked
+//! for via `stream_options.include_usage` (kept from clients that didn't ask
+//! for it themselves). When an engine reports none, prompt
+//! tokens come from the routing-time estimate and completion tokens are counted
+//! with the model's cached tokenizer (or the heuristic). The result is filled
+//! into the response so downstream consumers (the gateway's accounting and
+//! audit) always see a `usage`, and recorded per model and principal.
+
+use bytes::Bytes;
+use serde_json::Value;
+
+use nebula_common::usage::{completion_text, SseUsageScanner};
+use nebula_common::TokenUsage;
+
+use crate::state::AppState;
+
+/// Who a request is accounted to and how to count its tokens.
+#[derive(Debug, Clone)]
+pub struct UsageAccounting {
+    pub principal: String,
+    /// Served model name, for tokenizer lookup.
+    pub model_name: String,
+    /// Prompt tokens estimated at routing time, if any.
+    pub prompt_estimate: Option<u32>,
+    /// Requested `max_tokens`, if any.
+    pub max_tokens: Option<u32>,
+    /// Whether usage may be synthesized when the engine reports none
+    /// (chat/completions and completions only).
+    pub fill_missing: bool,
+    /// Whether the client asked for the usage chunk of a stream. When not,
+    /// the chunk is counted but not forwarded.
+    pub client_usage: bool,
+}
+
+impl UsageAccounting {
+    async fn fallback(&self, st: &AppState, generated: &str) -> TokenUsage {
+        TokenUsage {
+            prompt_tokens: self.prompt_estimate.unwrap_or(0) as u64,
+            completion_tokens: st.tokens.count_text(&self.model_name, generated).await as u64,
+        }
+    }
+
+    /// Record usage of a buffered response. Returns the body with a missing
+    /// `usage` object filled in, or `None` when the body is unchanged.
+    pub async fn account_body(&self, st: &AppState, model_uid: &str, status: u16, body: &[u8]) -> Option<Bytes> {
+        if !(200..300).contains(&status) {
+            return None;
+        }
+        let mut json = serde_json::from_slice::<Value>(body).ok()?;
+        if let Some(usage) = TokenUsage::from_json(&json) {
+            st.metrics.record_tokens(model_uid, &self.principal, usage);
+            return None;
+        }
+        if !self.fill_missing || json.get("choices").is_none() {
+            return None;
+        }
+        let usage = self.fallback(st, &completion_text(&json)).await;
+        st.metrics.record_tokens(model_uid, &self.principal, usage);
+        json["usage"] = usage.to_json();
+        serde_json::to_vec(&json).ok().map(Bytes::from)
+    }
+
+    /// Feed one SSE chunk. Returns a usage event to send before the chunk's
+    /// `[DONE]` marker when the engine reported no usage and the client asked
+    /// for it.
+    pub async fn scan_chunk(&self, st: &AppState, scanner: &mut SseUsageScanner, chunk: &[u8]) -> Option<(usize, Bytes)> {
+        let done_at = find(chunk, b"data: [DONE]");
+        match done_at {
+            Some(pos) => {
+                scanner.push(&chunk[..pos]);
+                if scanner.usage().is_some() || !self.fill_missing || !self.client_usage {
+                    return None;
+                }
+                let usage = self.fallback(st, scanner.text()).await;
+                let event = scanner.usage_event(usage);
+                scanner.push(event.as_bytes());
+                Some((pos, Bytes::from(event)))
+            }
+            None => {
+                scanner.push(chunk);
+                None
+            }
+        }
+    }
+
+    /// Record the usage seen on a finished stream.
+    pub async fn finish_stream(&self, st: &AppState, model_uid: &str, scanner: &SseUsageScanner) {
+        let usage = match scanner.usage() {
+            Some(usage) => usage,
+            None if self.fill_missing => self.fallback(st, scanner.text()).await,
+            None => return,
+        };
+        st.metrics.record_tokens(model_uid, &self.principal, usage);
+    }
+
+    /// Record a stream the client abandoned: the usage generated so far, and
+    /// the rest of the `max_tokens` budget as saved.
+    pub async fn cancel_stream(&self, st: &AppState, model_uid: &str, scanner: &SseUsageScanner) {
+        let generated = match scanner.usage() {
+            Some(usage) => usage,
+            None => self.fallback(st, scanner.text()).await,
+        };
+        st.metrics.record_tokens(model_uid, &self.principal, generated);
+        let saved = self
+            .max_tokens
+            .map(|max| (max as u64).saturating_sub(generated.completion_tokens))
+            .unwrap_or(0);
+        st.metrics.record_cancelled(saved);
+    }
+}
+
+fn find(haystack: &[u8], needle: &[u8]) -> Option<usize> {
+    haystack.windows(needle.len()).position(|w| w == needle)
+}
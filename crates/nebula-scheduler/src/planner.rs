@@ -1,8 +1,8 @@
 use std::collections::{HashMap, HashSet};
 
 use nebula_common::{
-    ModelConfig, ModelDeployment, ModelRequest, ModelSpec, NodeStatus, PlacementAssignment,
-    PlacementPlan,
+    DisaggregationSpec, ModelConfig, ModelDeployment, ModelRequest, ModelSpec, NodeStatus,
+    PlacementAssignment, PlacementPlan, ServingRole,
 };
 use nebula_meta::{EtcdMetaStore, MetaStore};
 
@@ -172,6 +172,37 @@ pub fn build_extra_args_from_config(cfg: &ModelConfig) -> Option<Vec<String>> {
     }
 }
 
+/// Add the engine KV-transfer flag for a prefill/decode replica to `extra_args`.
+pub fn with_kv_transfer_args(
+    extra_args: Option<Vec<String>>,
+    spec: &DisaggregationSpec,
+    role: ServingRole,
+) -> Option<Vec<String>> {
+    let kv_role = match role {
+        ServingRole::Prefill => "kv_producer",
+        ServingRole::Decode => "kv_consumer",
+        ServingRole::Both => return extra_args,
+    };
+    let mut args = extra_args.unwrap_or_default();
+    args.push("--kv-transfer-config".to_string());
+    args.push(
+        serde_json::json!({ "kv_connector": spec.kv_connector, "kv_role": kv_role }).to_string(),
+    );
+    Some(args)
+}
+
+/// Replica roles for a deployment: `replicas` monolithic engines, or the
+/// prefill group followed by the decode group when disaggregated.
+pub fn deployment_roles(deployment: &ModelDeployment) -> Vec<Option<ServingRole>> {
+    match &deployment.disaggregation {
+        Some(d) => [ServingRole::Prefill, ServingRole::Decode]
+            .into_iter()
+            .flat_map(|role| std::iter::repeat_n(Some(role), d.replicas_for(role) as usize))
+            .collect(),
+        None => vec![None; deployment.replicas.max(1) as usize],
+    }
+}
+
 /// Find the next available port starting from `start`, skipping any in `used`.
 pub fn allocate_port(start: u16, used: &HashSet<u16>) -> u16 {
     let mut port = start;
@@ -211,6 +242,7 @@ fn make_assignment(
         extra_args,
         engine_type,
         docker_image,
+        role: None,
     }
 }
 
@@ -289,7 +321,13 @@ pub async fn build_plan_from_deployment(
     mut used_ports: HashSet<u16>,
     mut used_gpus: HashMap<String, HashSet<u32>>,
 ) -> anyhow::Result<PlacementPlan> {
-    let replicas = deployment.replicas.max(1);
+    if deployment.disaggregation.is_some() && !DisaggregationSpec::supports_engine(spec.engine_type.as_deref()) {
+        anyhow::bail!(
+            "prefill/decode disaggregation is not supported by engine {}",
+            spec.engine_type.as_deref().unwrap_or_default()
+        );
+    }
+    let roles = deployment_roles(deployment);
 
     // Merge config: spec.config as base, deployment.config_overrides on top
     let merged_config = merge_config(spec.config.as_ref(), deployment.config_overrides.as_ref());
@@ -297,9 +335,10 @@ pub async fn build_plan_from_deployment(
         .as_ref()
         .and_then(|c| build_extra_args_from_config(c));
 
-    let mut assignments = Vec::with_capacity(replicas as usize);
+    let mut assignments = Vec::with_capacity(roles.len());
 
-    for replica_id in 0..replicas {
+    for (replica_id, role) in roles.into_iter().enumerate() {
+        let replica_id = replica_id as u32;
         // Node/GPU selection: respect affinity overrides from deployment
         let (node_id, gpu_indices) = select_node_and_gpus_for_deployment(
             store,
@@ -321,16 +360,23 @@ pub async fn build_plan_from_deployment(
             }
         }
 
-        assignments.push(make_assignment(
-            replica_id,
-            &spec.model_uid,
-            node_id,
-            port,
-            gpu_indices,
-            extra_args.clone(),
-            spec.engine_type.clone(),
-            spec.docker_image.clone(),
-        ));
+        let replica_args = match (&deployment.disaggregation, role) {
+            (Some(d), Some(role)) => with_kv_transfer_args(extra_args.clone(), d, role),
+            _ => extra_args.clone(),
+        };
+        assignments.push(PlacementAssignment {
+            role,
+            ..make_assignment(
+                replica_id,
+                &spec.model_uid,
+                node_id,
+                port,
+                gpu_indices,
+                replica_args,
+                spec.engine_type.clone(),
+                spec.docker_image.clone(),
+            )
+        });
     }
 
     Ok(PlacementPlan {
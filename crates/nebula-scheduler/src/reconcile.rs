@@ -6,8 +6,8 @@ use std::time::Duration;
 use tracing::{info, warn};
 
 use nebula_common::{
-    DesiredState, EndpointInfo, EndpointStats, EndpointStatus, ModelDeployment, ModelRequest,
-    ModelRequestStatus, PlacementPlan,
+    DesiredState, DisaggregationSpec, EndpointInfo, EndpointStats, EndpointStatus,
+    ModelDeployment, ModelRequest, ModelRequestStatus, PlacementPlan, ServingRole,
 };
 use nebula_meta::{EtcdMetaStore, MetaStore};
 
@@ -130,7 +130,11 @@ async fn reconcile_once(store: &EtcdMetaStore, default_port: u16, xtrace: Option
     for plan in &plans {
         // Check deployment first (new path), fallback to old model_request
         let (base_replicas, min_replicas, max_replicas) =
-            if let Some(dep) = deployments.get(&plan.model_uid) {
+            if let Some(d) = deployments.get(&plan.model_uid).and_then(|d| d.disaggregation.as_ref()) {
+                // Prefill/decode groups are sized explicitly and not autoscaled.
+                let total = d.total_replicas();
+                (total, total, total)
+            } else if let Some(dep) = deployments.get(&plan.model_uid) {
                 let base = dep.replicas.max(1);
                 let min = dep.min_replicas.unwrap_or(1).max(1);
                 let max = dep.max_replicas.unwrap_or(base);
@@ -249,10 +253,11 @@ async fn reconcile_once(store: &EtcdMetaStore, default_port: u16, xtrace: Option
                     &mut new_assignments,
                 )
                 .await;
-            } else if deployments.contains_key(&plan.model_uid) {
+            } else if let Some(dep) = deployments.get(&plan.model_uid) {
                 add_replacement_replicas_from_plan(
                     store,
                     plan,
+                    dep.disaggregation.as_ref(),
                     deficit,
                     default_port,
                     &mut new_assignments,
@@ -366,6 +371,7 @@ async fn add_replacement_replicas_from_request(
                     extra_args: extra_args.clone(),
                     engine_type: None,
                     docker_image: None,
+                    role: None,
                 });
 
                 info!(
@@ -388,9 +394,11 @@ async fn add_replacement_replicas_from_request(
 
 /// Add replacement replicas for deployment-managed plans.
 /// Re-uses existing plan assignments' extra_args/engine_type/docker_image as template.
+/// For disaggregated deployments, replacements refill whichever role group is short.
 async fn add_replacement_replicas_from_plan(
     store: &EtcdMetaStore,
     plan: &PlacementPlan,
+    disaggregation: Option<&DisaggregationSpec>,
     deficit: u32,
     default_port: u16,
     new_assignments: &mut Vec<nebula_common::PlacementAssignment>,
@@ -413,9 +421,8 @@ async fn add_replacement_replicas_from_plan(
         .max()
         .unwrap_or(0);
 
-    // Use the first existing assignment as a template for extra_args/engine_type/docker_image
+    // Use the first existing assignment as a template for engine_type/docker_image
     let template = plan.assignments.first();
-    let extra_args = template.and_then(|a| a.extra_args.clone());
     let engine_type = template.and_then(|a| a.engine_type.clone());
     let docker_image = template.and_then(|a| a.docker_image.clone());
 
@@ -442,6 +449,21 @@ async fn add_replacement_replicas_from_plan(
 
     for i in 0..deficit {
         let new_replica_id = max_existing_id + 1 + i;
+        let role = disaggregation.and_then(|d| {
+            [ServingRole::Prefill, ServingRole::Decode]
+                .into_iter()
+                .find(|&r| {
+                    let have = new_assignments.iter().filter(|a| a.role == Some(r)).count();
+                    (have as u32) < d.replicas_for(r)
+                })
+        });
+        // Same-role assignments carry the role-specific args (e.g. KV transfer config).
+        let extra_args = plan
+            .assignments
+            .iter()
+            .find(|a| a.role == role)
+            .or(template)
+            .and_then(|a| a.extra_args.clone());
 
         match select_node_and_gpus(store, &dummy_req, &used_gpus).await {
             Ok((node_id, gpu_indices)) => {
@@ -473,14 +495,16 @@ async fn add_replacement_replicas_from_plan(
                     port,
                     gpu_index,
                     gpu_indices: gpu_indices_field,
-                    extra_args: extra_args.clone(),
+                    extra_args,
                     engine_type: engine_type.clone(),
                     docker_image: docker_image.clone(),
+                    role,
                 });
 
                 info!(
                     model_uid=%plan.model_uid,
                     replica_id=new_replica_id,
+                    role=?role,
                     "added replacement assignment (from deployment)"
                 );
             }
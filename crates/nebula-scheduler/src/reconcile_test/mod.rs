@@ -43,6 +43,7 @@ mod tests {
                 extra_args: None,
                 engine_type: None,
                 docker_image: None,
+                role: None,
             }],
         };
         let val2 = serde_json::to_vec(&concurrent_plan).unwrap();